@@ -10,6 +10,10 @@ fn main() {
     message_passing::recv_msg_with_iterator();
     // 克隆通道的发送端来实现多个发送者
     message_passing::clone_tx_to_send_with_multi_producer();
+    // 使用 mpmc 通道实现多个消费者，并用 select! 同时等待多个通道
+    message_passing::share_work_with_multi_consumer();
+    // 用 actor 代替手写的生产者线程，带有请求/响应、监督重启和有序停止
+    message_passing::send_msg_with_actors();
 
     // 在单线程中使用Mutex
     shared_state::use_mutex_in_single_thread();
@@ -0,0 +1,446 @@
+/*
+Actor：
+    每个 actor 运行在自己的线程里，独占自己的状态，外部只能通过它的地址 Addr 发消息；
+    消息放在 actor 的邮箱（mpmc 通道）里，由 actor 线程按顺序逐条处理，所以状态不需要加锁。
+
+        send        发送一条消息，不等待处理结果
+        ask         发送一条带 ReplyTo 的消息，阻塞等待 actor 通过这个一次性通道回复
+        stop        邮箱里已有的消息处理完之后停止；所有 Addr 都被丢弃时也会在处理完剩下的消息后停止
+        wait        等待 actor 线程退出
+
+    监督：处理消息时 panic 不会让 actor 线程退出，而是按 spawn_supervised 时给出的 Restart 策略处理：
+        Never       停止 actor，邮箱里剩下的消息被丢弃
+        Resume      丢掉出错的那条消息，保留当前状态继续处理
+        Restart     用 factory 重新创建 actor，within 时间内重启超过 max 次就放弃，按 Never 处理
+*/
+
+use crate::mpmc;
+use std::{
+    collections::VecDeque,
+    error::Error,
+    fmt,
+    panic::{self, AssertUnwindSafe},
+    sync::mpsc::{RecvTimeoutError, SendError},
+    thread,
+    time::{Duration, Instant},
+};
+
+pub trait Actor: Send + 'static {
+    type Msg: Send + 'static;
+
+    /// 开始处理消息之前调用，每次重启之后也会调用
+    fn started(&mut self, _ctx: &mut Context) {}
+
+    fn handle(&mut self, msg: Self::Msg, ctx: &mut Context);
+
+    /// 正常停止时调用；因为 panic 被放弃的 actor 不会调用
+    fn stopped(&mut self, _ctx: &mut Context) {}
+}
+
+/// actor 处理消息时可以访问的运行信息
+#[derive(Debug, Default)]
+pub struct Context {
+    stopping: bool,
+    restarts: u32,
+}
+
+impl Context {
+    /// 处理完当前消息之后停止，邮箱里剩下的消息被丢弃
+    pub fn stop(&mut self) {
+        self.stopping = true;
+    }
+
+    /// 到目前为止被重启的次数
+    pub fn restarts(&self) -> u32 {
+        self.restarts
+    }
+}
+
+/// 处理消息时 panic 之后怎么办
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Restart {
+    Never,
+    Resume,
+    Restart { max: u32, within: Duration },
+}
+
+enum Envelope<M> {
+    Msg(M),
+    Stop,
+}
+
+/// actor 的地址，可以克隆后交给其他线程或者其他 actor
+pub struct Addr<A: Actor> {
+    mailbox: mpmc::Sender<Envelope<A::Msg>>,
+    /// actor 线程持有唯一的发送端，线程退出后这里返回 Disconnected
+    done: mpmc::Receiver<()>,
+}
+
+impl<A: Actor> Addr<A> {
+    /// actor 已经停止时把消息原样返回
+    pub fn send(&self, msg: A::Msg) -> Result<(), SendError<A::Msg>> {
+        self.mailbox
+            .send(Envelope::Msg(msg))
+            .map_err(|SendError(envelope)| match envelope {
+                Envelope::Msg(msg) => SendError(msg),
+                Envelope::Stop => unreachable!(),
+            })
+    }
+
+    /// 发送一条需要回复的消息并等待回复，make 用传入的 ReplyTo 构造消息
+    pub fn ask<R>(&self, make: impl FnOnce(ReplyTo<R>) -> A::Msg) -> Result<R, AskError> {
+        let rx = self.request(make)?;
+        rx.recv().map_err(|_| AskError::NoReply)
+    }
+
+    /// 和 ask 一样，最多等待 timeout
+    pub fn ask_timeout<R>(
+        &self,
+        make: impl FnOnce(ReplyTo<R>) -> A::Msg,
+        timeout: Duration,
+    ) -> Result<R, AskError> {
+        let rx = self.request(make)?;
+        rx.recv_timeout(timeout).map_err(|err| match err {
+            RecvTimeoutError::Timeout => AskError::Timeout,
+            RecvTimeoutError::Disconnected => AskError::NoReply,
+        })
+    }
+
+    fn request<R>(
+        &self,
+        make: impl FnOnce(ReplyTo<R>) -> A::Msg,
+    ) -> Result<mpmc::Receiver<R>, AskError> {
+        let (tx, rx) = mpmc::bounded(1);
+        self.send(make(ReplyTo(tx)))
+            .map_err(|_| AskError::Stopped)?;
+        Ok(rx)
+    }
+
+    /// 让 actor 处理完已经在邮箱里的消息之后停止，不等待
+    pub fn stop(&self) {
+        // actor 已经停止时没有什么需要做的
+        let _ = self.mailbox.send(Envelope::Stop);
+    }
+
+    /// 等待 actor 线程退出
+    pub fn wait(&self) {
+        while self.done.recv().is_ok() {}
+    }
+
+    pub fn is_alive(&self) -> bool {
+        self.done.try_recv() != Err(std::sync::mpsc::TryRecvError::Disconnected)
+    }
+}
+
+impl<A: Actor> Clone for Addr<A> {
+    fn clone(&self) -> Addr<A> {
+        Addr {
+            mailbox: self.mailbox.clone(),
+            done: self.done.clone(),
+        }
+    }
+}
+
+impl<A: Actor> fmt::Debug for Addr<A> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Addr")
+            .field("alive", &self.is_alive())
+            .finish()
+    }
+}
+
+/// 一次性的回复通道，actor 处理 ask 的消息时调用 reply
+pub struct ReplyTo<T>(mpmc::Sender<T>);
+
+impl<T> ReplyTo<T> {
+    pub fn reply(self, value: T) {
+        // 对方可能已经超时放弃等待
+        let _ = self.0.send(value);
+    }
+}
+
+impl<T> fmt::Debug for ReplyTo<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ReplyTo").finish_non_exhaustive()
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum AskError {
+    /// actor 已经停止，消息没有送达
+    Stopped,
+    /// actor 没有回复就丢掉了 ReplyTo，比如处理这条消息时 panic
+    NoReply,
+    Timeout,
+}
+
+impl fmt::Display for AskError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AskError::Stopped => write!(f, "actor has stopped"),
+            AskError::NoReply => write!(f, "actor dropped the request without replying"),
+            AskError::Timeout => write!(f, "timed out waiting for the reply"),
+        }
+    }
+}
+
+impl Error for AskError {}
+
+/// 启动一个不会重启的 actor
+pub fn spawn<A: Actor>(actor: A) -> Addr<A> {
+    let mut actor = Some(actor);
+    spawn_supervised(
+        move || {
+            actor
+                .take()
+                .expect("an actor that never restarts is created once")
+        },
+        Restart::Never,
+    )
+}
+
+/// 启动一个受监督的 actor，factory 用来创建第一个实例以及重启时的新实例
+pub fn spawn_supervised<A, F>(factory: F, restart: Restart) -> Addr<A>
+where
+    A: Actor,
+    F: FnMut() -> A + Send + 'static,
+{
+    let (mailbox, rx) = mpmc::unbounded();
+    let (done_tx, done) = mpmc::bounded(1);
+    let name = std::any::type_name::<A>()
+        .rsplit("::")
+        .next()
+        .unwrap_or("actor")
+        .to_string();
+    thread::Builder::new()
+        .name(name)
+        .spawn(move || {
+            let _done = done_tx;
+            run(factory, restart, rx);
+        })
+        .expect("failed to spawn actor thread");
+    Addr { mailbox, done }
+}
+
+/// actor 线程的主循环
+fn run<A: Actor>(
+    mut factory: impl FnMut() -> A,
+    restart: Restart,
+    rx: mpmc::Receiver<Envelope<A::Msg>>,
+) {
+    let mut ctx = Context::default();
+    // 重启的时间，用来判断是否超过了 within 时间内 max 次的限制
+    let mut history = VecDeque::new();
+    let mut actor = factory();
+    let mut ok = catch(|| actor.started(&mut ctx));
+    loop {
+        if !ok {
+            match restart {
+                Restart::Never => return,
+                Restart::Resume => {}
+                Restart::Restart { max, within } => {
+                    let now = Instant::now();
+                    while history
+                        .front()
+                        .is_some_and(|&at| now.duration_since(at) > within)
+                    {
+                        history.pop_front();
+                    }
+                    if history.len() >= max as usize {
+                        return;
+                    }
+                    history.push_back(now);
+                    ctx.restarts += 1;
+                    actor = factory();
+                    if !catch(|| actor.started(&mut ctx)) {
+                        continue;
+                    }
+                }
+            }
+        }
+        if ctx.stopping {
+            break;
+        }
+        match rx.recv() {
+            Ok(Envelope::Msg(msg)) => ok = catch(|| actor.handle(msg, &mut ctx)),
+            // 所有 Addr 都被丢弃时 recv 返回 Err
+            Ok(Envelope::Stop) | Err(_) => break,
+        }
+    }
+    actor.stopped(&mut ctx);
+}
+
+/// 执行 f，panic 时返回 false；panic 的信息已经由 panic hook 打印出来了
+fn catch(f: impl FnOnce()) -> bool {
+    panic::catch_unwind(AssertUnwindSafe(f)).is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    };
+
+    enum CounterMsg {
+        Add(usize),
+        Get(ReplyTo<usize>),
+        Panic,
+        Stop,
+    }
+
+    struct Counter {
+        count: usize,
+        stopped: Arc<AtomicUsize>,
+    }
+
+    impl Counter {
+        fn new(stopped: &Arc<AtomicUsize>) -> Counter {
+            Counter {
+                count: 0,
+                stopped: Arc::clone(stopped),
+            }
+        }
+    }
+
+    impl Actor for Counter {
+        type Msg = CounterMsg;
+
+        fn handle(&mut self, msg: CounterMsg, ctx: &mut Context) {
+            match msg {
+                CounterMsg::Add(n) => self.count += n,
+                CounterMsg::Get(reply) => reply.reply(self.count),
+                CounterMsg::Panic => panic!("counter asked to panic"),
+                CounterMsg::Stop => ctx.stop(),
+            }
+        }
+
+        fn stopped(&mut self, _ctx: &mut Context) {
+            self.stopped.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn ask_gets_the_reply_after_earlier_messages() {
+        let stopped = Arc::new(AtomicUsize::new(0));
+        let addr = spawn(Counter::new(&stopped));
+        for n in 1..=4 {
+            addr.send(CounterMsg::Add(n)).unwrap();
+        }
+        assert_eq!(addr.ask(CounterMsg::Get), Ok(10));
+        assert_eq!(
+            addr.ask_timeout(CounterMsg::Get, Duration::from_secs(5)),
+            Ok(10)
+        );
+        assert_eq!(addr.ask_timeout(CounterMsg::Get, Duration::MAX), Ok(10));
+        addr.stop();
+        addr.wait();
+        assert!(!addr.is_alive());
+        assert_eq!(stopped.load(Ordering::SeqCst), 1);
+        assert_eq!(addr.ask(CounterMsg::Get), Err(AskError::Stopped));
+        assert!(addr.send(CounterMsg::Add(1)).is_err());
+    }
+
+    #[test]
+    fn stop_processes_queued_messages_first() {
+        let stopped = Arc::new(AtomicUsize::new(0));
+        let addr = spawn(Counter::new(&stopped));
+        let (tx, rx) = mpmc::bounded(1);
+        for n in 0..100 {
+            addr.send(CounterMsg::Add(n)).unwrap();
+        }
+        addr.send(CounterMsg::Get(ReplyTo(tx))).unwrap();
+        addr.stop();
+        addr.wait();
+        assert_eq!(rx.recv(), Ok(4950));
+
+        // Context::stop 丢弃剩下的消息
+        let addr = spawn(Counter::new(&stopped));
+        addr.send(CounterMsg::Stop).unwrap();
+        let (tx, rx) = mpmc::bounded(1);
+        let _ = addr.send(CounterMsg::Get(ReplyTo(tx)));
+        addr.wait();
+        assert!(rx.recv().is_err());
+        assert_eq!(stopped.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn dropping_every_addr_stops_the_actor() {
+        let stopped = Arc::new(AtomicUsize::new(0));
+        let addr = spawn(Counter::new(&stopped));
+        let done = addr.done.clone();
+        drop(addr);
+        while done.recv().is_ok() {}
+        assert_eq!(stopped.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn restart_strategies() {
+        let stopped = Arc::new(AtomicUsize::new(0));
+
+        // Never：panic 之后 actor 停止，不调用 stopped
+        let addr = spawn(Counter::new(&stopped));
+        addr.send(CounterMsg::Add(1)).unwrap();
+        assert_eq!(
+            addr.ask(|_| CounterMsg::Panic),
+            Err::<(), _>(AskError::NoReply)
+        );
+        addr.wait();
+        assert_eq!(addr.ask(CounterMsg::Get), Err(AskError::Stopped));
+
+        // Resume：保留之前的状态
+        let s = Arc::clone(&stopped);
+        let addr = spawn_supervised(move || Counter::new(&s), Restart::Resume);
+        addr.send(CounterMsg::Add(5)).unwrap();
+        addr.send(CounterMsg::Panic).unwrap();
+        assert_eq!(addr.ask(CounterMsg::Get), Ok(5));
+        addr.stop();
+        addr.wait();
+
+        // Restart：状态重新开始，超过次数限制后停止
+        let s = Arc::clone(&stopped);
+        let created = Arc::new(AtomicUsize::new(0));
+        let c = Arc::clone(&created);
+        let addr = spawn_supervised(
+            move || {
+                c.fetch_add(1, Ordering::SeqCst);
+                Counter::new(&s)
+            },
+            Restart::Restart {
+                max: 2,
+                within: Duration::from_secs(60),
+            },
+        );
+        addr.send(CounterMsg::Add(5)).unwrap();
+        addr.send(CounterMsg::Panic).unwrap();
+        assert_eq!(addr.ask(CounterMsg::Get), Ok(0));
+        addr.send(CounterMsg::Panic).unwrap();
+        addr.send(CounterMsg::Panic).unwrap();
+        addr.wait();
+        assert_eq!(created.load(Ordering::SeqCst), 3);
+        assert_eq!(stopped.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn ask_timeout_gives_up_waiting() {
+        struct Slow;
+
+        impl Actor for Slow {
+            type Msg = ReplyTo<()>;
+
+            fn handle(&mut self, reply: ReplyTo<()>, _ctx: &mut Context) {
+                thread::sleep(Duration::from_millis(50));
+                reply.reply(());
+            }
+        }
+
+        let addr = spawn(Slow);
+        assert_eq!(
+            addr.ask_timeout(|reply| reply, Duration::from_millis(5)),
+            Err(AskError::Timeout)
+        );
+        // 对方放弃等待之后回复不会出错
+        assert_eq!(addr.ask(|reply| reply), Ok(()));
+    }
+}
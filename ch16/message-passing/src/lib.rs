@@ -3,8 +3,12 @@ Rust 中一个实现消息传递并发的主要工具是通道(channel)。这个
 “不要共享内存来通讯;而是要通讯来共享内存。”(“Do not communicate by sharing memory; instead, share memory by communicating.”)
 */
 
+use actor::{Actor, Addr, Context, ReplyTo, Restart};
 use std::{sync::mpsc, thread, time::Duration}; // Multi-producer, single-consumer
 
+pub mod actor;
+pub mod mpmc;
+
 pub fn communicate_between_thread_with_channel() {
     // tx 代表发送者，rx代表接收者；当发送者或接收者任一被丢弃时可以认为通道被 关闭(closed)了。
     let (tx, rx) = mpsc::channel();
@@ -22,7 +26,7 @@ pub fn communicate_between_thread_with_channel() {
     // 而 Err 值代表此时没有任何消息，并不会因为通道里没消息而阻塞线程
     let received = rx
         .recv()
-        .unwrap_or_else(|err| String::from(format!("receive msg err: {}", err)));
+        .unwrap_or_else(|err| format!("receive msg err: {}", err));
     println!("receive msg: {}", received);
     handle.join().unwrap();
 }
@@ -82,8 +86,155 @@ pub fn clone_tx_to_send_with_multi_producer() {
         }
     });
 
-    // rust 标准库中不支持多消费者，如果要实现多消费者，可以使用社区广泛使用的增强版通道的 crossbeam-channel
+    // rust 标准库中不支持多消费者，如果要实现多消费者，可以使用社区广泛使用的增强版通道的 crossbeam-channel，
+    // 或者本 crate 中的 mpmc 模块，见 share_work_with_multi_consumer
     for msg in rx {
         println!("Got: {}", msg);
     }
 }
+
+// mpmc 通道的接收端也可以克隆，多个消费者一起处理同一个通道里的消息，每条消息只会被一个消费者拿到
+pub fn share_work_with_multi_consumer() {
+    // 有界通道，消费者处理不过来时发送者会被阻塞
+    let (tx, rx) = mpmc::bounded(2);
+    let (done_tx, done_rx) = mpmc::unbounded();
+
+    let consumers: Vec<_> = (0..3)
+        .map(|id| {
+            let rx = rx.clone();
+            let done_tx = done_tx.clone();
+            thread::spawn(move || {
+                for job in rx {
+                    thread::sleep(Duration::from_millis(100));
+                    done_tx
+                        .send(format!("consumer {} handled job {}", id, job))
+                        .unwrap();
+                }
+            })
+        })
+        .collect();
+    // 只保留消费者手里的端，消费者都退出之后 done_rx 才会断开
+    drop(rx);
+    drop(done_tx);
+
+    thread::spawn(move || {
+        for job in 0..6 {
+            tx.send(job).unwrap();
+        }
+    });
+
+    // 同时等待两个通道：有结果就打印，长时间没有结果就提示一下
+    let (_tick_tx, tick_rx) = mpmc::unbounded::<()>();
+    loop {
+        let finished = crate::select! {
+            recv(done_rx) -> msg => match msg {
+                Ok(msg) => {
+                    println!("Got: {}", msg);
+                    false
+                }
+                Err(_) => true,
+            },
+            recv(tick_rx) -> _msg => false,
+            default(Duration::from_millis(500)) => {
+                println!("still waiting...");
+                false
+            },
+        };
+        if finished {
+            break;
+        }
+    }
+    for consumer in consumers {
+        consumer.join().unwrap();
+    }
+}
+
+// 用 actor 代替上面手写的生产者线程：生产者和打印者都是 actor，彼此只通过地址发消息
+enum PrinterMsg {
+    Print(String),
+    Count(ReplyTo<usize>),
+}
+
+#[derive(Default)]
+struct Printer {
+    count: usize,
+}
+
+impl Actor for Printer {
+    type Msg = PrinterMsg;
+
+    fn handle(&mut self, msg: PrinterMsg, _ctx: &mut Context) {
+        match msg {
+            PrinterMsg::Print(msg) => {
+                self.count += 1;
+                println!("Got: {}", msg);
+            }
+            PrinterMsg::Count(reply) => reply.reply(self.count),
+        }
+    }
+}
+
+struct Producer {
+    printer: Addr<Printer>,
+}
+
+impl Actor for Producer {
+    type Msg = Vec<String>;
+
+    fn handle(&mut self, vals: Vec<String>, ctx: &mut Context) {
+        for val in vals {
+            // 模拟出错：panic 之后由监督策略重启，这一批剩下的消息被丢掉
+            if val.is_empty() {
+                panic!("producer got an empty message");
+            }
+            if self.printer.send(PrinterMsg::Print(val)).is_err() {
+                ctx.stop();
+                return;
+            }
+            thread::sleep(Duration::from_millis(100));
+        }
+    }
+}
+
+pub fn send_msg_with_actors() {
+    let printer = actor::spawn(Printer::default());
+
+    // 两个生产者共用同一个 factory 的写法，重启时用 factory 重新创建
+    let producers: Vec<_> = (0..2)
+        .map(|_| {
+            let printer = printer.clone();
+            actor::spawn_supervised(
+                move || Producer {
+                    printer: printer.clone(),
+                },
+                Restart::Restart {
+                    max: 3,
+                    within: Duration::from_secs(10),
+                },
+            )
+        })
+        .collect();
+
+    let words = |vals: &[&str]| vals.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+    producers[0]
+        .send(words(&["hi", "from", "the", "actor"]))
+        .unwrap();
+    producers[1].send(words(&["more", "", "lost"])).unwrap();
+    producers[1]
+        .send(words(&["messages", "for", "you"]))
+        .unwrap();
+
+    // 先停生产者，等它们把邮箱里的消息处理完，再询问打印者收到了多少条
+    for producer in &producers {
+        producer.stop();
+    }
+    for producer in &producers {
+        producer.wait();
+    }
+    match printer.ask(PrinterMsg::Count) {
+        Ok(count) => println!("printer got {} messages", count),
+        Err(err) => println!("ask printer err: {}", err),
+    }
+    printer.stop();
+    printer.wait();
+}
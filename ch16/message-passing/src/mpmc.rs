@@ -0,0 +1,762 @@
+/*
+多生产者多消费者(MPMC)通道：
+    标准库的 mpsc 只允许一个接收者，这里的 Sender 和 Receiver 都可以克隆，多个线程可以同时从一个通道里取消息，
+    每条消息只会被其中一个接收者拿到，适合把任务分给一组线程。
+
+        unbounded()     不限制长度，send 永远不会阻塞
+        bounded(cap)    最多缓存 cap 条消息，满了之后 send 阻塞，直到有接收者取走消息
+
+    断开：所有 Sender 都被丢弃之后，接收者还能取完剩下的消息，然后 recv 返回 RecvError、迭代器结束；
+    所有 Receiver 都被丢弃之后，通道里剩下的消息被立即释放，send 返回 SendError，把消息还给调用方。错误类型直接使用 std::sync::mpsc 中的定义。
+
+    实现：一把 Mutex 保护队列和两端的计数，接收者在 not_empty 上等待，有界通道的发送者在 not_full 上等待。
+    select! 需要同时等待多个通道，每次等待时创建一个 Signal 登记到所有通道上，任何一个通道有了新消息或者断开，
+    都会触发 Signal；登记时在通道的锁内检查是否已经就绪，所以不会错过通知。
+*/
+
+use std::{
+    cell::Cell,
+    collections::VecDeque,
+    fmt,
+    sync::{
+        Arc, Condvar, Mutex, MutexGuard, PoisonError,
+        mpsc::{RecvError, RecvTimeoutError, SendError, TryRecvError, TrySendError},
+    },
+    time::{Duration, Instant},
+};
+
+struct State<T> {
+    queue: VecDeque<T>,
+    senders: usize,
+    receivers: usize,
+    /// 正在 select 中等待这个通道的线程
+    selectors: Vec<Arc<Signal>>,
+}
+
+struct Chan<T> {
+    state: Mutex<State<T>>,
+    not_empty: Condvar,
+    not_full: Condvar,
+    /// None 表示不限制长度
+    cap: Option<usize>,
+}
+
+impl<T> Chan<T> {
+    /// 通道内的数据只在锁内修改，持有锁的线程 panic 也不会让它处于一半的状态
+    fn lock(&self) -> MutexGuard<'_, State<T>> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl<T> State<T> {
+    /// 有消息可取，或者发送端已经全部断开，recv 不会再阻塞
+    fn ready(&self) -> bool {
+        !self.queue.is_empty() || self.senders == 0
+    }
+
+    fn wake_selectors(&self) {
+        for signal in &self.selectors {
+            signal.fire();
+        }
+    }
+}
+
+/// 创建一个不限制长度的通道
+pub fn unbounded<T>() -> (Sender<T>, Receiver<T>) {
+    channel(None)
+}
+
+/// 创建一个最多缓存 cap 条消息的通道
+///
+/// # Panics
+///
+/// cap 为 0 时 panic
+pub fn bounded<T>(cap: usize) -> (Sender<T>, Receiver<T>) {
+    assert!(cap > 0, "bounded channel capacity must be greater than 0");
+    channel(Some(cap))
+}
+
+fn channel<T>(cap: Option<usize>) -> (Sender<T>, Receiver<T>) {
+    let chan = Arc::new(Chan {
+        state: Mutex::new(State {
+            queue: VecDeque::new(),
+            senders: 1,
+            receivers: 1,
+            selectors: Vec::new(),
+        }),
+        not_empty: Condvar::new(),
+        not_full: Condvar::new(),
+        cap,
+    });
+    (
+        Sender {
+            chan: Arc::clone(&chan),
+        },
+        Receiver { chan },
+    )
+}
+
+/// 通道的发送端，可以克隆后交给多个线程
+pub struct Sender<T> {
+    chan: Arc<Chan<T>>,
+}
+
+impl<T> Sender<T> {
+    /// 发送一条消息，有界通道满了时阻塞；所有接收者都已经断开时把消息原样返回
+    pub fn send(&self, msg: T) -> Result<(), SendError<T>> {
+        let mut state = self.chan.lock();
+        loop {
+            if state.receivers == 0 {
+                return Err(SendError(msg));
+            }
+            if self.chan.cap.is_none_or(|cap| state.queue.len() < cap) {
+                break;
+            }
+            state = self
+                .chan
+                .not_full
+                .wait(state)
+                .unwrap_or_else(PoisonError::into_inner);
+        }
+        self.push(state, msg);
+        Ok(())
+    }
+
+    /// 不阻塞地发送，通道满了返回 Full，接收者都已经断开返回 Disconnected
+    pub fn try_send(&self, msg: T) -> Result<(), TrySendError<T>> {
+        let state = self.chan.lock();
+        if state.receivers == 0 {
+            return Err(TrySendError::Disconnected(msg));
+        }
+        if self.chan.cap.is_some_and(|cap| state.queue.len() >= cap) {
+            return Err(TrySendError::Full(msg));
+        }
+        self.push(state, msg);
+        Ok(())
+    }
+
+    fn push(&self, mut state: MutexGuard<'_, State<T>>, msg: T) {
+        state.queue.push_back(msg);
+        state.wake_selectors();
+        drop(state);
+        self.chan.not_empty.notify_one();
+    }
+
+    /// 通道里还没有被取走的消息数
+    pub fn len(&self) -> usize {
+        self.chan.lock().queue.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// 有界通道的容量，无界通道返回 None
+    pub fn capacity(&self) -> Option<usize> {
+        self.chan.cap
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Sender<T> {
+        self.chan.lock().senders += 1;
+        Sender {
+            chan: Arc::clone(&self.chan),
+        }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        let mut state = self.chan.lock();
+        state.senders -= 1;
+        if state.senders == 0 {
+            // 等待中的接收者需要醒来返回 RecvError
+            state.wake_selectors();
+            drop(state);
+            self.chan.not_empty.notify_all();
+        }
+    }
+}
+
+impl<T> fmt::Debug for Sender<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Sender").finish_non_exhaustive()
+    }
+}
+
+/// 通道的接收端，可以克隆后交给多个线程，每条消息只会被其中一个取走
+pub struct Receiver<T> {
+    chan: Arc<Chan<T>>,
+}
+
+impl<T> Receiver<T> {
+    /// 取一条消息，没有消息时阻塞；发送者都已经断开并且消息已经取完时返回 RecvError
+    pub fn recv(&self) -> Result<T, RecvError> {
+        let mut state = self.chan.lock();
+        loop {
+            if let Some(msg) = self.pop(&mut state) {
+                return Ok(msg);
+            }
+            if state.senders == 0 {
+                return Err(RecvError);
+            }
+            state = self
+                .chan
+                .not_empty
+                .wait(state)
+                .unwrap_or_else(PoisonError::into_inner);
+        }
+    }
+
+    /// 不阻塞地取一条消息
+    pub fn try_recv(&self) -> Result<T, TryRecvError> {
+        let mut state = self.chan.lock();
+        match self.pop(&mut state) {
+            Some(msg) => Ok(msg),
+            None if state.senders == 0 => Err(TryRecvError::Disconnected),
+            None => Err(TryRecvError::Empty),
+        }
+    }
+
+    /// 最多等待 timeout；和 std 一样，timeout 太长、截止时间超出 Instant 的范围时按 recv 一直等下去
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<T, RecvTimeoutError> {
+        let Some(deadline) = Instant::now().checked_add(timeout) else {
+            return self.recv().map_err(|_| RecvTimeoutError::Disconnected);
+        };
+        let mut state = self.chan.lock();
+        loop {
+            if let Some(msg) = self.pop(&mut state) {
+                return Ok(msg);
+            }
+            if state.senders == 0 {
+                return Err(RecvTimeoutError::Disconnected);
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(RecvTimeoutError::Timeout);
+            }
+            state = self
+                .chan
+                .not_empty
+                .wait_timeout(state, deadline - now)
+                .unwrap_or_else(PoisonError::into_inner)
+                .0;
+        }
+    }
+
+    fn pop(&self, state: &mut State<T>) -> Option<T> {
+        let msg = state.queue.pop_front()?;
+        if self.chan.cap.is_some() {
+            self.chan.not_full.notify_one();
+        }
+        Some(msg)
+    }
+
+    /// 阻塞的迭代器，发送者都断开并且消息取完之后结束
+    pub fn iter(&self) -> Iter<'_, T> {
+        Iter { rx: self }
+    }
+
+    /// 只取出当前已经在通道里的消息，不会阻塞
+    pub fn try_iter(&self) -> TryIter<'_, T> {
+        TryIter { rx: self }
+    }
+
+    pub fn len(&self) -> usize {
+        self.chan.lock().queue.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn capacity(&self) -> Option<usize> {
+        self.chan.cap
+    }
+}
+
+impl<T> Clone for Receiver<T> {
+    fn clone(&self) -> Receiver<T> {
+        self.chan.lock().receivers += 1;
+        Receiver {
+            chan: Arc::clone(&self.chan),
+        }
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        let mut state = self.chan.lock();
+        state.receivers -= 1;
+        if state.receivers == 0 {
+            // 没有人能再取走剩下的消息，立即释放它们，不用等到发送者也都断开；
+            // 消息的 drop 可能做任何事情，放在锁外
+            let unread = std::mem::take(&mut state.queue);
+            // 阻塞在满通道上的发送者需要醒来返回 SendError
+            drop(state);
+            self.chan.not_full.notify_all();
+            drop(unread);
+        }
+    }
+}
+
+impl<T> fmt::Debug for Receiver<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Receiver").finish_non_exhaustive()
+    }
+}
+
+pub struct Iter<'a, T> {
+    rx: &'a Receiver<T>,
+}
+
+impl<T> Iterator for Iter<'_, T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.rx.recv().ok()
+    }
+}
+
+pub struct TryIter<'a, T> {
+    rx: &'a Receiver<T>,
+}
+
+impl<T> Iterator for TryIter<'_, T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.rx.try_recv().ok()
+    }
+}
+
+pub struct IntoIter<T> {
+    rx: Receiver<T>,
+}
+
+impl<T> Iterator for IntoIter<T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.rx.recv().ok()
+    }
+}
+
+impl<'a, T> IntoIterator for &'a Receiver<T> {
+    type Item = T;
+    type IntoIter = Iter<'a, T>;
+
+    fn into_iter(self) -> Iter<'a, T> {
+        self.iter()
+    }
+}
+
+impl<T> IntoIterator for Receiver<T> {
+    type Item = T;
+    type IntoIter = IntoIter<T>;
+
+    fn into_iter(self) -> IntoIter<T> {
+        IntoIter { rx: self }
+    }
+}
+
+/// select 等待时登记到各个通道上的通知
+struct Signal {
+    fired: Mutex<bool>,
+    cond: Condvar,
+}
+
+impl Signal {
+    fn fire(&self) {
+        *self.fired.lock().unwrap_or_else(PoisonError::into_inner) = true;
+        self.cond.notify_one();
+    }
+
+    /// 等到被触发或者超过 deadline
+    fn wait(&self, deadline: Option<Instant>) {
+        let mut fired = self.fired.lock().unwrap_or_else(PoisonError::into_inner);
+        while !*fired {
+            fired = match deadline {
+                None => self
+                    .cond
+                    .wait(fired)
+                    .unwrap_or_else(PoisonError::into_inner),
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        return;
+                    }
+                    self.cond
+                        .wait_timeout(fired, deadline - now)
+                        .unwrap_or_else(PoisonError::into_inner)
+                        .0
+                }
+            };
+        }
+    }
+}
+
+/// Select 对不同消息类型的 Receiver 的统一视图
+trait Selectable {
+    /// 登记 signal，已经就绪时不登记，直接返回 true
+    fn register(&self, signal: &Arc<Signal>) -> bool;
+    fn unregister(&self, signal: &Arc<Signal>);
+    fn is_ready(&self) -> bool;
+}
+
+impl<T> Selectable for Receiver<T> {
+    fn register(&self, signal: &Arc<Signal>) -> bool {
+        let mut state = self.chan.lock();
+        if state.ready() {
+            return true;
+        }
+        state.selectors.push(Arc::clone(signal));
+        false
+    }
+
+    fn unregister(&self, signal: &Arc<Signal>) {
+        self.chan
+            .lock()
+            .selectors
+            .retain(|s| !Arc::ptr_eq(s, signal));
+    }
+
+    fn is_ready(&self) -> bool {
+        self.chan.lock().ready()
+    }
+}
+
+thread_local! {
+    /// 每次 select 从不同的接收者开始检查，一个一直有消息的通道不会让其他通道饿死
+    static START: Cell<usize> = const { Cell::new(0) };
+}
+
+/// 同时等待多个接收者，一般通过 [`select!`](crate::select) 使用
+///
+/// ```
+/// use message_passing::mpmc::{self, Select};
+///
+/// let (tx1, rx1) = mpmc::unbounded::<i32>();
+/// let (tx2, rx2) = mpmc::unbounded::<&str>();
+/// tx2.send("hi").unwrap();
+/// let mut sel = Select::new();
+/// let first = sel.recv(&rx1);
+/// let second = sel.recv(&rx2);
+/// assert_eq!(sel.ready(), second);
+/// assert_eq!(rx2.try_recv(), Ok("hi"));
+/// # drop((tx1, first));
+/// ```
+#[derive(Default)]
+pub struct Select<'a> {
+    receivers: Vec<&'a dyn Selectable>,
+}
+
+impl<'a> Select<'a> {
+    pub fn new() -> Select<'a> {
+        Select::default()
+    }
+
+    /// 加入一个接收者，返回它的下标
+    pub fn recv<T>(&mut self, rx: &'a Receiver<T>) -> usize {
+        self.receivers.push(rx);
+        self.receivers.len() - 1
+    }
+
+    /// 阻塞到某个接收者有消息或者已经断开，返回它的下标
+    ///
+    /// 只是“就绪”的提示：其他线程可能先一步取走消息，这时 try_recv 会返回 Empty，需要重新 select
+    ///
+    /// # Panics
+    ///
+    /// 没有加入任何接收者时 panic
+    pub fn ready(&mut self) -> usize {
+        self.wait(None).expect("select without a deadline")
+    }
+
+    /// 最多等待 timeout，超时返回 None；截止时间超出 Instant 的范围时不限时
+    pub fn ready_timeout(&mut self, timeout: Duration) -> Option<usize> {
+        self.wait(Instant::now().checked_add(timeout))
+    }
+
+    fn wait(&mut self, deadline: Option<Instant>) -> Option<usize> {
+        let n = self.receivers.len();
+        assert!(n > 0, "select needs at least one receiver");
+        let start = START.with(|start| {
+            let value = start.get();
+            start.set(value.wrapping_add(1));
+            value % n
+        });
+        loop {
+            let signal = Arc::new(Signal {
+                fired: Mutex::new(false),
+                cond: Condvar::new(),
+            });
+            let mut ready = None;
+            let mut registered = 0;
+            for i in (0..n).map(|k| (start + k) % n) {
+                if self.receivers[i].register(&signal) {
+                    ready = Some(i);
+                    break;
+                }
+                registered += 1;
+            }
+            if ready.is_none() {
+                signal.wait(deadline);
+            }
+            for i in (0..registered).map(|k| (start + k) % n) {
+                self.receivers[i].unregister(&signal);
+            }
+            if ready.is_some() {
+                return ready;
+            }
+            // 超时之前最后检查一遍；没有超时就回到开头，下一轮登记时会找到就绪的接收者
+            if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                return (0..n)
+                    .map(|k| (start + k) % n)
+                    .find(|&i| self.receivers[i].is_ready());
+            }
+        }
+    }
+}
+
+/// 同时等待多个接收者，执行第一个收到消息（或者断开）的分支
+///
+/// 每个分支的 msg 是 `Result<T, RecvError>`，发送者都断开并且消息取完时是 Err；
+/// 可选的 `default(timeout)` 分支在超时之后执行。接收者表达式会被求值多次，应该是简单的变量
+///
+/// ```
+/// use message_passing::{mpmc, select};
+/// use std::time::Duration;
+///
+/// let (tx1, rx1) = mpmc::unbounded::<i32>();
+/// let (_tx2, rx2) = mpmc::unbounded::<String>();
+/// tx1.send(7).unwrap();
+/// let got = select! {
+///     recv(rx1) -> msg => msg.unwrap(),
+///     recv(rx2) -> msg => msg.unwrap().len() as i32,
+///     default(Duration::from_secs(1)) => -1,
+/// };
+/// assert_eq!(got, 7);
+/// ```
+#[macro_export]
+macro_rules! select {
+    ($(recv($rx:expr) -> $msg:pat => $body:expr),+ $(,)?) => {
+        // 没有超时时间，ready 总是返回 Some
+        $crate::select!(@run None; $(recv($rx) -> $msg => $body),+; continue)
+    };
+    ($(recv($rx:expr) -> $msg:pat => $body:expr,)+ default($timeout:expr) => $default:expr $(,)?) => {
+        $crate::select!(@run Some($timeout); $(recv($rx) -> $msg => $body),+; break $default)
+    };
+    (@run $timeout:expr; $(recv($rx:expr) -> $msg:pat => $body:expr),+; $timed_out:expr) => {{
+        #[allow(unused_assignments)]
+        let result = loop {
+            let mut sel = $crate::mpmc::Select::new();
+            $(sel.recv(&$rx);)+
+            let timeout: ::std::option::Option<::std::time::Duration> = $timeout;
+            let ready = match timeout {
+                ::std::option::Option::Some(timeout) => sel.ready_timeout(timeout),
+                ::std::option::Option::None => ::std::option::Option::Some(sel.ready()),
+            };
+            let ::std::option::Option::Some(ready) = ready else {
+                $timed_out
+            };
+            let mut arm = 0usize;
+            $(
+                if ready == arm {
+                    let got = match $rx.try_recv() {
+                        ::std::result::Result::Ok(msg) => ::std::option::Option::Some(::std::result::Result::Ok(msg)),
+                        ::std::result::Result::Err(::std::sync::mpsc::TryRecvError::Disconnected) => {
+                            ::std::option::Option::Some(::std::result::Result::Err(::std::sync::mpsc::RecvError))
+                        }
+                        // 消息被其他线程抢先取走了，重新等待
+                        ::std::result::Result::Err(::std::sync::mpsc::TryRecvError::Empty) => ::std::option::Option::None,
+                    };
+                    if let ::std::option::Option::Some($msg) = got {
+                        break $body;
+                    }
+                }
+                arm += 1;
+            )+
+        };
+        result
+    }};
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn messages_keep_their_order() {
+        let (tx, rx) = unbounded();
+        for i in 0..5 {
+            tx.send(i).unwrap();
+        }
+        assert_eq!(rx.len(), 5);
+        assert_eq!(rx.try_iter().collect::<Vec<_>>(), [0, 1, 2, 3, 4]);
+        assert_eq!(rx.try_recv(), Err(TryRecvError::Empty));
+        assert_eq!(tx.capacity(), None);
+    }
+
+    #[test]
+    fn disconnection_is_seen_by_both_ends() {
+        let (tx, rx) = unbounded();
+        let tx2 = tx.clone();
+        tx.send(1).unwrap();
+        drop(tx);
+        tx2.send(2).unwrap();
+        drop(tx2);
+        // 发送端都断开之后仍然可以取完剩下的消息
+        assert_eq!(rx.iter().collect::<Vec<_>>(), [1, 2]);
+        assert_eq!(rx.recv(), Err(RecvError));
+        assert_eq!(rx.try_recv(), Err(TryRecvError::Disconnected));
+
+        let (tx, rx) = bounded(1);
+        let rx2 = rx.clone();
+        drop(rx);
+        tx.send("kept").unwrap();
+        drop(rx2);
+        assert_eq!(tx.send("lost"), Err(SendError("lost")));
+        assert!(tx.is_empty());
+        assert_eq!(tx.try_send("lost"), Err(TrySendError::Disconnected("lost")));
+    }
+
+    #[test]
+    fn bounded_channel_blocks_when_full() {
+        let (tx, rx) = bounded(2);
+        tx.send(1).unwrap();
+        tx.try_send(2).unwrap();
+        assert_eq!(tx.try_send(3), Err(TrySendError::Full(3)));
+        let sender = thread::spawn(move || {
+            tx.send(3).unwrap();
+            // 接收者都断开时，阻塞中的发送者也会返回
+            tx.send(4)
+        });
+        thread::sleep(Duration::from_millis(20));
+        assert!(!sender.is_finished());
+        assert_eq!(rx.recv(), Ok(1));
+        while rx.len() < 2 {
+            thread::sleep(Duration::from_millis(1));
+        }
+        drop(rx);
+        assert_eq!(sender.join().unwrap(), Err(SendError(4)));
+    }
+
+    #[test]
+    fn recv_timeout_waits_for_a_message() {
+        let (tx, rx) = unbounded();
+        let started = Instant::now();
+        assert_eq!(
+            rx.recv_timeout(Duration::from_millis(20)),
+            Err(RecvTimeoutError::Timeout)
+        );
+        assert!(started.elapsed() >= Duration::from_millis(20));
+        let sender = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(10));
+            tx.send("late").unwrap();
+        });
+        assert_eq!(rx.recv_timeout(Duration::from_secs(5)), Ok("late"));
+        sender.join().unwrap();
+        assert_eq!(
+            rx.recv_timeout(Duration::from_secs(5)),
+            Err(RecvTimeoutError::Disconnected)
+        );
+
+        // 太长的 timeout 不会溢出，按不限时等待
+        let (tx, rx) = unbounded();
+        tx.send("now").unwrap();
+        assert_eq!(rx.recv_timeout(Duration::MAX), Ok("now"));
+        drop(tx);
+        assert_eq!(
+            rx.recv_timeout(Duration::MAX),
+            Err(RecvTimeoutError::Disconnected)
+        );
+    }
+
+    #[test]
+    fn each_message_goes_to_one_receiver() {
+        let (tx, rx) = unbounded();
+        let consumers: Vec<_> = (0..3)
+            .map(|_| {
+                let rx = rx.clone();
+                thread::spawn(move || rx.iter().collect::<Vec<i32>>())
+            })
+            .collect();
+        drop(rx);
+        for i in 0..300 {
+            tx.send(i).unwrap();
+        }
+        drop(tx);
+        let mut all: Vec<i32> = consumers
+            .into_iter()
+            .flat_map(|c| c.join().unwrap())
+            .collect();
+        all.sort();
+        assert_eq!(all, (0..300).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn select_picks_the_ready_receiver() {
+        let (tx1, rx1) = unbounded::<i32>();
+        let (tx2, rx2) = bounded::<&str>(1);
+        let sender = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(10));
+            tx2.send("second").unwrap();
+        });
+        let got = select! {
+            recv(rx1) -> msg => format!("first {:?}", msg),
+            recv(rx2) -> msg => format!("second {:?}", msg),
+        };
+        assert_eq!(got, "second Ok(\"second\")");
+        sender.join().unwrap();
+
+        // 断开的通道也算就绪，msg 是 Err
+        let got = select! {
+            recv(rx1) -> _msg => "first",
+            recv(rx2) -> msg => if msg.is_err() { "closed" } else { "message" },
+        };
+        assert_eq!(got, "closed");
+
+        // 两个通道都没有消息时执行 default 分支
+        let (_tx3, rx3) = unbounded::<i32>();
+        let started = Instant::now();
+        let got = select! {
+            recv(rx1) -> _msg => 1,
+            recv(rx3) -> _msg => 3,
+            default(Duration::from_millis(20)) => 0,
+        };
+        assert_eq!(got, 0);
+        assert!(started.elapsed() >= Duration::from_millis(20));
+        drop(tx1);
+        let got = select! {
+            recv(rx1) -> msg => msg.is_err(),
+            default(Duration::MAX) => false,
+        };
+        assert!(got);
+    }
+
+    #[test]
+    fn select_leaves_no_signal_behind() {
+        let (_tx1, rx1) = unbounded::<i32>();
+        let (tx2, rx2) = unbounded::<i32>();
+        // 消息在 select 开始等待之后才到，走被唤醒之后重新检查的路径
+        let sender = thread::spawn(move || {
+            for i in 0..200 {
+                thread::sleep(Duration::from_millis(1));
+                tx2.send(i).unwrap();
+            }
+        });
+        for _ in 0..200 {
+            let mut sel = Select::new();
+            sel.recv(&rx1);
+            sel.recv(&rx2);
+            assert_eq!(sel.ready(), 1);
+            rx2.try_recv().unwrap();
+            assert_eq!(rx1.chan.lock().selectors.len(), 0);
+            assert_eq!(rx2.chan.lock().selectors.len(), 0);
+        }
+        sender.join().unwrap();
+    }
+}
@@ -0,0 +1,143 @@
+// mpmc 通道的压力测试：多个生产者、多个消费者同时收发，检查每条消息恰好被收到一次
+use message_passing::{mpmc, select};
+use std::{
+    collections::HashSet,
+    sync::mpsc::{RecvError, TrySendError},
+    thread,
+    time::Duration,
+};
+
+const PRODUCERS: usize = 8;
+const CONSUMERS: usize = 8;
+const PER_PRODUCER: usize = 5_000;
+
+fn run(tx: mpmc::Sender<usize>, rx: mpmc::Receiver<usize>) {
+    let producers: Vec<_> = (0..PRODUCERS)
+        .map(|p| {
+            let tx = tx.clone();
+            thread::spawn(move || {
+                for i in 0..PER_PRODUCER {
+                    tx.send(p * PER_PRODUCER + i).unwrap();
+                }
+            })
+        })
+        .collect();
+    drop(tx);
+    let consumers: Vec<_> = (0..CONSUMERS)
+        .map(|_| {
+            let rx = rx.clone();
+            thread::spawn(move || rx.iter().collect::<Vec<_>>())
+        })
+        .collect();
+    drop(rx);
+
+    for producer in producers {
+        producer.join().unwrap();
+    }
+    let mut seen = HashSet::new();
+    for consumer in consumers {
+        for msg in consumer.join().unwrap() {
+            assert!(seen.insert(msg), "message {} received twice", msg);
+        }
+    }
+    assert_eq!(seen.len(), PRODUCERS * PER_PRODUCER);
+}
+
+#[test]
+fn unbounded_delivers_every_message_exactly_once() {
+    let (tx, rx) = mpmc::unbounded();
+    run(tx, rx);
+}
+
+#[test]
+fn tiny_bounded_delivers_every_message_exactly_once() {
+    // 容量为 1，发送者和接收者频繁地互相等待
+    let (tx, rx) = mpmc::bounded(1);
+    run(tx, rx);
+}
+
+#[test]
+fn bounded_never_exceeds_capacity() {
+    let (tx, rx) = mpmc::bounded(3);
+    let producers: Vec<_> = (0..4)
+        .map(|_| {
+            let tx = tx.clone();
+            thread::spawn(move || {
+                for i in 0..2_000 {
+                    let mut msg = i;
+                    loop {
+                        match tx.try_send(msg) {
+                            Ok(()) => break,
+                            Err(TrySendError::Full(m)) => {
+                                msg = m;
+                                thread::yield_now();
+                            }
+                            Err(TrySendError::Disconnected(_)) => panic!("receivers dropped"),
+                        }
+                    }
+                    assert!(tx.len() <= 3);
+                }
+            })
+        })
+        .collect();
+    drop(tx);
+    let mut count = 0;
+    while let Ok(_msg) = rx.recv_timeout(Duration::from_secs(10)) {
+        assert!(rx.len() <= 3);
+        count += 1;
+    }
+    for producer in producers {
+        producer.join().unwrap();
+    }
+    assert_eq!(count, 4 * 2_000);
+}
+
+#[test]
+fn select_consumers_drain_all_channels() {
+    let (num_tx, num_rx) = mpmc::bounded::<usize>(4);
+    let (word_tx, word_rx) = mpmc::bounded::<String>(4);
+    let numbers = thread::spawn(move || {
+        for i in 0..2_000 {
+            num_tx.send(i).unwrap();
+        }
+    });
+    let words = thread::spawn(move || {
+        for i in 0..2_000 {
+            word_tx.send(i.to_string()).unwrap();
+        }
+    });
+
+    // 多个线程同时 select 同一组通道，抢不到消息的线程会重新等待
+    let consumers: Vec<_> = (0..4)
+        .map(|_| {
+            let num_rx = num_rx.clone();
+            let word_rx = word_rx.clone();
+            thread::spawn(move || {
+                let (mut nums, mut words) = (0, 0);
+                let (mut nums_done, mut words_done) = (false, false);
+                while !(nums_done && words_done) {
+                    select! {
+                        recv(num_rx) -> msg => match msg {
+                            Ok(_) => nums += 1,
+                            Err(RecvError) => nums_done = true,
+                        },
+                        recv(word_rx) -> msg => match msg {
+                            Ok(_) => words += 1,
+                            Err(RecvError) => words_done = true,
+                        },
+                    }
+                }
+                (nums, words)
+            })
+        })
+        .collect();
+    drop((num_rx, word_rx));
+
+    numbers.join().unwrap();
+    words.join().unwrap();
+    let (nums, words) = consumers
+        .into_iter()
+        .map(|c| c.join().unwrap())
+        .fold((0, 0), |acc, c| (acc.0 + c.0, acc.1 + c.1));
+    assert_eq!((nums, words), (2_000, 2_000));
+}
@@ -0,0 +1,133 @@
+/*
+比较两种后端在大量空闲 keep-alive 连接下的表现：
+    先打开 N 个连接，每个连接发送一个请求之后就不再读写，一直保持连接；
+    然后用新连接依次发送请求，统计延迟和吞吐。
+    线程池后端里每个空闲连接都占着一个工作线程，新请求只能排队等空闲超时，所以直接记为超时。
+
+    cargo bench --bench idle_keepalive
+    IDLE_CONNS=0,100,2000 REQUESTS=500 cargo bench --bench idle_keepalive
+
+连接数较多时需要调大文件描述符上限（ulimit -n），客户端和服务器各占一个。
+*/
+
+use std::{
+    env,
+    io::{Read, Write},
+    net::{SocketAddr, TcpStream},
+    thread,
+    time::{Duration, Instant},
+};
+use web_server::{
+    Config, Request, Response, Server, access_log::LogTarget, log::Level, server::Backend,
+};
+
+const WORKERS: u32 = 4;
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(2);
+
+fn start(backend: Backend) -> SocketAddr {
+    let config = Config {
+        binds: vec![SocketAddr::from(([127, 0, 0, 1], 0))],
+        workers: WORKERS,
+        backend,
+        header_timeout: Some(Duration::from_secs(60)),
+        keep_alive_timeout: Some(Duration::from_secs(60)),
+        connection_timeout: None,
+        max_connections_per_ip: 0,
+        log_level: Level::Error,
+        access_log: LogTarget::Off,
+        compression: false,
+        ..Config::default()
+    };
+    let server = Server::bind(config)
+        .expect("bind")
+        .handler(|_req: &mut Request| Response::text(200, "ok"));
+    let addr = server.local_addrs()[0];
+    thread::spawn(move || server.run());
+    addr
+}
+
+/// 打开一个连接并发送一个请求，不读取响应
+fn idle_conn(addr: SocketAddr) -> TcpStream {
+    let mut stream = TcpStream::connect(addr).unwrap_or_else(|err| {
+        panic!("connect failed: {} (try raising ulimit -n)", err);
+    });
+    stream.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+    stream
+}
+
+/// 用一个新连接完成一次请求，超时返回 None
+fn fresh_request(addr: SocketAddr) -> Option<Duration> {
+    let started = Instant::now();
+    let mut stream = TcpStream::connect(addr).ok()?;
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT)).ok()?;
+    stream
+        .write_all(b"GET / HTTP/1.1\r\nConnection: close\r\n\r\n")
+        .ok()?;
+    let mut resp = Vec::new();
+    stream.read_to_end(&mut resp).ok()?;
+    resp.starts_with(b"HTTP/1.1 200").then(|| started.elapsed())
+}
+
+fn percentile(sorted: &[Duration], p: f64) -> Duration {
+    let idx = ((sorted.len() - 1) as f64 * p).round() as usize;
+    sorted[idx]
+}
+
+fn env_list(name: &str, default: &str) -> Vec<usize> {
+    env::var(name)
+        .unwrap_or_else(|_| default.to_string())
+        .split(',')
+        .map(|n| n.trim().parse().expect(name))
+        .collect()
+}
+
+fn main() {
+    let idle_counts = env_list("IDLE_CONNS", "0,1000,5000");
+    let requests = env_list("REQUESTS", "200")[0];
+    println!(
+        "{:<8} {:>6} {:>10} {:>10} {:>10} {:>10}",
+        "backend", "idle", "ok", "p50", "p99", "req/s"
+    );
+    for backend in [Backend::Threads, Backend::Epoll] {
+        for &idle in &idle_counts {
+            let addr = start(backend);
+            let conns: Vec<TcpStream> = (0..idle).map(|_| idle_conn(addr)).collect();
+            // 等服务器把空闲连接上的请求处理完
+            thread::sleep(Duration::from_millis(200));
+
+            let started = Instant::now();
+            let mut latencies = Vec::with_capacity(requests);
+            for _ in 0..requests {
+                match fresh_request(addr) {
+                    Some(latency) => latencies.push(latency),
+                    // 一次超时说明工作线程已经被占满，后面的请求也不会更好
+                    None => break,
+                }
+            }
+            let elapsed = started.elapsed();
+            drop(conns);
+
+            let name = format!("{:?}", backend).to_lowercase();
+            if latencies.len() < requests {
+                println!(
+                    "{:<8} {:>6} {:>10} {:>10}",
+                    name,
+                    idle,
+                    format!("{}/{}", latencies.len(), requests),
+                    "timeout"
+                );
+                continue;
+            }
+            latencies.sort();
+            println!(
+                "{:<8} {:>6} {:>10} {:>10.2?} {:>10.2?} {:>10.0}",
+                name,
+                idle,
+                format!("{}/{}", latencies.len(), requests),
+                percentile(&latencies, 0.5),
+                percentile(&latencies, 0.99),
+                requests as f64 / elapsed.as_secs_f64()
+            );
+        }
+    }
+}
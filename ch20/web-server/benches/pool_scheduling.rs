@@ -0,0 +1,245 @@
+/*
+比较线程池的两种调度方式在大量小任务下的表现：
+    shared    原来的设计，所有工作线程共用一个 Arc<Mutex<mpsc::Receiver>>（这里保留了一份最小实现）
+    stealing  现在的 ThreadPool，每个工作线程一个队列，空闲时去别的队列偷任务
+
+    三种负载：
+        external   主线程连续提交 JOBS 个很小的任务
+        fan-out    任务在工作线程里继续提交子任务，组成一棵二叉树，叶子一共 JOBS 个
+        mixed      external 的任务里混入少量耗时较长的任务，看空闲线程能不能及时分担
+
+    cargo bench --bench pool_scheduling
+    WORKERS=8 JOBS=500000 cargo bench --bench pool_scheduling
+*/
+
+use std::{
+    env,
+    hint::black_box,
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        Arc, Condvar, Mutex,
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        mpsc,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+use web_server::ThreadPool;
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+type Workload<P> = fn(&Arc<P>, usize);
+
+trait Pool: Send + Sync + 'static {
+    fn submit(&self, job: Job);
+}
+
+impl Pool for ThreadPool {
+    fn submit(&self, job: Job) {
+        self.execute(job).unwrap();
+    }
+}
+
+/// 原来的线程池：一个通道，所有工作线程抢同一把锁；和 ThreadPool 一样维护统计计数、捕获 panic，
+/// 两者的差别只在调度上
+struct SharedQueuePool {
+    sender: Option<mpsc::Sender<Job>>,
+    workers: Vec<JoinHandle<()>>,
+    queued: Arc<AtomicUsize>,
+}
+
+impl SharedQueuePool {
+    fn new(size: usize) -> SharedQueuePool {
+        let (sender, receiver) = mpsc::channel::<Job>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        let queued = Arc::new(AtomicUsize::new(0));
+        let busy = Arc::new(AtomicUsize::new(0));
+        let completed = Arc::new(AtomicU64::new(0));
+        let workers = (0..size)
+            .map(|_| {
+                let receiver = Arc::clone(&receiver);
+                let (queued, busy, completed) = (
+                    Arc::clone(&queued),
+                    Arc::clone(&busy),
+                    Arc::clone(&completed),
+                );
+                thread::spawn(move || {
+                    loop {
+                        let job = receiver.lock().unwrap().recv();
+                        let Ok(job) = job else {
+                            break;
+                        };
+                        queued.fetch_sub(1, Ordering::Relaxed);
+                        busy.fetch_add(1, Ordering::Relaxed);
+                        if panic::catch_unwind(AssertUnwindSafe(job)).is_ok() {
+                            completed.fetch_add(1, Ordering::Relaxed);
+                        }
+                        busy.fetch_sub(1, Ordering::Relaxed);
+                    }
+                })
+            })
+            .collect();
+        SharedQueuePool {
+            sender: Some(sender),
+            workers,
+            queued,
+        }
+    }
+}
+
+impl Pool for SharedQueuePool {
+    fn submit(&self, job: Job) {
+        self.queued.fetch_add(1, Ordering::Relaxed);
+        self.sender.as_ref().unwrap().send(job).unwrap();
+    }
+}
+
+impl Drop for SharedQueuePool {
+    fn drop(&mut self) {
+        drop(self.sender.take());
+        for worker in self.workers.drain(..) {
+            worker.join().unwrap();
+        }
+    }
+}
+
+/// 等待一定数量的任务完成
+struct Latch {
+    left: AtomicUsize,
+    done: Mutex<bool>,
+    cond: Condvar,
+}
+
+impl Latch {
+    fn new(count: usize) -> Arc<Latch> {
+        Arc::new(Latch {
+            left: AtomicUsize::new(count),
+            done: Mutex::new(false),
+            cond: Condvar::new(),
+        })
+    }
+
+    fn count_down(&self) {
+        if self.left.fetch_sub(1, Ordering::AcqRel) == 1 {
+            *self.done.lock().unwrap() = true;
+            self.cond.notify_all();
+        }
+    }
+
+    fn wait(&self) {
+        let done = self.done.lock().unwrap();
+        drop(self.cond.wait_while(done, |done| !*done).unwrap());
+    }
+}
+
+/// 一个很小的任务，大约几十纳秒
+fn tiny_work(seed: usize) -> usize {
+    (0..32).fold(seed, |acc, i| {
+        black_box(acc.wrapping_mul(31).wrapping_add(i))
+    })
+}
+
+fn external<P: Pool>(pool: &Arc<P>, jobs: usize) {
+    let latch = Latch::new(jobs);
+    for i in 0..jobs {
+        let latch = Arc::clone(&latch);
+        pool.submit(Box::new(move || {
+            black_box(tiny_work(i));
+            latch.count_down();
+        }));
+    }
+    latch.wait();
+}
+
+fn fan_out<P: Pool>(pool: &Arc<P>, jobs: usize) {
+    fn node<P: Pool>(pool: Arc<P>, leaves: usize, latch: Arc<Latch>) {
+        if leaves == 1 {
+            black_box(tiny_work(leaves));
+            latch.count_down();
+            return;
+        }
+        let half = leaves / 2;
+        for part in [half, leaves - half] {
+            let (child_pool, latch) = (Arc::clone(&pool), Arc::clone(&latch));
+            pool.submit(Box::new(move || node(child_pool, part, latch)));
+        }
+    }
+    let latch = Latch::new(jobs);
+    let (root_pool, root_latch) = (Arc::clone(pool), Arc::clone(&latch));
+    pool.submit(Box::new(move || node(root_pool, jobs, root_latch)));
+    latch.wait();
+}
+
+fn mixed<P: Pool>(pool: &Arc<P>, jobs: usize) {
+    let latch = Latch::new(jobs);
+    for i in 0..jobs {
+        let latch = Arc::clone(&latch);
+        pool.submit(Box::new(move || {
+            // 每 1000 个任务里有一个需要 200 微秒
+            if i.is_multiple_of(1000) {
+                let started = Instant::now();
+                while started.elapsed() < Duration::from_micros(200) {
+                    black_box(tiny_work(i));
+                }
+            } else {
+                black_box(tiny_work(i));
+            }
+            latch.count_down();
+        }));
+    }
+    latch.wait();
+}
+
+fn env_usize(name: &str, default: usize) -> usize {
+    env::var(name)
+        .map(|v| v.parse().expect(name))
+        .unwrap_or(default)
+}
+
+/// 运行几次取最快的一次，减少偶然的干扰
+fn best_of<F: FnMut()>(runs: usize, mut f: F) -> Duration {
+    (0..runs)
+        .map(|_| {
+            let started = Instant::now();
+            f();
+            started.elapsed()
+        })
+        .min()
+        .unwrap()
+}
+
+fn main() {
+    let workers = env_usize(
+        "WORKERS",
+        thread::available_parallelism().map_or(4, |n| n.get()),
+    );
+    let jobs = env_usize("JOBS", 200_000);
+    let runs = env_usize("RUNS", 3);
+    println!("{} workers, {} jobs, best of {} runs", workers, jobs, runs);
+    println!(
+        "{:<10} {:<10} {:>12} {:>14}",
+        "workload", "pool", "time", "jobs/s"
+    );
+
+    let shared = Arc::new(SharedQueuePool::new(workers));
+    let stealing = Arc::new(ThreadPool::new(workers as u32));
+    let workloads: [(&str, Workload<SharedQueuePool>, Workload<ThreadPool>); 3] = [
+        ("external", external, external),
+        ("fan-out", fan_out, fan_out),
+        ("mixed", mixed, mixed),
+    ];
+    for (name, run_shared, run_stealing) in workloads {
+        let results = [
+            ("shared", best_of(runs, || run_shared(&shared, jobs))),
+            ("stealing", best_of(runs, || run_stealing(&stealing, jobs))),
+        ];
+        for (pool, elapsed) in results {
+            println!(
+                "{:<10} {:<10} {:>12.2?} {:>14.0}",
+                name,
+                pool,
+                elapsed,
+                jobs as f64 / elapsed.as_secs_f64()
+            );
+        }
+    }
+}
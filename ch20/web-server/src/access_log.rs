@@ -0,0 +1,427 @@
+/*
+访问日志：
+    每个请求处理完后记录一行，支持 Apache 的 Common / Combined Log Format 以及 JSON Lines。
+    引号里的字段来自客户端，和 Apache 一样转义 `"`、`\` 和控制字符，客户端没法伪造字段或者多写一行。
+    输出到标准输出或文件，写文件时按大小滚动：access.log -> access.log.1 -> access.log.2 ...
+*/
+
+use crate::{
+    http::{Request, Response},
+    log::civil,
+};
+use std::{
+    fmt::Write as _,
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    net::SocketAddr,
+    path::PathBuf,
+    str::FromStr,
+    sync::Mutex,
+    time::{Duration, SystemTime},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    Common,
+    Combined,
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<LogFormat, String> {
+        match s {
+            "common" => Ok(LogFormat::Common),
+            "combined" => Ok(LogFormat::Combined),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(format!("unknown access log format `{}`", s)),
+        }
+    }
+}
+
+/// 访问日志的输出位置
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LogTarget {
+    Off,
+    Stdout,
+    File(PathBuf),
+}
+
+impl FromStr for LogTarget {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<LogTarget, String> {
+        match s {
+            "" => Err(String::from("empty access log target")),
+            "off" => Ok(LogTarget::Off),
+            "stdout" | "-" => Ok(LogTarget::Stdout),
+            path => Ok(LogTarget::File(PathBuf::from(path))),
+        }
+    }
+}
+
+/// 日志文件的滚动策略
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rotation {
+    /// 文件超过该大小后滚动，0 表示不滚动
+    pub max_bytes: u64,
+    /// 保留的历史文件个数
+    pub keep: usize,
+}
+
+impl Default for Rotation {
+    fn default() -> Rotation {
+        Rotation {
+            max_bytes: 64 * 1024 * 1024,
+            keep: 5,
+        }
+    }
+}
+
+/// 一条访问记录
+#[derive(Debug, Clone)]
+pub struct Record {
+    pub time: SystemTime,
+    pub remote_addr: Option<SocketAddr>,
+    /// 原始请求行，请求无法解析时为 None
+    pub request_line: Option<String>,
+    pub method: Option<String>,
+    pub path: Option<String>,
+    pub status: u16,
+    pub bytes: usize,
+    pub duration: Duration,
+    pub referer: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+impl Record {
+    pub fn new(
+        req: Option<&Request>,
+        resp: &Response,
+        started: SystemTime,
+        duration: Duration,
+    ) -> Record {
+        Record {
+            time: started,
+            remote_addr: req.and_then(|r| r.remote_addr),
+            request_line: req.map(|r| format!("{} {} {}", r.method, r.target, r.version)),
+            method: req.map(|r| r.method.clone()),
+            path: req.map(|r| r.path.clone()),
+            status: resp.status,
+            bytes: resp.body.len(),
+            duration,
+            referer: req.and_then(|r| r.header("Referer")).map(String::from),
+            user_agent: req.and_then(|r| r.header("User-Agent")).map(String::from),
+        }
+    }
+
+    /// 按指定格式序列化成一行（包含结尾的换行符）
+    pub fn format(&self, format: LogFormat) -> String {
+        match format {
+            LogFormat::Common => format!("{}\n", self.common()),
+            LogFormat::Combined => {
+                let mut out = self.common();
+                out.push(' ');
+                clf_string(&mut out, self.referer.as_deref().unwrap_or("-"));
+                out.push(' ');
+                clf_string(&mut out, self.user_agent.as_deref().unwrap_or("-"));
+                out.push('\n');
+                out
+            }
+            LogFormat::Json => self.json(),
+        }
+    }
+
+    fn common(&self) -> String {
+        let (y, mo, d, h, mi, s) = civil(self.time);
+        const MONTHS: [&str; 12] = [
+            "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+        ];
+        let bytes = if self.bytes == 0 {
+            String::from("-")
+        } else {
+            self.bytes.to_string()
+        };
+        let mut request_line = String::new();
+        clf_string(
+            &mut request_line,
+            self.request_line.as_deref().unwrap_or("-"),
+        );
+        format!(
+            "{} - - [{:02}/{}/{:04}:{:02}:{:02}:{:02} +0000] {} {} {}",
+            self.remote_addr
+                .map_or(String::from("-"), |a| a.ip().to_string()),
+            d,
+            MONTHS[mo as usize - 1],
+            y,
+            h,
+            mi,
+            s,
+            request_line,
+            self.status,
+            bytes
+        )
+    }
+
+    fn json(&self) -> String {
+        let mut out = String::from("{");
+        let mut field = |name: &str, value: Option<&str>| {
+            let _ = write!(out, "\"{}\":", name);
+            match value {
+                Some(v) => json_string(&mut out, v),
+                None => out.push_str("null"),
+            }
+            out.push(',');
+        };
+        let remote = self.remote_addr.map(|a| a.ip().to_string());
+        field("time", Some(&crate::log::rfc3339(self.time)));
+        field("remote_addr", remote.as_deref());
+        field("method", self.method.as_deref());
+        field("path", self.path.as_deref());
+        field("referer", self.referer.as_deref());
+        field("user_agent", self.user_agent.as_deref());
+        let _ = writeln!(
+            out,
+            "\"status\":{},\"bytes\":{},\"duration_ms\":{:.3}}}",
+            self.status,
+            self.bytes,
+            self.duration.as_secs_f64() * 1000.0
+        );
+        out
+    }
+}
+
+/// 写入带引号并转义过的 JSON 字符串
+/// 写出一个带引号的 Common / Combined 字段，按 Apache 的方式转义：`\"`、`\\` 和 `\xhh`
+fn clf_string(out: &mut String, s: &str) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if c.is_ascii_control() => {
+                let _ = write!(out, "\\x{:02x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+pub(crate) fn json_string(out: &mut String, s: &str) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+enum Sink {
+    Off,
+    Stdout,
+    File(RotatingFile),
+}
+
+pub struct AccessLog {
+    format: LogFormat,
+    sink: Mutex<Sink>,
+}
+
+impl AccessLog {
+    pub fn new(target: &LogTarget, format: LogFormat, rotation: Rotation) -> io::Result<AccessLog> {
+        let sink = match target {
+            LogTarget::Off => Sink::Off,
+            LogTarget::Stdout => Sink::Stdout,
+            LogTarget::File(path) => Sink::File(RotatingFile::open(path.clone(), rotation)?),
+        };
+        Ok(AccessLog {
+            format,
+            sink: Mutex::new(sink),
+        })
+    }
+
+    pub fn record(&self, record: &Record) {
+        let line = record.format(self.format);
+        let mut sink = self.sink.lock().unwrap();
+        let res = match &mut *sink {
+            Sink::Off => Ok(()),
+            Sink::Stdout => io::stdout().write_all(line.as_bytes()),
+            Sink::File(file) => file.write_line(line.as_bytes()),
+        };
+        if let Err(err) = res {
+            crate::warn!("failed to write access log: {}", err);
+        }
+    }
+}
+
+/// 按大小滚动的日志文件
+struct RotatingFile {
+    path: PathBuf,
+    rotation: Rotation,
+    file: File,
+    written: u64,
+}
+
+impl RotatingFile {
+    fn open(path: PathBuf, rotation: Rotation) -> io::Result<RotatingFile> {
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let written = file.metadata()?.len();
+        Ok(RotatingFile {
+            path,
+            rotation,
+            file,
+            written,
+        })
+    }
+
+    fn write_line(&mut self, line: &[u8]) -> io::Result<()> {
+        let max = self.rotation.max_bytes;
+        if max > 0 && self.written > 0 && self.written + line.len() as u64 > max {
+            self.rotate()?;
+        }
+        self.file.write_all(line)?;
+        self.written += line.len() as u64;
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        let numbered = |n: usize| {
+            let mut name = self.path.clone().into_os_string();
+            name.push(format!(".{}", n));
+            PathBuf::from(name)
+        };
+        let keep = self.rotation.keep;
+        if keep == 0 {
+            fs::remove_file(&self.path)?;
+        } else {
+            // 从最老的文件开始依次后移，超过 keep 的文件会被覆盖掉
+            for n in (1..keep).rev() {
+                let from = numbered(n);
+                if from.exists() {
+                    fs::rename(&from, numbered(n + 1))?;
+                }
+            }
+            fs::rename(&self.path, numbered(1))?;
+        }
+        self.file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        self.written = 0;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::UNIX_EPOCH;
+
+    fn record() -> Record {
+        let mut req = Request::new("GET", "/apache_pb.gif?x=1");
+        req.version = String::from("HTTP/1.0");
+        req.remote_addr = Some("127.0.0.1:5000".parse().unwrap());
+        req.headers
+            .set("Referer", "http://www.example.com/start.html");
+        req.headers
+            .set("User-Agent", "Mozilla/4.08 [en] (Win98; I ;Nav)");
+        let resp = Response::new(200).with_body(vec![0; 2326]);
+        let started = UNIX_EPOCH + Duration::from_secs(971_186_136);
+        Record::new(Some(&req), &resp, started, Duration::from_micros(1500))
+    }
+
+    #[test]
+    fn common_and_combined() {
+        let r = record();
+        assert_eq!(
+            r.format(LogFormat::Common),
+            "127.0.0.1 - - [10/Oct/2000:13:55:36 +0000] \"GET /apache_pb.gif?x=1 HTTP/1.0\" 200 2326\n"
+        );
+        assert!(r.format(LogFormat::Combined).ends_with(
+            "2326 \"http://www.example.com/start.html\" \"Mozilla/4.08 [en] (Win98; I ;Nav)\"\n"
+        ));
+    }
+
+    #[test]
+    fn quoted_fields_are_escaped() {
+        let mut r = record();
+        r.request_line = Some(String::from("GET /\" 200 1 \"x HTTP/1.1"));
+        r.referer = Some(String::from("a\\b"));
+        r.user_agent = Some(String::from("evil\n127.0.0.1 - - \x7f"));
+        let line = r.format(LogFormat::Combined);
+        assert!(
+            line.contains(" \"GET /\\\" 200 1 \\\"x HTTP/1.1\" 200 "),
+            "{}",
+            line
+        );
+        assert!(
+            line.ends_with(" \"a\\\\b\" \"evil\\x0a127.0.0.1 - - \\x7f\"\n"),
+            "{}",
+            line
+        );
+        assert_eq!(line.lines().count(), 1);
+    }
+
+    #[test]
+    fn json_line() {
+        let mut r = record();
+        r.user_agent = Some(String::from("say \"hi\""));
+        assert_eq!(
+            r.format(LogFormat::Json),
+            "{\"time\":\"2000-10-10T13:55:36Z\",\"remote_addr\":\"127.0.0.1\",\"method\":\"GET\",\
+             \"path\":\"/apache_pb.gif\",\"referer\":\"http://www.example.com/start.html\",\
+             \"user_agent\":\"say \\\"hi\\\"\",\"status\":200,\"bytes\":2326,\"duration_ms\":1.500}\n"
+        );
+    }
+
+    #[test]
+    fn unparsable_request_uses_dashes() {
+        let r = Record::new(None, &Response::new(400), UNIX_EPOCH, Duration::ZERO);
+        assert_eq!(
+            r.format(LogFormat::Common),
+            "- - - [01/Jan/1970:00:00:00 +0000] \"-\" 400 -\n"
+        );
+    }
+
+    #[test]
+    fn rotates_by_size() {
+        let dir = std::env::temp_dir().join(format!("web-server-rotate-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("access.log");
+        let rotation = Rotation {
+            max_bytes: 200,
+            keep: 2,
+        };
+        let log =
+            AccessLog::new(&LogTarget::File(path.clone()), LogFormat::Common, rotation).unwrap();
+        let r = record();
+        let line_len = r.format(LogFormat::Common).len() as u64;
+        for _ in 0..5 {
+            log.record(&r);
+        }
+        // 每个文件只能放下两行，5 行日志滚动两次，最老的一行被丢弃
+        assert_eq!(fs::metadata(&path).unwrap().len(), line_len);
+        assert_eq!(
+            fs::metadata(dir.join("access.log.1")).unwrap().len(),
+            line_len * 2
+        );
+        assert_eq!(
+            fs::metadata(dir.join("access.log.2")).unwrap().len(),
+            line_len * 2
+        );
+        assert!(!dir.join("access.log.3").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
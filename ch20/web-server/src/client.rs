@@ -0,0 +1,568 @@
+/*
+阻塞的 HTTP/1.1 客户端：
+    主要用在集成测试和反向代理里，只支持 http（不支持 https）。
+
+        let client = Client::new().timeout(Some(Duration::from_secs(5)));
+        let resp = client.get("http://127.0.0.1:7878/").header("Accept", "text/html").send()?;
+        let resp = client.post("http://127.0.0.1:7878/items", "{}").send()?;
+
+    响应体可以用 Content-Length、分块编码（chunked）或者关闭连接来界定，分块的响应体会被解码。
+    响应读完之后，如果双方都同意保持连接，连接会放回按 host:port 分组的连接池，下一个请求直接复用。
+    池中的连接可能已经被服务器关闭，这种情况下在复用的连接上收不到任何响应，客户端会换一个新连接重试一次。
+    timeout 限制的是一个请求从发送到读完响应的总时长，connect_timeout 单独限制建立连接的时间。
+*/
+
+use crate::{
+    conn::{DeadlineReader, is_timeout},
+    http::{Headers, HttpError, Limits, Response, is_idempotent, read_line},
+};
+use std::{
+    collections::HashMap,
+    error::Error,
+    fmt,
+    io::{self, BufRead, BufReader, Read, Write},
+    net::{TcpStream, ToSocketAddrs},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// 池中的连接空闲超过这个时间就不再复用
+const IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Debug)]
+pub enum ClientError {
+    InvalidUrl(String),
+    Io(io::Error),
+    Timeout,
+    /// 服务器没有发送任何响应就关闭了连接
+    Closed,
+    /// 响应不符合 HTTP/1.1 的格式
+    Protocol(&'static str),
+    /// 响应头或者响应体超过了 Limits 的限制
+    TooLarge,
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ClientError::InvalidUrl(msg) => write!(f, "invalid url: {}", msg),
+            ClientError::Io(err) => write!(f, "io error: {}", err),
+            ClientError::Timeout => write!(f, "request timed out"),
+            ClientError::Closed => write!(f, "connection closed before a response was received"),
+            ClientError::Protocol(msg) => write!(f, "malformed response: {}", msg),
+            ClientError::TooLarge => write!(f, "response too large"),
+        }
+    }
+}
+
+impl Error for ClientError {}
+
+impl From<io::Error> for ClientError {
+    fn from(err: io::Error) -> ClientError {
+        if is_timeout(&err) {
+            ClientError::Timeout
+        } else {
+            ClientError::Io(err)
+        }
+    }
+}
+
+impl From<HttpError> for ClientError {
+    fn from(err: HttpError) -> ClientError {
+        match err {
+            HttpError::Io(err) => ClientError::Io(err),
+            HttpError::Closed => ClientError::Closed,
+            HttpError::Timeout => ClientError::Timeout,
+            HttpError::BadRequest(msg) | HttpError::NotImplemented(msg) => {
+                ClientError::Protocol(msg)
+            }
+            HttpError::HeaderTooLarge | HttpError::BodyTooLarge => ClientError::TooLarge,
+        }
+    }
+}
+
+/// 解析后的 http:// 地址
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct Url {
+    /// 不带方括号的主机名，用来解析地址
+    host: String,
+    port: u16,
+    /// Host 头的值，也是连接池的 key
+    pub(crate) authority: String,
+    /// 请求行中的 path 和 query
+    pub(crate) target: String,
+}
+
+impl Url {
+    pub(crate) fn parse(url: &str) -> Result<Url, ClientError> {
+        let invalid = |msg: &str| ClientError::InvalidUrl(format!("{}: {}", msg, url));
+        let rest = match url.split_once("://") {
+            Some(("http", rest)) => rest,
+            Some((scheme, _)) => return Err(invalid(&format!("unsupported scheme `{}`", scheme))),
+            None => return Err(invalid("missing scheme")),
+        };
+        let (authority, target) = match rest.find(['/', '?']) {
+            Some(i) if rest[i..].starts_with('?') => (&rest[..i], format!("/{}", &rest[i..])),
+            Some(i) => (&rest[..i], rest[i..].to_string()),
+            None => (rest, String::from("/")),
+        };
+        // [::1]:8080 这样的 IPv6 地址，端口在方括号之后
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) if !port.contains(']') => {
+                (host, port.parse().map_err(|_| invalid("invalid port"))?)
+            }
+            _ => (authority, 80),
+        };
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        if host.is_empty() {
+            return Err(invalid("missing host"));
+        }
+        let authority = if authority.ends_with(&format!(":{}", port)) {
+            authority.to_string()
+        } else {
+            format!("{}:{}", authority, port)
+        };
+        Ok(Url {
+            host: host.to_string(),
+            port,
+            authority,
+            target,
+        })
+    }
+}
+
+pub struct Client {
+    connect_timeout: Option<Duration>,
+    timeout: Option<Duration>,
+    limits: Limits,
+    max_idle_per_host: usize,
+    idle: Mutex<HashMap<String, Vec<(TcpStream, Instant)>>>,
+}
+
+impl Default for Client {
+    fn default() -> Client {
+        Client {
+            connect_timeout: Some(Duration::from_secs(10)),
+            timeout: Some(Duration::from_secs(30)),
+            limits: Limits {
+                max_header_bytes: 64 * 1024,
+                max_body_bytes: 64 * 1024 * 1024,
+            },
+            max_idle_per_host: 8,
+            idle: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl Client {
+    pub fn new() -> Client {
+        Client::default()
+    }
+
+    pub fn connect_timeout(mut self, timeout: Option<Duration>) -> Client {
+        self.connect_timeout = timeout;
+        self
+    }
+
+    /// 一个请求从发送到读完响应的总时长
+    pub fn timeout(mut self, timeout: Option<Duration>) -> Client {
+        self.timeout = timeout;
+        self
+    }
+
+    /// 响应头和响应体的大小限制
+    pub fn limits(mut self, limits: Limits) -> Client {
+        self.limits = limits;
+        self
+    }
+
+    /// 每个 host:port 最多保留的空闲连接数，0 表示不复用连接
+    pub fn max_idle_per_host(mut self, max: usize) -> Client {
+        self.max_idle_per_host = max;
+        self
+    }
+
+    pub fn get(&self, url: &str) -> ClientRequest<'_> {
+        self.request("GET", url)
+    }
+
+    pub fn post(&self, url: &str, body: impl Into<Vec<u8>>) -> ClientRequest<'_> {
+        self.request("POST", url).body(body)
+    }
+
+    pub fn request(&self, method: &str, url: &str) -> ClientRequest<'_> {
+        ClientRequest {
+            client: self,
+            method: method.to_ascii_uppercase(),
+            url: url.to_string(),
+            headers: Headers::new(),
+            body: Vec::new(),
+        }
+    }
+
+    /// 连接池中空闲连接的总数
+    pub fn idle_connections(&self) -> usize {
+        self.idle.lock().unwrap().values().map(Vec::len).sum()
+    }
+
+    fn checkout(&self, key: &str) -> Option<TcpStream> {
+        let mut idle = self.idle.lock().unwrap();
+        let conns = idle.get_mut(key)?;
+        while let Some((stream, since)) = conns.pop() {
+            if since.elapsed() < IDLE_TIMEOUT {
+                return Some(stream);
+            }
+        }
+        None
+    }
+
+    fn checkin(&self, key: &str, stream: TcpStream) {
+        let mut idle = self.idle.lock().unwrap();
+        let conns = idle.entry(key.to_string()).or_default();
+        if conns.len() < self.max_idle_per_host {
+            conns.push((stream, Instant::now()));
+        }
+    }
+
+    pub(crate) fn connect(&self, url: &Url) -> Result<TcpStream, ClientError> {
+        let addrs = (url.host.as_str(), url.port)
+            .to_socket_addrs()
+            .map_err(|err| {
+                ClientError::InvalidUrl(format!("cannot resolve {}: {}", url.host, err))
+            })?;
+        let mut last_err = None;
+        for addr in addrs {
+            let result = match self.connect_timeout {
+                Some(timeout) => TcpStream::connect_timeout(&addr, timeout),
+                None => TcpStream::connect(addr),
+            };
+            match result {
+                Ok(stream) => {
+                    // 请求一次性写出，关掉 Nagle 避免和延迟确认叠加出 40ms 的等待
+                    stream.set_nodelay(true)?;
+                    return Ok(stream);
+                }
+                Err(err) => last_err = Some(err),
+            }
+        }
+        Err(match last_err {
+            Some(err) => err.into(),
+            None => ClientError::InvalidUrl(format!("{} did not resolve", url.host)),
+        })
+    }
+
+    /// 在一个连接上发送请求并读取响应，连接可以复用时放回连接池
+    fn exchange(
+        &self,
+        stream: TcpStream,
+        key: &str,
+        raw: &[u8],
+        method: &str,
+        deadline: Option<Instant>,
+    ) -> Result<Response, ClientError> {
+        let remaining = |deadline: Instant| {
+            let left = deadline.saturating_duration_since(Instant::now());
+            // 0 对 set_write_timeout 来说是非法值
+            left.max(Duration::from_millis(1))
+        };
+        stream.set_write_timeout(deadline.map(remaining))?;
+        (&stream).write_all(raw)?;
+
+        let mut reader = DeadlineReader::new(stream, None);
+        reader.set_deadline(deadline);
+        let mut reader = BufReader::new(reader);
+        let (resp, reusable) = read_response(&mut reader, method, &self.limits)?;
+        // 服务器多发了数据的连接不能再用
+        if reusable && reader.buffer().is_empty() {
+            self.checkin(key, reader.into_inner().into_inner());
+        }
+        Ok(resp)
+    }
+}
+
+pub struct ClientRequest<'a> {
+    client: &'a Client,
+    method: String,
+    url: String,
+    headers: Headers,
+    body: Vec<u8>,
+}
+
+impl ClientRequest<'_> {
+    pub fn header(mut self, name: &str, value: impl Into<String>) -> Self {
+        self.headers.set(name, value);
+        self
+    }
+
+    /// 替换全部请求头，同名的头部可以出现多次
+    pub fn headers(mut self, headers: Headers) -> Self {
+        self.headers = headers;
+        self
+    }
+
+    pub fn body(mut self, body: impl Into<Vec<u8>>) -> Self {
+        self.body = body.into();
+        self
+    }
+
+    pub fn send(self) -> Result<Response, ClientError> {
+        let client = self.client;
+        let url = Url::parse(&self.url)?;
+        let raw = self.encode(&url);
+        let deadline = client.timeout.and_then(|t| Instant::now().checked_add(t));
+
+        if let Some(stream) = client.checkout(&url.authority) {
+            let result = client.exchange(stream, &url.authority, &raw, &self.method, deadline);
+            if !is_idempotent(&self.method) {
+                return result;
+            }
+            match result {
+                // 复用的连接多半是已经被服务器关闭了，但请求写出之后才被重置的话，
+                // 服务器可能已经处理过它，所以只有幂等的请求才换新连接重试
+                Err(ClientError::Closed) => {}
+                Err(ClientError::Io(err))
+                    if matches!(
+                        err.kind(),
+                        io::ErrorKind::ConnectionReset | io::ErrorKind::BrokenPipe
+                    ) => {}
+                result => return result,
+            }
+        }
+        let stream = client.connect(&url)?;
+        client.exchange(stream, &url.authority, &raw, &self.method, deadline)
+    }
+
+    /// 序列化请求行、头部和请求体
+    fn encode(&self, url: &Url) -> Vec<u8> {
+        let mut head = format!("{} {} HTTP/1.1\r\n", self.method, url.target);
+        if !self.headers.contains("Host") {
+            head.push_str(&format!("Host: {}\r\n", url.authority));
+        }
+        if !self.headers.contains("User-Agent") {
+            head.push_str("User-Agent: web-server-client\r\n");
+        }
+        if self.client.max_idle_per_host == 0 && !self.headers.contains("Connection") {
+            head.push_str("Connection: close\r\n");
+        }
+        for (name, value) in self.headers.iter() {
+            head.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        let expects_body = matches!(self.method.as_str(), "POST" | "PUT" | "PATCH");
+        if (expects_body || !self.body.is_empty()) && !self.headers.contains("Content-Length") {
+            head.push_str(&format!("Content-Length: {}\r\n", self.body.len()));
+        }
+        head.push_str("\r\n");
+        let mut raw = head.into_bytes();
+        raw.extend_from_slice(&self.body);
+        raw
+    }
+}
+
+/// 从 reader 中读取一个响应，method 用来判断响应有没有响应体（HEAD 请求的响应没有）
+///
+/// 返回的 bool 表示连接能否继续复用；分块的响应体会被解码，Transfer-Encoding 头随之移除
+pub fn read_response<R: BufRead>(
+    reader: &mut R,
+    method: &str,
+    limits: &Limits,
+) -> Result<(Response, bool), ClientError> {
+    loop {
+        let mut budget = limits.max_header_bytes;
+        let status_line = read_line(reader, &mut budget)?.ok_or(ClientError::Closed)?;
+        let mut parts = status_line.splitn(3, ' ');
+        let version = parts.next().unwrap_or("");
+        let status = parts
+            .next()
+            .and_then(|s| s.parse::<u16>().ok())
+            .filter(|s| (100..1000).contains(s));
+        let status = match status {
+            Some(status) if version.starts_with("HTTP/1.") => status,
+            _ => return Err(ClientError::Protocol("malformed status line")),
+        };
+
+        let mut resp = Response::new(status);
+        loop {
+            let line = read_line(reader, &mut budget)?
+                .ok_or(ClientError::Protocol("unexpected eof in headers"))?;
+            if line.is_empty() {
+                break;
+            }
+            let (name, value) = line
+                .split_once(':')
+                .ok_or(ClientError::Protocol("malformed header"))?;
+            resp.headers.append(name.trim(), value.trim());
+        }
+        // 100 Continue 之类的中间响应后面还跟着真正的响应
+        if (100..200).contains(&status) && status != 101 {
+            continue;
+        }
+
+        let mut delimited = true;
+        if method == "HEAD" || status == 204 || status == 304 || status < 200 {
+            // 没有响应体
+        } else if is_chunked(&resp.headers) {
+            resp.body = read_chunked(reader, limits)?;
+            resp.headers.remove("Transfer-Encoding");
+        } else if let Some(len) = resp.headers.get("Content-Length") {
+            let len: usize = len
+                .parse()
+                .map_err(|_| ClientError::Protocol("invalid content-length"))?;
+            if len > limits.max_body_bytes {
+                return Err(ClientError::TooLarge);
+            }
+            resp.body = vec![0; len];
+            reader.read_exact(&mut resp.body)?;
+        } else {
+            // 没有长度信息，响应体一直持续到连接关闭
+            delimited = false;
+            let max = limits.max_body_bytes as u64;
+            reader.take(max + 1).read_to_end(&mut resp.body)?;
+            if resp.body.len() as u64 > max {
+                return Err(ClientError::TooLarge);
+            }
+        }
+
+        let connection = resp.header("Connection").unwrap_or("").to_ascii_lowercase();
+        let has = |token: &str| connection.split(',').any(|t| t.trim() == token);
+        let keep_alive = if version == "HTTP/1.0" {
+            has("keep-alive")
+        } else {
+            !has("close")
+        };
+        return Ok((resp, delimited && keep_alive));
+    }
+}
+
+fn is_chunked(headers: &Headers) -> bool {
+    headers.get_all("Transfer-Encoding").any(|value| {
+        value
+            .split(',')
+            .any(|coding| coding.trim().eq_ignore_ascii_case("chunked"))
+    })
+}
+
+/// 解码分块编码的响应体，忽略 chunk 扩展和 trailer
+fn read_chunked<R: BufRead>(reader: &mut R, limits: &Limits) -> Result<Vec<u8>, ClientError> {
+    let mut body = Vec::new();
+    loop {
+        let mut budget = limits.max_header_bytes;
+        let line = read_line(reader, &mut budget)?
+            .ok_or(ClientError::Protocol("unexpected eof in chunked body"))?;
+        let size = line.split(';').next().unwrap_or("").trim();
+        let size = usize::from_str_radix(size, 16)
+            .map_err(|_| ClientError::Protocol("invalid chunk size"))?;
+        if size == 0 {
+            // 跳过 trailer，直到空行
+            while !read_line(reader, &mut budget)?
+                .ok_or(ClientError::Protocol("unexpected eof in trailers"))?
+                .is_empty()
+            {}
+            return Ok(body);
+        }
+        // 块大小由上游决定，可能接近 usize::MAX
+        if body
+            .len()
+            .checked_add(size)
+            .is_none_or(|n| n > limits.max_body_bytes)
+        {
+            return Err(ClientError::TooLarge);
+        }
+        let start = body.len();
+        body.resize(start + size, 0);
+        reader.read_exact(&mut body[start..])?;
+        if read_line(reader, &mut budget)?.as_deref() != Some("") {
+            return Err(ClientError::Protocol("missing crlf after chunk"));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(raw: &[u8], method: &str) -> Result<(Response, bool), ClientError> {
+        read_response(&mut &raw[..], method, &Limits::default())
+    }
+
+    #[test]
+    fn parses_urls() {
+        let url = Url::parse("http://localhost:8080/a/b?x=1").unwrap();
+        assert_eq!(url.host, "localhost");
+        assert_eq!(url.port, 8080);
+        assert_eq!(url.authority, "localhost:8080");
+        assert_eq!(url.target, "/a/b?x=1");
+
+        let url = Url::parse("http://[::1]:9000").unwrap();
+        assert_eq!((url.host.as_str(), url.port), ("::1", 9000));
+        assert_eq!(url.target, "/");
+        assert_eq!(Url::parse("http://example.com?q").unwrap().target, "/?q");
+        assert_eq!(Url::parse("http://example.com").unwrap().port, 80);
+
+        assert!(Url::parse("https://example.com/").is_err());
+        assert!(Url::parse("example.com/").is_err());
+        assert!(Url::parse("http://host:nope/").is_err());
+    }
+
+    #[test]
+    fn reads_length_and_chunked_bodies() {
+        let (resp, reusable) =
+            parse(b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhello", "GET").unwrap();
+        assert_eq!(
+            (resp.status, resp.body.as_slice(), reusable),
+            (200, &b"hello"[..], true)
+        );
+
+        let raw = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n\
+                    5;ext=1\r\nhello\r\n7\r\n, world\r\n0\r\nX-Trailer: t\r\n\r\n";
+        let (resp, reusable) = parse(raw, "GET").unwrap();
+        assert_eq!(resp.body, b"hello, world");
+        assert!(reusable);
+        assert!(!resp.headers.contains("Transfer-Encoding"));
+
+        let huge = format!(
+            "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n\
+             5\r\nhello\r\n{:x}\r\n",
+            usize::MAX - 2
+        );
+        assert!(matches!(
+            parse(huge.as_bytes(), "GET"),
+            Err(ClientError::TooLarge)
+        ));
+    }
+
+    #[test]
+    fn bodiless_and_close_delimited_responses() {
+        let (resp, _) = parse(b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\n", "HEAD").unwrap();
+        assert!(resp.body.is_empty());
+        let (resp, _) = parse(b"HTTP/1.1 204 No Content\r\n\r\n", "DELETE").unwrap();
+        assert_eq!(resp.status, 204);
+
+        let (resp, reusable) = parse(b"HTTP/1.0 200 OK\r\n\r\nuntil eof", "GET").unwrap();
+        assert_eq!(resp.body, b"until eof");
+        assert!(!reusable);
+        let (_, reusable) = parse(
+            b"HTTP/1.1 200 OK\r\nConnection: close\r\nContent-Length: 0\r\n\r\n",
+            "GET",
+        )
+        .unwrap();
+        assert!(!reusable);
+    }
+
+    #[test]
+    fn skips_interim_responses_and_rejects_garbage() {
+        let raw = b"HTTP/1.1 100 Continue\r\n\r\nHTTP/1.1 201 Created\r\nContent-Length: 0\r\n\r\n";
+        assert_eq!(parse(raw, "POST").unwrap().0.status, 201);
+        assert!(matches!(parse(b"", "GET"), Err(ClientError::Closed)));
+        assert!(matches!(
+            parse(b"SSH-2.0-OpenSSH\r\n\r\n", "GET"),
+            Err(ClientError::Protocol(_))
+        ));
+        assert!(matches!(
+            parse(
+                b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\nzz\r\n",
+                "GET"
+            ),
+            Err(ClientError::Protocol(_))
+        ));
+    }
+}
@@ -0,0 +1,830 @@
+/*
+响应压缩：
+    根据请求的 Accept-Encoding 选择 gzip 或 deflate 压缩响应体，只用标准库实现。
+
+    DEFLATE（RFC 1951）编码分两步：
+    1、LZ77：用哈希链在前 32KB 的窗口里找重复的字节串，把数据变成「字面量」和「(长度, 距离)」两种记号；
+    2、Huffman 编码：每个块分别计算固定 Huffman、动态 Huffman 和不压缩三种方式的大小，选最小的一种输出。
+    gzip（RFC 1952）和 zlib（RFC 1950，HTTP 中的 deflate）只是在 DEFLATE 数据外面加上头部和校验和。
+*/
+
+use crate::{
+    http::{Request, Response},
+    middleware::{Middleware, Next},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Gzip,
+    Deflate,
+}
+
+impl Encoding {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Encoding::Gzip => "gzip",
+            Encoding::Deflate => "deflate",
+        }
+    }
+
+    pub fn encode(&self, data: &[u8]) -> Vec<u8> {
+        match self {
+            Encoding::Gzip => gzip(data),
+            Encoding::Deflate => zlib(data),
+        }
+    }
+}
+
+/// 根据 Accept-Encoding 选出 q 值最高的编码，q 值相同时优先 gzip
+pub fn negotiate(accept_encoding: &str) -> Option<Encoding> {
+    let mut gzip_q = None;
+    let mut deflate_q = None;
+    let mut any_q = None;
+    for item in accept_encoding.split(',') {
+        let mut parts = item.split(';');
+        let name = parts.next().unwrap_or("").trim().to_ascii_lowercase();
+        let mut q = 1.0;
+        for param in parts {
+            if let Some((key, value)) = param.split_once('=')
+                && key.trim().eq_ignore_ascii_case("q")
+            {
+                q = value.trim().parse().unwrap_or(0.0);
+            }
+        }
+        match name.as_str() {
+            "gzip" | "x-gzip" => gzip_q = Some(q),
+            "deflate" => deflate_q = Some(q),
+            "*" => any_q = Some(q),
+            _ => {}
+        }
+    }
+    let gzip_q: f32 = gzip_q.or(any_q).unwrap_or(0.0);
+    let deflate_q: f32 = deflate_q.or(any_q).unwrap_or(0.0);
+    if gzip_q <= 0.0 && deflate_q <= 0.0 {
+        None
+    } else if gzip_q >= deflate_q {
+        Some(Encoding::Gzip)
+    } else {
+        Some(Encoding::Deflate)
+    }
+}
+
+/// 压缩中间件：响应体小于 min_size、类型不在白名单或已经编码过的响应保持原样
+pub struct Compression {
+    min_size: usize,
+    mime_types: Vec<String>,
+}
+
+impl Compression {
+    pub fn new(min_size: usize) -> Compression {
+        Compression {
+            min_size,
+            mime_types: [
+                "text/*",
+                "application/json",
+                "application/javascript",
+                "application/xml",
+                "image/svg+xml",
+            ]
+            .iter()
+            .map(|s| s.to_string())
+            .collect(),
+        }
+    }
+
+    /// 替换可压缩的 MIME 类型白名单，`text/*` 这样的写法匹配整个大类
+    pub fn mime_types(mut self, types: &[&str]) -> Compression {
+        self.mime_types = types.iter().map(|s| s.to_string()).collect();
+        self
+    }
+
+    fn compressible(&self, content_type: &str) -> bool {
+        let mime = content_type
+            .split(';')
+            .next()
+            .unwrap_or("")
+            .trim()
+            .to_ascii_lowercase();
+        self.mime_types
+            .iter()
+            .any(|allowed| match allowed.strip_suffix("/*") {
+                Some(major) => mime.split('/').next() == Some(major),
+                None => *allowed == mime,
+            })
+    }
+}
+
+impl Default for Compression {
+    fn default() -> Compression {
+        Compression::new(1024)
+    }
+}
+
+impl Middleware for Compression {
+    fn call(&self, req: &mut Request, next: Next<'_>) -> Response {
+        let accept = req.header("Accept-Encoding").map(String::from);
+        let mut resp = next.run(req);
+
+        // 接管连接的响应（协议升级、流式响应）由 Upgrade 自己写数据，没法在这里压缩
+        let eligible = !matches!(resp.status, 100..=199 | 204 | 304)
+            && resp.upgrade.is_none()
+            && !resp.headers.contains("Content-Encoding")
+            && resp.body.len() >= self.min_size
+            && resp
+                .header("Content-Type")
+                .is_some_and(|ct| self.compressible(ct));
+        if !eligible {
+            return resp;
+        }
+        // 只要结果取决于 Accept-Encoding，就要告诉缓存按这个头部区分，即使这次没有压缩
+        if !resp
+            .headers
+            .get_all("Vary")
+            .any(|v| v.to_ascii_lowercase().contains("accept-encoding"))
+        {
+            resp.headers.append("Vary", "Accept-Encoding");
+        }
+        let encoding = match accept.as_deref().and_then(negotiate) {
+            Some(encoding) => encoding,
+            None => return resp,
+        };
+        let encoded = encoding.encode(&resp.body);
+        if encoded.len() < resp.body.len() {
+            resp.body = encoded;
+            resp.headers.remove("Content-Length");
+            resp.headers.set("Content-Encoding", encoding.as_str());
+        }
+        resp
+    }
+}
+
+/// gzip 格式：10 字节头部 + DEFLATE 数据 + CRC32 + 原始长度
+pub fn gzip(data: &[u8]) -> Vec<u8> {
+    // ID1 ID2 CM FLG MTIME(4) XFL OS(255 = unknown)
+    let mut out = vec![0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 255];
+    out.extend_from_slice(&deflate(data));
+    out.extend_from_slice(&crc32(data).to_le_bytes());
+    out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    out
+}
+
+/// zlib 格式：2 字节头部 + DEFLATE 数据 + Adler-32（大端）
+pub fn zlib(data: &[u8]) -> Vec<u8> {
+    // CMF = 0x78（deflate，32K 窗口），FLG 使得 (CMF * 256 + FLG) % 31 == 0
+    let mut out = vec![0x78, 0x9c];
+    out.extend_from_slice(&deflate(data));
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut table = [0u32; 256];
+    for (i, entry) in table.iter_mut().enumerate() {
+        let mut c = i as u32;
+        for _ in 0..8 {
+            c = if c & 1 != 0 {
+                0xedb8_8320 ^ (c >> 1)
+            } else {
+                c >> 1
+            };
+        }
+        *entry = c;
+    }
+    let mut crc = 0xffff_ffffu32;
+    for &b in data {
+        crc = table[((crc ^ b as u32) & 0xff) as usize] ^ (crc >> 8);
+    }
+    crc ^ 0xffff_ffff
+}
+
+pub fn adler32(data: &[u8]) -> u32 {
+    const MOD: u32 = 65521;
+    let (mut a, mut b) = (1u32, 0u32);
+    // 每 5552 字节取一次模，保证 b 不会溢出
+    for chunk in data.chunks(5552) {
+        for &x in chunk {
+            a += x as u32;
+            b += a;
+        }
+        a %= MOD;
+        b %= MOD;
+    }
+    (b << 16) | a
+}
+
+const WINDOW: usize = 32 * 1024;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+const MAX_CHAIN: usize = 128;
+const HASH_BITS: u32 = 15;
+/// 每个块最多的记号数，块越小 Huffman 表越贴近局部数据，但块头的开销也越多
+const BLOCK_TOKENS: usize = 16 * 1024;
+/// 不压缩的块最多只能放 65535 字节
+const BLOCK_BYTES: usize = 65535 - MAX_MATCH;
+
+const LEN_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LEN_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DIST_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DIST_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+/// 码长码表的码长在块头中的写入顺序
+const CL_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+#[derive(Debug, Clone, Copy)]
+enum Token {
+    Literal(u8),
+    Match { len: u16, dist: u16 },
+}
+
+fn len_code(len: u16) -> usize {
+    LEN_BASE.iter().rposition(|&base| base <= len).unwrap()
+}
+
+fn dist_code(dist: u16) -> usize {
+    DIST_BASE.iter().rposition(|&base| base <= dist).unwrap()
+}
+
+/// 原始 DEFLATE 数据（不带 gzip/zlib 包装）
+pub fn deflate(data: &[u8]) -> Vec<u8> {
+    let mut w = BitWriter::new();
+    let tokens = lz77(data);
+
+    // 按记号数和原始字节数切分成块
+    let mut start = 0;
+    let mut raw_start = 0;
+    let mut raw_len = 0;
+    for (i, token) in tokens.iter().enumerate() {
+        raw_len += match token {
+            Token::Literal(_) => 1,
+            Token::Match { len, .. } => *len as usize,
+        };
+        if i + 1 - start >= BLOCK_TOKENS || raw_len >= BLOCK_BYTES {
+            let last = i + 1 == tokens.len();
+            write_block(
+                &mut w,
+                &tokens[start..=i],
+                &data[raw_start..raw_start + raw_len],
+                last,
+            );
+            start = i + 1;
+            raw_start += raw_len;
+            raw_len = 0;
+        }
+    }
+    if start < tokens.len() || tokens.is_empty() {
+        write_block(&mut w, &tokens[start..], &data[raw_start..], true);
+    }
+    w.finish()
+}
+
+fn lz77(data: &[u8]) -> Vec<Token> {
+    let mut tokens = Vec::with_capacity(data.len() / 2);
+    // head[h] 是哈希值为 h 的最近位置，prev[i % WINDOW] 是同一哈希值的上一个位置
+    let mut head = vec![usize::MAX; 1 << HASH_BITS];
+    let mut prev = vec![usize::MAX; WINDOW];
+    let hash = |i: usize| {
+        let v = (data[i] as u32) << 16 | (data[i + 1] as u32) << 8 | data[i + 2] as u32;
+        (v.wrapping_mul(0x9e37_79b1) >> (32 - HASH_BITS)) as usize
+    };
+    let insert = |i: usize, head: &mut [usize], prev: &mut [usize]| {
+        if i + MIN_MATCH <= data.len() {
+            let h = hash(i);
+            prev[i % WINDOW] = head[h];
+            head[h] = i;
+        }
+    };
+
+    let mut i = 0;
+    while i < data.len() {
+        let mut best_len = 0;
+        let mut best_dist = 0;
+        if i + MIN_MATCH <= data.len() {
+            let max_len = MAX_MATCH.min(data.len() - i);
+            let mut cand = head[hash(i)];
+            let mut chain = 0;
+            while cand != usize::MAX && i - cand <= WINDOW && chain < MAX_CHAIN {
+                // 先比较当前最长匹配的下一个字节，不可能更长的候选直接跳过
+                if data[cand + best_len.min(max_len - 1)] == data[i + best_len.min(max_len - 1)] {
+                    let len = data[cand..]
+                        .iter()
+                        .zip(&data[i..i + max_len])
+                        .take_while(|(a, b)| a == b)
+                        .count();
+                    if len > best_len {
+                        best_len = len;
+                        best_dist = i - cand;
+                        if len == max_len {
+                            break;
+                        }
+                    }
+                }
+                let next = prev[cand % WINDOW];
+                // 链表中的位置必须严格递减，否则说明槽位已经被新的位置覆盖
+                if next == usize::MAX || next >= cand {
+                    break;
+                }
+                cand = next;
+                chain += 1;
+            }
+        }
+        if best_len >= MIN_MATCH {
+            tokens.push(Token::Match {
+                len: best_len as u16,
+                dist: best_dist as u16,
+            });
+            for j in i..i + best_len {
+                insert(j, &mut head, &mut prev);
+            }
+            i += best_len;
+        } else {
+            tokens.push(Token::Literal(data[i]));
+            insert(i, &mut head, &mut prev);
+            i += 1;
+        }
+    }
+    tokens
+}
+
+fn write_block(w: &mut BitWriter, tokens: &[Token], raw: &[u8], last: bool) {
+    let mut lit_freq = [0u32; 286];
+    let mut dist_freq = [0u32; 30];
+    for token in tokens {
+        match *token {
+            Token::Literal(b) => lit_freq[b as usize] += 1,
+            Token::Match { len, dist } => {
+                lit_freq[257 + len_code(len)] += 1;
+                dist_freq[dist_code(dist)] += 1;
+            }
+        }
+    }
+    lit_freq[256] = 1;
+
+    let (fixed_lit, fixed_dist) = fixed_lengths();
+    let fixed_cost = 3 + data_cost(&lit_freq, &dist_freq, &fixed_lit, &fixed_dist);
+
+    let dyn_lit = huffman_lengths(&lit_freq, 15);
+    let dyn_dist = huffman_lengths(&dist_freq, 15);
+    let header = DynamicHeader::new(&dyn_lit, &dyn_dist);
+    let dynamic_cost = 3 + header.cost() + data_cost(&lit_freq, &dist_freq, &dyn_lit, &dyn_dist);
+
+    // 不压缩的块：3 位块头，对齐到字节，再加 LEN 和 NLEN
+    let stored_cost = 3 + 7 + 32 + raw.len() as u64 * 8;
+
+    if stored_cost < fixed_cost.min(dynamic_cost) {
+        w.write(last as u32, 1);
+        w.write(0, 2);
+        w.align();
+        w.write(raw.len() as u32, 16);
+        w.write(!(raw.len() as u32) & 0xffff, 16);
+        w.bytes(raw);
+    } else if fixed_cost <= dynamic_cost {
+        w.write(last as u32, 1);
+        w.write(1, 2);
+        write_tokens(w, tokens, &fixed_lit, &fixed_dist);
+    } else {
+        w.write(last as u32, 1);
+        w.write(2, 2);
+        header.write(w);
+        write_tokens(w, tokens, &dyn_lit, &dyn_dist);
+    }
+}
+
+fn write_tokens(w: &mut BitWriter, tokens: &[Token], lit_lens: &[u8], dist_lens: &[u8]) {
+    let lit_codes = canonical_codes(lit_lens);
+    let dist_codes = canonical_codes(dist_lens);
+    for token in tokens {
+        match *token {
+            Token::Literal(b) => w.code(lit_codes[b as usize], lit_lens[b as usize]),
+            Token::Match { len, dist } => {
+                let lc = len_code(len);
+                w.code(lit_codes[257 + lc], lit_lens[257 + lc]);
+                w.write((len - LEN_BASE[lc]) as u32, LEN_EXTRA[lc] as u32);
+                let dc = dist_code(dist);
+                w.code(dist_codes[dc], dist_lens[dc]);
+                w.write((dist - DIST_BASE[dc]) as u32, DIST_EXTRA[dc] as u32);
+            }
+        }
+    }
+    w.code(lit_codes[256], lit_lens[256]);
+}
+
+fn data_cost(lit_freq: &[u32], dist_freq: &[u32], lit_lens: &[u8], dist_lens: &[u8]) -> u64 {
+    let mut bits = 0u64;
+    for (sym, &f) in lit_freq.iter().enumerate() {
+        let extra = if sym > 256 {
+            LEN_EXTRA[sym - 257] as u64
+        } else {
+            0
+        };
+        bits += f as u64 * (lit_lens[sym] as u64 + extra);
+    }
+    for (sym, &f) in dist_freq.iter().enumerate() {
+        bits += f as u64 * (dist_lens[sym] as u64 + DIST_EXTRA[sym] as u64);
+    }
+    bits
+}
+
+/// RFC 1951 3.2.6 中定义的固定 Huffman 码长
+fn fixed_lengths() -> (Vec<u8>, Vec<u8>) {
+    let mut lit = vec![0u8; 288];
+    for (sym, len) in lit.iter_mut().enumerate() {
+        *len = match sym {
+            0..=143 => 8,
+            144..=255 => 9,
+            256..=279 => 7,
+            _ => 8,
+        };
+    }
+    (lit, vec![5u8; 30])
+}
+
+/// 根据频率计算码长，最长不超过 limit；超过时把频率减半重新计算，直到满足限制
+fn huffman_lengths(freq: &[u32], limit: u8) -> Vec<u8> {
+    let mut lens = vec![0u8; freq.len()];
+    let used: Vec<usize> = (0..freq.len()).filter(|&s| freq[s] > 0).collect();
+    // 只有 0 个或 1 个符号时凑出两个长度为 1 的码，保证码表是完整的
+    if used.len() < 2 {
+        let first = used.first().copied().unwrap_or(0);
+        let second = if first == 0 { 1 } else { 0 };
+        lens[first] = 1;
+        lens[second] = 1;
+        return lens;
+    }
+
+    let mut weights: Vec<u64> = freq.iter().map(|&f| f as u64).collect();
+    loop {
+        // 节点：(权重, 子节点)；叶子节点的子节点为 None
+        let mut nodes: Vec<(u64, Option<(usize, usize)>)> =
+            used.iter().map(|&s| (weights[s], None)).collect();
+        let mut queue: Vec<usize> = (0..nodes.len()).collect();
+        while queue.len() > 1 {
+            // 权重从大到小排序，末尾两个就是最小的
+            queue.sort_by(|&a, &b| nodes[b].0.cmp(&nodes[a].0));
+            let a = queue.pop().unwrap();
+            let b = queue.pop().unwrap();
+            nodes.push((nodes[a].0 + nodes[b].0, Some((a, b))));
+            queue.push(nodes.len() - 1);
+        }
+        let mut depth = vec![0u8; nodes.len()];
+        for n in (0..nodes.len()).rev() {
+            if let Some((a, b)) = nodes[n].1 {
+                depth[a] = depth[n] + 1;
+                depth[b] = depth[n] + 1;
+            }
+        }
+        if depth[..used.len()].iter().all(|&d| d <= limit) {
+            for (i, &s) in used.iter().enumerate() {
+                lens[s] = depth[i];
+            }
+            return lens;
+        }
+        for w in weights.iter_mut().filter(|w| **w > 0) {
+            *w = (*w).div_ceil(2);
+        }
+    }
+}
+
+/// 由码长生成规范 Huffman 码（RFC 1951 3.2.2）
+fn canonical_codes(lens: &[u8]) -> Vec<u16> {
+    let mut bl_count = [0u16; 16];
+    for &l in lens {
+        if l > 0 {
+            bl_count[l as usize] += 1;
+        }
+    }
+    let mut next_code = [0u16; 16];
+    let mut code = 0u16;
+    for bits in 1..16 {
+        code = (code + bl_count[bits - 1]) << 1;
+        next_code[bits] = code;
+    }
+    lens.iter()
+        .map(|&l| {
+            if l == 0 {
+                0
+            } else {
+                let c = next_code[l as usize];
+                next_code[l as usize] += 1;
+                c
+            }
+        })
+        .collect()
+}
+
+/// 动态 Huffman 块的块头：用游程编码压缩后的码长序列
+struct DynamicHeader {
+    hlit: usize,
+    hdist: usize,
+    hclen: usize,
+    /// (码长符号, 额外位的值)
+    symbols: Vec<(u8, u8)>,
+    cl_lens: Vec<u8>,
+}
+
+impl DynamicHeader {
+    fn new(lit_lens: &[u8], dist_lens: &[u8]) -> DynamicHeader {
+        let hlit = 257.max(lit_lens.iter().rposition(|&l| l > 0).map_or(0, |p| p + 1));
+        let hdist = 1.max(dist_lens.iter().rposition(|&l| l > 0).map_or(0, |p| p + 1));
+        let mut all = lit_lens[..hlit].to_vec();
+        all.extend_from_slice(&dist_lens[..hdist]);
+
+        let mut symbols = Vec::new();
+        let mut i = 0;
+        while i < all.len() {
+            let l = all[i];
+            let run = all[i..].iter().take_while(|&&x| x == l).count();
+            if l == 0 && run >= 11 {
+                let n = run.min(138);
+                symbols.push((18, (n - 11) as u8));
+                i += n;
+            } else if l == 0 && run >= 3 {
+                symbols.push((17, (run - 3) as u8));
+                i += run;
+            } else if l != 0 && run >= 4 {
+                // 先写一个字面量，后面的用 16 重复前一个码长
+                symbols.push((l, 0));
+                let n = (run - 1).min(6);
+                symbols.push((16, (n - 3) as u8));
+                i += 1 + n;
+            } else {
+                symbols.push((l, 0));
+                i += 1;
+            }
+        }
+
+        let mut cl_freq = [0u32; 19];
+        for &(s, _) in &symbols {
+            cl_freq[s as usize] += 1;
+        }
+        let cl_lens = huffman_lengths(&cl_freq, 7);
+        let hclen = 4.max(
+            CL_ORDER
+                .iter()
+                .rposition(|&s| cl_lens[s] > 0)
+                .map_or(0, |p| p + 1),
+        );
+        DynamicHeader {
+            hlit,
+            hdist,
+            hclen,
+            symbols,
+            cl_lens,
+        }
+    }
+
+    fn cost(&self) -> u64 {
+        let mut bits = 5 + 5 + 4 + 3 * self.hclen as u64;
+        for &(s, _) in &self.symbols {
+            bits += self.cl_lens[s as usize] as u64 + extra_bits(s) as u64;
+        }
+        bits
+    }
+
+    fn write(&self, w: &mut BitWriter) {
+        w.write((self.hlit - 257) as u32, 5);
+        w.write((self.hdist - 1) as u32, 5);
+        w.write((self.hclen - 4) as u32, 4);
+        for &s in &CL_ORDER[..self.hclen] {
+            w.write(self.cl_lens[s] as u32, 3);
+        }
+        let codes = canonical_codes(&self.cl_lens);
+        for &(s, extra) in &self.symbols {
+            w.code(codes[s as usize], self.cl_lens[s as usize]);
+            w.write(extra as u32, extra_bits(s));
+        }
+    }
+}
+
+fn extra_bits(cl_symbol: u8) -> u32 {
+    match cl_symbol {
+        16 => 2,
+        17 => 3,
+        18 => 7,
+        _ => 0,
+    }
+}
+
+/// DEFLATE 按最低位优先的顺序打包比特
+struct BitWriter {
+    out: Vec<u8>,
+    acc: u64,
+    nbits: u32,
+}
+
+impl BitWriter {
+    fn new() -> BitWriter {
+        BitWriter {
+            out: Vec::new(),
+            acc: 0,
+            nbits: 0,
+        }
+    }
+
+    fn write(&mut self, value: u32, n: u32) {
+        self.acc |= (value as u64) << self.nbits;
+        self.nbits += n;
+        while self.nbits >= 8 {
+            self.out.push(self.acc as u8);
+            self.acc >>= 8;
+            self.nbits -= 8;
+        }
+    }
+
+    /// Huffman 码从最高位开始写，所以要先把比特反转
+    fn code(&mut self, code: u16, len: u8) {
+        let reversed = code.reverse_bits() >> (16 - len as u32);
+        self.write(reversed as u32, len as u32);
+    }
+
+    fn align(&mut self) {
+        if self.nbits > 0 {
+            self.write(0, 8 - self.nbits);
+        }
+    }
+
+    fn bytes(&mut self, data: &[u8]) {
+        debug_assert_eq!(self.nbits, 0);
+        self.out.extend_from_slice(data);
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        self.align();
+        self.out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::middleware::{Handler, Pipeline};
+    use std::{
+        io::Write,
+        process::{Command, Stdio},
+    };
+
+    /// 用系统的 gzip 作为参考解码器
+    fn gunzip(data: &[u8]) -> Vec<u8> {
+        let mut child = Command::new("gzip")
+            .arg("-dc")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .expect("cannot run the gzip binary");
+        let mut stdin = child.stdin.take().unwrap();
+        let input = data.to_vec();
+        let writer = std::thread::spawn(move || stdin.write_all(&input));
+        let out = child.wait_with_output().unwrap();
+        writer.join().unwrap().unwrap();
+        assert!(
+            out.status.success(),
+            "gzip rejected our stream: {}",
+            String::from_utf8_lossy(&out.stderr)
+        );
+        out.stdout
+    }
+
+    /// 简单的线性同余伪随机数，生成不可压缩的数据
+    fn noise(n: usize) -> Vec<u8> {
+        let mut x = 0x1234_5678u32;
+        (0..n)
+            .map(|_| {
+                x = x.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                (x >> 16) as u8
+            })
+            .collect()
+    }
+
+    fn samples() -> Vec<Vec<u8>> {
+        let html = include_bytes!("hello.html").to_vec();
+        let mut mixed = html.repeat(50);
+        mixed.extend(noise(5000));
+        mixed.extend(b"abc".repeat(30000));
+        vec![
+            Vec::new(),
+            b"a".to_vec(),
+            b"hello hello hello hello".to_vec(),
+            (0..=255u8).collect(),
+            vec![0; 100_000],
+            noise(70_000),
+            html,
+            mixed,
+        ]
+    }
+
+    #[test]
+    fn checksums() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+        assert_eq!(adler32(b"Wikipedia"), 0x11e6_0398);
+        assert_eq!(adler32(&vec![0xff; 100_000]), 0x149a_302c);
+    }
+
+    #[test]
+    #[ignore = "needs the gzip binary; run with `cargo test -- --ignored`"]
+    fn gzip_round_trips_through_reference_decoder() {
+        for sample in samples() {
+            let out = gunzip(&gzip(&sample));
+            assert!(out == sample, "mismatch for {} bytes", sample.len());
+        }
+    }
+
+    #[test]
+    fn zlib_matches_known_vectors() {
+        // 和 zlib 库 compress() 的输出逐字节相同：空的固定 Huffman 块、一个字面量
+        assert_eq!(zlib(b""), [0x78, 0x9c, 0x03, 0x00, 0x00, 0x00, 0x00, 0x01]);
+        assert_eq!(
+            zlib(b"a"),
+            [0x78, 0x9c, 0x4b, 0x04, 0x00, 0x00, 0x62, 0x00, 0x62]
+        );
+        // 不可压缩的数据放进一个不压缩的块：BFINAL = 1、BTYPE = 00，LEN = 1000 和它的反码，
+        // 之后是原始数据和大端的 Adler-32
+        let data = noise(1000);
+        let mut expected = vec![0x78, 0x9c, 0x01, 0xe8, 0x03, 0x17, 0xfc];
+        expected.extend_from_slice(&data);
+        expected.extend_from_slice(&[0x65, 0xe6, 0xf7, 0x20]);
+        assert_eq!(zlib(&data), expected);
+    }
+
+    #[test]
+    fn compresses_repetitive_data() {
+        let data = b"<p>Hi from Rust</p>\n".repeat(500);
+        assert!(gzip(&data).len() < data.len() / 20);
+        let z = zlib(&data);
+        assert_eq!(&z[..2], &[0x78, 0x9c]);
+        assert_eq!(((z[0] as u16) << 8 | z[1] as u16) % 31, 0);
+        assert_eq!(&z[z.len() - 4..], &adler32(&data).to_be_bytes());
+    }
+
+    #[test]
+    fn incompressible_data_uses_stored_blocks() {
+        let data = noise(10_000);
+        // 不压缩的块只多出几个字节的块头
+        assert!(deflate(&data).len() <= data.len() + 5);
+    }
+
+    #[test]
+    fn negotiate_accept_encoding() {
+        assert_eq!(negotiate("gzip, deflate, br"), Some(Encoding::Gzip));
+        assert_eq!(negotiate("deflate"), Some(Encoding::Deflate));
+        assert_eq!(
+            negotiate("gzip;q=0.5, deflate;q=0.8"),
+            Some(Encoding::Deflate)
+        );
+        assert_eq!(negotiate("gzip;q=0, *"), Some(Encoding::Deflate));
+        assert_eq!(negotiate("*;q=0"), None);
+        assert_eq!(negotiate("br, identity"), None);
+    }
+
+    fn run(pipeline: &Pipeline, target: &str, accept: Option<&str>) -> Response {
+        let mut req = Request::new("GET", target);
+        if let Some(accept) = accept {
+            req.headers.set("Accept-Encoding", accept);
+        }
+        pipeline.handle(&mut req)
+    }
+
+    #[test]
+    fn middleware_respects_threshold_and_mime() {
+        let page = "<p>hello</p>".repeat(200);
+        let big = page.clone();
+        let pipeline = Pipeline::new(move |req: &mut Request| match req.query.as_deref() {
+            Some("small") => Response::html(200, "<p>tiny</p>"),
+            Some("png") => Response::new(200)
+                .with_header("Content-Type", "image/png")
+                .with_body(big.clone()),
+            _ => Response::html(200, big.clone()),
+        })
+        .with(Compression::new(256));
+
+        let resp = run(&pipeline, "/", Some("gzip"));
+        assert_eq!(resp.header("Content-Encoding"), Some("gzip"));
+        assert_eq!(resp.header("Vary"), Some("Accept-Encoding"));
+        assert!(resp.body.len() < page.len());
+
+        // 客户端不接受压缩时仍然要带上 Vary
+        let resp = run(&pipeline, "/", None);
+        assert_eq!(resp.header("Content-Encoding"), None);
+        assert_eq!(resp.header("Vary"), Some("Accept-Encoding"));
+        assert_eq!(resp.body, page.as_bytes());
+
+        let resp = run(&pipeline, "/?small", Some("gzip"));
+        assert_eq!(resp.header("Content-Encoding"), None);
+        assert_eq!(resp.header("Vary"), None);
+
+        let resp = run(&pipeline, "/?png", Some("gzip"));
+        assert_eq!(resp.header("Content-Encoding"), None);
+    }
+}
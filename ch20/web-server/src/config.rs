@@ -0,0 +1,818 @@
+/*
+服务器配置：
+    先读取 `--config` 指定的配置文件，再用命令行参数覆盖其中的值，最后统一做校验。
+    配置文件是 TOML 的一个子集，支持 `[section]`、`key = value`、字符串、整数、字符串数组和 `#` 注释：
+
+        bind = ["127.0.0.1:7878", "[::1]:7878"]
+        workers = 4
+        max_workers = 16      # 排队时最多增加到的线程数，0 表示固定为 workers
+        worker_keep_alive = "60s"   # 多出来的线程空闲多久后退出
+        doc_root = "public"
+        backend = "threads"   # threads / epoll
+        queue_capacity = 1024 # 线程池排队任务数上限，0 表示不限制
+        queue_policy = "reject"     # block / reject / drop_oldest / caller_runs
+
+        [timeouts]
+        read = "30s"          # 两次读取之间的最长间隔
+        write = "30s"
+        header = "10s"        # 收完请求行和头部的总时长
+        keep_alive = "5s"     # 两个请求之间的空闲时间
+        connection = "60s"    # 一个连接的最长存活时间
+
+        [limits]
+        max_header_bytes = 8192
+        max_body_bytes = 1048576
+        max_connections_per_ip = 64
+
+        [log]
+        level = "info"              # off / error / warn / info / debug
+        access = "logs/access.log"  # stdout / off / 文件路径
+        format = "combined"         # common / combined / json
+        max_bytes = 67108864
+        keep = 5
+
+        [compression]
+        enabled = true
+        min_size = 1024
+
+        [metrics]
+        enabled = true
+        path = "/metrics"     # Prometheus 文本格式
+
+        [http2]
+        enabled = true        # 明文 HTTP/2：prior knowledge 和 Upgrade: h2c
+
+        [proxy]
+        routes = ["/api=http://127.0.0.1:9001,http://127.0.0.1:9002"]
+        balance = "round_robin"     # round_robin / least_connections
+        health_check = "/health"
+        health_interval = "5s"
+        retries = 1
+        timeout = "30s"
+*/
+
+use crate::{
+    QueuePolicy,
+    access_log::{LogFormat, LogTarget, Rotation},
+    http::Limits,
+    log::Level,
+    proxy::ProxyConfig,
+    server::Backend,
+};
+use std::{
+    error::Error,
+    fmt, fs, io,
+    net::{SocketAddr, ToSocketAddrs},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+pub const USAGE: &str = "\
+Usage: main [OPTIONS]
+
+Options:
+    -c, --config <FILE>         read settings from a config file
+    -b, --bind <ADDR>           listen address, may be repeated (default 127.0.0.1:7878)
+    -w, --workers <N>           number of worker threads (default 4)
+        --max-workers <N>       grow the pool up to N threads while jobs are queued, 0 = fixed
+        --worker-keep-alive <DUR>
+                                idle time before an extra worker exits (default 60s)
+    -d, --doc-root <DIR>        directory the html files are served from
+        --backend <NAME>        threads or epoll (default threads)
+        --queue-capacity <N>    jobs allowed to wait for a worker, 0 = unlimited (default 1024)
+        --queue-policy <NAME>   block, reject, drop_oldest or caller_runs when the queue is full
+                                (default reject; the epoll backend always rejects)
+        --read-timeout <DUR>    socket read timeout, e.g. 30s, 500ms, 0 to disable
+        --write-timeout <DUR>   socket write timeout
+        --header-timeout <DUR>  deadline for receiving the request line and headers
+        --keep-alive-timeout <DUR>
+                                idle time allowed between requests on one connection
+        --connection-timeout <DUR>
+                                maximum lifetime of a connection
+        --max-conns-per-ip <N>  concurrent connections allowed per client ip, 0 = unlimited
+        --max-header-bytes <N>  maximum size of the request line and headers
+        --max-body-bytes <N>    maximum size of the request body
+        --log-level <LEVEL>     off, error, warn, info or debug (default info)
+        --access-log <TARGET>   stdout, off or a file path (default stdout)
+        --access-log-format <F> common, combined or json (default combined)
+        --compression <on|off>  gzip/deflate responses the client accepts (default on)
+        --metrics <on|off>      count requests and serve them in Prometheus format (default on)
+        --metrics-path <PATH>   where the metrics are served (default /metrics)
+        --http2 <on|off>        accept cleartext HTTP/2 connections (default on)
+        --proxy <PREFIX=URL,...>
+                                forward a path prefix to upstream servers, may be repeated
+        --proxy-balance <NAME>  round_robin or least_connections (default round_robin)
+        --proxy-health-check <PATH>
+                                path polled on every upstream to detect failures
+    -h, --help                  print this help
+";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Config {
+    pub binds: Vec<SocketAddr>,
+    pub workers: u32,
+    /// 0 表示线程数固定为 workers
+    pub max_workers: u32,
+    pub worker_keep_alive: Duration,
+    pub doc_root: PathBuf,
+    pub backend: Backend,
+    pub queue_capacity: usize,
+    pub queue_policy: QueuePolicy,
+    pub read_timeout: Option<Duration>,
+    pub write_timeout: Option<Duration>,
+    pub header_timeout: Option<Duration>,
+    pub keep_alive_timeout: Option<Duration>,
+    pub connection_timeout: Option<Duration>,
+    pub limits: Limits,
+    pub max_connections_per_ip: usize,
+    pub log_level: Level,
+    pub access_log: LogTarget,
+    pub access_log_format: LogFormat,
+    pub access_log_rotation: Rotation,
+    pub compression: bool,
+    /// 小于该大小的响应体不压缩
+    pub compression_min_size: usize,
+    pub metrics: bool,
+    pub metrics_path: String,
+    pub http2: bool,
+    pub proxy: ProxyConfig,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            binds: vec![SocketAddr::from(([127, 0, 0, 1], 7878))],
+            workers: 4,
+            max_workers: 0,
+            worker_keep_alive: Duration::from_secs(60),
+            doc_root: PathBuf::from(concat!(env!("CARGO_MANIFEST_DIR"), "/src")),
+            backend: Backend::Threads,
+            queue_capacity: 1024,
+            queue_policy: QueuePolicy::Reject,
+            read_timeout: Some(Duration::from_secs(30)),
+            write_timeout: Some(Duration::from_secs(30)),
+            header_timeout: Some(Duration::from_secs(10)),
+            keep_alive_timeout: Some(Duration::from_secs(5)),
+            connection_timeout: Some(Duration::from_secs(60)),
+            limits: Limits::default(),
+            max_connections_per_ip: 64,
+            log_level: Level::Info,
+            access_log: LogTarget::Stdout,
+            access_log_format: LogFormat::Combined,
+            access_log_rotation: Rotation::default(),
+            compression: true,
+            compression_min_size: 1024,
+            metrics: true,
+            metrics_path: String::from("/metrics"),
+            http2: true,
+            proxy: ProxyConfig::default(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    /// 用户请求打印帮助信息，并不是真正的错误
+    Help,
+    Usage(String),
+    Io(PathBuf, io::Error),
+    Parse {
+        line: usize,
+        msg: String,
+    },
+    Invalid(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::Help => write!(f, "{}", USAGE),
+            ConfigError::Usage(msg) => write!(f, "{}\n\n{}", msg, USAGE),
+            ConfigError::Io(path, err) => write!(f, "cannot read {}: {}", path.display(), err),
+            ConfigError::Parse { line, msg } => write!(f, "config line {}: {}", line, msg),
+            ConfigError::Invalid(msg) => write!(f, "invalid config: {}", msg),
+        }
+    }
+}
+
+impl Error for ConfigError {}
+
+impl Config {
+    /// 解析命令行参数（不包含程序名），并在最后做校验
+    pub fn from_args<I>(args: I) -> Result<Config, ConfigError>
+    where
+        I: IntoIterator<Item = String>,
+    {
+        let args: Vec<String> = args.into_iter().collect();
+
+        // 配置文件的优先级低于命令行参数，所以要先找出来加载
+        let mut config = Config::default();
+        let mut iter = args.iter();
+        while let Some(arg) = iter.next() {
+            if arg == "-c" || arg == "--config" {
+                let path = iter
+                    .next()
+                    .ok_or_else(|| ConfigError::Usage(format!("{} requires a value", arg)))?;
+                config = Config::from_file(Path::new(path))?;
+            } else if let Some(path) = arg.strip_prefix("--config=") {
+                config = Config::from_file(Path::new(path))?;
+            }
+        }
+
+        let mut binds_from_cli = false;
+        let mut iter = args.into_iter();
+        while let Some(arg) = iter.next() {
+            let (flag, inline) = match arg.split_once('=') {
+                Some((flag, value)) if flag.starts_with("--") => {
+                    (flag.to_string(), Some(value.to_string()))
+                }
+                _ => (arg, None),
+            };
+            if flag == "-h" || flag == "--help" {
+                return Err(ConfigError::Help);
+            }
+            let key = match flag.as_str() {
+                "-c" | "--config" => None,
+                "-b" | "--bind" => Some("bind"),
+                "-w" | "--workers" => Some("workers"),
+                "--max-workers" => Some("max_workers"),
+                "--worker-keep-alive" => Some("worker_keep_alive"),
+                "-d" | "--doc-root" => Some("doc_root"),
+                "--backend" => Some("backend"),
+                "--queue-capacity" => Some("queue_capacity"),
+                "--queue-policy" => Some("queue_policy"),
+                "--read-timeout" => Some("timeouts.read"),
+                "--write-timeout" => Some("timeouts.write"),
+                "--header-timeout" => Some("timeouts.header"),
+                "--keep-alive-timeout" => Some("timeouts.keep_alive"),
+                "--connection-timeout" => Some("timeouts.connection"),
+                "--max-conns-per-ip" => Some("limits.max_connections_per_ip"),
+                "--max-header-bytes" => Some("limits.max_header_bytes"),
+                "--max-body-bytes" => Some("limits.max_body_bytes"),
+                "--log-level" => Some("log.level"),
+                "--access-log" => Some("log.access"),
+                "--access-log-format" => Some("log.format"),
+                "--compression" => Some("compression.enabled"),
+                "--metrics" => Some("metrics.enabled"),
+                "--metrics-path" => Some("metrics.path"),
+                "--http2" => Some("http2.enabled"),
+                "--proxy" => Some("proxy.routes"),
+                "--proxy-balance" => Some("proxy.balance"),
+                "--proxy-health-check" => Some("proxy.health_check"),
+                _ => return Err(ConfigError::Usage(format!("unknown option `{}`", flag))),
+            };
+            let value = match inline.or_else(|| iter.next()) {
+                Some(value) => value,
+                None => return Err(ConfigError::Usage(format!("{} requires a value", flag))),
+            };
+            let key = match key {
+                Some(key) => key,
+                None => continue,
+            };
+            // 命令行上第一次出现 --bind 时替换掉默认值或配置文件中的地址，之后的 --bind 追加
+            if key == "bind" && !binds_from_cli {
+                config.binds.clear();
+                binds_from_cli = true;
+            }
+            let value = if key == "bind" {
+                Value::Array(vec![Value::Str(value)])
+            } else {
+                Value::Str(value)
+            };
+            config
+                .set(key, value, Path::new("."))
+                .map_err(|msg| ConfigError::Usage(format!("{}: {}", flag, msg)))?;
+        }
+
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// 读取配置文件，配置文件中的相对路径以配置文件所在的目录为基准
+    pub fn from_file(path: &Path) -> Result<Config, ConfigError> {
+        let src =
+            fs::read_to_string(path).map_err(|err| ConfigError::Io(path.to_path_buf(), err))?;
+        let base = path.parent().unwrap_or(Path::new("."));
+        let mut config = Config::default();
+        config.apply_str(&src, base)?;
+        Ok(config)
+    }
+
+    /// 将配置文本中的设置应用到当前配置上
+    pub fn apply_str(&mut self, src: &str, base: &Path) -> Result<(), ConfigError> {
+        let mut section = String::new();
+        let mut binds_seen = false;
+        for (idx, raw) in src.lines().enumerate() {
+            let line_no = idx + 1;
+            let err = |msg: String| ConfigError::Parse { line: line_no, msg };
+            let line = strip_comment(raw).trim();
+            if line.is_empty() {
+                continue;
+            }
+            if let Some(rest) = line.strip_prefix('[') {
+                let name = rest
+                    .strip_suffix(']')
+                    .ok_or_else(|| err(String::from("unterminated section header")))?;
+                section = name.trim().to_string();
+                continue;
+            }
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| err(String::from("expected `key = value`")))?;
+            let key = key.trim();
+            let key = if section.is_empty() {
+                key.to_string()
+            } else {
+                format!("{}.{}", section, key)
+            };
+            let value = Value::parse(value.trim()).map_err(err)?;
+            if key == "bind" && !binds_seen {
+                self.binds.clear();
+                binds_seen = true;
+            }
+            self.set(&key, value, base).map_err(err)?;
+        }
+        Ok(())
+    }
+
+    fn set(&mut self, key: &str, value: Value, base: &Path) -> Result<(), String> {
+        match key {
+            "bind" => {
+                let items = match value {
+                    Value::Array(items) => items,
+                    other => vec![other],
+                };
+                for item in items {
+                    let addr = item.into_string()?;
+                    let mut addrs = addr
+                        .to_socket_addrs()
+                        .map_err(|err| format!("invalid bind address `{}`: {}", addr, err))?;
+                    let addr = addrs
+                        .next()
+                        .ok_or_else(|| format!("bind address `{}` did not resolve", addr))?;
+                    self.binds.push(addr);
+                }
+            }
+            "workers" => self.workers = value.into_number()?,
+            "max_workers" => self.max_workers = value.into_number()?,
+            "worker_keep_alive" => {
+                self.worker_keep_alive = parse_duration(&value.into_string()?)?
+                    .ok_or_else(|| String::from("worker_keep_alive must be greater than 0"))?
+            }
+            "doc_root" => self.doc_root = base.join(value.into_string()?),
+            "backend" => self.backend = value.into_string()?.parse()?,
+            "queue_capacity" => self.queue_capacity = value.into_number()?,
+            "queue_policy" => self.queue_policy = value.into_string()?.parse()?,
+            "timeouts.read" => self.read_timeout = parse_duration(&value.into_string()?)?,
+            "timeouts.write" => self.write_timeout = parse_duration(&value.into_string()?)?,
+            "timeouts.header" => self.header_timeout = parse_duration(&value.into_string()?)?,
+            "timeouts.keep_alive" => {
+                self.keep_alive_timeout = parse_duration(&value.into_string()?)?
+            }
+            "timeouts.connection" => {
+                self.connection_timeout = parse_duration(&value.into_string()?)?
+            }
+            "limits.max_connections_per_ip" => self.max_connections_per_ip = value.into_number()?,
+            "limits.max_header_bytes" => self.limits.max_header_bytes = value.into_number()?,
+            "limits.max_body_bytes" => self.limits.max_body_bytes = value.into_number()?,
+            "log.level" => self.log_level = value.into_string()?.parse()?,
+            "log.access" => {
+                self.access_log = match value.into_string()?.parse()? {
+                    LogTarget::File(path) => LogTarget::File(base.join(path)),
+                    target => target,
+                }
+            }
+            "log.format" => self.access_log_format = value.into_string()?.parse()?,
+            "log.max_bytes" => self.access_log_rotation.max_bytes = value.into_number()?,
+            "log.keep" => self.access_log_rotation.keep = value.into_number()?,
+            "compression.enabled" => self.compression = value.into_bool()?,
+            "compression.min_size" => self.compression_min_size = value.into_number()?,
+            "metrics.enabled" => self.metrics = value.into_bool()?,
+            "metrics.path" => {
+                let path = value.into_string()?;
+                if !path.starts_with('/') {
+                    return Err(format!("metrics path `{}` must start with `/`", path));
+                }
+                self.metrics_path = path;
+            }
+            "http2.enabled" => self.http2 = value.into_bool()?,
+            "proxy.routes" => {
+                let items = match value {
+                    Value::Array(items) => items,
+                    other => vec![other],
+                };
+                for item in items {
+                    self.proxy.routes.push(item.into_string()?.parse()?);
+                }
+            }
+            "proxy.balance" => self.proxy.balance = value.into_string()?.parse()?,
+            "proxy.health_check" => {
+                let path = value.into_string()?;
+                if !path.starts_with('/') {
+                    return Err(format!("health check path `{}` must start with `/`", path));
+                }
+                self.proxy.health_check = Some(path);
+            }
+            "proxy.health_interval" => {
+                self.proxy.health_interval = parse_duration(&value.into_string()?)?
+                    .ok_or_else(|| String::from("health_interval must be greater than 0"))?
+            }
+            "proxy.retries" => self.proxy.retries = value.into_number()?,
+            "proxy.timeout" => self.proxy.timeout = parse_duration(&value.into_string()?)?,
+            _ => return Err(format!("unknown setting `{}`", key)),
+        }
+        Ok(())
+    }
+
+    /// 校验配置之间的约束，启动时的错误应该尽早暴露出来
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.binds.is_empty() {
+            return Err(ConfigError::Invalid(String::from(
+                "at least one bind address is required",
+            )));
+        }
+        if self.workers == 0 || self.workers > 1024 {
+            return Err(ConfigError::Invalid(format!(
+                "workers must be between 1 and 1024, got {}",
+                self.workers
+            )));
+        }
+        if self.max_workers != 0 && (self.max_workers < self.workers || self.max_workers > 1024) {
+            return Err(ConfigError::Invalid(format!(
+                "max_workers must be between workers ({}) and 1024, got {}",
+                self.workers, self.max_workers
+            )));
+        }
+        if !self.doc_root.is_dir() {
+            return Err(ConfigError::Invalid(format!(
+                "doc_root {} is not a directory",
+                self.doc_root.display()
+            )));
+        }
+        if self.limits.max_header_bytes < 64 {
+            return Err(ConfigError::Invalid(String::from(
+                "limits.max_header_bytes must be at least 64",
+            )));
+        }
+        let timeouts = [
+            ("timeouts.read", self.read_timeout),
+            ("timeouts.write", self.write_timeout),
+            ("timeouts.header", self.header_timeout),
+            ("timeouts.keep_alive", self.keep_alive_timeout),
+            ("timeouts.connection", self.connection_timeout),
+        ];
+        for (name, timeout) in timeouts {
+            if timeout.is_some_and(|t| t > MAX_DURATION) {
+                return Err(ConfigError::Invalid(format!(
+                    "{} must not be longer than a day",
+                    name
+                )));
+            }
+        }
+        if let LogTarget::File(path) = &self.access_log {
+            let dir = path.parent().filter(|p| !p.as_os_str().is_empty());
+            if dir.is_some_and(|dir| !dir.is_dir()) {
+                return Err(ConfigError::Invalid(format!(
+                    "access log directory for {} does not exist",
+                    path.display()
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// 配置文件中的值
+#[derive(Debug, Clone, PartialEq)]
+enum Value {
+    Str(String),
+    Int(u64),
+    Bool(bool),
+    Array(Vec<Value>),
+}
+
+impl Value {
+    fn parse(raw: &str) -> Result<Value, String> {
+        if let Some(inner) = raw.strip_prefix('[') {
+            let inner = inner
+                .strip_suffix(']')
+                .ok_or_else(|| String::from("unterminated array"))?;
+            return split_items(inner)
+                .into_iter()
+                .map(str::trim)
+                .filter(|item| !item.is_empty())
+                .map(Value::parse)
+                .collect::<Result<Vec<_>, _>>()
+                .map(Value::Array);
+        }
+        if let Some(inner) = raw.strip_prefix('"') {
+            return inner
+                .strip_suffix('"')
+                .map(|s| Value::Str(s.to_string()))
+                .ok_or_else(|| String::from("unterminated string"));
+        }
+        match raw {
+            "true" => return Ok(Value::Bool(true)),
+            "false" => return Ok(Value::Bool(false)),
+            _ => {}
+        }
+        raw.replace('_', "")
+            .parse()
+            .map(Value::Int)
+            .map_err(|_| format!("invalid value `{}`", raw))
+    }
+
+    fn into_string(self) -> Result<String, String> {
+        match self {
+            Value::Str(s) => Ok(s),
+            Value::Int(n) => Ok(n.to_string()),
+            Value::Bool(b) => Ok(b.to_string()),
+            Value::Array(_) => Err(String::from("expected a single value, found an array")),
+        }
+    }
+
+    fn into_number<T: std::str::FromStr>(self) -> Result<T, String> {
+        let s = self.into_string()?;
+        s.parse().map_err(|_| format!("invalid number `{}`", s))
+    }
+
+    /// 命令行上的值都是字符串，所以也接受 on/off 这样的写法
+    fn into_bool(self) -> Result<bool, String> {
+        match self.into_string()?.as_str() {
+            "true" | "on" | "yes" => Ok(true),
+            "false" | "off" | "no" => Ok(false),
+            other => Err(format!("invalid boolean `{}`", other)),
+        }
+    }
+}
+
+/// 按逗号拆分数组元素，字符串中的逗号保留
+fn split_items(inner: &str) -> Vec<&str> {
+    let mut items = Vec::new();
+    let mut in_str = false;
+    let mut start = 0;
+    for (i, c) in inner.char_indices() {
+        match c {
+            '"' => in_str = !in_str,
+            ',' if !in_str => {
+                items.push(&inner[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    items.push(&inner[start..]);
+    items
+}
+
+/// 去掉 `#` 开始的注释，字符串中的 `#` 保留
+fn strip_comment(line: &str) -> &str {
+    let mut in_str = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            '"' => in_str = !in_str,
+            '#' if !in_str => return &line[..i],
+            _ => {}
+        }
+    }
+    line
+}
+
+/// 配置里允许的最长时长，更大的值多半是写错了，而且会让 `Instant` 加法溢出
+pub const MAX_DURATION: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// 解析 `30s`、`500ms`、`2m` 这样的时长，没有单位时按秒处理，0 表示不超时，
+/// 超过 [`MAX_DURATION`] 时返回错误
+pub fn parse_duration(s: &str) -> Result<Option<Duration>, String> {
+    let s = s.trim();
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (num, unit) = s.split_at(split);
+    let n: u64 = num
+        .parse()
+        .map_err(|_| format!("invalid duration `{}`", s))?;
+    let millis = match unit {
+        "" | "s" => n.checked_mul(1000),
+        "ms" => Some(n),
+        "m" => n.checked_mul(60 * 1000),
+        _ => return Err(format!("invalid duration unit in `{}`", s)),
+    };
+    let d = millis
+        .map(Duration::from_millis)
+        .filter(|d| *d <= MAX_DURATION)
+        .ok_or_else(|| format!("duration `{}` is longer than a day", s))?;
+    Ok(if d.is_zero() { None } else { Some(d) })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proxy::Balance;
+
+    fn args(s: &str) -> Vec<String> {
+        s.split_whitespace().map(String::from).collect()
+    }
+
+    #[test]
+    fn defaults_are_valid() {
+        let config = Config::from_args(Vec::new()).unwrap();
+        assert_eq!(config, Config::default());
+    }
+
+    #[test]
+    fn parse_config_text() {
+        let mut config = Config::default();
+        let src = r#"
+# listen on two addresses
+bind = ["127.0.0.1:8080", "127.0.0.1:8081"]
+workers = 8
+max_workers = 32
+worker_keep_alive = "10s"
+doc_root = "src"   # relative to the config file
+queue_capacity = 0
+queue_policy = "caller_runs"
+
+[timeouts]
+read = "500ms"
+write = 0
+
+header = "2s"
+
+[limits]
+max_header_bytes = 16_384
+max_connections_per_ip = 0
+
+[log]
+level = "debug"
+access = "logs/access.log"
+format = "json"
+
+[compression]
+enabled = false
+
+[metrics]
+path = "/internal/metrics"
+
+[http2]
+enabled = false
+"#;
+        config.apply_str(src, Path::new("/srv")).unwrap();
+        assert_eq!(config.binds.len(), 2);
+        assert_eq!(config.binds[1].port(), 8081);
+        assert_eq!(config.workers, 8);
+        assert_eq!(config.max_workers, 32);
+        assert_eq!(config.worker_keep_alive, Duration::from_secs(10));
+        assert_eq!(config.queue_capacity, 0);
+        assert_eq!(config.queue_policy, QueuePolicy::CallerRuns);
+        assert_eq!(config.doc_root, PathBuf::from("/srv/src"));
+        assert_eq!(config.read_timeout, Some(Duration::from_millis(500)));
+        assert_eq!(config.write_timeout, None);
+        assert_eq!(config.header_timeout, Some(Duration::from_secs(2)));
+        assert_eq!(config.limits.max_header_bytes, 16384);
+        assert_eq!(config.max_connections_per_ip, 0);
+        assert_eq!(config.log_level, Level::Debug);
+        assert_eq!(
+            config.access_log,
+            LogTarget::File(PathBuf::from("/srv/logs/access.log"))
+        );
+        assert_eq!(config.access_log_format, LogFormat::Json);
+        assert!(!config.compression);
+        assert!(config.metrics);
+        assert_eq!(config.metrics_path, "/internal/metrics");
+        assert!(!config.http2);
+    }
+
+    #[test]
+    fn unknown_key_reports_line() {
+        let mut config = Config::default();
+        let err = config
+            .apply_str("workers = 2\n\nthreads = 3\n", Path::new("."))
+            .unwrap_err();
+        assert!(matches!(err, ConfigError::Parse { line: 3, .. }), "{}", err);
+    }
+
+    #[test]
+    fn cli_overrides_and_repeats_bind() {
+        let config = Config::from_args(args(
+            "--bind 127.0.0.1:0 -b 127.0.0.1:9000 --workers=2 --read-timeout 5s --http2 off \
+             --queue-capacity 16 --queue-policy block",
+        ))
+        .unwrap();
+        assert_eq!(config.binds.len(), 2);
+        assert_eq!(config.binds[0].port(), 0);
+        assert_eq!(config.workers, 2);
+        assert_eq!(config.queue_capacity, 16);
+        assert_eq!(config.queue_policy, QueuePolicy::Block);
+        assert_eq!(config.read_timeout, Some(Duration::from_secs(5)));
+        assert!(!config.http2);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn selects_epoll_backend() {
+        let config = Config::from_args(args("--backend epoll")).unwrap();
+        assert_eq!(config.backend, Backend::Epoll);
+        let mut config = Config::default();
+        config
+            .apply_str("backend = \"threads\"\n", Path::new("."))
+            .unwrap();
+        assert_eq!(config.backend, Backend::Threads);
+    }
+
+    #[test]
+    fn proxy_routes_from_file_and_cli() {
+        let mut config = Config::default();
+        let src = r#"
+[proxy]
+routes = ["/api=http://127.0.0.1:9001,http://127.0.0.1:9002", "/img=http://[::1]:9003"]
+balance = "least_connections"
+health_interval = "1s"
+retries = 2
+"#;
+        config.apply_str(src, Path::new(".")).unwrap();
+        assert_eq!(config.proxy.routes.len(), 2);
+        assert_eq!(config.proxy.routes[0].upstreams.len(), 2);
+        assert_eq!(config.proxy.balance, Balance::LeastConnections);
+        assert_eq!(config.proxy.health_interval, Duration::from_secs(1));
+        assert_eq!(config.proxy.retries, 2);
+
+        let config = Config::from_args(args(
+            "--proxy /a=http://127.0.0.1:1 --proxy=/b=http://127.0.0.1:2 --proxy-health-check /up",
+        ))
+        .unwrap();
+        let prefixes: Vec<_> = config.proxy.routes.iter().map(|r| &r.prefix).collect();
+        assert_eq!(prefixes, ["/a", "/b"]);
+        assert_eq!(config.proxy.health_check.as_deref(), Some("/up"));
+    }
+
+    #[test]
+    fn validation_errors() {
+        assert!(matches!(
+            Config::from_args(args("--workers 0")),
+            Err(ConfigError::Invalid(_))
+        ));
+        assert!(matches!(
+            Config::from_args(args("--doc-root /definitely/not/here")),
+            Err(ConfigError::Invalid(_))
+        ));
+        assert!(matches!(
+            Config::from_args(args("--bind nope")),
+            Err(ConfigError::Usage(_))
+        ));
+        assert!(matches!(
+            Config::from_args(args("--access-log-format xml")),
+            Err(ConfigError::Usage(_))
+        ));
+        assert!(matches!(
+            Config::from_args(args("--access-log /no/such/dir/access.log")),
+            Err(ConfigError::Invalid(_))
+        ));
+        assert!(matches!(
+            Config::from_args(args("--backend green-threads")),
+            Err(ConfigError::Usage(_))
+        ));
+        assert!(matches!(
+            Config::from_args(args("--queue-policy lifo")),
+            Err(ConfigError::Usage(_))
+        ));
+        assert!(matches!(
+            Config::from_args(args("--workers 8 --max-workers 4")),
+            Err(ConfigError::Invalid(_))
+        ));
+        assert!(matches!(
+            Config::from_args(args("--worker-keep-alive 0")),
+            Err(ConfigError::Usage(_))
+        ));
+        assert!(matches!(
+            Config::from_args(args("--read-timeout 18446744073709551615m")),
+            Err(ConfigError::Usage(_))
+        ));
+        for timeout in ["18446744073709551615", "86401s", "1441m"] {
+            assert!(matches!(
+                Config::from_args(args(&format!("--header-timeout {}", timeout))),
+                Err(ConfigError::Usage(_))
+            ));
+        }
+        assert_eq!(parse_duration("1440m"), Ok(Some(MAX_DURATION)));
+        let config = Config {
+            header_timeout: Some(Duration::MAX),
+            ..Config::default()
+        };
+        assert!(matches!(config.validate(), Err(ConfigError::Invalid(_))));
+        assert!(matches!(
+            Config::from_args(args("--proxy /api=ftp://127.0.0.1:21")),
+            Err(ConfigError::Usage(_))
+        ));
+        assert!(matches!(
+            Config::from_args(args("--metrics-path metrics")),
+            Err(ConfigError::Usage(_))
+        ));
+        assert!(matches!(
+            Config::from_args(args("--frobnicate")),
+            Err(ConfigError::Usage(_))
+        ));
+        assert!(matches!(
+            Config::from_args(args("--help")),
+            Err(ConfigError::Help)
+        ));
+    }
+}
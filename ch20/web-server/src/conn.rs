@@ -0,0 +1,186 @@
+/*
+连接级别的保护措施：
+    1、DeadlineReader：给读取设置一个绝对的截止时间。单纯的读超时只限制两次读取之间的间隔，
+       客户端每隔几秒发一个字节（slowloris 攻击）就能一直占住一个工作线程；截止时间限制的是总时长。
+    2、ConnLimiter：限制同一个 IP 同时打开的连接数，避免单个客户端占满线程池。
+*/
+
+use std::{
+    collections::HashMap,
+    io::{self, Read},
+    net::{IpAddr, TcpStream},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// 返回两个可选截止时间中较早的一个
+pub fn earliest(a: Option<Instant>, b: Option<Instant>) -> Option<Instant> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, None) => a,
+        (None, b) => b,
+    }
+}
+
+/// 超时错误在不同平台上可能是 TimedOut 也可能是 WouldBlock
+pub fn is_timeout(err: &io::Error) -> bool {
+    matches!(
+        err.kind(),
+        io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock
+    )
+}
+
+pub struct DeadlineReader {
+    stream: TcpStream,
+    read_timeout: Option<Duration>,
+    deadline: Option<Instant>,
+}
+
+impl DeadlineReader {
+    pub fn new(stream: TcpStream, read_timeout: Option<Duration>) -> DeadlineReader {
+        DeadlineReader {
+            stream,
+            read_timeout,
+            deadline: None,
+        }
+    }
+
+    pub fn set_deadline(&mut self, deadline: Option<Instant>) {
+        self.deadline = deadline;
+    }
+
+    pub fn get_ref(&self) -> &TcpStream {
+        &self.stream
+    }
+
+    pub fn into_inner(self) -> TcpStream {
+        self.stream
+    }
+}
+
+impl Read for DeadlineReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut timeout = self.read_timeout;
+        if let Some(deadline) = self.deadline {
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(io::Error::new(io::ErrorKind::TimedOut, "deadline exceeded"));
+            }
+            let left = deadline - now;
+            timeout = Some(timeout.map_or(left, |t| t.min(left)));
+        }
+        self.stream.set_read_timeout(timeout)?;
+        self.stream.read(buf)
+    }
+}
+
+/// 按 IP 统计并发连接数
+/// 克隆出来的 ConnLimiter 共享同一份计数
+#[derive(Clone)]
+pub struct ConnLimiter {
+    max_per_ip: usize,
+    counts: Arc<Mutex<HashMap<IpAddr, usize>>>,
+}
+
+impl ConnLimiter {
+    /// max_per_ip 为 0 表示不限制
+    pub fn new(max_per_ip: usize) -> ConnLimiter {
+        ConnLimiter {
+            max_per_ip,
+            counts: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// 占用一个名额，超过上限时返回 None；返回的 guard 被丢弃时归还名额
+    pub fn try_acquire(&self, ip: IpAddr) -> Option<ConnGuard> {
+        let mut counts = self.counts.lock().unwrap();
+        let count = counts.entry(ip).or_insert(0);
+        if self.max_per_ip > 0 && *count >= self.max_per_ip {
+            return None;
+        }
+        *count += 1;
+        Some(ConnGuard {
+            ip,
+            counts: Arc::clone(&self.counts),
+        })
+    }
+
+    pub fn active(&self, ip: IpAddr) -> usize {
+        self.counts.lock().unwrap().get(&ip).copied().unwrap_or(0)
+    }
+
+    /// 所有 IP 当前打开的连接总数
+    pub fn total(&self) -> usize {
+        self.counts.lock().unwrap().values().sum()
+    }
+}
+
+pub struct ConnGuard {
+    ip: IpAddr,
+    counts: Arc<Mutex<HashMap<IpAddr, usize>>>,
+}
+
+impl Drop for ConnGuard {
+    fn drop(&mut self) {
+        let mut counts = self.counts.lock().unwrap();
+        if let Some(count) = counts.get_mut(&self.ip) {
+            *count -= 1;
+            // 计数归零后移除，避免 HashMap 随着访问过的 IP 无限增长
+            if *count == 0 {
+                counts.remove(&self.ip);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{io::Write, net::TcpListener, thread};
+
+    #[test]
+    fn limiter_releases_on_drop() {
+        let limiter = ConnLimiter::new(2);
+        let ip: IpAddr = "10.0.0.1".parse().unwrap();
+        let a = limiter.try_acquire(ip).unwrap();
+        let _b = limiter.try_acquire(ip).unwrap();
+        assert!(limiter.try_acquire(ip).is_none());
+        assert!(limiter.try_acquire("10.0.0.2".parse().unwrap()).is_some());
+        drop(a);
+        assert_eq!(limiter.active(ip), 1);
+        assert_eq!(limiter.clone().total(), 1);
+        assert!(limiter.try_acquire(ip).is_some());
+    }
+
+    #[test]
+    fn deadline_stops_trickling_reads() {
+        let ln = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = ln.local_addr().unwrap();
+        let client = thread::spawn(move || {
+            let mut s = TcpStream::connect(addr).unwrap();
+            // 每 50ms 发一个字节，单次读超时永远不会触发
+            for _ in 0..40 {
+                if s.write_all(b"x").is_err() {
+                    break;
+                }
+                thread::sleep(Duration::from_millis(50));
+            }
+        });
+        let (stream, _) = ln.accept().unwrap();
+        let mut reader = DeadlineReader::new(stream, Some(Duration::from_secs(1)));
+        let started = Instant::now();
+        reader.set_deadline(Some(started + Duration::from_millis(300)));
+        let mut buf = [0; 16];
+        let err = loop {
+            match reader.read(&mut buf) {
+                Ok(0) => panic!("unexpected eof"),
+                Ok(_) => continue,
+                Err(err) => break err,
+            }
+        };
+        assert!(is_timeout(&err), "{:?}", err);
+        assert!(started.elapsed() < Duration::from_millis(900));
+        drop(reader);
+        client.join().unwrap();
+    }
+}
@@ -0,0 +1,926 @@
+/*
+事件循环后端（仅 Linux）：
+    线程池后端里一个连接从建立到关闭都占着一个工作线程，空闲的 keep-alive 连接也不例外，
+    所以能同时服务的连接数等于线程数。这里改用 epoll：所有 socket 都是非阻塞的，
+    由一个线程等待读写就绪事件，每个连接是一个小的状态机：
+
+        Head ──收齐请求头──▶ Body ──收齐请求体──▶ Handling ──handler 返回──▶ Writing
+         ▲                                                                    │
+         └─────────────────────────── 写完并且保持连接 ───────────────────────┘
+
+    handler 的接口仍然是阻塞的，所以 Handling 阶段把请求交给线程池执行，
+    结果通过 channel 送回事件循环，再往一对 UnixStream 里写一个字节唤醒 epoll_wait。
+    空闲连接只占一个文件描述符和一点缓冲区。各种截止时间放在一个小根堆里，
+    过期的条目在弹出时和连接当前的截止时间比较，不一致就说明已经作废，直接丢弃。
+
+    设置了 AsyncHandler 时，请求不再交给线程池，而是作为任务交给事件循环线程上的 Executor，
+    异步 I/O 和定时器通过同一个 epoll 上的 Reactor 唤醒任务，见 runtime 模块。
+    带 Upgrade 的响应写完之后，连接从 epoll 中移除、恢复为阻塞模式，交给 Upgrade 接管。
+    HTTP/2 的连接（连接前言或者 Upgrade: h2c）也是这样交给 http2 模块的连接线程。
+*/
+
+use crate::{
+    ThreadPool,
+    access_log::Record,
+    conn::{ConnGuard, earliest},
+    debug, error,
+    http::{HttpError, Limits, Request, Response, Upgrade},
+    http2,
+    runtime::{CatchUnwind, Executor},
+    server::{self, Shared},
+    warn,
+};
+use std::{
+    cell::{Cell, RefCell},
+    cmp::Reverse,
+    collections::{BinaryHeap, HashMap},
+    io::{self, Read, Write},
+    mem,
+    net::{SocketAddr, TcpListener, TcpStream},
+    os::{
+        fd::{AsRawFd, FromRawFd, OwnedFd, RawFd},
+        unix::net::UnixStream,
+    },
+    rc::Rc,
+    sync::{Arc, mpsc},
+    task::Waker,
+    time::{Duration, Instant, SystemTime},
+};
+
+/// epoll 的系统调用声明，标准库已经链接了 libc，这里只需要声明函数签名
+mod sys {
+    use std::os::raw::c_int;
+
+    pub const EPOLL_CLOEXEC: c_int = 0o2000000;
+    pub const EPOLL_CTL_ADD: c_int = 1;
+    pub const EPOLL_CTL_DEL: c_int = 2;
+    pub const EPOLL_CTL_MOD: c_int = 3;
+    pub const EPOLLIN: u32 = 0x001;
+    pub const EPOLLOUT: u32 = 0x004;
+    pub const EPOLLERR: u32 = 0x008;
+    pub const EPOLLHUP: u32 = 0x010;
+
+    /// 内核中的 struct epoll_event，在 x86_64 上是 packed 的
+    #[cfg_attr(target_arch = "x86_64", repr(C, packed))]
+    #[cfg_attr(not(target_arch = "x86_64"), repr(C))]
+    #[derive(Clone, Copy)]
+    pub struct EpollEvent {
+        pub events: u32,
+        pub data: u64,
+    }
+
+    unsafe extern "C" {
+        pub fn epoll_create1(flags: c_int) -> c_int;
+        pub fn epoll_ctl(epfd: c_int, op: c_int, fd: c_int, event: *mut EpollEvent) -> c_int;
+        pub fn epoll_wait(
+            epfd: c_int,
+            events: *mut EpollEvent,
+            maxevents: c_int,
+            timeout: c_int,
+        ) -> c_int;
+    }
+}
+
+const NONE: u32 = 0;
+pub(crate) const READ: u32 = sys::EPOLLIN;
+pub(crate) const WRITE: u32 = sys::EPOLLOUT;
+
+/// 对 epoll 文件描述符的简单封装，使用水平触发
+struct Epoll {
+    fd: OwnedFd,
+}
+
+impl Epoll {
+    fn new() -> io::Result<Epoll> {
+        let fd = unsafe { sys::epoll_create1(sys::EPOLL_CLOEXEC) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // fd 是刚创建出来的，没有其他所有者
+        Ok(Epoll {
+            fd: unsafe { OwnedFd::from_raw_fd(fd) },
+        })
+    }
+
+    fn add(&self, fd: RawFd, token: u64, interest: u32) -> io::Result<()> {
+        self.ctl(sys::EPOLL_CTL_ADD, fd, token, interest)
+    }
+
+    fn modify(&self, fd: RawFd, token: u64, interest: u32) -> io::Result<()> {
+        self.ctl(sys::EPOLL_CTL_MOD, fd, token, interest)
+    }
+
+    // 连接关闭文件描述符时内核会自动把它从 epoll 中移除，只有异步 I/O 需要显式删除
+    fn delete(&self, fd: RawFd) -> io::Result<()> {
+        self.ctl(sys::EPOLL_CTL_DEL, fd, 0, NONE)
+    }
+
+    fn ctl(&self, op: i32, fd: RawFd, token: u64, interest: u32) -> io::Result<()> {
+        let mut event = sys::EpollEvent {
+            events: interest,
+            data: token,
+        };
+        if unsafe { sys::epoll_ctl(self.fd.as_raw_fd(), op, fd, &mut event) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    /// 等待就绪事件，timeout 为 None 时一直等待；返回就绪事件的个数
+    fn wait(&self, events: &mut [sys::EpollEvent], timeout: Option<Duration>) -> io::Result<usize> {
+        // 向上取整，避免截止时间还差不到 1ms 时反复空转
+        let timeout = timeout.map_or(-1, |t| {
+            t.as_nanos().div_ceil(1_000_000).min(i32::MAX as u128) as i32
+        });
+        let n = unsafe {
+            sys::epoll_wait(
+                self.fd.as_raw_fd(),
+                events.as_mut_ptr(),
+                events.len() as i32,
+                timeout,
+            )
+        };
+        if n < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(n as usize)
+    }
+}
+
+/// 异步 I/O 的 token 设置最高位，和连接的 token 区分开
+const IO_TOKEN: u64 = 1 << 63;
+
+thread_local! {
+    /// 事件循环线程上的反应器，runtime 中的 Future 通过它登记 Waker
+    static CURRENT: RefCell<Option<Rc<Reactor>>> = const { RefCell::new(None) };
+}
+
+/// 取得当前线程的反应器
+///
+/// # Panics
+///
+/// 不在事件循环线程上调用时会 panic
+pub(crate) fn with_reactor<R>(f: impl FnOnce(&Reactor) -> R) -> R {
+    let reactor = CURRENT.with(|cur| cur.borrow().clone());
+    f(&reactor.expect("async I/O must be polled on the event loop thread"))
+}
+
+/// 和 with_reactor 一样，但不在事件循环线程上时什么也不做，用在 Drop 中
+pub(crate) fn try_with_reactor(f: impl FnOnce(&Reactor)) {
+    if let Some(reactor) = CURRENT.try_with(|cur| cur.borrow().clone()).ok().flatten() {
+        f(&reactor);
+    }
+}
+
+/// 等待文件描述符就绪的 Waker，就绪之后 waker 被取走，等待 Future 下一次轮询时移除
+struct IoWaiter {
+    fd: RawFd,
+    waker: Option<Waker>,
+}
+
+/// 反应器：保存异步 I/O 和定时器登记的 Waker，事件循环在它们就绪或到期时调用
+pub(crate) struct Reactor {
+    epoll: Epoll,
+    pool: Arc<ThreadPool>,
+    next_token: Cell<u64>,
+    io: RefCell<HashMap<u64, IoWaiter>>,
+    timers: RefCell<BinaryHeap<Reverse<(Instant, u64)>>>,
+    timer_wakers: RefCell<HashMap<u64, Waker>>,
+}
+
+impl Reactor {
+    fn new(epoll: Epoll, pool: Arc<ThreadPool>) -> Reactor {
+        Reactor {
+            epoll,
+            pool,
+            next_token: Cell::new(IO_TOKEN),
+            io: RefCell::new(HashMap::new()),
+            timers: RefCell::new(BinaryHeap::new()),
+            timer_wakers: RefCell::new(HashMap::new()),
+        }
+    }
+
+    pub(crate) fn pool(&self) -> &Arc<ThreadPool> {
+        &self.pool
+    }
+
+    fn token(&self) -> u64 {
+        let token = self.next_token.get();
+        self.next_token.set(token + 1);
+        token
+    }
+
+    pub(crate) fn register_io(&self, fd: RawFd, interest: u32, waker: Waker) -> io::Result<u64> {
+        let token = self.token();
+        self.epoll.add(fd, token, interest)?;
+        let waiter = IoWaiter {
+            fd,
+            waker: Some(waker),
+        };
+        self.io.borrow_mut().insert(token, waiter);
+        Ok(token)
+    }
+
+    /// 登记的事件是否已经发生；还没有发生时更新 Waker
+    pub(crate) fn poll_io(&self, token: u64, waker: &Waker) -> bool {
+        let mut io = self.io.borrow_mut();
+        match io.get_mut(&token) {
+            Some(IoWaiter {
+                waker: Some(current),
+                ..
+            }) => {
+                current.clone_from(waker);
+                false
+            }
+            _ => {
+                io.remove(&token);
+                true
+            }
+        }
+    }
+
+    pub(crate) fn cancel_io(&self, token: u64) {
+        let waiter = self.io.borrow_mut().remove(&token);
+        if let Some(IoWaiter { fd, waker: Some(_) }) = waiter {
+            let _ = self.epoll.delete(fd);
+        }
+    }
+
+    fn fire_io(&self, token: u64) {
+        let waker = match self.io.borrow_mut().get_mut(&token) {
+            Some(waiter) => {
+                let _ = self.epoll.delete(waiter.fd);
+                waiter.waker.take()
+            }
+            None => None,
+        };
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+
+    pub(crate) fn add_timer(&self, at: Instant, waker: Waker) -> u64 {
+        let id = self.token();
+        self.timers.borrow_mut().push(Reverse((at, id)));
+        self.timer_wakers.borrow_mut().insert(id, waker);
+        id
+    }
+
+    pub(crate) fn update_timer(&self, id: u64, waker: &Waker) {
+        if let Some(current) = self.timer_wakers.borrow_mut().get_mut(&id) {
+            current.clone_from(waker);
+        }
+    }
+
+    pub(crate) fn cancel_timer(&self, id: u64) {
+        // 堆中的条目留到到期时再丢弃
+        self.timer_wakers.borrow_mut().remove(&id);
+    }
+
+    fn next_timer(&self) -> Option<Instant> {
+        self.timers.borrow().peek().map(|Reverse((at, _))| *at)
+    }
+
+    fn fire_timers(&self, now: Instant) {
+        loop {
+            let id = match self.timers.borrow_mut().peek() {
+                Some(&Reverse((at, id))) if at <= now => id,
+                _ => return,
+            };
+            self.timers.borrow_mut().pop();
+            let waker = self.timer_wakers.borrow_mut().remove(&id);
+            if let Some(waker) = waker {
+                waker.wake();
+            }
+        }
+    }
+}
+
+enum State {
+    /// 等待请求行和头部
+    Head,
+    /// 头部已经解析，等待请求体收齐
+    Body {
+        req: Box<Request>,
+        head_len: usize,
+        body_len: usize,
+    },
+    /// 请求正在线程池中处理
+    Handling,
+    /// 正在发送响应
+    Writing,
+}
+
+struct Conn {
+    stream: TcpStream,
+    peer: SocketAddr,
+    guard: ConnGuard,
+    state: State,
+    interest: u32,
+    /// 已经读到但还没有处理的字节，可能包含客户端流水线发送的下一个请求
+    buf: Vec<u8>,
+    out: Vec<u8>,
+    written: usize,
+    keep_alive: bool,
+    /// 处理完一个请求之后、下一个请求的第一个字节到来之前为 true
+    idle: bool,
+    conn_deadline: Option<Instant>,
+    deadline: Option<Instant>,
+    /// 当前响应写完之后接管连接
+    upgrade: Option<Upgrade>,
+}
+
+/// 线程池处理完的请求
+struct Completion {
+    token: u64,
+    req: Request,
+    resp: Response,
+}
+
+struct EventLoop {
+    reactor: Rc<Reactor>,
+    executor: Executor,
+    listeners: Vec<TcpListener>,
+    /// 唤醒管道的读端和写端
+    wake_rx: UnixStream,
+    wake_tx: Arc<UnixStream>,
+    done_tx: mpsc::Sender<Completion>,
+    done_rx: mpsc::Receiver<Completion>,
+    conns: HashMap<u64, Conn>,
+    timers: BinaryHeap<Reverse<(Instant, u64)>>,
+    next_token: u64,
+    pool: Arc<ThreadPool>,
+    shared: Arc<Shared>,
+}
+
+/// 运行事件循环，只有 epoll 本身出错时才会返回
+pub(crate) fn run(
+    listeners: Vec<TcpListener>,
+    pool: Arc<ThreadPool>,
+    shared: Arc<Shared>,
+) -> io::Result<()> {
+    let mut el = EventLoop::new(listeners, pool, shared)?;
+    CURRENT.with(|cur| *cur.borrow_mut() = Some(Rc::clone(&el.reactor)));
+    let mut events = vec![sys::EpollEvent { events: 0, data: 0 }; 1024];
+    loop {
+        let timeout = el.next_timeout();
+        let n = match el.reactor.epoll.wait(&mut events, timeout) {
+            Ok(n) => n,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        };
+        for ev in &events[..n] {
+            let (token, flags) = (ev.data, ev.events);
+            el.dispatch(token, flags);
+        }
+        el.expire(Instant::now());
+        el.run_tasks();
+    }
+}
+
+impl EventLoop {
+    fn new(
+        listeners: Vec<TcpListener>,
+        pool: Arc<ThreadPool>,
+        shared: Arc<Shared>,
+    ) -> io::Result<EventLoop> {
+        let epoll = Epoll::new()?;
+        // 监听 socket 的 token 是它们的下标，唤醒管道紧随其后，连接的 token 从更大的数开始
+        for (i, ln) in listeners.iter().enumerate() {
+            ln.set_nonblocking(true)?;
+            epoll.add(ln.as_raw_fd(), i as u64, READ)?;
+        }
+        let (wake_rx, wake_tx) = UnixStream::pair()?;
+        wake_rx.set_nonblocking(true)?;
+        wake_tx.set_nonblocking(true)?;
+        let waker = listeners.len() as u64;
+        epoll.add(wake_rx.as_raw_fd(), waker, READ)?;
+        let (done_tx, done_rx) = mpsc::channel();
+        let wake_tx = Arc::new(wake_tx);
+        let notify = Arc::clone(&wake_tx);
+        // 任务可能在其他线程上被唤醒（例如 spawn_blocking），需要把事件循环从 epoll_wait 中叫醒
+        let executor = Executor::new(move || {
+            let _ = (&*notify).write(&[1]);
+        });
+        Ok(EventLoop {
+            reactor: Rc::new(Reactor::new(epoll, Arc::clone(&pool))),
+            executor,
+            listeners,
+            wake_rx,
+            wake_tx,
+            done_tx,
+            done_rx,
+            conns: HashMap::new(),
+            timers: BinaryHeap::new(),
+            next_token: waker + 1,
+            pool,
+            shared,
+        })
+    }
+
+    fn dispatch(&mut self, token: u64, flags: u32) {
+        if token >= IO_TOKEN {
+            self.reactor.fire_io(token);
+            return;
+        }
+        let waker = self.listeners.len() as u64;
+        if token < waker {
+            self.accept(token as usize);
+            return;
+        }
+        if token == waker {
+            let mut buf = [0; 256];
+            while let Ok(n) = (&self.wake_rx).read(&mut buf) {
+                if n == 0 {
+                    break;
+                }
+            }
+            return;
+        }
+        let Some(conn) = self.conns.get(&token) else {
+            return;
+        };
+        // 出错或者挂断时继续读写，由读写的结果决定怎么关闭连接
+        let broken = flags & (sys::EPOLLERR | sys::EPOLLHUP) != 0;
+        match conn.state {
+            State::Head | State::Body { .. } if broken || flags & READ != 0 => self.readable(token),
+            State::Writing if broken || flags & WRITE != 0 => self.flush(token),
+            // 处理期间不关注任何事件，但 ERR/HUP 总会上报；对端已经完全关闭，响应也没法送达了
+            State::Handling if broken => self.close(token),
+            _ => {}
+        }
+    }
+
+    fn accept(&mut self, idx: usize) {
+        loop {
+            match self.listeners[idx].accept() {
+                Ok((stream, peer)) => self.register(stream, peer),
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => return,
+                Err(err) => {
+                    warn!("accept error: {}", err);
+                    return;
+                }
+            }
+        }
+    }
+
+    fn register(&mut self, mut stream: TcpStream, peer: SocketAddr) {
+        if stream.set_nonblocking(true).is_err() {
+            return;
+        }
+        let guard = match self.shared.limiter.try_acquire(peer.ip()) {
+            Some(guard) => guard,
+            None => {
+                debug!("too many connections from {}", peer.ip());
+                // 响应很小，一次就能写进 socket 缓冲区，写不进去也无所谓
+                let resp = Response::error(429).with_header("Connection", "close");
+                let _ = resp.write_to(&mut stream);
+                return;
+            }
+        };
+        let token = self.next_token;
+        self.next_token += 1;
+        if let Err(err) = self.reactor.epoll.add(stream.as_raw_fd(), token, READ) {
+            warn!("epoll add: {}", err);
+            return;
+        }
+        debug!("Connection established!, remote addr: {}", peer);
+        let config = &self.shared.config;
+        let now = Instant::now();
+        let conn_deadline = config.connection_timeout.and_then(|t| now.checked_add(t));
+        let header_deadline = config.header_timeout.and_then(|t| now.checked_add(t));
+        self.conns.insert(
+            token,
+            Conn {
+                stream,
+                peer,
+                guard,
+                state: State::Head,
+                interest: READ,
+                buf: Vec::new(),
+                out: Vec::new(),
+                written: 0,
+                keep_alive: false,
+                idle: false,
+                conn_deadline,
+                deadline: None,
+                upgrade: None,
+            },
+        );
+        self.set_deadline(token, earliest(header_deadline, conn_deadline));
+    }
+
+    fn readable(&mut self, token: u64) {
+        let conn = self.conns.get_mut(&token).unwrap();
+        let eof = match read_some(&mut conn.stream, &mut conn.buf) {
+            Ok(eof) => eof,
+            Err(_) => return self.close(token),
+        };
+        if conn.idle && !conn.buf.is_empty() {
+            // 下一个请求开始了，从这里开始计算读取请求头的时间
+            conn.idle = false;
+            let header_deadline = self
+                .shared
+                .config
+                .header_timeout
+                .and_then(|t| Instant::now().checked_add(t));
+            let deadline = earliest(header_deadline, conn.conn_deadline);
+            self.set_deadline(token, deadline);
+        }
+        self.advance(token);
+        if eof && let Some(conn) = self.conns.get(&token) {
+            match conn.state {
+                State::Head if !conn.buf.is_empty() => {
+                    let err = HttpError::BadRequest("unexpected eof in headers");
+                    self.fail(token, &err);
+                }
+                State::Head | State::Body { .. } => self.close(token),
+                _ => {}
+            }
+        }
+    }
+
+    /// 尝试从缓冲区中解析出一个完整的请求，成功就交给线程池
+    fn advance(&mut self, token: u64) {
+        let conn = self.conns.get_mut(&token).unwrap();
+        let limits = &self.shared.config.limits;
+        let head_parsed = matches!(conn.state, State::Head);
+        let req = match parse(conn, limits) {
+            Ok(Some(req)) => req,
+            Ok(None) => {
+                if head_parsed && matches!(conn.state, State::Body { .. }) {
+                    // 请求体只受连接总时长限制，和线程池后端保持一致
+                    let deadline = conn.conn_deadline;
+                    self.set_deadline(token, deadline);
+                }
+                return;
+            }
+            Err(err) => return self.fail(token, &err),
+        };
+        if !self.set_interest(token, NONE) {
+            return;
+        }
+        self.set_deadline(token, None);
+
+        let mut req = req;
+        let conn = &self.conns[&token];
+        req.remote_addr = Some(conn.peer);
+        debug!("Request: {} {} {}", req.method, req.target, req.version);
+        if http2::is_preface(&req) {
+            return match http2::accept_preface(conn.peer, &self.shared) {
+                Some(upgrade) => self.hand_off(token, upgrade),
+                None => self.fail(token, &HttpError::BadRequest("unsupported http version")),
+            };
+        }
+        if let Some(resp) = http2::accept_upgrade(&req, conn.peer, &self.shared) {
+            return self.respond(token, &resp, false);
+        }
+        if self.shared.async_handler.is_some() {
+            return self.spawn_async(token, req);
+        }
+        let shared = Arc::clone(&self.shared);
+        let done = self.done_tx.clone();
+        let waker = Arc::clone(&self.wake_tx);
+        // 不能用线程池配置的策略：Block 会卡住事件循环，CallerRuns 会让 handler 跑在事件循环线程上，
+        // 两种情况下所有连接都停下来，所以队列满时直接回 503
+        let submitted = self.pool.try_execute(move || {
+            let resp = shared.handle(&mut req);
+            let _ = done.send(Completion { token, req, resp });
+            // 管道写满说明事件循环已经有待处理的唤醒了，忽略错误即可
+            let _ = (&*waker).write(&[1]);
+        });
+        if let Err(err) = submitted {
+            warn!("cannot handle request: {}", err);
+            self.respond(token, &Response::error(503), false);
+        }
+    }
+
+    /// 把请求交给异步 handler，在事件循环线程上作为任务运行
+    fn spawn_async(&mut self, token: u64, mut req: Request) {
+        // 请求按值交给 handler，留一份不含请求体的副本用于访问日志和 keep-alive 判断
+        let body = mem::take(&mut req.body);
+        let meta = req.clone();
+        req.body = body;
+        let shared = Arc::clone(&self.shared);
+        let done = self.done_tx.clone();
+        self.executor.spawn(async move {
+            let started = SystemTime::now();
+            let timer = Instant::now();
+            let handler = shared.async_handler.as_ref().unwrap();
+            let resp = match CatchUnwind(handler.call(req)).await {
+                Ok(resp) => resp,
+                Err(_) => {
+                    error!("async handler panicked: {} {}", meta.method, meta.target);
+                    Response::error(500)
+                }
+            };
+            let record = Record::new(Some(&meta), &resp, started, timer.elapsed());
+            shared.access_log.record(&record);
+            let _ = done.send(Completion {
+                token,
+                req: meta,
+                resp,
+            });
+        });
+    }
+
+    /// 轮询就绪的异步任务，然后收取所有处理完的请求，开始发送响应
+    fn run_tasks(&mut self) {
+        self.executor.run_ready();
+        while let Ok(Completion {
+            token,
+            req,
+            mut resp,
+        }) = self.done_rx.try_recv()
+        {
+            // 处理期间对端断开的连接已经被移除了
+            let Some(conn) = self.conns.get(&token) else {
+                continue;
+            };
+            let keep_alive =
+                resp.upgrade.is_none() && server::finish(&req, &mut resp, conn.conn_deadline);
+            self.respond(token, &resp, keep_alive);
+        }
+    }
+
+    /// 请求无法解析：记录访问日志，回复错误后关闭连接
+    fn fail(&mut self, token: u64, err: &HttpError) {
+        let peer = self.conns[&token].peer;
+        match server::reject(&self.shared, peer, err) {
+            Some(resp) => self.respond(token, &resp, false),
+            None => self.close(token),
+        }
+    }
+
+    fn respond(&mut self, token: u64, resp: &Response, keep_alive: bool) {
+        let conn = self.conns.get_mut(&token).unwrap();
+        conn.out.clear();
+        // 写入 Vec 不会失败
+        let _ = resp.write_to(&mut conn.out);
+        conn.written = 0;
+        conn.keep_alive = keep_alive;
+        conn.upgrade = resp.upgrade.clone();
+        conn.state = State::Writing;
+        self.flush(token);
+    }
+
+    /// 尽可能多地发送响应，socket 缓冲区满了就等待可写事件
+    fn flush(&mut self, token: u64) {
+        let conn = self.conns.get_mut(&token).unwrap();
+        while conn.written < conn.out.len() {
+            match conn.stream.write(&conn.out[conn.written..]) {
+                Ok(0) => return self.close(token),
+                Ok(n) => conn.written += n,
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => {
+                    let write_deadline = self
+                        .shared
+                        .config
+                        .write_timeout
+                        .and_then(|t| Instant::now().checked_add(t));
+                    if self.set_interest(token, WRITE) {
+                        self.set_deadline(token, write_deadline);
+                    }
+                    return;
+                }
+                Err(_) => return self.close(token),
+            }
+        }
+        if let Some(upgrade) = conn.upgrade.take() {
+            return self.hand_off(token, upgrade);
+        }
+        if !conn.keep_alive {
+            return self.close(token);
+        }
+
+        conn.state = State::Head;
+        conn.out.clear();
+        let config = &self.shared.config;
+        let now = Instant::now();
+        let deadline = if conn.buf.is_empty() {
+            conn.idle = true;
+            earliest(
+                config.keep_alive_timeout.and_then(|t| now.checked_add(t)),
+                conn.conn_deadline,
+            )
+        } else {
+            earliest(
+                config.header_timeout.and_then(|t| now.checked_add(t)),
+                conn.conn_deadline,
+            )
+        };
+        let pipelined = !conn.buf.is_empty();
+        if !self.set_interest(token, READ) {
+            return;
+        }
+        self.set_deadline(token, deadline);
+        if pipelined {
+            self.advance(token);
+        }
+    }
+
+    /// 把连接交给 Upgrade，之后事件循环不再管理它
+    fn hand_off(&mut self, token: u64, upgrade: Upgrade) {
+        let conn = self.conns.remove(&token).unwrap();
+        let stream = conn.stream;
+        if let Err(err) = self
+            .reactor
+            .epoll
+            .delete(stream.as_raw_fd())
+            .and_then(|_| stream.set_nonblocking(false))
+        {
+            warn!("hand off connection from {}: {}", conn.peer, err);
+            return;
+        }
+        upgrade.run(stream, conn.buf, conn.guard);
+    }
+
+    fn close(&mut self, token: u64) {
+        // 丢弃 TcpStream 会关闭文件描述符，同时归还 ConnGuard 占用的名额
+        self.conns.remove(&token);
+    }
+
+    /// 返回 false 表示修改失败，连接已经被关闭，调用方不能再访问它
+    fn set_interest(&mut self, token: u64, interest: u32) -> bool {
+        let conn = self.conns.get_mut(&token).unwrap();
+        if conn.interest == interest {
+            return true;
+        }
+        conn.interest = interest;
+        if let Err(err) = self
+            .reactor
+            .epoll
+            .modify(conn.stream.as_raw_fd(), token, interest)
+        {
+            warn!("epoll modify: {}", err);
+            self.close(token);
+            return false;
+        }
+        true
+    }
+
+    fn set_deadline(&mut self, token: u64, deadline: Option<Instant>) {
+        if let Some(conn) = self.conns.get_mut(&token) {
+            conn.deadline = deadline;
+            if let Some(at) = deadline {
+                self.timers.push(Reverse((at, token)));
+            }
+        }
+    }
+
+    fn next_timeout(&self) -> Option<Duration> {
+        let conn_timer = self.timers.peek().map(|Reverse((at, _))| *at);
+        earliest(conn_timer, self.reactor.next_timer())
+            .map(|at| at.saturating_duration_since(Instant::now()))
+    }
+
+    /// 处理所有已经过期的截止时间
+    fn expire(&mut self, now: Instant) {
+        self.reactor.fire_timers(now);
+        while let Some(&Reverse((at, token))) = self.timers.peek() {
+            if at > now {
+                break;
+            }
+            self.timers.pop();
+            let conn = match self.conns.get(&token) {
+                Some(conn) if conn.deadline == Some(at) => conn,
+                _ => continue,
+            };
+            match conn.state {
+                // 两个请求之间空闲超时，直接关闭，不需要响应
+                State::Head if conn.idle => self.close(token),
+                State::Head | State::Body { .. } => {
+                    debug!("request from {} timed out", conn.peer);
+                    self.fail(token, &HttpError::Timeout);
+                }
+                State::Writing => self.close(token),
+                State::Handling => {}
+            }
+        }
+    }
+}
+
+/// 读取 socket 中已经到达的数据，返回对端是否已经关闭
+///
+/// 每次最多读取 64KB，剩下的留给下一轮事件，避免一个发送很快的客户端一直占着事件循环
+fn read_some(stream: &mut TcpStream, buf: &mut Vec<u8>) -> io::Result<bool> {
+    let mut chunk = [0; 4096];
+    for _ in 0..16 {
+        match stream.read(&mut chunk) {
+            Ok(0) => return Ok(true),
+            Ok(n) => buf.extend_from_slice(&chunk[..n]),
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => break,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(false)
+}
+
+/// 缓冲区里的数据足够时解析出一个完整的请求，并从缓冲区中移除它
+fn parse(conn: &mut Conn, limits: &Limits) -> Result<Option<Request>, HttpError> {
+    if let State::Head = conn.state {
+        let head_len = match head_len(&conn.buf) {
+            Some(len) => len,
+            None if conn.buf.len() > limits.max_header_bytes => {
+                return Err(HttpError::HeaderTooLarge);
+            }
+            None => return Ok(None),
+        };
+        // 头部已经完整地在内存里了，复用阻塞版本的解析代码
+        let req = Request::read_head(&mut &conn.buf[..head_len], limits)?;
+        let body_len = body_len(&req, limits);
+        conn.state = State::Body {
+            req: Box::new(req),
+            head_len,
+            body_len,
+        };
+    }
+    if let State::Body {
+        head_len, body_len, ..
+    } = conn.state
+    {
+        if conn.buf.len() < head_len + body_len {
+            return Ok(None);
+        }
+        let State::Body { mut req, .. } = mem::replace(&mut conn.state, State::Handling) else {
+            unreachable!()
+        };
+        req.read_body(&mut &conn.buf[head_len..], limits)?;
+        conn.buf.drain(..head_len + body_len);
+        return Ok(Some(*req));
+    }
+    Ok(None)
+}
+
+/// 请求行和头部的总长度（包含结尾的空行），数据还不完整时返回 None
+fn head_len(buf: &[u8]) -> Option<usize> {
+    let mut start = 0;
+    let mut seen_request_line = false;
+    while let Some(pos) = buf[start..].iter().position(|&b| b == b'\n') {
+        let line = &buf[start..start + pos];
+        start += pos + 1;
+        let empty = line.is_empty() || line == b"\r";
+        // 请求行之前的空行会被忽略
+        if empty && seen_request_line {
+            return Some(start);
+        }
+        seen_request_line |= !empty;
+    }
+    None
+}
+
+/// 需要等待的请求体长度；不合法的 Content-Length 或 Transfer-Encoding 返回 0，
+/// 交给 read_body 报告错误
+fn body_len(req: &Request, limits: &Limits) -> usize {
+    if req.headers.contains("Transfer-Encoding") {
+        return 0;
+    }
+    match req.header("Content-Length").map(str::parse::<usize>) {
+        Some(Ok(len)) if len <= limits.max_body_bytes => len,
+        _ => 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_end_of_head() {
+        assert_eq!(head_len(b"GET / HTTP/1.1\r\nHost: x\r\n"), None);
+        assert_eq!(head_len(b"GET / HTTP/1.1\r\nHost: x\r\n\r\nbody"), Some(27));
+        assert_eq!(head_len(b"\r\n\r\nGET / HTTP/1.1\n\n"), Some(20));
+    }
+
+    #[test]
+    fn body_len_defers_invalid_values_to_read_body() {
+        let limits = Limits::default();
+        let mut req = Request::new("POST", "/");
+        assert_eq!(body_len(&req, &limits), 0);
+        req.headers.set("Content-Length", "5");
+        assert_eq!(body_len(&req, &limits), 5);
+        req.headers.set("Content-Length", "nope");
+        assert_eq!(body_len(&req, &limits), 0);
+        assert!(req.read_body(&mut &b""[..], &limits).is_err());
+    }
+
+    #[test]
+    fn epoll_reports_readable_sockets() {
+        let epoll = Epoll::new().unwrap();
+        let (a, mut b) = UnixStream::pair().unwrap();
+        epoll.add(a.as_raw_fd(), 7, READ).unwrap();
+        let mut events = [sys::EpollEvent { events: 0, data: 0 }; 4];
+        let n = epoll
+            .wait(&mut events, Some(Duration::from_millis(10)))
+            .unwrap();
+        assert_eq!(n, 0);
+        b.write_all(b"x").unwrap();
+        let n = epoll
+            .wait(&mut events, Some(Duration::from_secs(1)))
+            .unwrap();
+        assert_eq!(n, 1);
+        let (token, flags) = (events[0].data, events[0].events);
+        assert_eq!(token, 7);
+        assert_ne!(flags & READ, 0);
+    }
+}
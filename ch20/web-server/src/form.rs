@@ -0,0 +1,185 @@
+/*
+application/x-www-form-urlencoded：
+    查询字符串和 HTML 表单的请求体都使用这种编码：`name=ferris&tags=a+b&tags=c%21`。
+    `+` 表示空格，`%XX` 是百分号编码的字节，同一个名字可以出现多次，所以解析结果是有序的键值对列表。
+    解析是宽松的：不合法的百分号序列原样保留，解码后不是 UTF-8 的字节替换成 U+FFFD。
+
+        let page = req.query_param("page");
+        let Form(fields) = match Form::from_request(req) { ... };
+*/
+
+use crate::{
+    http::{Request, Response},
+    json,
+};
+
+/// 解析成键值对，没有 `=` 的项值为空字符串
+pub fn parse(input: &str) -> Vec<(String, String)> {
+    input
+        .split('&')
+        .filter(|item| !item.is_empty())
+        .map(|item| {
+            let (name, value) = item.split_once('=').unwrap_or((item, ""));
+            (decode(name), decode(value))
+        })
+        .collect()
+}
+
+pub fn decode(input: &str) -> String {
+    let bytes = input.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => out.push(b' '),
+            b'%' => match hex_pair(&bytes[i + 1..]) {
+                Some(byte) => {
+                    out.push(byte);
+                    i += 2;
+                }
+                None => out.push(b'%'),
+            },
+            b => out.push(b),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+fn hex_pair(bytes: &[u8]) -> Option<u8> {
+    let digit = |b: u8| (b as char).to_digit(16);
+    match bytes {
+        [hi, lo, ..] => Some((digit(*hi)? * 16 + digit(*lo)?) as u8),
+        _ => None,
+    }
+}
+
+/// 除了字母、数字和 `-_.*` 之外的字节都做百分号编码，空格编码成 `+`
+pub fn encode(input: &str) -> String {
+    let mut out = String::with_capacity(input.len());
+    for b in input.bytes() {
+        match b {
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'*' => {
+                out.push(b as char)
+            }
+            b' ' => out.push('+'),
+            b => out.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    out
+}
+
+/// 把键值对编码成查询字符串或表单请求体
+pub fn serialize<K: AsRef<str>, V: AsRef<str>>(pairs: &[(K, V)]) -> String {
+    pairs
+        .iter()
+        .map(|(name, value)| format!("{}={}", encode(name.as_ref()), encode(value.as_ref())))
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
+/// 表单请求体
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Form(pub Vec<(String, String)>);
+
+impl Form {
+    /// Content-Type 不是 application/x-www-form-urlencoded 时返回 415，错误响应的格式与 Json 相同
+    pub fn from_request(req: &Request) -> Result<Form, Response> {
+        let is_form = req.header("Content-Type").is_some_and(|ct| {
+            ct.split(';')
+                .next()
+                .unwrap_or("")
+                .trim()
+                .eq_ignore_ascii_case("application/x-www-form-urlencoded")
+        });
+        if !is_form {
+            return Err(json::error(
+                415,
+                "expected an application/x-www-form-urlencoded body",
+            ));
+        }
+        Ok(Form(parse(&String::from_utf8_lossy(&req.body))))
+    }
+
+    /// 第一个同名字段的值
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.0
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn get_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.0
+            .iter()
+            .filter(move |(n, _)| n == name)
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_plus_and_percent_sequences() {
+        assert_eq!(decode("a+b%20c%21"), "a b c!");
+        assert_eq!(decode("caf%C3%A9"), "café");
+        // 不合法的序列原样保留
+        assert_eq!(decode("100%"), "100%");
+        assert_eq!(decode("%zz%4"), "%zz%4");
+        assert_eq!(decode("%FF"), "\u{FFFD}");
+    }
+
+    #[test]
+    fn parses_repeated_and_bare_names() {
+        let pairs = parse("tags=a+b&tags=c%21&&flag&empty=&x=1=2");
+        let expected = [
+            ("tags", "a b"),
+            ("tags", "c!"),
+            ("flag", ""),
+            ("empty", ""),
+            ("x", "1=2"),
+        ];
+        assert_eq!(
+            pairs,
+            expected
+                .iter()
+                .map(|(n, v)| (n.to_string(), v.to_string()))
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn serialize_round_trips() {
+        let pairs = [
+            ("q", "rust & ferris"),
+            ("emoji", "🦀"),
+            ("path", "/a/b?c=d"),
+        ];
+        let encoded = serialize(&pairs);
+        assert_eq!(
+            encoded,
+            "q=rust+%26+ferris&emoji=%F0%9F%A6%80&path=%2Fa%2Fb%3Fc%3Dd"
+        );
+        let decoded = parse(&encoded);
+        for ((n, v), (dn, dv)) in pairs.iter().zip(&decoded) {
+            assert_eq!((*n, *v), (dn.as_str(), dv.as_str()));
+        }
+    }
+
+    #[test]
+    fn extracts_forms_from_requests() {
+        let mut req = Request::new("POST", "/login");
+        req.body = b"user=ferris&remember=on&remember=yes".to_vec();
+        assert_eq!(Form::from_request(&req).unwrap_err().status, 415);
+        req.headers.set(
+            "Content-Type",
+            "application/x-www-form-urlencoded; charset=utf-8",
+        );
+        let form = Form::from_request(&req).unwrap();
+        assert_eq!(form.get("user"), Some("ferris"));
+        assert_eq!(form.get("password"), None);
+        assert_eq!(form.get_all("remember").collect::<Vec<_>>(), ["on", "yes"]);
+    }
+}
@@ -0,0 +1,931 @@
+/*
+HPACK（RFC 7541）：
+    HTTP/2 的头部压缩。每个头部可以编码成：
+        静态表或动态表中的索引                            一个字节就能表示 `:method: GET`
+        名字用索引、值用字面量，可以选择插入动态表          后面的请求再出现时只需要一个索引
+        名字和值都是字面量
+    字面量字符串可以用固定的 Huffman 编码（附录 B），只在更短时使用。
+    动态表是一个按插入顺序排列的 FIFO，总大小（每项 name + value + 32 字节）超出上限时淘汰最旧的条目。
+    编码端和解码端按相同的顺序插入和淘汰，两边的表始终一致，
+    所以同一个连接上的头部块必须按发送的顺序逐个编码和解码。
+
+    Encoder 的策略：完全匹配的头部用索引，其余头部插入动态表，
+    每次都会变化的值（content-length、date 等）和敏感的值（authorization、set-cookie）不插入。
+*/
+
+use std::{collections::VecDeque, fmt, sync::OnceLock};
+
+/// 默认的动态表大小（SETTINGS_HEADER_TABLE_SIZE 的初始值）
+pub const DEFAULT_TABLE_SIZE: usize = 4096;
+
+/// 静态表，索引从 1 开始
+const STATIC_TABLE: [(&str, &str); 61] = [
+    (":authority", ""),
+    (":method", "GET"),
+    (":method", "POST"),
+    (":path", "/"),
+    (":path", "/index.html"),
+    (":scheme", "http"),
+    (":scheme", "https"),
+    (":status", "200"),
+    (":status", "204"),
+    (":status", "206"),
+    (":status", "304"),
+    (":status", "400"),
+    (":status", "404"),
+    (":status", "500"),
+    ("accept-charset", ""),
+    ("accept-encoding", "gzip, deflate"),
+    ("accept-language", ""),
+    ("accept-ranges", ""),
+    ("accept", ""),
+    ("access-control-allow-origin", ""),
+    ("age", ""),
+    ("allow", ""),
+    ("authorization", ""),
+    ("cache-control", ""),
+    ("content-disposition", ""),
+    ("content-encoding", ""),
+    ("content-language", ""),
+    ("content-length", ""),
+    ("content-location", ""),
+    ("content-range", ""),
+    ("content-type", ""),
+    ("cookie", ""),
+    ("date", ""),
+    ("etag", ""),
+    ("expect", ""),
+    ("expires", ""),
+    ("from", ""),
+    ("host", ""),
+    ("if-match", ""),
+    ("if-modified-since", ""),
+    ("if-none-match", ""),
+    ("if-range", ""),
+    ("if-unmodified-since", ""),
+    ("last-modified", ""),
+    ("link", ""),
+    ("location", ""),
+    ("max-forwards", ""),
+    ("proxy-authenticate", ""),
+    ("proxy-authorization", ""),
+    ("range", ""),
+    ("referer", ""),
+    ("refresh", ""),
+    ("retry-after", ""),
+    ("server", ""),
+    ("set-cookie", ""),
+    ("strict-transport-security", ""),
+    ("transfer-encoding", ""),
+    ("user-agent", ""),
+    ("vary", ""),
+    ("via", ""),
+    ("www-authenticate", ""),
+];
+
+/// 值不插入动态表的头部：每个响应都不一样，插入只会把有用的条目挤出去
+const UNINDEXED: [&str; 6] = [
+    ":path",
+    "content-length",
+    "date",
+    "etag",
+    "last-modified",
+    "location",
+];
+
+/// 敏感的头部用 never indexed 编码，中间的代理也不会把它们放进动态表
+const SENSITIVE: [&str; 4] = [
+    "authorization",
+    "proxy-authorization",
+    "cookie",
+    "set-cookie",
+];
+
+/// 附录 B 的 Huffman 编码，按符号排列：(码字, 位数)，256 是 EOS
+const HUFFMAN: [(u32, u8); 257] = [
+    (0x1ff8, 13),
+    (0x7fffd8, 23),
+    (0xfffffe2, 28),
+    (0xfffffe3, 28),
+    (0xfffffe4, 28),
+    (0xfffffe5, 28),
+    (0xfffffe6, 28),
+    (0xfffffe7, 28),
+    (0xfffffe8, 28),
+    (0xffffea, 24),
+    (0x3ffffffc, 30),
+    (0xfffffe9, 28),
+    (0xfffffea, 28),
+    (0x3ffffffd, 30),
+    (0xfffffeb, 28),
+    (0xfffffec, 28),
+    (0xfffffed, 28),
+    (0xfffffee, 28),
+    (0xfffffef, 28),
+    (0xffffff0, 28),
+    (0xffffff1, 28),
+    (0xffffff2, 28),
+    (0x3ffffffe, 30),
+    (0xffffff3, 28),
+    (0xffffff4, 28),
+    (0xffffff5, 28),
+    (0xffffff6, 28),
+    (0xffffff7, 28),
+    (0xffffff8, 28),
+    (0xffffff9, 28),
+    (0xffffffa, 28),
+    (0xffffffb, 28),
+    (0x14, 6),
+    (0x3f8, 10),
+    (0x3f9, 10),
+    (0xffa, 12),
+    (0x1ff9, 13),
+    (0x15, 6),
+    (0xf8, 8),
+    (0x7fa, 11),
+    (0x3fa, 10),
+    (0x3fb, 10),
+    (0xf9, 8),
+    (0x7fb, 11),
+    (0xfa, 8),
+    (0x16, 6),
+    (0x17, 6),
+    (0x18, 6),
+    (0x0, 5),
+    (0x1, 5),
+    (0x2, 5),
+    (0x19, 6),
+    (0x1a, 6),
+    (0x1b, 6),
+    (0x1c, 6),
+    (0x1d, 6),
+    (0x1e, 6),
+    (0x1f, 6),
+    (0x5c, 7),
+    (0xfb, 8),
+    (0x7ffc, 15),
+    (0x20, 6),
+    (0xffb, 12),
+    (0x3fc, 10),
+    (0x1ffa, 13),
+    (0x21, 6),
+    (0x5d, 7),
+    (0x5e, 7),
+    (0x5f, 7),
+    (0x60, 7),
+    (0x61, 7),
+    (0x62, 7),
+    (0x63, 7),
+    (0x64, 7),
+    (0x65, 7),
+    (0x66, 7),
+    (0x67, 7),
+    (0x68, 7),
+    (0x69, 7),
+    (0x6a, 7),
+    (0x6b, 7),
+    (0x6c, 7),
+    (0x6d, 7),
+    (0x6e, 7),
+    (0x6f, 7),
+    (0x70, 7),
+    (0x71, 7),
+    (0x72, 7),
+    (0xfc, 8),
+    (0x73, 7),
+    (0xfd, 8),
+    (0x1ffb, 13),
+    (0x7fff0, 19),
+    (0x1ffc, 13),
+    (0x3ffc, 14),
+    (0x22, 6),
+    (0x7ffd, 15),
+    (0x3, 5),
+    (0x23, 6),
+    (0x4, 5),
+    (0x24, 6),
+    (0x5, 5),
+    (0x25, 6),
+    (0x26, 6),
+    (0x27, 6),
+    (0x6, 5),
+    (0x74, 7),
+    (0x75, 7),
+    (0x28, 6),
+    (0x29, 6),
+    (0x2a, 6),
+    (0x7, 5),
+    (0x2b, 6),
+    (0x76, 7),
+    (0x2c, 6),
+    (0x8, 5),
+    (0x9, 5),
+    (0x2d, 6),
+    (0x77, 7),
+    (0x78, 7),
+    (0x79, 7),
+    (0x7a, 7),
+    (0x7b, 7),
+    (0x7ffe, 15),
+    (0x7fc, 11),
+    (0x3ffd, 14),
+    (0x1ffd, 13),
+    (0xffffffc, 28),
+    (0xfffe6, 20),
+    (0x3fffd2, 22),
+    (0xfffe7, 20),
+    (0xfffe8, 20),
+    (0x3fffd3, 22),
+    (0x3fffd4, 22),
+    (0x3fffd5, 22),
+    (0x7fffd9, 23),
+    (0x3fffd6, 22),
+    (0x7fffda, 23),
+    (0x7fffdb, 23),
+    (0x7fffdc, 23),
+    (0x7fffdd, 23),
+    (0x7fffde, 23),
+    (0xffffeb, 24),
+    (0x7fffdf, 23),
+    (0xffffec, 24),
+    (0xffffed, 24),
+    (0x3fffd7, 22),
+    (0x7fffe0, 23),
+    (0xffffee, 24),
+    (0x7fffe1, 23),
+    (0x7fffe2, 23),
+    (0x7fffe3, 23),
+    (0x7fffe4, 23),
+    (0x1fffdc, 21),
+    (0x3fffd8, 22),
+    (0x7fffe5, 23),
+    (0x3fffd9, 22),
+    (0x7fffe6, 23),
+    (0x7fffe7, 23),
+    (0xffffef, 24),
+    (0x3fffda, 22),
+    (0x1fffdd, 21),
+    (0xfffe9, 20),
+    (0x3fffdb, 22),
+    (0x3fffdc, 22),
+    (0x7fffe8, 23),
+    (0x7fffe9, 23),
+    (0x1fffde, 21),
+    (0x7fffea, 23),
+    (0x3fffdd, 22),
+    (0x3fffde, 22),
+    (0xfffff0, 24),
+    (0x1fffdf, 21),
+    (0x3fffdf, 22),
+    (0x7fffeb, 23),
+    (0x7fffec, 23),
+    (0x1fffe0, 21),
+    (0x1fffe1, 21),
+    (0x3fffe0, 22),
+    (0x1fffe2, 21),
+    (0x7fffed, 23),
+    (0x3fffe1, 22),
+    (0x7fffee, 23),
+    (0x7fffef, 23),
+    (0xfffea, 20),
+    (0x3fffe2, 22),
+    (0x3fffe3, 22),
+    (0x3fffe4, 22),
+    (0x7ffff0, 23),
+    (0x3fffe5, 22),
+    (0x3fffe6, 22),
+    (0x7ffff1, 23),
+    (0x3ffffe0, 26),
+    (0x3ffffe1, 26),
+    (0xfffeb, 20),
+    (0x7fff1, 19),
+    (0x3fffe7, 22),
+    (0x7ffff2, 23),
+    (0x3fffe8, 22),
+    (0x1ffffec, 25),
+    (0x3ffffe2, 26),
+    (0x3ffffe3, 26),
+    (0x3ffffe4, 26),
+    (0x7ffffde, 27),
+    (0x7ffffdf, 27),
+    (0x3ffffe5, 26),
+    (0xfffff1, 24),
+    (0x1ffffed, 25),
+    (0x7fff2, 19),
+    (0x1fffe3, 21),
+    (0x3ffffe6, 26),
+    (0x7ffffe0, 27),
+    (0x7ffffe1, 27),
+    (0x3ffffe7, 26),
+    (0x7ffffe2, 27),
+    (0xfffff2, 24),
+    (0x1fffe4, 21),
+    (0x1fffe5, 21),
+    (0x3ffffe8, 26),
+    (0x3ffffe9, 26),
+    (0xffffffd, 28),
+    (0x7ffffe3, 27),
+    (0x7ffffe4, 27),
+    (0x7ffffe5, 27),
+    (0xfffec, 20),
+    (0xfffff3, 24),
+    (0xfffed, 20),
+    (0x1fffe6, 21),
+    (0x3fffe9, 22),
+    (0x1fffe7, 21),
+    (0x1fffe8, 21),
+    (0x7ffff3, 23),
+    (0x3fffea, 22),
+    (0x3fffeb, 22),
+    (0x1ffffee, 25),
+    (0x1ffffef, 25),
+    (0xfffff4, 24),
+    (0xfffff5, 24),
+    (0x3ffffea, 26),
+    (0x7ffff4, 23),
+    (0x3ffffeb, 26),
+    (0x7ffffe6, 27),
+    (0x3ffffec, 26),
+    (0x3ffffed, 26),
+    (0x7ffffe7, 27),
+    (0x7ffffe8, 27),
+    (0x7ffffe9, 27),
+    (0x7ffffea, 27),
+    (0x7ffffeb, 27),
+    (0xffffffe, 28),
+    (0x7ffffec, 27),
+    (0x7ffffed, 27),
+    (0x7ffffee, 27),
+    (0x7ffffef, 27),
+    (0x7fffff0, 27),
+    (0x3ffffee, 26),
+    (0x3fffffff, 30),
+];
+
+/// 解码出错时整个连接都要以 COMPRESSION_ERROR 关闭，所以只需要一个描述
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DecodeError(pub &'static str);
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "hpack: {}", self.0)
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+/// 一项占用的大小，RFC 7541 4.1
+fn entry_size(name: &str, value: &str) -> usize {
+    name.len() + value.len() + 32
+}
+
+struct Table {
+    /// 最新插入的在最前面，动态表的索引从 62 开始
+    entries: VecDeque<(String, String)>,
+    size: usize,
+    max_size: usize,
+}
+
+impl Table {
+    fn new(max_size: usize) -> Table {
+        Table {
+            entries: VecDeque::new(),
+            size: 0,
+            max_size,
+        }
+    }
+
+    fn get(&self, index: usize) -> Option<(&str, &str)> {
+        match index {
+            0 => None,
+            1..=61 => Some(STATIC_TABLE[index - 1]),
+            _ => self
+                .entries
+                .get(index - 62)
+                .map(|(n, v)| (n.as_str(), v.as_str())),
+        }
+    }
+
+    fn insert(&mut self, name: String, value: String) {
+        let size = entry_size(&name, &value);
+        self.evict(self.max_size.saturating_sub(size));
+        // 比整张表还大的条目不会插入，只是清空了表
+        if size <= self.max_size {
+            self.size += size;
+            self.entries.push_front((name, value));
+        }
+    }
+
+    fn set_max_size(&mut self, max_size: usize) {
+        self.max_size = max_size;
+        self.evict(max_size);
+    }
+
+    fn evict(&mut self, until: usize) {
+        while self.size > until {
+            let (name, value) = self.entries.pop_back().unwrap();
+            self.size -= entry_size(&name, &value);
+        }
+    }
+
+    /// 完全匹配的索引，以及只有名字匹配的索引
+    fn find(&self, name: &str, value: &str) -> (Option<usize>, Option<usize>) {
+        let mut name_only = None;
+        let statics = STATIC_TABLE
+            .iter()
+            .enumerate()
+            .map(|(i, (n, v))| (i + 1, *n, *v));
+        let dynamics = self
+            .entries
+            .iter()
+            .enumerate()
+            .map(|(i, (n, v))| (i + 62, n.as_str(), v.as_str()));
+        for (index, n, v) in statics.chain(dynamics) {
+            if n == name {
+                if v == value {
+                    return (Some(index), name_only);
+                }
+                name_only.get_or_insert(index);
+            }
+        }
+        (None, name_only)
+    }
+}
+
+pub struct Decoder {
+    table: Table,
+    /// 通过 SETTINGS_HEADER_TABLE_SIZE 告诉对端的上限，对端的大小更新不能超过它
+    limit: usize,
+}
+
+impl Decoder {
+    pub fn new(limit: usize) -> Decoder {
+        Decoder {
+            table: Table::new(limit),
+            limit,
+        }
+    }
+
+    /// 解码一个完整的头部块（HEADERS 加上所有 CONTINUATION 的内容）
+    pub fn decode(&mut self, block: &[u8]) -> Result<Vec<(String, String)>, DecodeError> {
+        let mut headers = Vec::new();
+        let mut pos = 0;
+        while pos < block.len() {
+            let first = block[pos];
+            if first & 0x80 != 0 {
+                let index = decode_int(block, &mut pos, 7)?;
+                let (name, value) = self.lookup(index)?;
+                headers.push((name.to_string(), value.to_string()));
+            } else if first & 0xc0 == 0x40 {
+                let (name, value) = self.literal(block, &mut pos, 6)?;
+                self.table.insert(name.clone(), value.clone());
+                headers.push((name, value));
+            } else if first & 0xe0 == 0x20 {
+                // 大小更新只能出现在头部块的开头
+                if !headers.is_empty() {
+                    return Err(DecodeError("table size update after a header field"));
+                }
+                let size = decode_int(block, &mut pos, 5)?;
+                if size > self.limit {
+                    return Err(DecodeError("table size update above the limit"));
+                }
+                self.table.set_max_size(size);
+            } else {
+                // without indexing（0000）和 never indexed（0001）解码时的处理相同
+                headers.push(self.literal(block, &mut pos, 4)?);
+            }
+        }
+        Ok(headers)
+    }
+
+    fn lookup(&self, index: usize) -> Result<(&str, &str), DecodeError> {
+        self.table
+            .get(index)
+            .ok_or(DecodeError("invalid table index"))
+    }
+
+    fn literal(
+        &self,
+        block: &[u8],
+        pos: &mut usize,
+        prefix: u8,
+    ) -> Result<(String, String), DecodeError> {
+        let index = decode_int(block, pos, prefix)?;
+        let name = if index == 0 {
+            decode_string(block, pos)?
+        } else {
+            self.lookup(index)?.0.to_string()
+        };
+        let value = decode_string(block, pos)?;
+        Ok((name, value))
+    }
+}
+
+pub struct Encoder {
+    table: Table,
+    /// 对端调小了表的上限，下一个头部块的开头要带上大小更新
+    pending_update: Option<usize>,
+}
+
+impl Encoder {
+    pub fn new() -> Encoder {
+        Encoder {
+            table: Table::new(DEFAULT_TABLE_SIZE),
+            pending_update: None,
+        }
+    }
+
+    /// 对端的 SETTINGS_HEADER_TABLE_SIZE，自己最多使用 DEFAULT_TABLE_SIZE
+    pub fn set_max_table_size(&mut self, size: usize) {
+        let size = size.min(DEFAULT_TABLE_SIZE);
+        if size != self.table.max_size {
+            self.table.set_max_size(size);
+            self.pending_update = Some(size);
+        }
+    }
+
+    /// 头部名字应该已经是小写的
+    pub fn encode<N: AsRef<str>, V: AsRef<str>>(&mut self, headers: &[(N, V)]) -> Vec<u8> {
+        let mut out = Vec::new();
+        if let Some(size) = self.pending_update.take() {
+            encode_int(&mut out, 0x20, 5, size);
+        }
+        for (name, value) in headers {
+            let (name, value) = (name.as_ref(), value.as_ref());
+            let (exact, name_index) = self.table.find(name, value);
+            if SENSITIVE.contains(&name) {
+                encode_literal(&mut out, 0x10, 4, name_index, name, value);
+            } else if let Some(index) = exact {
+                encode_int(&mut out, 0x80, 7, index);
+            } else if UNINDEXED.contains(&name)
+                || entry_size(name, value) > self.table.max_size * 3 / 4
+            {
+                encode_literal(&mut out, 0x00, 4, name_index, name, value);
+            } else {
+                encode_literal(&mut out, 0x40, 6, name_index, name, value);
+                self.table.insert(name.to_string(), value.to_string());
+            }
+        }
+        out
+    }
+}
+
+impl Default for Encoder {
+    fn default() -> Encoder {
+        Encoder::new()
+    }
+}
+
+fn encode_literal(
+    out: &mut Vec<u8>,
+    flags: u8,
+    prefix: u8,
+    name_index: Option<usize>,
+    name: &str,
+    value: &str,
+) {
+    match name_index {
+        Some(index) => encode_int(out, flags, prefix, index),
+        None => {
+            out.push(flags);
+            encode_string(out, name);
+        }
+    }
+    encode_string(out, value);
+}
+
+/// 带 prefix 位前缀的整数，RFC 7541 5.1；flags 是第一个字节中前缀之外的高位
+pub fn encode_int(out: &mut Vec<u8>, flags: u8, prefix: u8, value: usize) {
+    let max = (1usize << prefix) - 1;
+    if value < max {
+        out.push(flags | value as u8);
+        return;
+    }
+    out.push(flags | max as u8);
+    let mut rest = value - max;
+    while rest >= 128 {
+        out.push((rest % 128) as u8 | 0x80);
+        rest /= 128;
+    }
+    out.push(rest as u8);
+}
+
+pub fn decode_int(buf: &[u8], pos: &mut usize, prefix: u8) -> Result<usize, DecodeError> {
+    let max = (1usize << prefix) - 1;
+    let first = *buf.get(*pos).ok_or(DecodeError("truncated integer"))?;
+    *pos += 1;
+    let mut value = first as usize & max;
+    if value < max {
+        return Ok(value);
+    }
+    let mut shift = 0;
+    loop {
+        let byte = *buf.get(*pos).ok_or(DecodeError("truncated integer"))?;
+        *pos += 1;
+        // 头部块里的整数都是长度和索引，超过 2^28 的值没有意义
+        if shift > 21 {
+            return Err(DecodeError("integer overflow"));
+        }
+        value += (byte as usize & 0x7f) << shift;
+        shift += 7;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+}
+
+fn encode_string(out: &mut Vec<u8>, s: &str) {
+    let huffman_len = huffman_len(s.as_bytes());
+    if huffman_len < s.len() {
+        encode_int(out, 0x80, 7, huffman_len);
+        huffman_encode(out, s.as_bytes());
+    } else {
+        encode_int(out, 0x00, 7, s.len());
+        out.extend_from_slice(s.as_bytes());
+    }
+}
+
+fn decode_string(buf: &[u8], pos: &mut usize) -> Result<String, DecodeError> {
+    let huffman = buf.get(*pos).is_some_and(|b| b & 0x80 != 0);
+    let len = decode_int(buf, pos, 7)?;
+    let data = buf
+        .get(*pos..*pos + len)
+        .ok_or(DecodeError("truncated string"))?;
+    *pos += len;
+    let bytes = if huffman {
+        huffman_decode(data)?
+    } else {
+        data.to_vec()
+    };
+    Ok(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+fn huffman_len(data: &[u8]) -> usize {
+    let bits: usize = data.iter().map(|&b| HUFFMAN[b as usize].1 as usize).sum();
+    bits.div_ceil(8)
+}
+
+pub fn huffman_encode(out: &mut Vec<u8>, data: &[u8]) {
+    let mut acc: u64 = 0;
+    let mut bits = 0;
+    for &b in data {
+        let (code, len) = HUFFMAN[b as usize];
+        acc = (acc << len) | code as u64;
+        bits += len as u32;
+        while bits >= 8 {
+            bits -= 8;
+            out.push((acc >> bits) as u8);
+        }
+    }
+    // 最后不满一个字节的部分用 EOS 的高位（全 1）补齐
+    if bits > 0 {
+        out.push(((acc << (8 - bits)) as u8) | (0xff >> bits));
+    }
+}
+
+/// 解码树的节点：两个子节点，叶子节点保存符号
+const LEAF: u16 = 0x8000;
+
+fn tree() -> &'static Vec<[u16; 2]> {
+    static TREE: OnceLock<Vec<[u16; 2]>> = OnceLock::new();
+    TREE.get_or_init(|| {
+        let mut nodes = vec![[0u16; 2]];
+        for (symbol, &(code, len)) in HUFFMAN.iter().enumerate() {
+            let mut node = 0;
+            for i in (0..len).rev() {
+                let bit = (code >> i) as usize & 1;
+                if i == 0 {
+                    nodes[node][bit] = LEAF | symbol as u16;
+                } else {
+                    if nodes[node][bit] == 0 {
+                        nodes.push([0; 2]);
+                        nodes[node][bit] = (nodes.len() - 1) as u16;
+                    }
+                    node = nodes[node][bit] as usize;
+                }
+            }
+        }
+        nodes
+    })
+}
+
+pub fn huffman_decode(data: &[u8]) -> Result<Vec<u8>, DecodeError> {
+    let tree = tree();
+    let mut out = Vec::with_capacity(data.len() * 8 / 5);
+    let mut node = 0;
+    // 当前符号已经读了多少位、是否全是 1，用来检查结尾的填充
+    let mut depth = 0;
+    let mut ones = true;
+    for &byte in data {
+        for i in (0..8).rev() {
+            let bit = (byte >> i) as usize & 1;
+            depth += 1;
+            ones &= bit == 1;
+            let next = tree[node][bit];
+            if next & LEAF != 0 {
+                let symbol = next & !LEAF;
+                if symbol == 256 {
+                    return Err(DecodeError("EOS in huffman string"));
+                }
+                out.push(symbol as u8);
+                node = 0;
+                depth = 0;
+                ones = true;
+            } else {
+                node = next as usize;
+            }
+        }
+    }
+    if depth > 7 || !ones {
+        return Err(DecodeError("invalid huffman padding"));
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(s: &str) -> Vec<u8> {
+        let s: String = s.split_whitespace().collect();
+        (0..s.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    fn pairs(list: &[(&str, &str)]) -> Vec<(String, String)> {
+        list.iter()
+            .map(|(n, v)| (n.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn huffman_code_is_complete_and_prefix_free() {
+        // Kraft 不等式取等号：每一个比特串都能被唯一地解码
+        let kraft: u64 = HUFFMAN.iter().map(|&(_, len)| 1u64 << (30 - len)).sum();
+        assert_eq!(kraft, 1 << 30);
+        let all: Vec<u8> = (0..=255).collect();
+        let mut encoded = Vec::new();
+        huffman_encode(&mut encoded, &all);
+        assert_eq!(huffman_decode(&encoded).unwrap(), all);
+    }
+
+    #[test]
+    fn huffman_rejects_bad_padding() {
+        // "a" 是 00011，后面必须用 1 填充
+        assert_eq!(huffman_decode(&[0b0001_1111]).unwrap(), b"a");
+        assert!(huffman_decode(&[0b0001_1011]).is_err());
+        // 填充超过 7 位
+        assert!(huffman_decode(&[0b0001_1111, 0xff]).is_err());
+        // EOS 是 30 个 1
+        assert!(huffman_decode(&[0xff, 0xff, 0xff, 0xfc]).is_err());
+    }
+
+    #[test]
+    fn integers_from_the_rfc() {
+        let mut out = Vec::new();
+        encode_int(&mut out, 0, 5, 10);
+        encode_int(&mut out, 0, 5, 1337);
+        encode_int(&mut out, 0, 8, 42);
+        assert_eq!(out, [0x0a, 0x1f, 0x9a, 0x0a, 0x2a]);
+        let mut pos = 0;
+        assert_eq!(decode_int(&out, &mut pos, 5), Ok(10));
+        assert_eq!(decode_int(&out, &mut pos, 5), Ok(1337));
+        assert_eq!(decode_int(&out, &mut pos, 8), Ok(42));
+        assert!(decode_int(&[0x1f, 0xff, 0xff, 0xff, 0xff, 0x7f], &mut 0, 5).is_err());
+        assert!(decode_int(&[0x1f, 0x9a], &mut 0, 5).is_err());
+    }
+
+    #[test]
+    fn requests_with_huffman_from_the_rfc() {
+        // RFC 7541 C.4：同一个连接上的三个请求，后面的请求引用动态表中的条目
+        let requests = [
+            (
+                vec![
+                    (":method", "GET"),
+                    (":scheme", "http"),
+                    (":path", "/"),
+                    (":authority", "www.example.com"),
+                ],
+                "8286 8441 8cf1 e3c2 e5f2 3a6b a0ab 90f4 ff",
+            ),
+            (
+                vec![
+                    (":method", "GET"),
+                    (":scheme", "http"),
+                    (":path", "/"),
+                    (":authority", "www.example.com"),
+                    ("cache-control", "no-cache"),
+                ],
+                "8286 84be 5886 a8eb 1064 9cbf",
+            ),
+            (
+                vec![
+                    (":method", "GET"),
+                    (":scheme", "https"),
+                    (":path", "/index.html"),
+                    (":authority", "www.example.com"),
+                    ("custom-key", "custom-value"),
+                ],
+                "8287 85bf 4088 25a8 49e9 5ba9 7d7f 8925 a849 e95b b8e8 b4bf",
+            ),
+        ];
+        let mut encoder = Encoder::new();
+        let mut decoder = Decoder::new(DEFAULT_TABLE_SIZE);
+        for (headers, wire) in &requests {
+            assert_eq!(encoder.encode(headers), hex(wire));
+            assert_eq!(decoder.decode(&hex(wire)).unwrap(), pairs(headers));
+        }
+        assert_eq!(decoder.table.size, 164);
+    }
+
+    #[test]
+    fn responses_with_eviction_from_the_rfc() {
+        // RFC 7541 C.6：动态表只有 256 字节，插入新条目时淘汰旧的
+        let mut decoder = Decoder::new(256);
+        let first = decoder
+            .decode(&hex(
+                "4882 6402 5885 aec3 771a 4b61 96d0 7abe 9410 54d4 44a8 2005 9504 0b81 66e0 \
+                 82a6 2d1b ff6e 919d 29ad 1718 63c7 8f0b 97c8 e9ae 82ae 43d3",
+            ))
+            .unwrap();
+        assert_eq!(
+            first,
+            pairs(&[
+                (":status", "302"),
+                ("cache-control", "private"),
+                ("date", "Mon, 21 Oct 2013 20:13:21 GMT"),
+                ("location", "https://www.example.com"),
+            ])
+        );
+        assert_eq!(decoder.table.size, 222);
+
+        let second = decoder.decode(&hex("4883 640e ffc1 c0bf")).unwrap();
+        assert_eq!(second[0], (String::from(":status"), String::from("307")));
+        assert_eq!(second[1..], first[1..]);
+
+        let third = decoder
+            .decode(&hex(
+                "88c1 6196 d07a be94 1054 d444 a820 0595 040b 8166 e084 a62d 1bff c05a 839b \
+                 d9ab 77ad 94e7 821d d7f2 e6c7 b335 dfdf cd5b 3960 d5af 2708 7f36 72c1 ab27 \
+                 0fb5 291f 9587 3160 65c0 03ed 4ee5 b106 3d50 07",
+            ))
+            .unwrap();
+        assert_eq!(
+            third,
+            pairs(&[
+                (":status", "200"),
+                ("cache-control", "private"),
+                ("date", "Mon, 21 Oct 2013 20:13:22 GMT"),
+                ("location", "https://www.example.com"),
+                ("content-encoding", "gzip"),
+                (
+                    "set-cookie",
+                    "foo=ASDJKHQKBZXOQWEOPIUAXQWEOIU; max-age=3600; version=1"
+                ),
+            ])
+        );
+        assert_eq!(decoder.table.size, 215);
+        assert_eq!(decoder.table.entries.len(), 3);
+    }
+
+    #[test]
+    fn table_size_updates() {
+        let mut encoder = Encoder::new();
+        encoder.encode(&[("x-custom", "value")]);
+        // 对端把表缩到 0：下一个块以大小更新开头，之后不再引用动态表
+        encoder.set_max_table_size(0);
+        let block = encoder.encode(&[("x-custom", "value")]);
+        assert_eq!(block[0], 0x20);
+        assert!(encoder.table.entries.is_empty());
+
+        let mut decoder = Decoder::new(DEFAULT_TABLE_SIZE);
+        assert!(decoder.decode(&[0x3f, 0x81, 0x1f]).is_ok());
+        assert_eq!(decoder.table.max_size, 4000);
+        // 超过 SETTINGS 中的上限，或者出现在头部字段之后
+        assert!(decoder.decode(&[0x3f, 0xe2, 0x1f]).is_err());
+        assert!(decoder.decode(&[0x82, 0x20]).is_err());
+        assert!(decoder.decode(&[0x80]).is_err());
+        assert!(decoder.decode(&[0xbe]).is_err());
+    }
+
+    #[test]
+    fn sensitive_and_changing_values_stay_out_of_the_table() {
+        let mut encoder = Encoder::new();
+        let headers = [
+            ("set-cookie", "id=1"),
+            ("content-length", "12"),
+            ("content-type", "text/html"),
+        ];
+        let block = encoder.encode(&headers);
+        assert_eq!(encoder.table.entries.len(), 1);
+        // set-cookie 是静态表第 55 项，never indexed 的 4 位前缀放不下
+        assert_eq!(block[..2], [0x1f, 0x28]);
+        let mut decoder = Decoder::new(DEFAULT_TABLE_SIZE);
+        assert_eq!(decoder.decode(&block).unwrap(), pairs(&headers));
+        let again = encoder.encode(&headers);
+        assert_eq!(*again.last().unwrap(), 0x80 | 62);
+        assert_eq!(decoder.decode(&again).unwrap(), pairs(&headers));
+    }
+}
@@ -0,0 +1,590 @@
+/*
+HTTP/1.x 报文的最小实现：
+    请求行 + 头部 + 可选的 Content-Length 请求体。
+    读取时会对头部和请求体的大小做限制，防止客户端无限制地占用内存。
+    响应可以携带一个 Upgrade，服务器写出响应之后把连接交给它，不再按 HTTP 处理：
+    可以是 101 协议升级，也可以是以关闭连接结束的流式响应（例如 SSE）。
+*/
+
+use crate::{conn::ConnGuard, form};
+use std::{
+    fmt,
+    io::{self, BufRead, Read, Write},
+    net::{SocketAddr, TcpStream},
+    sync::{Arc, Mutex},
+};
+
+/// 请求头部与请求体的大小限制
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    /// 请求行加全部头部的最大字节数
+    pub max_header_bytes: usize,
+    /// 请求体的最大字节数
+    pub max_body_bytes: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Limits {
+        Limits {
+            max_header_bytes: 8 * 1024,
+            max_body_bytes: 1024 * 1024,
+        }
+    }
+}
+
+/// 大小写不敏感的头部列表，保留插入顺序，允许同名头部出现多次
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Headers(Vec<(String, String)>);
+
+impl Headers {
+    pub fn new() -> Headers {
+        Headers(Vec::new())
+    }
+
+    /// 返回第一个同名头部的值
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.0
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn get_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.0
+            .iter()
+            .filter(move |(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+
+    /// 替换所有同名头部
+    pub fn set(&mut self, name: &str, value: impl Into<String>) {
+        self.remove(name);
+        self.0.push((name.to_string(), value.into()));
+    }
+
+    /// 追加一个头部，不影响已有的同名头部
+    pub fn append(&mut self, name: &str, value: impl Into<String>) {
+        self.0.push((name.to_string(), value.into()));
+    }
+
+    pub fn remove(&mut self, name: &str) {
+        self.0.retain(|(n, _)| !n.eq_ignore_ascii_case(name));
+    }
+
+    /// 逗号分隔的头部（例如 Connection）中是否包含某个 token，大小写不敏感
+    pub fn has_token(&self, name: &str, token: &str) -> bool {
+        self.get_all(name)
+            .flat_map(|v| v.split(','))
+            .any(|t| t.trim().eq_ignore_ascii_case(token))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.0.iter().map(|(n, v)| (n.as_str(), v.as_str()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+/// 读取请求时可能出现的错误，每一种错误都对应一个响应状态码
+#[derive(Debug)]
+pub enum HttpError {
+    Io(io::Error),
+    /// 连接在请求开始之前就被对端关闭了
+    Closed,
+    /// 没能在规定时间内收到完整的请求
+    Timeout,
+    BadRequest(&'static str),
+    HeaderTooLarge,
+    BodyTooLarge,
+    NotImplemented(&'static str),
+}
+
+impl HttpError {
+    /// 返回该错误对应的状态码，连接已关闭时没有可以响应的对象
+    pub fn status(&self) -> Option<u16> {
+        match self {
+            HttpError::Io(_) | HttpError::Closed => None,
+            HttpError::Timeout => Some(408),
+            HttpError::BadRequest(_) => Some(400),
+            HttpError::HeaderTooLarge => Some(431),
+            HttpError::BodyTooLarge => Some(413),
+            HttpError::NotImplemented(_) => Some(501),
+        }
+    }
+}
+
+impl fmt::Display for HttpError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HttpError::Io(err) => write!(f, "io error: {}", err),
+            HttpError::Closed => write!(f, "connection closed"),
+            HttpError::Timeout => write!(f, "timed out reading request"),
+            HttpError::BadRequest(msg) => write!(f, "bad request: {}", msg),
+            HttpError::HeaderTooLarge => write!(f, "request header too large"),
+            HttpError::BodyTooLarge => write!(f, "request body too large"),
+            HttpError::NotImplemented(msg) => write!(f, "not implemented: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for HttpError {}
+
+impl From<io::Error> for HttpError {
+    fn from(err: io::Error) -> HttpError {
+        match err.kind() {
+            io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock => HttpError::Timeout,
+            _ => HttpError::Io(err),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Request {
+    pub method: String,
+    /// 请求行中原始的 request-target，例如 `/index.html?a=1`
+    pub target: String,
+    pub path: String,
+    pub query: Option<String>,
+    pub version: String,
+    pub headers: Headers,
+    pub body: Vec<u8>,
+    pub remote_addr: Option<SocketAddr>,
+    /// 路由匹配出来的路径参数
+    pub params: Vec<(String, String)>,
+    /// 匹配到的路由模式，例如 `/items/:id`，没有匹配任何路由时为 None
+    pub route: Option<String>,
+}
+
+impl Request {
+    pub fn new(method: &str, target: &str) -> Request {
+        let (path, query) = match target.split_once('?') {
+            Some((path, query)) => (path.to_string(), Some(query.to_string())),
+            None => (target.to_string(), None),
+        };
+        Request {
+            method: method.to_string(),
+            target: target.to_string(),
+            path,
+            query,
+            version: String::from("HTTP/1.1"),
+            headers: Headers::new(),
+            body: Vec::new(),
+            remote_addr: None,
+            params: Vec::new(),
+            route: None,
+        }
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name)
+    }
+
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// 解码后的查询参数，保留原来的顺序和重复的名字
+    pub fn query_pairs(&self) -> Vec<(String, String)> {
+        self.query.as_deref().map(form::parse).unwrap_or_default()
+    }
+
+    /// 第一个同名查询参数解码后的值
+    pub fn query_param(&self, name: &str) -> Option<String> {
+        self.query_pairs()
+            .into_iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v)
+    }
+
+    /// 客户端是否希望保持连接：HTTP/1.1 默认保持，HTTP/1.0 需要显式声明
+    pub fn keep_alive(&self) -> bool {
+        let connection = self.header("Connection").unwrap_or("").to_ascii_lowercase();
+        let has = |token: &str| connection.split(',').any(|t| t.trim() == token);
+        if self.version == "HTTP/1.0" {
+            has("keep-alive")
+        } else {
+            !has("close")
+        }
+    }
+
+    /// 从一个带缓冲的读取器中解析出一个完整的请求
+    pub fn read_from<R: BufRead>(reader: &mut R, limits: &Limits) -> Result<Request, HttpError> {
+        let mut req = Request::read_head(reader, limits)?;
+        req.read_body(reader, limits)?;
+        Ok(req)
+    }
+
+    /// 只读取请求行和头部，请求体留在 reader 中
+    pub fn read_head<R: BufRead>(reader: &mut R, limits: &Limits) -> Result<Request, HttpError> {
+        let mut budget = limits.max_header_bytes;
+
+        // 按照 RFC 9112 的建议，忽略请求行之前的空行
+        let request_line = loop {
+            let line = match read_line(reader, &mut budget)? {
+                Some(line) => line,
+                None => return Err(HttpError::Closed),
+            };
+            if !line.is_empty() {
+                break line;
+            }
+        };
+
+        let mut parts = request_line.split(' ');
+        let (method, target, version) =
+            match (parts.next(), parts.next(), parts.next(), parts.next()) {
+                (Some(m), Some(t), Some(v), None) if !m.is_empty() && !t.is_empty() => (m, t, v),
+                _ => return Err(HttpError::BadRequest("malformed request line")),
+            };
+        // HTTP/2 的连接前言看起来像一个请求行，交给调用方识别（见 http2 模块）
+        let preface = method == "PRI" && target == "*" && version == "HTTP/2.0";
+        if !version.starts_with("HTTP/1.") && !preface {
+            return Err(HttpError::BadRequest("unsupported http version"));
+        }
+        let mut req = Request::new(method, target);
+        req.version = version.to_string();
+
+        loop {
+            let line = match read_line(reader, &mut budget)? {
+                Some(line) => line,
+                None => return Err(HttpError::BadRequest("unexpected eof in headers")),
+            };
+            if line.is_empty() {
+                break;
+            }
+            let (name, value) = match line.split_once(':') {
+                Some((name, value)) if !name.is_empty() && !name.ends_with(' ') => (name, value),
+                _ => return Err(HttpError::BadRequest("malformed header")),
+            };
+            req.headers.append(name, value.trim());
+        }
+        Ok(req)
+    }
+
+    /// 按照 Content-Length 读取请求体
+    pub fn read_body<R: BufRead>(
+        &mut self,
+        reader: &mut R,
+        limits: &Limits,
+    ) -> Result<(), HttpError> {
+        if self.headers.contains("Transfer-Encoding") {
+            return Err(HttpError::NotImplemented("transfer-encoding"));
+        }
+        if let Some(len) = self.headers.get("Content-Length") {
+            let len: usize = len
+                .parse()
+                .map_err(|_| HttpError::BadRequest("invalid content-length"))?;
+            if len > limits.max_body_bytes {
+                return Err(HttpError::BodyTooLarge);
+            }
+            self.body = vec![0; len];
+            reader.read_exact(&mut self.body)?;
+        }
+        Ok(())
+    }
+}
+
+/// 读取一行（不包含 CRLF），消耗 budget 中的字节数；在任何数据到来之前遇到 EOF 返回 None
+pub(crate) fn read_line<R: BufRead>(
+    reader: &mut R,
+    budget: &mut usize,
+) -> Result<Option<String>, HttpError> {
+    let mut line = Vec::new();
+    // 多读一个字节，用来区分「刚好用完」和「超出限制」
+    let n = reader
+        .by_ref()
+        .take(*budget as u64 + 1)
+        .read_until(b'\n', &mut line)?;
+    if n == 0 {
+        return Ok(None);
+    }
+    if n > *budget {
+        return Err(HttpError::HeaderTooLarge);
+    }
+    *budget -= n;
+    if line.pop() != Some(b'\n') {
+        return Err(HttpError::BadRequest("unexpected eof in headers"));
+    }
+    if line.last() == Some(&b'\r') {
+        line.pop();
+    }
+    String::from_utf8(line)
+        .map(Some)
+        .map_err(|_| HttpError::BadRequest("header is not utf-8"))
+}
+
+/// 响应写出之后接管连接的回调，参数是连接本身、已经读进缓冲区还没有处理的字节，
+/// 以及连接占用的 IP 名额
+type UpgradeFn = Box<dyn FnOnce(TcpStream, Vec<u8>, ConnGuard) + Send>;
+
+/// 回调在服务器自己的线程上执行（工作线程或者事件循环线程），需要长时间使用连接时应该另外启动线程，
+/// 并且把 ConnGuard 一起带过去，这样接管之后的连接仍然计入 max_connections_per_ip
+#[derive(Clone)]
+pub struct Upgrade(Arc<Mutex<Option<UpgradeFn>>>);
+
+impl Upgrade {
+    pub fn new<F>(f: F) -> Upgrade
+    where
+        F: FnOnce(TcpStream, Vec<u8>, ConnGuard) + Send + 'static,
+    {
+        Upgrade(Arc::new(Mutex::new(Some(Box::new(f)))))
+    }
+
+    /// 交出连接，同一个 Upgrade 的克隆之间只有第一次调用生效
+    pub fn run(self, stream: TcpStream, buffered: Vec<u8>, guard: ConnGuard) {
+        let f = self.0.lock().unwrap().take();
+        if let Some(f) = f {
+            f(stream, buffered, guard);
+        }
+    }
+}
+
+impl fmt::Debug for Upgrade {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Upgrade")
+    }
+}
+
+impl PartialEq for Upgrade {
+    fn eq(&self, other: &Upgrade) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for Upgrade {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Response {
+    pub status: u16,
+    pub headers: Headers,
+    pub body: Vec<u8>,
+    /// 响应写出之后接管连接，这样的响应不会自动补上 Content-Length
+    pub upgrade: Option<Upgrade>,
+}
+
+impl Response {
+    pub fn new(status: u16) -> Response {
+        Response {
+            status,
+            headers: Headers::new(),
+            body: Vec::new(),
+            upgrade: None,
+        }
+    }
+
+    pub fn html(status: u16, body: impl Into<Vec<u8>>) -> Response {
+        Response::new(status)
+            .with_header("Content-Type", "text/html; charset=utf-8")
+            .with_body(body)
+    }
+
+    pub fn text(status: u16, body: impl Into<Vec<u8>>) -> Response {
+        Response::new(status)
+            .with_header("Content-Type", "text/plain; charset=utf-8")
+            .with_body(body)
+    }
+
+    /// 使用状态码的原因短语作为响应体，用于各种错误响应
+    pub fn error(status: u16) -> Response {
+        Response::text(status, format!("{} {}\n", status, reason(status)))
+    }
+
+    pub fn with_header(mut self, name: &str, value: impl Into<String>) -> Response {
+        self.headers.set(name, value);
+        self
+    }
+
+    pub fn with_body(mut self, body: impl Into<Vec<u8>>) -> Response {
+        self.body = body.into();
+        self
+    }
+
+    pub fn with_upgrade(mut self, upgrade: Upgrade) -> Response {
+        self.upgrade = Some(upgrade);
+        self
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name)
+    }
+
+    /// 将响应序列化写入 w，没有设置 Content-Length 时会自动补上
+    ///
+    /// 1xx、204 和 304 响应没有响应体，接管连接的响应由 Upgrade 继续写，这几种都不补
+    pub fn write_to<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        let mut head = format!("HTTP/1.1 {} {}\r\n", self.status, reason(self.status));
+        for (name, value) in self.headers.iter() {
+            head.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        let has_body = self.has_body();
+        if has_body && self.upgrade.is_none() && !self.headers.contains("Content-Length") {
+            head.push_str(&format!("Content-Length: {}\r\n", self.body.len()));
+        }
+        head.push_str("\r\n");
+        w.write_all(head.as_bytes())?;
+        if has_body {
+            w.write_all(&self.body)?;
+        }
+        w.flush()
+    }
+
+    fn has_body(&self) -> bool {
+        self.status >= 200 && !matches!(self.status, 204 | 304)
+    }
+
+    /// HEAD 请求由 GET 的处理函数生成响应，写出前去掉响应体，Content-Length 仍是完整响应体的长度
+    pub(crate) fn strip_body(&mut self) {
+        if self.has_body() && self.upgrade.is_none() && !self.headers.contains("Content-Length") {
+            self.headers
+                .set("Content-Length", self.body.len().to_string());
+        }
+        self.body.clear();
+    }
+}
+
+/// 幂等的方法重复发送没有额外的副作用，连接中途出错时可以重试
+pub fn is_idempotent(method: &str) -> bool {
+    matches!(
+        method,
+        "GET" | "HEAD" | "OPTIONS" | "PUT" | "DELETE" | "TRACE"
+    )
+}
+
+/// 状态码对应的原因短语
+pub fn reason(status: u16) -> &'static str {
+    match status {
+        100 => "Continue",
+        101 => "Switching Protocols",
+        200 => "OK",
+        201 => "Created",
+        204 => "No Content",
+        301 => "Moved Permanently",
+        302 => "Found",
+        304 => "Not Modified",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        408 => "Request Timeout",
+        411 => "Length Required",
+        413 => "Content Too Large",
+        415 => "Unsupported Media Type",
+        422 => "Unprocessable Content",
+        426 => "Upgrade Required",
+        429 => "Too Many Requests",
+        431 => "Request Header Fields Too Large",
+        500 => "Internal Server Error",
+        501 => "Not Implemented",
+        502 => "Bad Gateway",
+        503 => "Service Unavailable",
+        504 => "Gateway Timeout",
+        _ => "Unknown",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(raw: &str, limits: &Limits) -> Result<Request, HttpError> {
+        Request::read_from(&mut raw.as_bytes(), limits)
+    }
+
+    #[test]
+    fn parse_request_with_body() {
+        let raw = "POST /items?id=1 HTTP/1.1\r\nHost: x\r\nContent-Length: 5\r\n\r\nhello";
+        let req = parse(raw, &Limits::default()).unwrap();
+        assert_eq!(req.method, "POST");
+        assert_eq!(req.path, "/items");
+        assert_eq!(req.query.as_deref(), Some("id=1"));
+        assert_eq!(req.query_param("id").as_deref(), Some("1"));
+        assert_eq!(req.query_param("missing"), None);
+        assert_eq!(req.header("host"), Some("x"));
+        assert_eq!(req.body, b"hello");
+    }
+
+    #[test]
+    fn header_limit_is_enforced() {
+        let limits = Limits {
+            max_header_bytes: 32,
+            max_body_bytes: 0,
+        };
+        let raw = format!("GET / HTTP/1.1\r\nX-Long: {}\r\n\r\n", "a".repeat(64));
+        assert!(matches!(
+            parse(&raw, &limits),
+            Err(HttpError::HeaderTooLarge)
+        ));
+    }
+
+    #[test]
+    fn body_limit_is_enforced() {
+        let limits = Limits {
+            max_header_bytes: 1024,
+            max_body_bytes: 4,
+        };
+        let raw = "POST / HTTP/1.1\r\nContent-Length: 5\r\n\r\nhello";
+        assert!(matches!(parse(raw, &limits), Err(HttpError::BodyTooLarge)));
+    }
+
+    #[test]
+    fn keep_alive_defaults_by_version() {
+        let mut req = Request::new("GET", "/");
+        assert!(req.keep_alive());
+        req.headers.set("Connection", "Close");
+        assert!(!req.keep_alive());
+        req.version = String::from("HTTP/1.0");
+        assert!(!req.keep_alive());
+        req.headers.set("Connection", "keep-alive");
+        assert!(req.keep_alive());
+    }
+
+    #[test]
+    fn malformed_request_line() {
+        let err = parse("GARBAGE\r\n\r\n", &Limits::default()).unwrap_err();
+        assert_eq!(err.status(), Some(400));
+    }
+
+    #[test]
+    fn response_adds_content_length() {
+        let mut out = Vec::new();
+        Response::text(200, "hi").write_to(&mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(out.contains("Content-Length: 2\r\n"));
+        assert!(out.ends_with("\r\n\r\nhi"));
+    }
+
+    #[test]
+    fn bodiless_responses_have_no_content_length() {
+        for status in [204, 304] {
+            let mut out = Vec::new();
+            Response::text(status, "ignored")
+                .write_to(&mut out)
+                .unwrap();
+            let out = String::from_utf8(out).unwrap();
+            assert!(!out.contains("Content-Length"), "{}", out);
+            assert!(out.ends_with("\r\n\r\n"), "{}", out);
+        }
+
+        let mut resp = Response::text(200, "hello");
+        resp.strip_body();
+        let mut out = Vec::new();
+        resp.write_to(&mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains("Content-Length: 5\r\n"), "{}", out);
+        assert!(out.ends_with("\r\n\r\n"), "{}", out);
+    }
+}
@@ -0,0 +1,1880 @@
+/*
+HTTP/2 明文连接（h2c，RFC 9113）：
+    客户端有两种方式进入 HTTP/2：
+        prior knowledge  一连上就发送连接前言 `PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n`
+        Upgrade: h2c     HTTP/1.1 请求带上 Upgrade: h2c 和 HTTP2-Settings，服务器回复 101，
+                         这个请求本身成为 1 号流，它的响应用 HTTP/2 发送
+    两种情况都是先由 HTTP/1 的解析代码读到请求，再通过 Upgrade 把连接交给一个单独的连接线程，
+    工作线程（或者事件循环）立刻被释放。连接线程一直占用所属 IP 的连接名额，直到连接关闭。
+
+    一个连接上的多个流共用同一个 TCP 连接，帧交错发送：
+        连接线程   读取所有的帧，维护流的状态，收齐一个请求之后交给线程池执行处理链，
+                   线程池队列已满时用 REFUSED_STREAM 拒绝这个流，客户端可以稍后重试
+        流任务     在工作线程上把 Response 编码成 HEADERS 和 DATA 帧发送，写入由一把锁串行化
+    HTTP/1 和 HTTP/2 的请求经过同一个 Pipeline，handler 看到的 Request 的 version 是 HTTP/2.0，
+    伪头部转成普通的字段：:method 和 :path 变成方法和请求目标，:authority 变成 Host，
+    多个 cookie 头部合并成一个。响应头部转成小写，去掉 Connection 这类 HTTP/2 中禁止的头部。
+    接管连接的响应（WebSocket、SSE）没法放在一个流里，返回 501。
+
+    流量控制：发送 DATA 同时受连接窗口和流窗口的限制，窗口用完时流任务等待对端的 WINDOW_UPDATE。
+    收到的请求体直接读进内存（受 max_body_bytes 限制），所以每收到一个 DATA 帧就立即归还窗口。
+    同时处理的流最多 MAX_CONCURRENT_STREAMS 个，超出的流用 REFUSED_STREAM 拒绝。
+    违反协议时发送 GOAWAY 关闭整个连接，只影响一个流的错误用 RST_STREAM 重置这个流。
+    连接上没有进行中的流、空闲超过 keep_alive 超时时，服务器发送 GOAWAY 关闭连接。
+
+    Connection 是一个阻塞的客户端，用于测试和示例，可以在一个连接上同时发出多个请求。
+*/
+
+use crate::{
+    access_log::Record,
+    client::{Client, ClientError, Url, read_response},
+    conn::{ConnGuard, is_timeout},
+    debug,
+    hpack::{Decoder, Encoder},
+    http::{Limits, Request, Response, Upgrade},
+    server::Shared,
+    warn,
+};
+use std::{
+    collections::HashMap,
+    fmt,
+    io::{self, BufRead, BufReader, Cursor, Read, Write},
+    net::{Shutdown, SocketAddr, TcpStream},
+    sync::{
+        Arc, Condvar, Mutex,
+        atomic::{AtomicUsize, Ordering},
+    },
+    thread,
+    time::{Duration, SystemTime},
+};
+
+/// 客户端的连接前言，之后紧跟一个 SETTINGS 帧
+pub const PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+
+const DATA: u8 = 0x0;
+const HEADERS: u8 = 0x1;
+const PRIORITY: u8 = 0x2;
+const RST_STREAM: u8 = 0x3;
+const SETTINGS: u8 = 0x4;
+const PUSH_PROMISE: u8 = 0x5;
+const PING: u8 = 0x6;
+const GOAWAY: u8 = 0x7;
+const WINDOW_UPDATE: u8 = 0x8;
+const CONTINUATION: u8 = 0x9;
+
+const END_STREAM: u8 = 0x1;
+const ACK: u8 = 0x1;
+const END_HEADERS: u8 = 0x4;
+const PADDED: u8 = 0x8;
+const PRIORITY_FLAG: u8 = 0x20;
+
+/// 错误码
+pub const NO_ERROR: u32 = 0x0;
+pub const PROTOCOL_ERROR: u32 = 0x1;
+pub const INTERNAL_ERROR: u32 = 0x2;
+pub const FLOW_CONTROL_ERROR: u32 = 0x3;
+pub const STREAM_CLOSED: u32 = 0x5;
+pub const FRAME_SIZE_ERROR: u32 = 0x6;
+pub const REFUSED_STREAM: u32 = 0x7;
+pub const CANCEL: u32 = 0x8;
+pub const COMPRESSION_ERROR: u32 = 0x9;
+pub const ENHANCE_YOUR_CALM: u32 = 0xb;
+
+/// SETTINGS 参数
+pub const SETTINGS_HEADER_TABLE_SIZE: u16 = 0x1;
+pub const SETTINGS_ENABLE_PUSH: u16 = 0x2;
+pub const SETTINGS_MAX_CONCURRENT_STREAMS: u16 = 0x3;
+pub const SETTINGS_INITIAL_WINDOW_SIZE: u16 = 0x4;
+pub const SETTINGS_MAX_FRAME_SIZE: u16 = 0x5;
+pub const SETTINGS_MAX_HEADER_LIST_SIZE: u16 = 0x6;
+
+/// 流和连接窗口的初始大小
+pub const DEFAULT_WINDOW: u32 = 65_535;
+/// SETTINGS_MAX_FRAME_SIZE 的初始值，也是它的下限
+pub const DEFAULT_MAX_FRAME_SIZE: usize = 16_384;
+const MAX_FRAME_SIZE_LIMIT: u32 = (1 << 24) - 1;
+const MAX_WINDOW: i64 = (1 << 31) - 1;
+
+/// 一个连接上同时处理的流
+pub const MAX_CONCURRENT_STREAMS: usize = 100;
+
+/// HTTP/2 中禁止出现的逐跳头部
+const CONNECTION_HEADERS: [&str; 5] = [
+    "connection",
+    "keep-alive",
+    "proxy-connection",
+    "transfer-encoding",
+    "upgrade",
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Frame {
+    /// pad 是填充占用的字节数（包括 Pad Length 字段），同样计入流量控制
+    Data {
+        stream: u32,
+        data: Vec<u8>,
+        end_stream: bool,
+        pad: usize,
+    },
+    /// block 是头部块的片段，没有 END_HEADERS 时后面跟着 CONTINUATION
+    Headers {
+        stream: u32,
+        block: Vec<u8>,
+        end_stream: bool,
+        end_headers: bool,
+    },
+    /// 优先级在 RFC 9113 中已经废弃，读到之后直接忽略
+    Priority {
+        stream: u32,
+    },
+    RstStream {
+        stream: u32,
+        code: u32,
+    },
+    Settings {
+        ack: bool,
+        params: Vec<(u16, u32)>,
+    },
+    PushPromise {
+        stream: u32,
+    },
+    Ping {
+        ack: bool,
+        data: [u8; 8],
+    },
+    GoAway {
+        last_stream: u32,
+        code: u32,
+        debug: Vec<u8>,
+    },
+    WindowUpdate {
+        stream: u32,
+        increment: u32,
+    },
+    Continuation {
+        stream: u32,
+        block: Vec<u8>,
+        end_headers: bool,
+    },
+    /// 不认识的帧类型，按规范忽略
+    Unknown {
+        kind: u8,
+        stream: u32,
+    },
+}
+
+#[derive(Debug)]
+pub enum H2Error {
+    Io(io::Error),
+    /// 连接错误：发送 GOAWAY 之后关闭整个连接
+    Connection(u32, &'static str),
+    /// 流错误：用 RST_STREAM 重置这个流（流 id，错误码），连接继续使用
+    Stream(u32, u32),
+}
+
+impl fmt::Display for H2Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            H2Error::Io(err) => write!(f, "io error: {}", err),
+            H2Error::Connection(code, msg) => write!(f, "connection error {:#x}: {}", code, msg),
+            H2Error::Stream(stream, code) => write!(f, "stream {} error {:#x}", stream, code),
+        }
+    }
+}
+
+impl std::error::Error for H2Error {}
+
+impl From<io::Error> for H2Error {
+    fn from(err: io::Error) -> H2Error {
+        H2Error::Io(err)
+    }
+}
+
+impl From<H2Error> for ClientError {
+    fn from(err: H2Error) -> ClientError {
+        match err {
+            H2Error::Io(err) => ClientError::from(err),
+            H2Error::Connection(_, msg) => ClientError::Protocol(msg),
+            H2Error::Stream(..) => ClientError::Protocol("stream error"),
+        }
+    }
+}
+
+fn protocol_error(msg: &'static str) -> H2Error {
+    H2Error::Connection(PROTOCOL_ERROR, msg)
+}
+
+fn frame_size_error(msg: &'static str) -> H2Error {
+    H2Error::Connection(FRAME_SIZE_ERROR, msg)
+}
+
+impl Frame {
+    /// 读取一帧，max_size 是这一端通告的 SETTINGS_MAX_FRAME_SIZE
+    pub fn read_from<R: Read>(r: &mut R, max_size: usize) -> Result<Frame, H2Error> {
+        let mut head = [0; 9];
+        r.read_exact(&mut head)?;
+        let len = u32::from_be_bytes([0, head[0], head[1], head[2]]) as usize;
+        let (kind, flags) = (head[3], head[4]);
+        // 最高位保留，读取时忽略
+        let stream = u32::from_be_bytes([head[5], head[6], head[7], head[8]]) & 0x7fff_ffff;
+        if len > max_size {
+            return Err(frame_size_error(
+                "frame larger than SETTINGS_MAX_FRAME_SIZE",
+            ));
+        }
+        let mut payload = vec![0; len];
+        r.read_exact(&mut payload)?;
+
+        let on_stream = matches!(
+            kind,
+            DATA | HEADERS | PRIORITY | RST_STREAM | PUSH_PROMISE | CONTINUATION
+        );
+        let on_connection = matches!(kind, SETTINGS | PING | GOAWAY);
+        if (on_stream && stream == 0) || (on_connection && stream != 0) {
+            return Err(protocol_error("frame sent on the wrong stream"));
+        }
+        let word = |bytes: &[u8]| u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        let frame = match kind {
+            DATA => {
+                let (data, pad) = unpad(flags, payload)?;
+                Frame::Data {
+                    stream,
+                    data,
+                    end_stream: flags & END_STREAM != 0,
+                    pad,
+                }
+            }
+            HEADERS => {
+                let (mut block, _) = unpad(flags, payload)?;
+                if flags & PRIORITY_FLAG != 0 {
+                    if block.len() < 5 {
+                        return Err(frame_size_error("HEADERS too short for its priority"));
+                    }
+                    block.drain(..5);
+                }
+                Frame::Headers {
+                    stream,
+                    block,
+                    end_stream: flags & END_STREAM != 0,
+                    end_headers: flags & END_HEADERS != 0,
+                }
+            }
+            PRIORITY if len != 5 => return Err(H2Error::Stream(stream, FRAME_SIZE_ERROR)),
+            PRIORITY => Frame::Priority { stream },
+            RST_STREAM if len != 4 => return Err(frame_size_error("RST_STREAM must be 4 bytes")),
+            RST_STREAM => Frame::RstStream {
+                stream,
+                code: word(&payload),
+            },
+            SETTINGS => {
+                let ack = flags & ACK != 0;
+                if (ack && len != 0) || !len.is_multiple_of(6) {
+                    return Err(frame_size_error("malformed SETTINGS"));
+                }
+                let params = payload
+                    .chunks(6)
+                    .map(|p| (u16::from_be_bytes([p[0], p[1]]), word(&p[2..])))
+                    .collect();
+                Frame::Settings { ack, params }
+            }
+            PUSH_PROMISE => Frame::PushPromise { stream },
+            PING if len != 8 => return Err(frame_size_error("PING must be 8 bytes")),
+            PING => Frame::Ping {
+                ack: flags & ACK != 0,
+                data: payload.try_into().unwrap(),
+            },
+            GOAWAY if len < 8 => return Err(frame_size_error("GOAWAY too short")),
+            GOAWAY => Frame::GoAway {
+                last_stream: word(&payload) & 0x7fff_ffff,
+                code: word(&payload[4..]),
+                debug: payload[8..].to_vec(),
+            },
+            WINDOW_UPDATE if len != 4 => {
+                return Err(frame_size_error("WINDOW_UPDATE must be 4 bytes"));
+            }
+            WINDOW_UPDATE => {
+                let increment = word(&payload) & 0x7fff_ffff;
+                match (increment, stream) {
+                    (0, 0) => return Err(protocol_error("zero window increment")),
+                    (0, _) => return Err(H2Error::Stream(stream, PROTOCOL_ERROR)),
+                    _ => Frame::WindowUpdate { stream, increment },
+                }
+            }
+            CONTINUATION => Frame::Continuation {
+                stream,
+                block: payload,
+                end_headers: flags & END_HEADERS != 0,
+            },
+            _ => Frame::Unknown { kind, stream },
+        };
+        Ok(frame)
+    }
+
+    /// 帧头和负载一次写出
+    pub fn write_to<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        let flag = |set: bool, flag: u8| if set { flag } else { 0 };
+        let (kind, flags, stream, payload) = match self {
+            Frame::Data {
+                stream,
+                data,
+                end_stream,
+                pad,
+            } => {
+                let mut payload = Vec::with_capacity(data.len() + pad);
+                if *pad > 0 {
+                    payload.push((pad - 1) as u8);
+                }
+                payload.extend_from_slice(data);
+                payload.resize(data.len() + pad, 0);
+                let flags = flag(*end_stream, END_STREAM) | flag(*pad > 0, PADDED);
+                (DATA, flags, *stream, payload)
+            }
+            Frame::Headers {
+                stream,
+                block,
+                end_stream,
+                end_headers,
+            } => {
+                let flags = flag(*end_stream, END_STREAM) | flag(*end_headers, END_HEADERS);
+                (HEADERS, flags, *stream, block.clone())
+            }
+            // 依赖 0 号流，权重 16
+            Frame::Priority { stream } => (PRIORITY, 0, *stream, vec![0, 0, 0, 0, 15]),
+            Frame::RstStream { stream, code } => {
+                (RST_STREAM, 0, *stream, code.to_be_bytes().to_vec())
+            }
+            Frame::Settings { ack, params } => {
+                let mut payload = Vec::with_capacity(params.len() * 6);
+                for (id, value) in params {
+                    payload.extend_from_slice(&id.to_be_bytes());
+                    payload.extend_from_slice(&value.to_be_bytes());
+                }
+                (SETTINGS, flag(*ack, ACK), 0, payload)
+            }
+            Frame::PushPromise { stream } => (PUSH_PROMISE, END_HEADERS, *stream, vec![0; 4]),
+            Frame::Ping { ack, data } => (PING, flag(*ack, ACK), 0, data.to_vec()),
+            Frame::GoAway {
+                last_stream,
+                code,
+                debug,
+            } => {
+                let mut payload = last_stream.to_be_bytes().to_vec();
+                payload.extend_from_slice(&code.to_be_bytes());
+                payload.extend_from_slice(debug);
+                (GOAWAY, 0, 0, payload)
+            }
+            Frame::WindowUpdate { stream, increment } => {
+                (WINDOW_UPDATE, 0, *stream, increment.to_be_bytes().to_vec())
+            }
+            Frame::Continuation {
+                stream,
+                block,
+                end_headers,
+            } => (
+                CONTINUATION,
+                flag(*end_headers, END_HEADERS),
+                *stream,
+                block.clone(),
+            ),
+            Frame::Unknown { kind, stream } => (*kind, 0, *stream, Vec::new()),
+        };
+        let mut buf = Vec::with_capacity(9 + payload.len());
+        buf.extend_from_slice(&(payload.len() as u32).to_be_bytes()[1..]);
+        buf.push(kind);
+        buf.push(flags);
+        buf.extend_from_slice(&stream.to_be_bytes());
+        buf.extend_from_slice(&payload);
+        w.write_all(&buf)
+    }
+}
+
+/// 去掉 DATA 和 HEADERS 的填充，返回内容和填充占用的字节数
+fn unpad(flags: u8, mut payload: Vec<u8>) -> Result<(Vec<u8>, usize), H2Error> {
+    if flags & PADDED == 0 {
+        return Ok((payload, 0));
+    }
+    let pad = match payload.first() {
+        Some(&pad) if (pad as usize) < payload.len() => pad as usize,
+        _ => return Err(protocol_error("padding longer than the frame")),
+    };
+    payload.truncate(payload.len() - pad);
+    payload.remove(0);
+    Ok((payload, pad + 1))
+}
+
+/// 一个头部块编码成 HEADERS 加上若干 CONTINUATION，中间不能插入其他帧，所以一起写出
+fn header_frames(stream: u32, block: &[u8], end_stream: bool, max_frame: usize) -> Vec<u8> {
+    let chunks: Vec<&[u8]> = if block.is_empty() {
+        vec![&[]]
+    } else {
+        block.chunks(max_frame).collect()
+    };
+    let mut buf = Vec::with_capacity(block.len() + 9 * chunks.len());
+    let last = chunks.len() - 1;
+    for (i, chunk) in chunks.iter().enumerate() {
+        let frame = if i == 0 {
+            Frame::Headers {
+                stream,
+                block: chunk.to_vec(),
+                end_stream,
+                end_headers: i == last,
+            }
+        } else {
+            Frame::Continuation {
+                stream,
+                block: chunk.to_vec(),
+                end_headers: i == last,
+            }
+        };
+        // 写入 Vec 不会失败
+        let _ = frame.write_to(&mut buf);
+    }
+    buf
+}
+
+/// HTTP/1 解析出来的请求是不是 HTTP/2 的连接前言
+pub fn is_preface(req: &Request) -> bool {
+    req.method == "PRI" && req.target == "*" && req.version == "HTTP/2.0"
+}
+
+/// 设置了 AsyncHandler 时请求不经过 Pipeline，HTTP/2 的流也就没有处理的地方
+fn enabled(shared: &Shared) -> bool {
+    #[cfg(target_os = "linux")]
+    if shared.async_handler.is_some() {
+        return false;
+    }
+    shared.config.http2
+}
+
+/// 收到连接前言之后接管连接，没有启用 HTTP/2 时返回 None
+pub(crate) fn accept_preface(peer: SocketAddr, shared: &Arc<Shared>) -> Option<Upgrade> {
+    if !enabled(shared) {
+        return None;
+    }
+    let shared = Arc::clone(shared);
+    Some(Upgrade::new(move |stream, buffered, guard| {
+        spawn(stream, buffered, peer, shared, None, guard)
+    }))
+}
+
+/// 请求带有 Upgrade: h2c 时返回 101 响应，之后原来的请求作为 1 号流处理
+///
+/// HTTP2-Settings 缺失或者无法解码时按普通的 HTTP/1.1 请求处理
+pub(crate) fn accept_upgrade(
+    req: &Request,
+    peer: SocketAddr,
+    shared: &Arc<Shared>,
+) -> Option<Response> {
+    if !enabled(shared)
+        || !req.headers.has_token("Upgrade", "h2c")
+        || !req.headers.has_token("Connection", "Upgrade")
+    {
+        return None;
+    }
+    let mut values = req.headers.get_all("HTTP2-Settings");
+    let settings = match (values.next(), values.next()) {
+        (Some(value), None) => decode_settings(value)?,
+        _ => return None,
+    };
+    let mut request = req.clone();
+    for name in ["Connection", "Upgrade", "HTTP2-Settings"] {
+        request.headers.remove(name);
+    }
+    request.version = String::from("HTTP/2.0");
+    let shared = Arc::clone(shared);
+    Some(
+        Response::new(101)
+            .with_header("Connection", "Upgrade")
+            .with_header("Upgrade", "h2c")
+            .with_upgrade(Upgrade::new(move |stream, buffered, guard| {
+                spawn(
+                    stream,
+                    buffered,
+                    peer,
+                    shared,
+                    Some((settings, request)),
+                    guard,
+                )
+            })),
+    )
+}
+
+/// HTTP2-Settings 是 SETTINGS 帧负载的 base64url 编码（不带填充）
+fn decode_settings(value: &str) -> Option<Vec<(u16, u32)>> {
+    let payload = base64url_decode(value.trim())?;
+    if !payload.len().is_multiple_of(6) {
+        return None;
+    }
+    let params = payload
+        .chunks(6)
+        .map(|p| {
+            let value = u32::from_be_bytes([p[2], p[3], p[4], p[5]]);
+            (u16::from_be_bytes([p[0], p[1]]), value)
+        })
+        .collect();
+    Some(params)
+}
+
+fn base64url_decode(s: &str) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity(s.len() * 3 / 4);
+    let mut acc = 0u32;
+    let mut bits = 0;
+    for b in s.trim_end_matches('=').bytes() {
+        let v = match b {
+            b'A'..=b'Z' => b - b'A',
+            b'a'..=b'z' => b - b'a' + 26,
+            b'0'..=b'9' => b - b'0' + 52,
+            b'-' => 62,
+            b'_' => 63,
+            _ => return None,
+        };
+        acc = (acc << 6) | v as u32;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((acc >> bits) as u8);
+        }
+    }
+    Some(out)
+}
+
+fn base64url_encode(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+    let mut out = String::with_capacity(data.len().div_ceil(3) * 4);
+    let mut acc = 0u32;
+    let mut bits = 0;
+    for &b in data {
+        acc = (acc << 8) | b as u32;
+        bits += 8;
+        while bits >= 6 {
+            bits -= 6;
+            out.push(ALPHABET[(acc >> bits) as usize & 63] as char);
+        }
+    }
+    if bits > 0 {
+        out.push(ALPHABET[(acc << (6 - bits)) as usize & 63] as char);
+    }
+    out
+}
+
+type Upgraded = Option<(Vec<(u16, u32)>, Request)>;
+
+fn spawn(
+    stream: TcpStream,
+    buffered: Vec<u8>,
+    peer: SocketAddr,
+    shared: Arc<Shared>,
+    upgraded: Upgraded,
+    guard: ConnGuard,
+) {
+    let spawned = thread::Builder::new()
+        .name(String::from("http2"))
+        .spawn(move || {
+            if let Err(err) = serve(stream, buffered, peer, shared, upgraded) {
+                debug!("http2 connection from {}: {}", peer, err);
+            }
+            drop(guard);
+        });
+    if let Err(err) = spawned {
+        warn!("cannot start http2 connection: {}", err);
+    }
+}
+
+/// 连接线程和流任务共享的状态
+struct Session {
+    writer: Mutex<Writer>,
+    flow: Mutex<Flow>,
+    /// 窗口变大、流被重置或者连接断开时通知等待窗口的流任务，流结束时通知等待的连接线程
+    flow_changed: Condvar,
+    /// 正在执行处理链或者发送响应的流，包括还在线程池里排队的流
+    active: AtomicUsize,
+    peer: SocketAddr,
+    shared: Arc<Shared>,
+}
+
+struct Writer {
+    stream: TcpStream,
+    /// 头部块必须按写出的顺序编码，所以编码器和连接放在同一把锁里
+    encoder: Encoder,
+}
+
+/// 发送方向的流量控制，窗口可能因为对端调小 SETTINGS_INITIAL_WINDOW_SIZE 变成负数
+struct Flow {
+    window: i64,
+    /// 还在发送响应的流，被对端重置的流会被移除
+    streams: HashMap<u32, i64>,
+    /// 对端的 SETTINGS_INITIAL_WINDOW_SIZE 和 SETTINGS_MAX_FRAME_SIZE
+    initial_window: i64,
+    max_frame: usize,
+    closed: bool,
+}
+
+impl Session {
+    fn write(&self, frame: &Frame) -> io::Result<()> {
+        let mut buf = Vec::new();
+        let _ = frame.write_to(&mut buf);
+        self.write_bytes(|_| buf)
+    }
+
+    /// 在持有写锁的情况下生成要写出的字节，写失败之后不再等待窗口
+    fn write_bytes<F: FnOnce(&mut Encoder) -> Vec<u8>>(&self, make: F) -> io::Result<()> {
+        let result = {
+            let mut writer = self.writer.lock().unwrap();
+            let buf = make(&mut writer.encoder);
+            writer.stream.write_all(&buf)
+        };
+        if result.is_err() {
+            self.close();
+        }
+        result
+    }
+
+    fn reset(&self, stream: u32, code: u32) -> io::Result<()> {
+        self.cancel(stream);
+        self.write(&Frame::RstStream { stream, code })
+    }
+
+    /// 流被重置，正在等待窗口的流任务放弃发送
+    fn cancel(&self, stream: u32) {
+        if self.flow.lock().unwrap().streams.remove(&stream).is_some() {
+            self.flow_changed.notify_all();
+        }
+    }
+
+    fn close(&self) {
+        self.flow.lock().unwrap().closed = true;
+        self.flow_changed.notify_all();
+    }
+
+    fn window_update(&self, stream: u32, increment: u32) -> Result<(), H2Error> {
+        let mut flow = self.flow.lock().unwrap();
+        if stream == 0 {
+            flow.window += increment as i64;
+            if flow.window > MAX_WINDOW {
+                return Err(H2Error::Connection(
+                    FLOW_CONTROL_ERROR,
+                    "connection window overflow",
+                ));
+            }
+        } else if let Some(window) = flow.streams.get_mut(&stream) {
+            *window += increment as i64;
+            if *window > MAX_WINDOW {
+                drop(flow);
+                self.reset(stream, FLOW_CONTROL_ERROR)?;
+                return Ok(());
+            }
+        }
+        drop(flow);
+        self.flow_changed.notify_all();
+        Ok(())
+    }
+
+    fn apply_settings(&self, params: &[(u16, u32)]) -> Result<(), H2Error> {
+        for &(id, value) in params {
+            match id {
+                SETTINGS_HEADER_TABLE_SIZE => {
+                    self.writer
+                        .lock()
+                        .unwrap()
+                        .encoder
+                        .set_max_table_size(value as usize);
+                }
+                SETTINGS_ENABLE_PUSH if value > 1 => {
+                    return Err(protocol_error("invalid SETTINGS_ENABLE_PUSH"));
+                }
+                SETTINGS_INITIAL_WINDOW_SIZE => {
+                    if value as i64 > MAX_WINDOW {
+                        return Err(H2Error::Connection(
+                            FLOW_CONTROL_ERROR,
+                            "invalid SETTINGS_INITIAL_WINDOW_SIZE",
+                        ));
+                    }
+                    // 新的初始值按差值调整所有已经打开的流
+                    let mut flow = self.flow.lock().unwrap();
+                    let delta = value as i64 - flow.initial_window;
+                    flow.initial_window = value as i64;
+                    for window in flow.streams.values_mut() {
+                        *window += delta;
+                        if *window > MAX_WINDOW {
+                            return Err(H2Error::Connection(
+                                FLOW_CONTROL_ERROR,
+                                "stream window overflow",
+                            ));
+                        }
+                    }
+                }
+                SETTINGS_MAX_FRAME_SIZE => {
+                    if !(DEFAULT_MAX_FRAME_SIZE as u32..=MAX_FRAME_SIZE_LIMIT).contains(&value) {
+                        return Err(protocol_error("invalid SETTINGS_MAX_FRAME_SIZE"));
+                    }
+                    self.flow.lock().unwrap().max_frame = value as usize;
+                }
+                _ => {}
+            }
+        }
+        self.flow_changed.notify_all();
+        Ok(())
+    }
+
+    /// 开始处理一个流：打开它的发送窗口
+    fn open(&self, stream: u32) {
+        let mut flow = self.flow.lock().unwrap();
+        let initial = flow.initial_window;
+        flow.streams.insert(stream, initial);
+        self.active.fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn finish(&self, stream: u32) {
+        // 持有锁的时候减少计数并通知，等待所有流结束的连接线程不会错过唤醒
+        let mut flow = self.flow.lock().unwrap();
+        flow.streams.remove(&stream);
+        self.active.fetch_sub(1, Ordering::SeqCst);
+        self.flow_changed.notify_all();
+    }
+
+    /// 等待所有的流任务结束
+    fn wait_streams(&self) {
+        let mut flow = self.flow.lock().unwrap();
+        while self.active.load(Ordering::SeqCst) > 0 {
+            flow = self.flow_changed.wait(flow).unwrap();
+        }
+    }
+
+    fn is_open(&self, stream: u32) -> bool {
+        self.flow.lock().unwrap().streams.contains_key(&stream)
+    }
+
+    /// 流任务：执行处理链（或者使用已经决定好的响应），发送响应
+    ///
+    /// complete 为 false 时请求体还没有收完，发完响应之后用 NO_ERROR 重置这个流，让客户端停止发送
+    fn run_stream(&self, stream: u32, mut req: Request, fixed: Option<Response>, complete: bool) {
+        let resp = match fixed {
+            Some(resp) => {
+                // 没有经过处理链，单独记录访问日志
+                let record = Record::new(Some(&req), &resp, SystemTime::now(), Duration::ZERO);
+                self.shared.access_log.record(&record);
+                resp
+            }
+            None => self.shared.handle(&mut req),
+        };
+        let mut result = self.respond(stream, &req, resp);
+        if result.is_ok() && !complete {
+            result = self.write(&Frame::RstStream {
+                stream,
+                code: NO_ERROR,
+            });
+        }
+        if let Err(err) = result {
+            debug!("http2 stream {} from {}: {}", stream, self.peer, err);
+        }
+    }
+
+    fn respond(&self, stream: u32, req: &Request, mut resp: Response) -> io::Result<()> {
+        if resp.upgrade.is_some() {
+            warn!(
+                "{} {}: responses that take over the connection are not supported over http/2",
+                req.method, req.target
+            );
+            resp = Response::error(501);
+        }
+        let no_body = req.method == "HEAD" || matches!(resp.status, 204 | 304);
+        let body: &[u8] = if no_body { &[] } else { &resp.body };
+        let headers = response_headers(&resp);
+        if !self.is_open(stream) {
+            return Ok(());
+        }
+        let max_frame = self.flow.lock().unwrap().max_frame;
+        self.write_bytes(|encoder| {
+            header_frames(
+                stream,
+                &encoder.encode(&headers),
+                body.is_empty(),
+                max_frame,
+            )
+        })?;
+        self.send_data(stream, body)
+    }
+
+    /// 按流量控制窗口分块发送响应体，最后一块带 END_STREAM
+    fn send_data(&self, stream: u32, body: &[u8]) -> io::Result<()> {
+        let timeout = self.shared.config.write_timeout;
+        let mut sent = 0;
+        while sent < body.len() {
+            let n = {
+                let mut flow = self.flow.lock().unwrap();
+                loop {
+                    if flow.closed {
+                        return Err(io::Error::new(
+                            io::ErrorKind::ConnectionAborted,
+                            "connection closed",
+                        ));
+                    }
+                    // 被对端重置的流不再发送
+                    let Some(&window) = flow.streams.get(&stream) else {
+                        return Ok(());
+                    };
+                    let available = flow.window.min(window);
+                    if available > 0 {
+                        let n = (available as usize)
+                            .min(body.len() - sent)
+                            .min(flow.max_frame);
+                        flow.window -= n as i64;
+                        *flow.streams.get_mut(&stream).unwrap() -= n as i64;
+                        break n;
+                    }
+                    // 对端一直不归还窗口时，和写超时一样放弃这个流
+                    flow = match timeout {
+                        Some(timeout) => {
+                            let (flow, waited) =
+                                self.flow_changed.wait_timeout(flow, timeout).unwrap();
+                            if waited.timed_out() {
+                                drop(flow);
+                                self.reset(stream, CANCEL)?;
+                                return Err(io::Error::new(
+                                    io::ErrorKind::TimedOut,
+                                    "timed out waiting for the flow-control window",
+                                ));
+                            }
+                            flow
+                        }
+                        None => self.flow_changed.wait(flow).unwrap(),
+                    };
+                }
+            };
+            self.write(&Frame::Data {
+                stream,
+                data: body[sent..sent + n].to_vec(),
+                end_stream: sent + n == body.len(),
+                pad: 0,
+            })?;
+            sent += n;
+        }
+        Ok(())
+    }
+}
+
+/// :status 加上转成小写的响应头部，补上 content-length
+fn response_headers(resp: &Response) -> Vec<(String, String)> {
+    let mut headers = vec![(String::from(":status"), resp.status.to_string())];
+    for (name, value) in resp.headers.iter() {
+        let name = name.to_ascii_lowercase();
+        if !CONNECTION_HEADERS.contains(&name.as_str()) {
+            headers.push((name, value.to_string()));
+        }
+    }
+    if !matches!(resp.status, 204 | 304) && !resp.headers.contains("Content-Length") {
+        headers.push((String::from("content-length"), resp.body.len().to_string()));
+    }
+    headers
+}
+
+/// 把解码出来的头部列表转成 Request，格式不对时返回原因（流会被 PROTOCOL_ERROR 重置）
+fn build_request(fields: Vec<(String, String)>) -> Result<Request, &'static str> {
+    let (mut method, mut scheme, mut path, mut authority) = (None, None, None, None);
+    let mut regular = Vec::new();
+    let mut cookies = Vec::new();
+    for (name, value) in fields {
+        if let Some(pseudo) = name.strip_prefix(':') {
+            if !regular.is_empty() || !cookies.is_empty() {
+                return Err("pseudo-header after a regular header");
+            }
+            let slot = match pseudo {
+                "method" => &mut method,
+                "scheme" => &mut scheme,
+                "path" => &mut path,
+                "authority" => &mut authority,
+                _ => return Err("unknown pseudo-header"),
+            };
+            if slot.replace(value).is_some() {
+                return Err("duplicate pseudo-header");
+            }
+            continue;
+        }
+        if name.bytes().any(|b| b.is_ascii_uppercase()) {
+            return Err("uppercase header name");
+        }
+        if CONNECTION_HEADERS.contains(&name.as_str()) || (name == "te" && value != "trailers") {
+            return Err("connection-specific header");
+        }
+        if name == "cookie" {
+            cookies.push(value);
+        } else {
+            regular.push((name, value));
+        }
+    }
+    // CONNECT 没有 :scheme 和 :path，这里不支持
+    let (Some(method), Some(_), Some(path)) = (method, scheme, path) else {
+        return Err("missing pseudo-header");
+    };
+    if path.is_empty() {
+        return Err("empty :path");
+    }
+    let mut req = Request::new(&method, &path);
+    req.version = String::from("HTTP/2.0");
+    if let Some(authority) = authority
+        && !regular.iter().any(|(name, _)| name == "host")
+    {
+        req.headers.append("host", authority);
+    }
+    for (name, value) in regular {
+        req.headers.append(&name, value);
+    }
+    if !cookies.is_empty() {
+        req.headers.append("cookie", cookies.join("; "));
+    }
+    Ok(req)
+}
+
+/// 正在接收请求体的流
+struct Incoming {
+    req: Request,
+    /// content-length 头部的值，收齐之后和实际长度比较
+    expected: Option<usize>,
+}
+
+/// 连接线程的状态
+struct Conn {
+    session: Arc<Session>,
+    decoder: Decoder,
+    limits: Limits,
+    incoming: HashMap<u32, Incoming>,
+    /// 客户端打开过的最大的流 id，也是 GOAWAY 中的 last stream id
+    last_stream: u32,
+    /// 客户端发送了 GOAWAY，不会再有新的流
+    going_away: bool,
+    /// 收到了没有 END_HEADERS 的 HEADERS：流 id、已经收到的头部块、END_STREAM
+    continuation: Option<(u32, Vec<u8>, bool)>,
+}
+
+/// 一个已经打开的流，丢弃时结束这个流
+///
+/// 流任务执行完，或者任务没有被线程池执行就被丢弃（队列已满、线程池关闭）时都会丢弃它
+struct OpenStream {
+    session: Arc<Session>,
+    stream: u32,
+}
+
+impl Drop for OpenStream {
+    fn drop(&mut self) {
+        self.session.finish(self.stream);
+    }
+}
+
+fn serve(
+    stream: TcpStream,
+    buffered: Vec<u8>,
+    peer: SocketAddr,
+    shared: Arc<Shared>,
+    upgraded: Upgraded,
+) -> Result<(), H2Error> {
+    let config = &shared.config;
+    stream.set_write_timeout(config.write_timeout)?;
+    // HEADERS 和 DATA 是分开写的，不关掉 Nagle 算法的话第二次写要等对端的延迟 ACK
+    stream.set_nodelay(true)?;
+    // 读超时只用来发现空闲的连接
+    stream.set_read_timeout(config.keep_alive_timeout)?;
+    let mut reader = BufReader::new(Cursor::new(buffered).chain(stream.try_clone()?));
+    let limits = config.limits;
+    let session = Arc::new(Session {
+        writer: Mutex::new(Writer {
+            stream,
+            encoder: Encoder::new(),
+        }),
+        flow: Mutex::new(Flow {
+            window: DEFAULT_WINDOW as i64,
+            streams: HashMap::new(),
+            initial_window: DEFAULT_WINDOW as i64,
+            max_frame: DEFAULT_MAX_FRAME_SIZE,
+            closed: false,
+        }),
+        flow_changed: Condvar::new(),
+        active: AtomicUsize::new(0),
+        peer,
+        shared: Arc::clone(&shared),
+    });
+    // 服务器的连接前言是一个 SETTINGS 帧，不需要等客户端
+    session.write(&Frame::Settings {
+        ack: false,
+        params: vec![
+            (
+                SETTINGS_MAX_CONCURRENT_STREAMS,
+                MAX_CONCURRENT_STREAMS as u32,
+            ),
+            (
+                SETTINGS_MAX_HEADER_LIST_SIZE,
+                limits.max_header_bytes as u32,
+            ),
+        ],
+    })?;
+
+    let mut conn = Conn {
+        session: Arc::clone(&session),
+        decoder: Decoder::new(crate::hpack::DEFAULT_TABLE_SIZE),
+        limits,
+        incoming: HashMap::new(),
+        last_stream: 0,
+        going_away: false,
+        continuation: None,
+    };
+    let result = conn.run(&mut reader, upgraded);
+    if let Err(H2Error::Connection(code, msg)) = &result {
+        let _ = session.write(&Frame::GoAway {
+            last_stream: conn.last_stream,
+            code: *code,
+            debug: msg.as_bytes().to_vec(),
+        });
+    }
+    // 已经有窗口的响应继续写完，等待窗口的流放弃
+    session.close();
+    session.wait_streams();
+    let _ = session
+        .writer
+        .lock()
+        .unwrap()
+        .stream
+        .shutdown(Shutdown::Both);
+    result
+}
+
+impl Conn {
+    fn run<R: BufRead>(&mut self, reader: &mut R, upgraded: Upgraded) -> Result<(), H2Error> {
+        // prior knowledge 时 HTTP/1 的解析代码已经读走了前言的第一部分
+        let rest = if upgraded.is_some() {
+            PREFACE
+        } else {
+            &PREFACE[18..]
+        };
+        let mut preface = vec![0; rest.len()];
+        reader.read_exact(&mut preface)?;
+        if preface != rest {
+            return Err(protocol_error("invalid connection preface"));
+        }
+        if let Some((settings, req)) = upgraded {
+            self.session.apply_settings(&settings)?;
+            self.last_stream = 1;
+            self.dispatch(1, req, None, true);
+        }
+
+        let mut first = true;
+        loop {
+            match reader.fill_buf() {
+                Ok([]) => return Ok(()),
+                Ok(_) => {}
+                Err(err) if is_timeout(&err) => {
+                    if self.incoming.is_empty() && self.session.active.load(Ordering::SeqCst) == 0 {
+                        debug!("closing idle http2 connection from {}", self.session.peer);
+                        self.session.write(&Frame::GoAway {
+                            last_stream: self.last_stream,
+                            code: NO_ERROR,
+                            debug: Vec::new(),
+                        })?;
+                        return Ok(());
+                    }
+                    continue;
+                }
+                Err(err) => return Err(err.into()),
+            }
+            let frame = match Frame::read_from(reader, DEFAULT_MAX_FRAME_SIZE) {
+                Ok(frame) => frame,
+                Err(H2Error::Stream(stream, code)) => {
+                    self.incoming.remove(&stream);
+                    self.session.reset(stream, code)?;
+                    continue;
+                }
+                Err(err) => return Err(err),
+            };
+            // 客户端的连接前言以 SETTINGS 结束
+            if first && !matches!(frame, Frame::Settings { ack: false, .. }) {
+                return Err(protocol_error("expected SETTINGS after the preface"));
+            }
+            first = false;
+            self.handle(frame)?;
+        }
+    }
+
+    fn handle(&mut self, frame: Frame) -> Result<(), H2Error> {
+        // 头部块没有结束时，只能收到同一个流的 CONTINUATION
+        if let Some((stream, mut block, end_stream)) = self.continuation.take() {
+            let Frame::Continuation {
+                stream: next,
+                block: more,
+                end_headers,
+            } = frame
+            else {
+                return Err(protocol_error("expected CONTINUATION"));
+            };
+            if next != stream {
+                return Err(protocol_error("CONTINUATION on a different stream"));
+            }
+            block.extend_from_slice(&more);
+            // 头部块在解码之前没有大小限制，防止被无限的 CONTINUATION 占满内存
+            if block.len() > 4 * self.limits.max_header_bytes {
+                return Err(H2Error::Connection(
+                    ENHANCE_YOUR_CALM,
+                    "header block too large",
+                ));
+            }
+            if !end_headers {
+                self.continuation = Some((stream, block, end_stream));
+                return Ok(());
+            }
+            return self.on_headers(stream, &block, end_stream);
+        }
+
+        match frame {
+            Frame::Data {
+                stream,
+                data,
+                end_stream,
+                pad,
+            } => self.on_data(stream, data, end_stream, pad),
+            Frame::Headers {
+                stream,
+                block,
+                end_stream,
+                end_headers: true,
+            } => self.on_headers(stream, &block, end_stream),
+            Frame::Headers {
+                stream,
+                block,
+                end_stream,
+                end_headers: false,
+            } => {
+                self.continuation = Some((stream, block, end_stream));
+                Ok(())
+            }
+            Frame::Continuation { .. } => Err(protocol_error("unexpected CONTINUATION")),
+            Frame::RstStream { stream, .. } => {
+                if stream > self.last_stream {
+                    return Err(protocol_error("RST_STREAM on an idle stream"));
+                }
+                self.incoming.remove(&stream);
+                self.session.cancel(stream);
+                Ok(())
+            }
+            Frame::Settings { ack: true, .. } => Ok(()),
+            Frame::Settings { ack: false, params } => {
+                self.session.apply_settings(&params)?;
+                self.session.write(&Frame::Settings {
+                    ack: true,
+                    params: Vec::new(),
+                })?;
+                Ok(())
+            }
+            Frame::PushPromise { .. } => Err(protocol_error("clients cannot push")),
+            Frame::Ping { ack: false, data } => {
+                self.session.write(&Frame::Ping { ack: true, data })?;
+                Ok(())
+            }
+            Frame::GoAway { code, .. } => {
+                debug!(
+                    "http2 client {} is going away, code {:#x}",
+                    self.session.peer, code
+                );
+                self.going_away = true;
+                Ok(())
+            }
+            Frame::WindowUpdate { stream, increment } => {
+                self.session.window_update(stream, increment)
+            }
+            Frame::Ping { ack: true, .. } | Frame::Priority { .. } | Frame::Unknown { .. } => {
+                Ok(())
+            }
+        }
+    }
+
+    fn on_headers(&mut self, stream: u32, block: &[u8], end_stream: bool) -> Result<(), H2Error> {
+        // 即使之后要拒绝这个流，也必须先解码，动态表是整个连接共享的
+        let fields = self
+            .decoder
+            .decode(block)
+            .map_err(|err| H2Error::Connection(COMPRESSION_ERROR, err.0))?;
+        if stream.is_multiple_of(2) {
+            return Err(protocol_error("clients must use odd stream ids"));
+        }
+        if let Some(incoming) = self.incoming.remove(&stream) {
+            // 请求体之后的 trailers，内容被忽略
+            if !end_stream {
+                self.session.reset(stream, PROTOCOL_ERROR)?;
+                return Ok(());
+            }
+            return self.complete(stream, incoming);
+        }
+        if stream <= self.last_stream {
+            self.session.reset(stream, STREAM_CLOSED)?;
+            return Ok(());
+        }
+        self.last_stream = stream;
+        let active = self.incoming.len() + self.session.active.load(Ordering::SeqCst);
+        if self.going_away || active >= MAX_CONCURRENT_STREAMS {
+            self.session.reset(stream, REFUSED_STREAM)?;
+            return Ok(());
+        }
+
+        let list_size: usize = fields.iter().map(|(n, v)| n.len() + v.len() + 32).sum();
+        let mut req = match build_request(fields) {
+            Ok(req) => req,
+            Err(msg) => {
+                debug!(
+                    "malformed http2 request from {}: {}",
+                    self.session.peer, msg
+                );
+                self.session.reset(stream, PROTOCOL_ERROR)?;
+                return Ok(());
+            }
+        };
+        req.remote_addr = Some(self.session.peer);
+        debug!("Request: {} {} {}", req.method, req.target, req.version);
+        if list_size > self.limits.max_header_bytes {
+            self.dispatch(stream, req, Some(Response::error(431)), end_stream);
+            return Ok(());
+        }
+        let expected = match req.header("content-length").map(str::parse::<usize>) {
+            None => None,
+            Some(Ok(len)) => Some(len),
+            Some(Err(_)) => {
+                self.session.reset(stream, PROTOCOL_ERROR)?;
+                return Ok(());
+            }
+        };
+        if expected.is_some_and(|len| len > self.limits.max_body_bytes) {
+            self.dispatch(stream, req, Some(Response::error(413)), end_stream);
+            return Ok(());
+        }
+        let incoming = Incoming { req, expected };
+        if end_stream {
+            self.complete(stream, incoming)
+        } else {
+            self.incoming.insert(stream, incoming);
+            Ok(())
+        }
+    }
+
+    fn on_data(
+        &mut self,
+        stream: u32,
+        data: Vec<u8>,
+        end_stream: bool,
+        pad: usize,
+    ) -> Result<(), H2Error> {
+        if stream > self.last_stream {
+            return Err(protocol_error("DATA on an idle stream"));
+        }
+        // 数据已经读进内存，连接窗口马上归还；已经关闭或者重置的流上的数据也要计入连接窗口
+        let len = (data.len() + pad) as u32;
+        if len > 0 {
+            self.session.write(&Frame::WindowUpdate {
+                stream: 0,
+                increment: len,
+            })?;
+        }
+        let Some(incoming) = self.incoming.get_mut(&stream) else {
+            return Ok(());
+        };
+        if incoming.req.body.len() + data.len() > self.limits.max_body_bytes {
+            let incoming = self.incoming.remove(&stream).unwrap();
+            self.dispatch(stream, incoming.req, Some(Response::error(413)), end_stream);
+            return Ok(());
+        }
+        incoming.req.body.extend_from_slice(&data);
+        if end_stream {
+            let incoming = self.incoming.remove(&stream).unwrap();
+            return self.complete(stream, incoming);
+        }
+        if len > 0 {
+            self.session.write(&Frame::WindowUpdate {
+                stream,
+                increment: len,
+            })?;
+        }
+        Ok(())
+    }
+
+    /// 请求已经收齐
+    fn complete(&mut self, stream: u32, incoming: Incoming) -> Result<(), H2Error> {
+        if incoming
+            .expected
+            .is_some_and(|len| len != incoming.req.body.len())
+        {
+            self.session.reset(stream, PROTOCOL_ERROR)?;
+            return Ok(());
+        }
+        self.dispatch(stream, incoming.req, None, true);
+        Ok(())
+    }
+
+    /// 把请求交给线程池处理，fixed 不为空时直接发送这个响应
+    ///
+    /// 和事件循环一样使用 try_execute：连接线程不能因为队列满了而阻塞，也不能自己去执行处理链
+    fn dispatch(&mut self, stream: u32, req: Request, fixed: Option<Response>, complete: bool) {
+        self.session.open(stream);
+        let open = OpenStream {
+            session: Arc::clone(&self.session),
+            stream,
+        };
+        let submitted = self.session.shared.pool.try_execute(move || {
+            open.session.run_stream(open.stream, req, fixed, complete);
+        });
+        // 没有执行的任务已经被丢弃，流也随之结束
+        if let Err(err) = submitted {
+            warn!("refusing http2 stream from {}: {}", self.session.peer, err);
+            let _ = self.session.reset(stream, REFUSED_STREAM);
+        }
+    }
+}
+
+/// 客户端收到的一个流的响应
+#[derive(Default)]
+struct Pending {
+    resp: Option<Response>,
+    done: bool,
+    reset: Option<u32>,
+}
+
+/// 阻塞的 HTTP/2 客户端连接
+///
+/// request 发出一个请求并返回流 id，response 读取这个流的响应，
+/// 期间收到的其他流的帧先缓存起来，所以可以先发出多个请求再逐个读取响应
+pub struct Connection {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+    encoder: Encoder,
+    decoder: Decoder,
+    authority: String,
+    next_stream: u32,
+    pending: HashMap<u32, Pending>,
+    /// 发送方向的连接窗口和各个流的窗口
+    window: i64,
+    windows: HashMap<u32, i64>,
+    initial_window: i64,
+    max_frame: usize,
+    /// 服务器发送了 GOAWAY，其中的 last stream id
+    goaway: Option<u32>,
+}
+
+/// 用 prior knowledge 连接 http:// 地址（路径被忽略）
+pub fn connect(url: &str) -> Result<Connection, ClientError> {
+    let url = Url::parse(url)?;
+    let stream = Client::new().connect(&url)?;
+    let reader = BufReader::new(stream.try_clone()?);
+    let mut conn = Connection::new(reader, stream, url.authority, 1);
+    conn.writer.write_all(PREFACE)?;
+    conn.send_frame(&Frame::Settings {
+        ack: false,
+        params: Vec::new(),
+    })?;
+    Ok(conn)
+}
+
+/// 发送一个带 Upgrade: h2c 的 GET 请求，返回升级之后的连接和这个请求（1 号流）的响应
+pub fn connect_upgrade(url: &str) -> Result<(Connection, Response), ClientError> {
+    let url = Url::parse(url)?;
+    let mut stream = Client::new().connect(&url)?;
+    write!(
+        stream,
+        "GET {} HTTP/1.1\r\nHost: {}\r\nConnection: Upgrade, HTTP2-Settings\r\n\
+         Upgrade: h2c\r\nHTTP2-Settings: {}\r\n\r\n",
+        url.target,
+        url.authority,
+        base64url_encode(&[])
+    )?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let (resp, _) = read_response(&mut reader, "GET", &Limits::default())?;
+    if resp.status != 101 {
+        return Err(ClientError::Protocol("server refused the h2c upgrade"));
+    }
+    // 服务器的 SETTINGS 可能已经在 reader 的缓冲区里了，所以继续使用同一个 reader
+    let mut conn = Connection::new(reader, stream, url.authority, 3);
+    conn.windows.insert(1, conn.initial_window);
+    conn.writer.write_all(PREFACE)?;
+    conn.send_frame(&Frame::Settings {
+        ack: false,
+        params: Vec::new(),
+    })?;
+    let resp = conn.response(1)?;
+    Ok((conn, resp))
+}
+
+impl Connection {
+    fn new(
+        reader: BufReader<TcpStream>,
+        writer: TcpStream,
+        authority: String,
+        next_stream: u32,
+    ) -> Connection {
+        Connection {
+            reader,
+            writer,
+            encoder: Encoder::new(),
+            decoder: Decoder::new(crate::hpack::DEFAULT_TABLE_SIZE),
+            authority,
+            next_stream,
+            pending: HashMap::new(),
+            window: DEFAULT_WINDOW as i64,
+            windows: HashMap::new(),
+            initial_window: DEFAULT_WINDOW as i64,
+            max_frame: DEFAULT_MAX_FRAME_SIZE,
+            goaway: None,
+        }
+    }
+
+    /// 发出一个请求，返回它的流 id；请求体受流量控制，窗口不够时先处理服务器发来的帧
+    pub fn request(&mut self, req: &Request) -> Result<u32, ClientError> {
+        let stream = self.next_stream;
+        self.next_stream += 2;
+        let authority = req.header("Host").unwrap_or(&self.authority);
+        let mut fields = vec![
+            (String::from(":method"), req.method.clone()),
+            (String::from(":scheme"), String::from("http")),
+            (String::from(":path"), req.target.clone()),
+            (String::from(":authority"), authority.to_string()),
+        ];
+        for (name, value) in req.headers.iter() {
+            let name = name.to_ascii_lowercase();
+            if name != "host" && !CONNECTION_HEADERS.contains(&name.as_str()) {
+                fields.push((name, value.to_string()));
+            }
+        }
+        let block = self.encoder.encode(&fields);
+        self.windows.insert(stream, self.initial_window);
+        self.writer.write_all(&header_frames(
+            stream,
+            &block,
+            req.body.is_empty(),
+            self.max_frame,
+        ))?;
+
+        let mut sent = 0;
+        while sent < req.body.len() {
+            // 服务器提前给出响应或者重置了流（比如请求体太大）时不再继续发送
+            if self
+                .pending
+                .get(&stream)
+                .is_some_and(|pending| pending.done || pending.reset.is_some())
+            {
+                break;
+            }
+            let available = self.window.min(self.windows[&stream]);
+            if available <= 0 {
+                self.read_frame()?;
+                continue;
+            }
+            let n = (available as usize)
+                .min(req.body.len() - sent)
+                .min(self.max_frame);
+            self.window -= n as i64;
+            *self.windows.get_mut(&stream).unwrap() -= n as i64;
+            self.send_frame(&Frame::Data {
+                stream,
+                data: req.body[sent..sent + n].to_vec(),
+                end_stream: sent + n == req.body.len(),
+                pad: 0,
+            })?;
+            sent += n;
+        }
+        Ok(stream)
+    }
+
+    /// 读取一个流的完整响应
+    pub fn response(&mut self, stream: u32) -> Result<Response, ClientError> {
+        loop {
+            if let Some(pending) = self.pending.get(&stream) {
+                // 完整的响应之后跟着 RST_STREAM(NO_ERROR) 只是让客户端停止发送请求体
+                if pending.done {
+                    let pending = self.pending.remove(&stream).unwrap();
+                    self.windows.remove(&stream);
+                    return pending
+                        .resp
+                        .ok_or(ClientError::Protocol("stream ended without a response"));
+                }
+                if pending.reset.is_some() {
+                    self.pending.remove(&stream);
+                    self.windows.remove(&stream);
+                    return Err(ClientError::Protocol("stream reset by the server"));
+                }
+            }
+            if self.goaway.is_some_and(|last| stream > last) {
+                return Err(ClientError::Protocol("stream refused by GOAWAY"));
+            }
+            self.read_frame()?;
+        }
+    }
+
+    /// 发出请求并等待它的响应
+    pub fn send(&mut self, req: &Request) -> Result<Response, ClientError> {
+        let stream = self.request(req)?;
+        self.response(stream)
+    }
+
+    pub fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.writer.set_read_timeout(timeout)
+    }
+
+    /// 底层的连接，测试里可以用它发送任意的帧
+    pub fn get_ref(&self) -> &TcpStream {
+        &self.writer
+    }
+
+    fn send_frame(&mut self, frame: &Frame) -> io::Result<()> {
+        frame.write_to(&mut self.writer)
+    }
+
+    /// 读取并处理一帧
+    fn read_frame(&mut self) -> Result<(), ClientError> {
+        match Frame::read_from(&mut self.reader, DEFAULT_MAX_FRAME_SIZE)? {
+            Frame::Settings { ack: false, params } => {
+                for (id, value) in params {
+                    match id {
+                        SETTINGS_HEADER_TABLE_SIZE => {
+                            self.encoder.set_max_table_size(value as usize)
+                        }
+                        SETTINGS_INITIAL_WINDOW_SIZE => {
+                            let delta = value as i64 - self.initial_window;
+                            self.initial_window = value as i64;
+                            self.windows.values_mut().for_each(|w| *w += delta);
+                        }
+                        SETTINGS_MAX_FRAME_SIZE => self.max_frame = value as usize,
+                        _ => {}
+                    }
+                }
+                self.send_frame(&Frame::Settings {
+                    ack: true,
+                    params: Vec::new(),
+                })?;
+            }
+            Frame::Ping { ack: false, data } => {
+                self.send_frame(&Frame::Ping { ack: true, data })?;
+            }
+            Frame::WindowUpdate { stream, increment } => {
+                let window = match stream {
+                    0 => Some(&mut self.window),
+                    _ => self.windows.get_mut(&stream),
+                };
+                if let Some(window) = window {
+                    *window += increment as i64;
+                }
+            }
+            Frame::Headers {
+                stream,
+                mut block,
+                end_stream,
+                mut end_headers,
+            } => {
+                while !end_headers {
+                    match Frame::read_from(&mut self.reader, DEFAULT_MAX_FRAME_SIZE)? {
+                        Frame::Continuation {
+                            stream: next,
+                            block: more,
+                            end_headers: end,
+                        } if next == stream => {
+                            block.extend_from_slice(&more);
+                            end_headers = end;
+                        }
+                        _ => return Err(ClientError::Protocol("expected CONTINUATION")),
+                    }
+                }
+                let fields = self
+                    .decoder
+                    .decode(&block)
+                    .map_err(|_| ClientError::Protocol("invalid header block"))?;
+                let pending = self.pending.entry(stream).or_default();
+                // 第一个头部块是响应头，1xx 之后的才是真正的响应，之后的是 trailers
+                if pending.resp.is_none() {
+                    let status = fields
+                        .iter()
+                        .find(|(name, _)| name == ":status")
+                        .and_then(|(_, value)| value.parse::<u16>().ok())
+                        .ok_or(ClientError::Protocol("missing :status"))?;
+                    if status >= 200 {
+                        let mut resp = Response::new(status);
+                        for (name, value) in fields.iter().filter(|(n, _)| !n.starts_with(':')) {
+                            resp.headers.append(name, value.as_str());
+                        }
+                        pending.resp = Some(resp);
+                    }
+                }
+                pending.done |= end_stream;
+            }
+            Frame::Data {
+                stream,
+                data,
+                end_stream,
+                pad,
+            } => {
+                let len = (data.len() + pad) as u32;
+                if len > 0 {
+                    self.send_frame(&Frame::WindowUpdate {
+                        stream: 0,
+                        increment: len,
+                    })?;
+                    if !end_stream {
+                        self.send_frame(&Frame::WindowUpdate {
+                            stream,
+                            increment: len,
+                        })?;
+                    }
+                }
+                let pending = self.pending.entry(stream).or_default();
+                if let Some(resp) = &mut pending.resp {
+                    resp.body.extend_from_slice(&data);
+                }
+                pending.done |= end_stream;
+            }
+            Frame::RstStream { stream, code } => {
+                self.pending.entry(stream).or_default().reset = Some(code);
+            }
+            Frame::GoAway { last_stream, .. } => self.goaway = Some(last_stream),
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(frame: Frame) -> Frame {
+        let mut buf = Vec::new();
+        frame.write_to(&mut buf).unwrap();
+        Frame::read_from(&mut &buf[..], DEFAULT_MAX_FRAME_SIZE).unwrap()
+    }
+
+    fn read(bytes: &[u8]) -> Result<Frame, H2Error> {
+        Frame::read_from(&mut &bytes[..], DEFAULT_MAX_FRAME_SIZE)
+    }
+
+    #[test]
+    fn frames_round_trip() {
+        let frames = [
+            Frame::Data {
+                stream: 1,
+                data: b"hello".to_vec(),
+                end_stream: true,
+                pad: 4,
+            },
+            Frame::Headers {
+                stream: 3,
+                block: vec![0x82, 0x84],
+                end_stream: false,
+                end_headers: true,
+            },
+            Frame::RstStream {
+                stream: 5,
+                code: CANCEL,
+            },
+            Frame::Settings {
+                ack: false,
+                params: vec![(SETTINGS_INITIAL_WINDOW_SIZE, 1 << 20)],
+            },
+            Frame::Ping {
+                ack: true,
+                data: *b"12345678",
+            },
+            Frame::GoAway {
+                last_stream: 7,
+                code: PROTOCOL_ERROR,
+                debug: b"bye".to_vec(),
+            },
+            Frame::WindowUpdate {
+                stream: 0,
+                increment: 1000,
+            },
+            Frame::Continuation {
+                stream: 3,
+                block: vec![0x86],
+                end_headers: true,
+            },
+            Frame::Unknown {
+                kind: 0xfa,
+                stream: 0,
+            },
+        ];
+        for frame in frames {
+            assert_eq!(round_trip(frame.clone()), frame);
+        }
+    }
+
+    #[test]
+    fn headers_with_padding_and_priority() {
+        // HEADERS，流 1，PADDED | PRIORITY | END_HEADERS，填充 2 字节，优先级 5 字节
+        let bytes = [
+            0, 0, 10, HEADERS, 0x2c, 0, 0, 0, 1, 2, 0, 0, 0, 0, 16, 0x82, 0x84, 0, 0,
+        ];
+        assert_eq!(
+            read(&bytes).unwrap(),
+            Frame::Headers {
+                stream: 1,
+                block: vec![0x82, 0x84],
+                end_stream: false,
+                end_headers: true,
+            }
+        );
+    }
+
+    #[test]
+    fn invalid_frames() {
+        let code = |bytes: &[u8]| match read(bytes) {
+            Err(H2Error::Connection(code, _)) => code,
+            other => panic!("expected a connection error, got {:?}", other),
+        };
+        // 超过 SETTINGS_MAX_FRAME_SIZE
+        assert_eq!(code(&[0, 0x40, 1, DATA, 0, 0, 0, 0, 1]), FRAME_SIZE_ERROR);
+        // DATA 在 0 号流上，SETTINGS 在非 0 号流上
+        assert_eq!(code(&[0, 0, 0, DATA, 0, 0, 0, 0, 0]), PROTOCOL_ERROR);
+        assert_eq!(code(&[0, 0, 0, SETTINGS, 0, 0, 0, 0, 1]), PROTOCOL_ERROR);
+        // 带负载的 SETTINGS ACK、长度不是 6 的倍数
+        assert_eq!(
+            code(&[0, 0, 6, SETTINGS, ACK, 0, 0, 0, 0, 0, 4, 0, 0, 0, 1]),
+            FRAME_SIZE_ERROR
+        );
+        assert_eq!(
+            code(&[0, 0, 1, SETTINGS, 0, 0, 0, 0, 0, 0]),
+            FRAME_SIZE_ERROR
+        );
+        // 填充比帧还长
+        assert_eq!(
+            code(&[0, 0, 2, DATA, PADDED, 0, 0, 0, 1, 5, 0]),
+            PROTOCOL_ERROR
+        );
+        assert_eq!(
+            code(&[0, 0, 4, WINDOW_UPDATE, 0, 0, 0, 0, 0, 0, 0, 0, 0]),
+            PROTOCOL_ERROR
+        );
+        // 流上的 0 增量只重置这个流
+        assert!(matches!(
+            read(&[0, 0, 4, WINDOW_UPDATE, 0, 0, 0, 0, 3, 0, 0, 0, 0]),
+            Err(H2Error::Stream(3, PROTOCOL_ERROR))
+        ));
+    }
+
+    #[test]
+    fn long_header_blocks_use_continuation() {
+        let block: Vec<u8> = (0..40).collect();
+        let bytes = header_frames(1, &block, true, 16);
+        let mut r = &bytes[..];
+        let mut frames = Vec::new();
+        while !r.is_empty() {
+            frames.push(Frame::read_from(&mut r, DEFAULT_MAX_FRAME_SIZE).unwrap());
+        }
+        assert_eq!(frames.len(), 3);
+        assert!(matches!(
+            &frames[0],
+            Frame::Headers {
+                end_stream: true,
+                end_headers: false,
+                ..
+            }
+        ));
+        assert!(matches!(
+            &frames[2],
+            Frame::Continuation {
+                end_headers: true,
+                block,
+                ..
+            } if block.len() == 8
+        ));
+    }
+
+    #[test]
+    fn maps_pseudo_headers_to_a_request() {
+        let fields = |list: &[(&str, &str)]| {
+            list.iter()
+                .map(|(n, v)| (n.to_string(), v.to_string()))
+                .collect::<Vec<_>>()
+        };
+        let req = build_request(fields(&[
+            (":method", "POST"),
+            (":scheme", "http"),
+            (":path", "/items?page=2"),
+            (":authority", "example.com"),
+            ("cookie", "a=1"),
+            ("content-type", "text/plain"),
+            ("cookie", "b=2"),
+        ]))
+        .unwrap();
+        assert_eq!(req.method, "POST");
+        assert_eq!(req.path, "/items");
+        assert_eq!(req.query.as_deref(), Some("page=2"));
+        assert_eq!(req.version, "HTTP/2.0");
+        assert_eq!(req.header("Host"), Some("example.com"));
+        assert_eq!(req.header("Cookie"), Some("a=1; b=2"));
+
+        let malformed = [
+            &[(":method", "GET"), (":path", "/")][..],
+            &[
+                (":method", "GET"),
+                (":scheme", "http"),
+                (":path", "/"),
+                (":path", "/"),
+            ],
+            &[
+                (":method", "GET"),
+                (":scheme", "http"),
+                (":path", "/"),
+                (":foo", "x"),
+            ],
+            &[
+                (":method", "GET"),
+                ("accept", "*/*"),
+                (":scheme", "http"),
+                (":path", "/"),
+            ],
+            &[
+                (":method", "GET"),
+                (":scheme", "http"),
+                (":path", "/"),
+                ("Accept", "*/*"),
+            ],
+            &[
+                (":method", "GET"),
+                (":scheme", "http"),
+                (":path", "/"),
+                ("connection", "close"),
+            ],
+            &[
+                (":method", "GET"),
+                (":scheme", "http"),
+                (":path", "/"),
+                ("te", "gzip"),
+            ],
+        ];
+        for list in malformed {
+            assert!(build_request(fields(list)).is_err(), "{:?}", list);
+        }
+    }
+
+    #[test]
+    fn response_headers_are_lowercase_without_hop_by_hop() {
+        let resp = Response::text(200, "hi")
+            .with_header("Connection", "keep-alive")
+            .with_header("X-Request-Id", "7");
+        assert_eq!(
+            response_headers(&resp),
+            [
+                (":status", "200"),
+                ("content-type", "text/plain; charset=utf-8"),
+                ("x-request-id", "7"),
+                ("content-length", "2"),
+            ]
+            .map(|(n, v)| (n.to_string(), v.to_string()))
+        );
+    }
+
+    #[test]
+    fn http2_settings_header() {
+        let payload = [0, 4, 0, 0, 0x40, 0, 0, 3, 0, 0, 0, 100];
+        let encoded = base64url_encode(&payload);
+        assert_eq!(encoded, "AAQAAEAAAAMAAABk");
+        assert_eq!(
+            decode_settings(&encoded),
+            Some(vec![
+                (SETTINGS_INITIAL_WINDOW_SIZE, 16384),
+                (SETTINGS_MAX_CONCURRENT_STREAMS, 100)
+            ])
+        );
+        assert_eq!(decode_settings(""), Some(Vec::new()));
+        assert_eq!(decode_settings("AAQ"), None);
+        assert_eq!(decode_settings("AA+A"), None);
+    }
+}
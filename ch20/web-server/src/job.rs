@@ -0,0 +1,452 @@
+/*
+带返回值的任务：
+    ThreadPool::spawn 提交一个有返回值的任务，得到 JobHandle<T>：
+        join()                阻塞等待结果
+        join_timeout(d)       最多等待 d，超时返回 None，之后还可以继续等待
+        is_finished()         不阻塞地检查任务是否结束
+        .await                JobHandle 同时也是一个 Future，可以在异步代码里等待
+    任务 panic 时得到 JobError::Panicked，panic 仍然会交给线程池的 panic 回调并计入统计；
+    任务还没执行就被丢弃（比如线程池关闭）时得到 JobError::Cancelled。
+
+    ThreadPool::scope 和 std::thread::scope 类似：作用域里提交的任务可以借用调用方栈上的数据，
+    scope 在所有任务结束之后才返回，即使传入的闭包 panic 也一样。
+    注意不要在线程池自己的任务里调用 scope，工作线程都在等待时任务永远得不到执行。
+*/
+
+use crate::{ExecuteError, Job, ThreadPool};
+use std::{
+    error::Error,
+    fmt,
+    future::Future,
+    marker::PhantomData,
+    mem,
+    panic::{self, AssertUnwindSafe},
+    pin::Pin,
+    sync::{Arc, Condvar, Mutex},
+    task::{Context, Poll, Waker},
+    time::{Duration, Instant},
+};
+
+/// 任务没有产生结果的原因
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum JobError {
+    /// 任务 panic 了，附带 panic 的消息
+    Panicked(String),
+    /// 任务还没执行就被丢弃了
+    Cancelled,
+}
+
+impl fmt::Display for JobError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            JobError::Panicked(msg) => write!(f, "job panicked: {}", msg),
+            JobError::Cancelled => write!(f, "job was cancelled before it ran"),
+        }
+    }
+}
+
+impl Error for JobError {}
+
+/// 任务和 JobHandle 共享的结果槽
+struct Slot<T> {
+    state: Mutex<State<T>>,
+    done: Condvar,
+}
+
+struct State<T> {
+    result: Option<Result<T, JobError>>,
+    /// 结果已经被取走
+    taken: bool,
+    waker: Option<Waker>,
+}
+
+impl<T> Slot<T> {
+    fn new() -> Slot<T> {
+        Slot {
+            state: Mutex::new(State {
+                result: None,
+                taken: false,
+                waker: None,
+            }),
+            done: Condvar::new(),
+        }
+    }
+
+    fn set(&self, result: Result<T, JobError>) {
+        let mut state = self.state.lock().unwrap();
+        state.result = Some(result);
+        self.done.notify_all();
+        if let Some(waker) = state.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+/// 放在任务里，任务没有给出结果就被丢弃时把结果设为 Cancelled
+struct Completer<T> {
+    slot: Option<Arc<Slot<T>>>,
+}
+
+impl<T> Completer<T> {
+    fn complete(mut self, result: Result<T, JobError>) {
+        self.slot.take().unwrap().set(result);
+    }
+}
+
+impl<T> Drop for Completer<T> {
+    fn drop(&mut self) {
+        if let Some(slot) = self.slot.take() {
+            slot.set(Err(JobError::Cancelled));
+        }
+    }
+}
+
+/// 把有返回值的闭包包装成线程池的任务
+fn wrap<'a, F, T>(f: F) -> (Box<dyn FnOnce() + Send + 'a>, JobHandle<T>)
+where
+    F: FnOnce() -> T + Send + 'a,
+    T: Send + 'a,
+{
+    let slot = Arc::new(Slot::new());
+    let completer = Completer {
+        slot: Some(Arc::clone(&slot)),
+    };
+    let job = move || match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(value) => completer.complete(Ok(value)),
+        Err(payload) => {
+            let msg = if let Some(msg) = payload.downcast_ref::<&str>() {
+                msg.to_string()
+            } else if let Some(msg) = payload.downcast_ref::<String>() {
+                msg.clone()
+            } else {
+                String::from("Box<dyn Any>")
+            };
+            completer.complete(Err(JobError::Panicked(msg)));
+            // 继续向外抛出，交给工作线程的 panic 回调和计数
+            panic::resume_unwind(payload);
+        }
+    };
+    (Box::new(job), JobHandle { slot })
+}
+
+/// 任务的句柄，用来取得任务的返回值；丢弃句柄不会取消任务
+pub struct JobHandle<T> {
+    slot: Arc<Slot<T>>,
+}
+
+impl<T> JobHandle<T> {
+    /// 阻塞等待任务结束
+    ///
+    /// # Panics
+    ///
+    /// 结果已经通过 join_timeout 或者 .await 取走时会 panic
+    pub fn join(self) -> Result<T, JobError> {
+        let state = self.slot.state.lock().unwrap();
+        let mut state = self
+            .slot
+            .done
+            .wait_while(state, |state| state.result.is_none() && !state.taken)
+            .unwrap();
+        take(&mut state)
+    }
+
+    /// 最多等待 timeout，任务还没结束时返回 None；timeout 太长、截止时间超出 Instant 的范围时一直等下去
+    ///
+    /// # Panics
+    ///
+    /// 结果已经被取走时会 panic
+    pub fn join_timeout(&mut self, timeout: Duration) -> Option<Result<T, JobError>> {
+        let deadline = Instant::now().checked_add(timeout);
+        let mut state = self.slot.state.lock().unwrap();
+        let Some(deadline) = deadline else {
+            let mut state = self
+                .slot
+                .done
+                .wait_while(state, |state| state.result.is_none() && !state.taken)
+                .unwrap();
+            return Some(take(&mut state));
+        };
+        while state.result.is_none() && !state.taken {
+            let now = Instant::now();
+            if now >= deadline {
+                return None;
+            }
+            state = self
+                .slot
+                .done
+                .wait_timeout(state, deadline - now)
+                .unwrap()
+                .0;
+        }
+        Some(take(&mut state))
+    }
+
+    /// 任务是否已经结束（包括 panic 和被取消）
+    pub fn is_finished(&self) -> bool {
+        let state = self.slot.state.lock().unwrap();
+        state.result.is_some() || state.taken
+    }
+}
+
+fn take<T>(state: &mut State<T>) -> Result<T, JobError> {
+    assert!(!state.taken, "job result already taken");
+    state.taken = true;
+    state.result.take().unwrap()
+}
+
+impl<T> Future for JobHandle<T> {
+    type Output = Result<T, JobError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut state = self.slot.state.lock().unwrap();
+        if state.result.is_some() || state.taken {
+            return Poll::Ready(take(&mut state));
+        }
+        state.waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+impl<T> fmt::Debug for JobHandle<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("JobHandle")
+            .field("finished", &self.is_finished())
+            .finish()
+    }
+}
+
+/// ThreadPool::scope 中提交任务的作用域
+pub struct Scope<'scope, 'env: 'scope> {
+    pool: &'scope ThreadPool,
+    pending: Arc<Pending>,
+    scope: PhantomData<&'scope mut &'scope ()>,
+    env: PhantomData<&'env mut &'env ()>,
+}
+
+/// 作用域里还没结束的任务数
+#[derive(Default)]
+struct Pending {
+    count: Mutex<usize>,
+    done: Condvar,
+}
+
+/// 任务的闭包被消耗或者丢弃之后才减少计数，这时任务已经不再引用作用域外的数据
+struct PendingGuard(Arc<Pending>);
+
+impl Drop for PendingGuard {
+    fn drop(&mut self) {
+        let mut count = self.0.count.lock().unwrap();
+        *count -= 1;
+        if *count == 0 {
+            self.0.done.notify_all();
+        }
+    }
+}
+
+/// 结构体的字段按声明顺序丢弃，job 一定在 guard 之前被丢弃
+struct ScopedJob<'scope> {
+    job: Box<dyn FnOnce() + Send + 'scope>,
+    _guard: PendingGuard,
+}
+
+impl<'scope> Scope<'scope, '_> {
+    /// 提交一个可以借用作用域外数据的任务
+    pub fn spawn<F, T>(&'scope self, f: F) -> Result<JobHandle<T>, ExecuteError>
+    where
+        F: FnOnce() -> T + Send + 'scope,
+        T: Send + 'scope,
+    {
+        let (job, handle) = wrap(f);
+        *self.pending.count.lock().unwrap() += 1;
+        let scoped = ScopedJob {
+            job,
+            _guard: PendingGuard(Arc::clone(&self.pending)),
+        };
+        let job: Box<dyn FnOnce() + Send + 'scope> = Box::new(move || {
+            let scoped = scoped;
+            (scoped.job)();
+        });
+        // SAFETY: scope 返回之前会等待 pending 归零，也就是所有任务的闭包都已经被消耗或者丢弃，
+        // 闭包借用的 'scope 数据在这之前一直有效
+        let job: Job = unsafe { mem::transmute::<Box<dyn FnOnce() + Send + 'scope>, Job>(job) };
+        self.pool.submit(job)?;
+        Ok(handle)
+    }
+
+    fn wait(&self) {
+        let count = self.pending.count.lock().unwrap();
+        let count = self.pending.done.wait_while(count, |count| *count > 0);
+        drop(count.unwrap());
+    }
+}
+
+impl ThreadPool {
+    /// 提交一个有返回值的任务
+    ///
+    /// ```
+    /// let pool = web_server::ThreadPool::new(2);
+    /// let handle = pool.spawn(|| 6 * 7).unwrap();
+    /// assert_eq!(handle.join(), Ok(42));
+    /// ```
+    pub fn spawn<F, T>(&self, f: F) -> Result<JobHandle<T>, ExecuteError>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let (job, handle) = wrap(f);
+        self.submit(job)?;
+        Ok(handle)
+    }
+
+    /// 创建一个作用域，作用域里的任务可以借用调用方的数据，所有任务结束后才返回
+    ///
+    /// ```
+    /// let pool = web_server::ThreadPool::new(2);
+    /// let words = vec!["hello", "scoped", "jobs"];
+    /// let total = pool.scope(|s| {
+    ///     let handles: Vec<_> = words
+    ///         .iter()
+    ///         .map(|word| s.spawn(move || word.len()).unwrap())
+    ///         .collect();
+    ///     handles.into_iter().map(|h| h.join().unwrap()).sum::<usize>()
+    /// });
+    /// assert_eq!(total, 15);
+    /// ```
+    pub fn scope<'env, F, R>(&self, f: F) -> R
+    where
+        F: for<'scope> FnOnce(&'scope Scope<'scope, 'env>) -> R,
+    {
+        let scope = Scope {
+            pool: self,
+            pending: Arc::new(Pending::default()),
+            scope: PhantomData,
+            env: PhantomData,
+        };
+        let result = panic::catch_unwind(AssertUnwindSafe(|| f(&scope)));
+        scope.wait();
+        match result {
+            Ok(value) => value,
+            Err(payload) => panic::resume_unwind(payload),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        sync::{atomic::AtomicUsize, atomic::Ordering, mpsc},
+        task::Wake,
+        thread,
+    };
+
+    #[test]
+    fn join_returns_values_and_panics() {
+        let pool = ThreadPool::builder(2)
+            .panic_handler(|_| {})
+            .build()
+            .unwrap();
+        let ok = pool.spawn(|| String::from("done")).unwrap();
+        let failed = pool.spawn(|| -> u32 { panic!("bad input {}", 7) }).unwrap();
+        assert_eq!(ok.join(), Ok(String::from("done")));
+        assert_eq!(
+            failed.join(),
+            Err(JobError::Panicked(String::from("bad input 7")))
+        );
+        // panic 同样计入线程池的统计
+        let monitor = pool.monitor();
+        drop(pool);
+        assert_eq!(monitor.stats().panicked, 1);
+    }
+
+    #[test]
+    fn join_timeout_can_be_retried() {
+        let pool = ThreadPool::new(1);
+        let (release, gate) = mpsc::channel::<()>();
+        let mut handle = pool
+            .spawn(move || {
+                gate.recv().unwrap();
+                1
+            })
+            .unwrap();
+        assert_eq!(handle.join_timeout(Duration::from_millis(20)), None);
+        assert!(!handle.is_finished());
+        release.send(()).unwrap();
+        assert_eq!(handle.join_timeout(Duration::from_secs(5)), Some(Ok(1)));
+        assert!(handle.is_finished());
+
+        let mut handle = pool.spawn(|| 2).unwrap();
+        assert_eq!(handle.join_timeout(Duration::MAX), Some(Ok(2)));
+    }
+
+    #[test]
+    fn dropped_jobs_are_cancelled() {
+        let (job, handle) = wrap(|| 1);
+        drop(job);
+        assert_eq!(handle.join(), Err(JobError::Cancelled));
+    }
+
+    struct CountWakes(AtomicUsize);
+
+    impl Wake for CountWakes {
+        fn wake(self: Arc<Self>) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn handles_are_futures() {
+        let pool = ThreadPool::new(1);
+        let (release, gate) = mpsc::channel::<()>();
+        let mut handle = pool.spawn(move || gate.recv().map(|_| "ready")).unwrap();
+        let wakes = Arc::new(CountWakes(AtomicUsize::new(0)));
+        let waker = Waker::from(Arc::clone(&wakes));
+        let mut cx = Context::from_waker(&waker);
+        assert!(Pin::new(&mut handle).poll(&mut cx).is_pending());
+        release.send(()).unwrap();
+        while wakes.0.load(Ordering::SeqCst) == 0 {
+            thread::sleep(Duration::from_millis(1));
+        }
+        assert_eq!(
+            Pin::new(&mut handle).poll(&mut cx),
+            Poll::Ready(Ok(Ok("ready")))
+        );
+    }
+
+    #[test]
+    fn scoped_jobs_borrow_from_the_stack() {
+        let pool = ThreadPool::new(3);
+        let mut counts = [0usize; 8];
+        let text = "the quick brown fox jumps over the lazy dog";
+        pool.scope(|s| {
+            for (i, count) in counts.iter_mut().enumerate() {
+                // 每个任务独占一个元素，同时共享 text
+                s.spawn(move || {
+                    *count = text.split(' ').filter(|w| w.len() == i).count();
+                })
+                .unwrap();
+            }
+        });
+        assert_eq!(counts, [0, 0, 0, 4, 2, 3, 0, 0]);
+    }
+
+    #[test]
+    fn scope_waits_for_jobs_even_when_the_closure_panics() {
+        let pool = ThreadPool::new(2);
+        let finished = AtomicUsize::new(0);
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            pool.scope(|s| {
+                for _ in 0..4 {
+                    s.spawn(|| {
+                        thread::sleep(Duration::from_millis(20));
+                        finished.fetch_add(1, Ordering::SeqCst);
+                    })
+                    .unwrap();
+                }
+                panic!("scope body failed");
+            })
+        }));
+        assert!(result.is_err());
+        assert_eq!(finished.load(Ordering::SeqCst), 4);
+    }
+}
@@ -0,0 +1,848 @@
+/*
+JSON：
+    只依赖标准库的 JSON 值类型、解析器和序列化器，以及在请求和响应之间转换的辅助函数。
+    自定义类型实现 FromJson / ToJson 之后，就可以直接从请求体中取出、写进响应体：
+
+        .post("/todos", |req: &mut Request| {
+            let Json(todo) = match Json::<Todo>::from_request(req) {
+                Ok(json) => json,
+                Err(resp) => return resp,
+            };
+            json::response(201, &todo)
+        })
+
+    从请求中取 JSON 失败时的状态码：Content-Type 不是 JSON 返回 415，无法解析返回 400，
+    结构或者字段不符合要求返回 422。错误响应统一是下面的格式，见 error 函数：
+
+        {"error":{"message":"`title`: expected a string","status":422}}
+
+    对象使用 BTreeMap 保存，序列化时按键排序，输出是确定的。
+*/
+
+use crate::http::{Request, Response};
+use std::{collections::BTreeMap, error::Error, fmt, ops::Index, str::FromStr};
+
+/// 嵌套层数的上限，防止恶意的深层嵌套耗尽栈空间
+const MAX_DEPTH: usize = 128;
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub enum Value {
+    #[default]
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Value>),
+    Object(BTreeMap<String, Value>),
+}
+
+static NULL: Value = Value::Null;
+
+impl Value {
+    pub fn parse(src: &str) -> Result<Value, ParseError> {
+        let mut parser = Parser {
+            src,
+            pos: 0,
+            depth: 0,
+        };
+        let value = parser.value()?;
+        parser.skip_ws();
+        if parser.pos != src.len() {
+            return Err(parser.error("trailing characters"));
+        }
+        Ok(value)
+    }
+
+    /// 对象中的字段，不是对象或者没有这个字段时返回 None
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        match self {
+            Value::Object(map) => map.get(key),
+            _ => None,
+        }
+    }
+
+    pub fn is_null(&self) -> bool {
+        matches!(self, Value::Null)
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Value::Bool(b) => Some(*b),
+            _ => None,
+        }
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Value::Number(n) => Some(*n),
+            _ => None,
+        }
+    }
+
+    /// 没有小数部分、并且在 i64 范围内的数字
+    pub fn as_i64(&self) -> Option<i64> {
+        let n = self.as_f64()?;
+        (n.fract() == 0.0 && n >= i64::MIN as f64 && n < i64::MAX as f64).then_some(n as i64)
+    }
+
+    pub fn as_u64(&self) -> Option<u64> {
+        self.as_i64().and_then(|n| u64::try_from(n).ok())
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&Vec<Value>> {
+        match self {
+            Value::Array(items) => Some(items),
+            _ => None,
+        }
+    }
+
+    pub fn as_object(&self) -> Option<&BTreeMap<String, Value>> {
+        match self {
+            Value::Object(map) => Some(map),
+            _ => None,
+        }
+    }
+
+    /// 取出对象中的字段并转换成 T，缺少的字段按 null 处理，所以 Option 类型的字段可以省略
+    pub fn field<T: FromJson>(&self, name: &str) -> Result<T, JsonError> {
+        let map = self
+            .as_object()
+            .ok_or_else(|| JsonError::new("expected an object"))?;
+        T::from_json(map.get(name).unwrap_or(&NULL)).map_err(|err| err.at(name))
+    }
+}
+
+impl FromStr for Value {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Value, ParseError> {
+        Value::parse(s)
+    }
+}
+
+/// `value["key"]` 取对象字段，字段不存在时得到 null
+impl Index<&str> for Value {
+    type Output = Value;
+
+    fn index(&self, key: &str) -> &Value {
+        self.get(key).unwrap_or(&NULL)
+    }
+}
+
+/// `value[0]` 取数组元素，越界时得到 null
+impl Index<usize> for Value {
+    type Output = Value;
+
+    fn index(&self, idx: usize) -> &Value {
+        match self {
+            Value::Array(items) => items.get(idx).unwrap_or(&NULL),
+            _ => &NULL,
+        }
+    }
+}
+
+/// 紧凑格式的序列化
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Null => f.write_str("null"),
+            Value::Bool(b) => write!(f, "{}", b),
+            // NaN 和无穷大在 JSON 中没有表示
+            Value::Number(n) if !n.is_finite() => f.write_str("null"),
+            Value::Number(n) if n.fract() == 0.0 && n.abs() < 1e16 => write!(f, "{}", *n as i64),
+            Value::Number(n) => write!(f, "{}", n),
+            Value::String(s) => write_string(f, s),
+            Value::Array(items) => {
+                f.write_str("[")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(",")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                f.write_str("]")
+            }
+            Value::Object(map) => {
+                f.write_str("{")?;
+                for (i, (key, value)) in map.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(",")?;
+                    }
+                    write_string(f, key)?;
+                    write!(f, ":{}", value)?;
+                }
+                f.write_str("}")
+            }
+        }
+    }
+}
+
+fn write_string(f: &mut fmt::Formatter, s: &str) -> fmt::Result {
+    f.write_str("\"")?;
+    for c in s.chars() {
+        match c {
+            '"' => f.write_str("\\\"")?,
+            '\\' => f.write_str("\\\\")?,
+            '\n' => f.write_str("\\n")?,
+            '\r' => f.write_str("\\r")?,
+            '\t' => f.write_str("\\t")?,
+            c if c < ' ' => write!(f, "\\u{:04x}", c as u32)?,
+            c => write!(f, "{}", c)?,
+        }
+    }
+    f.write_str("\"")
+}
+
+impl From<bool> for Value {
+    fn from(b: bool) -> Value {
+        Value::Bool(b)
+    }
+}
+
+impl From<f64> for Value {
+    fn from(n: f64) -> Value {
+        Value::Number(n)
+    }
+}
+
+macro_rules! from_integer {
+    ($($t:ty),*) => {
+        $(
+            impl From<$t> for Value {
+                fn from(n: $t) -> Value {
+                    Value::Number(n as f64)
+                }
+            }
+        )*
+    };
+}
+
+from_integer!(i32, i64, u16, u32, u64, usize);
+
+impl From<&str> for Value {
+    fn from(s: &str) -> Value {
+        Value::String(s.to_string())
+    }
+}
+
+impl From<String> for Value {
+    fn from(s: String) -> Value {
+        Value::String(s)
+    }
+}
+
+impl<T: Into<Value>> From<Option<T>> for Value {
+    fn from(opt: Option<T>) -> Value {
+        opt.map_or(Value::Null, Into::into)
+    }
+}
+
+impl<T: Into<Value>> From<Vec<T>> for Value {
+    fn from(items: Vec<T>) -> Value {
+        Value::Array(items.into_iter().map(Into::into).collect())
+    }
+}
+
+/// 由键值对构造对象：`Value::from_iter([("id", Value::from(1)), ("ok", Value::from(true))])`
+impl<K: Into<String>> FromIterator<(K, Value)> for Value {
+    fn from_iter<I: IntoIterator<Item = (K, Value)>>(iter: I) -> Value {
+        Value::Object(iter.into_iter().map(|(k, v)| (k.into(), v)).collect())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    /// 出错位置的字节偏移
+    pub offset: usize,
+    pub msg: &'static str,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at byte {}", self.msg, self.offset)
+    }
+}
+
+impl Error for ParseError {}
+
+struct Parser<'a> {
+    src: &'a str,
+    pos: usize,
+    depth: usize,
+}
+
+impl Parser<'_> {
+    fn error(&self, msg: &'static str) -> ParseError {
+        ParseError {
+            offset: self.pos,
+            msg,
+        }
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.src.as_bytes().get(self.pos).copied()
+    }
+
+    fn skip_ws(&mut self) {
+        while matches!(self.peek(), Some(b' ' | b'\t' | b'\n' | b'\r')) {
+            self.pos += 1;
+        }
+    }
+
+    fn expect(&mut self, byte: u8, msg: &'static str) -> Result<(), ParseError> {
+        self.skip_ws();
+        if self.peek() != Some(byte) {
+            return Err(self.error(msg));
+        }
+        self.pos += 1;
+        Ok(())
+    }
+
+    fn value(&mut self) -> Result<Value, ParseError> {
+        self.skip_ws();
+        match self.peek() {
+            Some(b'n') => self.literal("null", Value::Null),
+            Some(b't') => self.literal("true", Value::Bool(true)),
+            Some(b'f') => self.literal("false", Value::Bool(false)),
+            Some(b'"') => self.string().map(Value::String),
+            Some(b'[') => self.array(),
+            Some(b'{') => self.object(),
+            Some(b'-' | b'0'..=b'9') => self.number(),
+            Some(_) => Err(self.error("unexpected character")),
+            None => Err(self.error("unexpected end of input")),
+        }
+    }
+
+    fn literal(&mut self, word: &str, value: Value) -> Result<Value, ParseError> {
+        if !self.src[self.pos..].starts_with(word) {
+            return Err(self.error("invalid literal"));
+        }
+        self.pos += word.len();
+        Ok(value)
+    }
+
+    fn digits(&mut self) -> usize {
+        let start = self.pos;
+        while matches!(self.peek(), Some(b'0'..=b'9')) {
+            self.pos += 1;
+        }
+        self.pos - start
+    }
+
+    /// JSON 的数字比 Rust 的 f64 语法严格：不允许前导 0、单独的小数点和正号
+    fn number(&mut self) -> Result<Value, ParseError> {
+        let start = self.pos;
+        if self.peek() == Some(b'-') {
+            self.pos += 1;
+        }
+        match self.peek() {
+            Some(b'0') => self.pos += 1,
+            Some(b'1'..=b'9') => {
+                self.digits();
+            }
+            _ => return Err(self.error("invalid number")),
+        }
+        if self.peek() == Some(b'.') {
+            self.pos += 1;
+            if self.digits() == 0 {
+                return Err(self.error("invalid number"));
+            }
+        }
+        if matches!(self.peek(), Some(b'e' | b'E')) {
+            self.pos += 1;
+            if matches!(self.peek(), Some(b'+' | b'-')) {
+                self.pos += 1;
+            }
+            if self.digits() == 0 {
+                return Err(self.error("invalid number"));
+            }
+        }
+        self.src[start..self.pos]
+            .parse()
+            .map(Value::Number)
+            .map_err(|_| self.error("invalid number"))
+    }
+
+    fn string(&mut self) -> Result<String, ParseError> {
+        self.pos += 1;
+        let mut out = String::new();
+        loop {
+            match self.peek() {
+                None => return Err(self.error("unterminated string")),
+                Some(b'"') => {
+                    self.pos += 1;
+                    return Ok(out);
+                }
+                Some(b'\\') => {
+                    self.pos += 1;
+                    let c = match self.peek() {
+                        Some(b'"') => '"',
+                        Some(b'\\') => '\\',
+                        Some(b'/') => '/',
+                        Some(b'b') => '\u{8}',
+                        Some(b'f') => '\u{c}',
+                        Some(b'n') => '\n',
+                        Some(b'r') => '\r',
+                        Some(b't') => '\t',
+                        Some(b'u') => {
+                            self.pos += 1;
+                            out.push(self.unicode_escape()?);
+                            continue;
+                        }
+                        _ => return Err(self.error("invalid escape")),
+                    };
+                    self.pos += 1;
+                    out.push(c);
+                }
+                Some(0..0x20) => return Err(self.error("control character in string")),
+                Some(_) => {
+                    // 普通字符整段复制，分界处都是 ASCII，不会切断多字节字符
+                    let start = self.pos;
+                    while !matches!(self.peek(), None | Some(b'"' | b'\\' | 0..0x20)) {
+                        self.pos += 1;
+                    }
+                    out.push_str(&self.src[start..self.pos]);
+                }
+            }
+        }
+    }
+
+    fn hex4(&mut self) -> Result<u32, ParseError> {
+        let digits = self
+            .src
+            .get(self.pos..self.pos + 4)
+            .filter(|s| s.bytes().all(|b| b.is_ascii_hexdigit()))
+            .ok_or_else(|| self.error("invalid unicode escape"))?;
+        self.pos += 4;
+        Ok(u32::from_str_radix(digits, 16).unwrap())
+    }
+
+    /// `\uXXXX`，基本平面之外的字符用一对代理项表示
+    fn unicode_escape(&mut self) -> Result<char, ParseError> {
+        let hi = self.hex4()?;
+        let code = if (0xD800..0xDC00).contains(&hi) {
+            if !self.src[self.pos..].starts_with("\\u") {
+                return Err(self.error("unpaired surrogate"));
+            }
+            self.pos += 2;
+            let lo = self.hex4()?;
+            if !(0xDC00..0xE000).contains(&lo) {
+                return Err(self.error("unpaired surrogate"));
+            }
+            0x10000 + ((hi - 0xD800) << 10) + (lo - 0xDC00)
+        } else {
+            hi
+        };
+        char::from_u32(code).ok_or_else(|| self.error("unpaired surrogate"))
+    }
+
+    fn enter(&mut self) -> Result<(), ParseError> {
+        self.depth += 1;
+        if self.depth > MAX_DEPTH {
+            return Err(self.error("nesting too deep"));
+        }
+        self.pos += 1;
+        Ok(())
+    }
+
+    fn array(&mut self) -> Result<Value, ParseError> {
+        self.enter()?;
+        let mut items = Vec::new();
+        self.skip_ws();
+        if self.peek() == Some(b']') {
+            self.pos += 1;
+        } else {
+            loop {
+                items.push(self.value()?);
+                self.skip_ws();
+                match self.peek() {
+                    Some(b',') => self.pos += 1,
+                    Some(b']') => {
+                        self.pos += 1;
+                        break;
+                    }
+                    _ => return Err(self.error("expected `,` or `]`")),
+                }
+            }
+        }
+        self.depth -= 1;
+        Ok(Value::Array(items))
+    }
+
+    fn object(&mut self) -> Result<Value, ParseError> {
+        self.enter()?;
+        let mut map = BTreeMap::new();
+        self.skip_ws();
+        if self.peek() == Some(b'}') {
+            self.pos += 1;
+        } else {
+            loop {
+                self.skip_ws();
+                if self.peek() != Some(b'"') {
+                    return Err(self.error("expected a string key"));
+                }
+                let key = self.string()?;
+                self.expect(b':', "expected `:`")?;
+                // 重复的键以最后一个为准
+                map.insert(key, self.value()?);
+                self.skip_ws();
+                match self.peek() {
+                    Some(b',') => self.pos += 1,
+                    Some(b'}') => {
+                        self.pos += 1;
+                        break;
+                    }
+                    _ => return Err(self.error("expected `,` or `}`")),
+                }
+            }
+        }
+        self.depth -= 1;
+        Ok(Value::Object(map))
+    }
+}
+
+/// JSON 值转换成 Rust 类型时的错误，记录出错字段的路径
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JsonError {
+    path: Vec<String>,
+    msg: String,
+}
+
+impl JsonError {
+    pub fn new(msg: impl Into<String>) -> JsonError {
+        JsonError {
+            path: Vec::new(),
+            msg: msg.into(),
+        }
+    }
+
+    /// 在路径前面加上外层的字段名
+    pub fn at(mut self, field: &str) -> JsonError {
+        self.path.insert(0, field.to_string());
+        self
+    }
+}
+
+impl fmt::Display for JsonError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.path.is_empty() {
+            write!(f, "{}", self.msg)
+        } else {
+            write!(f, "`{}`: {}", self.path.join("."), self.msg)
+        }
+    }
+}
+
+impl Error for JsonError {}
+
+pub trait ToJson {
+    fn to_json(&self) -> Value;
+}
+
+pub trait FromJson: Sized {
+    fn from_json(value: &Value) -> Result<Self, JsonError>;
+}
+
+impl ToJson for Value {
+    fn to_json(&self) -> Value {
+        self.clone()
+    }
+}
+
+impl FromJson for Value {
+    fn from_json(value: &Value) -> Result<Value, JsonError> {
+        Ok(value.clone())
+    }
+}
+
+impl<T: ToJson + ?Sized> ToJson for &T {
+    fn to_json(&self) -> Value {
+        (**self).to_json()
+    }
+}
+
+impl ToJson for bool {
+    fn to_json(&self) -> Value {
+        Value::Bool(*self)
+    }
+}
+
+impl FromJson for bool {
+    fn from_json(value: &Value) -> Result<bool, JsonError> {
+        value
+            .as_bool()
+            .ok_or_else(|| JsonError::new("expected a boolean"))
+    }
+}
+
+impl ToJson for str {
+    fn to_json(&self) -> Value {
+        Value::from(self)
+    }
+}
+
+impl ToJson for String {
+    fn to_json(&self) -> Value {
+        Value::from(self.as_str())
+    }
+}
+
+impl FromJson for String {
+    fn from_json(value: &Value) -> Result<String, JsonError> {
+        value
+            .as_str()
+            .map(String::from)
+            .ok_or_else(|| JsonError::new("expected a string"))
+    }
+}
+
+impl ToJson for f64 {
+    fn to_json(&self) -> Value {
+        Value::Number(*self)
+    }
+}
+
+impl FromJson for f64 {
+    fn from_json(value: &Value) -> Result<f64, JsonError> {
+        value
+            .as_f64()
+            .ok_or_else(|| JsonError::new("expected a number"))
+    }
+}
+
+macro_rules! json_integer {
+    ($($t:ty),*) => {
+        $(
+            impl ToJson for $t {
+                fn to_json(&self) -> Value {
+                    Value::from(*self)
+                }
+            }
+
+            impl FromJson for $t {
+                fn from_json(value: &Value) -> Result<$t, JsonError> {
+                    value
+                        .as_i64()
+                        .and_then(|n| <$t>::try_from(n).ok())
+                        .ok_or_else(|| {
+                            JsonError::new(concat!("expected an integer in the range of ", stringify!($t)))
+                        })
+                }
+            }
+        )*
+    };
+}
+
+json_integer!(i32, i64, u16, u32, u64, usize);
+
+/// null 对应 None，所以对象中可以省略 Option 类型的字段
+impl<T: ToJson> ToJson for Option<T> {
+    fn to_json(&self) -> Value {
+        self.as_ref().map_or(Value::Null, ToJson::to_json)
+    }
+}
+
+impl<T: FromJson> FromJson for Option<T> {
+    fn from_json(value: &Value) -> Result<Option<T>, JsonError> {
+        match value {
+            Value::Null => Ok(None),
+            value => T::from_json(value).map(Some),
+        }
+    }
+}
+
+impl<T: ToJson> ToJson for [T] {
+    fn to_json(&self) -> Value {
+        Value::Array(self.iter().map(ToJson::to_json).collect())
+    }
+}
+
+impl<T: ToJson> ToJson for Vec<T> {
+    fn to_json(&self) -> Value {
+        self.as_slice().to_json()
+    }
+}
+
+impl<T: FromJson> FromJson for Vec<T> {
+    fn from_json(value: &Value) -> Result<Vec<T>, JsonError> {
+        let items = value
+            .as_array()
+            .ok_or_else(|| JsonError::new("expected an array"))?;
+        items
+            .iter()
+            .enumerate()
+            .map(|(i, item)| T::from_json(item).map_err(|err| err.at(&i.to_string())))
+            .collect()
+    }
+}
+
+/// 请求体或响应体中的 JSON
+#[derive(Debug, Clone, PartialEq)]
+pub struct Json<T>(pub T);
+
+impl<T: FromJson> Json<T> {
+    /// 从请求体中取出 T，失败时返回可以直接交给客户端的错误响应
+    pub fn from_request(req: &Request) -> Result<Json<T>, Response> {
+        if !req.header("Content-Type").is_some_and(is_json) {
+            return Err(error(415, "expected an application/json body"));
+        }
+        let text =
+            std::str::from_utf8(&req.body).map_err(|_| error(400, "body is not valid utf-8"))?;
+        let value =
+            Value::parse(text).map_err(|err| error(400, &format!("invalid json: {}", err)))?;
+        T::from_json(&value)
+            .map(Json)
+            .map_err(|err| error(422, &err.to_string()))
+    }
+}
+
+impl<T: ToJson> From<Json<T>> for Response {
+    fn from(json: Json<T>) -> Response {
+        response(200, &json.0)
+    }
+}
+
+/// application/json 以及 application/problem+json 这类带 +json 后缀的类型
+fn is_json(content_type: &str) -> bool {
+    let mime = content_type.split(';').next().unwrap_or("").trim();
+    mime.eq_ignore_ascii_case("application/json") || mime.to_ascii_lowercase().ends_with("+json")
+}
+
+pub fn response<T: ToJson + ?Sized>(status: u16, value: &T) -> Response {
+    Response::new(status)
+        .with_header("Content-Type", "application/json")
+        .with_body(value.to_json().to_string())
+}
+
+/// 统一格式的错误响应：`{"error":{"message":"...","status":404}}`
+pub fn error(status: u16, message: &str) -> Response {
+    let detail = Value::from_iter([
+        ("status", Value::from(status)),
+        ("message", Value::from(message)),
+    ]);
+    response(status, &Value::from_iter([("error", detail)]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(src: &str) -> Value {
+        Value::parse(src).unwrap()
+    }
+
+    #[test]
+    fn parses_and_serializes() {
+        let src = r#" {"name": "ferris", "tags": ["crab", null, true], "age": 7, "pi": -3.5e-1, "nested": {}} "#;
+        let value = parse(src);
+        assert_eq!(value["name"].as_str(), Some("ferris"));
+        assert_eq!(value["tags"][2], Value::Bool(true));
+        assert_eq!(value["age"].as_u64(), Some(7));
+        assert_eq!(value["pi"].as_f64(), Some(-0.35));
+        assert!(value["missing"][3].is_null());
+        let compact =
+            r#"{"age":7,"name":"ferris","nested":{},"pi":-0.35,"tags":["crab",null,true]}"#;
+        assert_eq!(value.to_string(), compact);
+        assert_eq!(parse(compact), value);
+    }
+
+    #[test]
+    fn strings_round_trip_escapes() {
+        let value = parse(r#""line\nquote\" tab\t é 🦀 \/ \u0001""#);
+        assert_eq!(value.as_str(), Some("line\nquote\" tab\t é 🦀 / \u{1}"));
+        assert_eq!(value.to_string(), r#""line\nquote\" tab\t é 🦀 / \u0001""#);
+        assert_eq!(parse(&value.to_string()), value);
+    }
+
+    #[test]
+    fn numbers() {
+        assert_eq!(Value::from(1e20).to_string(), "100000000000000000000");
+        assert_eq!(Value::from(42u64).to_string(), "42");
+        assert_eq!(Value::from(0.1).to_string(), "0.1");
+        assert_eq!(Value::from(f64::NAN).to_string(), "null");
+        assert_eq!(parse("-0").as_i64(), Some(0));
+        assert_eq!(parse("1.5").as_i64(), None);
+        for bad in ["01", "1.", ".5", "+1", "1e", "-"] {
+            assert!(Value::parse(bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn reports_error_offsets() {
+        let err = |src: &str| Value::parse(src).unwrap_err();
+        assert_eq!(err("[1, 2,]").offset, 6);
+        assert_eq!(err(r#"{"a" 1}"#).msg, "expected `:`");
+        assert_eq!(err(r#""abc"#).msg, "unterminated string");
+        assert_eq!(err(r#""\ud800""#).msg, "unpaired surrogate");
+        assert_eq!(err("[1] x").msg, "trailing characters");
+        assert_eq!(err(&"[".repeat(200)).msg, "nesting too deep");
+        assert_eq!(err("tru").msg, "invalid literal");
+    }
+
+    #[derive(Debug, PartialEq)]
+    struct Point {
+        x: i64,
+        label: Option<String>,
+        tags: Vec<String>,
+    }
+
+    impl FromJson for Point {
+        fn from_json(value: &Value) -> Result<Point, JsonError> {
+            Ok(Point {
+                x: value.field("x")?,
+                label: value.field("label")?,
+                tags: value.field::<Option<_>>("tags")?.unwrap_or_default(),
+            })
+        }
+    }
+
+    #[test]
+    fn from_json_reports_field_paths() {
+        let point = Point::from_json(&parse(r#"{"x": 3}"#)).unwrap();
+        assert_eq!(
+            point,
+            Point {
+                x: 3,
+                label: None,
+                tags: Vec::new()
+            }
+        );
+        let err = Point::from_json(&parse(r#"{"x": 1, "tags": ["a", 2]}"#)).unwrap_err();
+        assert_eq!(err.to_string(), "`tags.1`: expected a string");
+        let err = Point::from_json(&parse(r#"{"x": 1.5}"#)).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "`x`: expected an integer in the range of i64"
+        );
+        assert!(Point::from_json(&parse("[]")).is_err());
+    }
+
+    #[test]
+    fn extracts_json_from_requests() {
+        let mut req = Request::new("POST", "/");
+        req.body = br#"{"x": 1}"#.to_vec();
+        assert_eq!(Json::<Point>::from_request(&req).unwrap_err().status, 415);
+
+        req.headers
+            .set("Content-Type", "application/json; charset=utf-8");
+        assert_eq!(Json::<Point>::from_request(&req).unwrap().0.x, 1);
+
+        req.body = b"{".to_vec();
+        let resp = Json::<Point>::from_request(&req).unwrap_err();
+        assert_eq!(resp.status, 400);
+        let body = parse(std::str::from_utf8(&resp.body).unwrap());
+        assert_eq!(body["error"]["status"].as_u64(), Some(400));
+
+        req.body = br#"{"x": "one"}"#.to_vec();
+        let resp = Json::<Point>::from_request(&req).unwrap_err();
+        assert_eq!(resp.status, 422);
+        assert_eq!(resp.header("Content-Type"), Some("application/json"));
+    }
+}
@@ -1,41 +1,672 @@
+pub mod access_log;
+pub mod client;
+pub mod compress;
+pub mod config;
+pub mod conn;
+#[cfg(target_os = "linux")]
+mod event_loop;
+pub mod form;
+pub mod hpack;
+pub mod http;
+pub mod http2;
+pub mod job;
+pub mod json;
+pub mod log;
+pub mod metrics;
+pub mod middleware;
+pub mod proxy;
+pub mod rate_limit;
+pub mod rest;
+pub mod router;
+#[cfg(target_os = "linux")]
+pub mod runtime;
+mod scheduler;
+pub mod server;
+pub mod sse;
+mod timer;
+pub mod websocket;
+
+pub use config::Config;
+pub use http::{Request, Response};
+pub use job::{JobError, JobHandle, Scope};
+pub use middleware::{Handler, Middleware, Next, Pipeline};
+pub use router::Router;
+#[cfg(target_os = "linux")]
+pub use runtime::AsyncHandler;
+pub use server::Server;
+pub use timer::CancelToken;
+
+use scheduler::Scheduler;
 use std::{
+    any::Any,
+    error::Error,
+    fmt, io,
+    panic::{self, AssertUnwindSafe},
+    str::FromStr,
     sync::Arc,
-    sync::Mutex,
-    sync::mpsc,
+    sync::atomic::{AtomicU64, AtomicUsize, Ordering},
+    sync::{Condvar, Mutex, PoisonError},
     thread::{self, JoinHandle},
+    time::{Duration, Instant},
 };
+use timer::Timers;
+
+/// 线程池自己的日志，quiet 模式下不输出
+macro_rules! pool_log {
+    ($workers:expr, $level:ident, $($arg:tt)*) => {
+        if !$workers.quiet {
+            $level!($($arg)*)
+        }
+    };
+}
+
+/// 工作线程共享的状态
+struct Workers {
+    scheduler: Scheduler,
+    counters: Arc<Counters>,
+    /// 每个工作线程的 JoinHandle，长度是线程数的上限；线程被替换时更新对应的位置，空闲退出后留空
+    handles: Mutex<Vec<Option<JoinHandle<()>>>>,
+    name: String,
+    stack_size: Option<usize>,
+    /// 没有设置时输出一条 error 日志
+    panic_handler: Option<Box<PanicHandler>>,
+    policy: QueuePolicy,
+    quiet: bool,
+    /// 工作线程退出时通知 shutdown_timeout
+    exited: Mutex<()>,
+    exited_cond: Condvar,
+    min: usize,
+    max: usize,
+    /// 线程数可以伸缩时，空闲超过这个时间的线程退出
+    keep_alive: Option<Duration>,
+}
+
+type PanicHandler = dyn Fn(&JobPanic) + Send + Sync;
+
+impl Workers {
+    /// 启动一个新的 id 号工作线程并计数，调用方需要持有 handles 的锁并保存返回的 JoinHandle
+    fn start(self: &Arc<Workers>, id: usize) -> io::Result<JoinHandle<()>> {
+        let jh = self.spawn(id)?;
+        let count = self.counters.workers.fetch_add(1, Ordering::Relaxed) + 1;
+        self.counters.peak.fetch_max(count, Ordering::Relaxed);
+        Ok(jh)
+    }
+
+    /// 启动 id 号工作线程，不改变计数
+    fn spawn(self: &Arc<Workers>, id: usize) -> io::Result<JoinHandle<()>> {
+        let mut builder = thread::Builder::new().name(format!("{}-{}", self.name, id));
+        if let Some(bytes) = self.stack_size {
+            builder = builder.stack_size(bytes);
+        }
+        let workers = Arc::clone(self);
+        let jh = builder.spawn(move || {
+            let mut sentinel = Sentinel {
+                workers: &workers,
+                id,
+                retired: false,
+            };
+            sentinel.retired = workers.run(id);
+        })?;
+        Ok(jh)
+    }
+
+    /// 执行任务直到线程池关闭，空闲退出时返回 true
+    fn run(&self, id: usize) -> bool {
+        self.scheduler.enter(id);
+        loop {
+            let Some(job) = self.scheduler.pop(id, self.keep_alive) else {
+                // 线程池关闭并且队列里的任务都执行完了
+                if self.scheduler.is_shutdown() {
+                    return false;
+                }
+                if self.retire(id) {
+                    return true;
+                }
+                continue;
+            };
+            pool_log!(self, debug, "Worker {} got a job.", id);
+            self.counters.queued.fetch_sub(1, Ordering::Relaxed);
+            let _busy = Busy::start(&self.counters);
+            self.run_job(job);
+        }
+    }
+
+    /// 提交任务；队列已满时按照 policy 处理
+    fn submit(
+        self: &Arc<Workers>,
+        job: Job,
+        priority: Priority,
+        policy: QueuePolicy,
+    ) -> Result<(), ExecuteError> {
+        let counters = &self.counters;
+        let scheduler = &self.scheduler;
+        // 工作线程都退出了而且无法替换，任务永远不会被执行
+        if scheduler.is_shutdown() || counters.workers.load(Ordering::Relaxed) == 0 {
+            return Err(ExecuteError::ShuttingDown);
+        }
+        // 先计数再放进队列，工作线程取走任务时计数不会变成负数
+        counters.queued.fetch_add(1, Ordering::Relaxed);
+        let job = match scheduler.try_push(job, priority) {
+            Ok(()) => {
+                self.grow();
+                return Ok(());
+            }
+            Err(job) => job,
+        };
+        match policy {
+            QueuePolicy::Reject => {
+                counters.queued.fetch_sub(1, Ordering::Relaxed);
+                counters.rejected.fetch_add(1, Ordering::Relaxed);
+                Err(ExecuteError::Full)
+            }
+            QueuePolicy::Block if !scheduler.is_worker() => {
+                scheduler.push_blocking(job, priority).map_err(|_| {
+                    counters.queued.fetch_sub(1, Ordering::Relaxed);
+                    ExecuteError::ShuttingDown
+                })?;
+                self.grow();
+                Ok(())
+            }
+            QueuePolicy::DropOldest => {
+                if let Some(evicted) = scheduler.push_evict(job, priority) {
+                    counters.queued.fetch_sub(1, Ordering::Relaxed);
+                    counters.rejected.fetch_add(1, Ordering::Relaxed);
+                    drop(evicted);
+                }
+                self.grow();
+                Ok(())
+            }
+            // 工作线程等待空位时，如果所有工作线程都在等待就再也没有线程取任务了
+            QueuePolicy::Block | QueuePolicy::CallerRuns => {
+                counters.queued.fetch_sub(1, Ordering::Relaxed);
+                self.run_job(job);
+                Ok(())
+            }
+        }
+    }
+
+    /// 空闲超时的线程在线程数多于下限时退出
+    fn retire(&self, id: usize) -> bool {
+        let mut handles = self.handles.lock().unwrap_or_else(PoisonError::into_inner);
+        if self.scheduler.is_shutdown() {
+            return false;
+        }
+        let retired = self
+            .counters
+            .workers
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| {
+                (n > self.min).then(|| n - 1)
+            })
+            .is_ok();
+        if retired {
+            // 自己的 JoinHandle 直接丢弃，线程在 run 返回后就结束了
+            handles[id] = None;
+            self.scheduler.leave(id);
+            pool_log!(self, debug, "worker {} retired after being idle", id);
+        }
+        if retired {
+            self.exited();
+        }
+        retired
+    }
+
+    /// 存活线程数减少了，叫醒等待线程退出的 shutdown_timeout
+    fn exited(&self) {
+        drop(self.exited.lock().unwrap_or_else(PoisonError::into_inner));
+        self.exited_cond.notify_all();
+    }
 
-struct Worker {
+    /// 等待所有工作线程退出，超过 deadline 返回 false
+    fn wait_exited(&self, deadline: Instant) -> bool {
+        let mut exited = self.exited.lock().unwrap_or_else(PoisonError::into_inner);
+        while self.counters.workers.load(Ordering::SeqCst) > 0 {
+            let now = Instant::now();
+            if now >= deadline {
+                return false;
+            }
+            exited = self
+                .exited_cond
+                .wait_timeout(exited, deadline - now)
+                .unwrap_or_else(PoisonError::into_inner)
+                .0;
+        }
+        true
+    }
+
+    /// 排队的任务比空闲的线程多、线程数还没有到上限时增加一个工作线程
+    fn grow(self: &Arc<Workers>) {
+        let full = || self.counters.workers.load(Ordering::Relaxed) >= self.max;
+        if full() || self.scheduler.len() <= self.scheduler.idle() {
+            return;
+        }
+        let mut handles = self.handles.lock().unwrap_or_else(PoisonError::into_inner);
+        if full() || self.scheduler.is_shutdown() {
+            return;
+        }
+        let Some(id) = handles.iter().position(Option::is_none) else {
+            return;
+        };
+        match self.start(id) {
+            Ok(jh) => {
+                pool_log!(self, debug, "queue is backing up, started worker {}", id);
+                handles[id] = Some(jh);
+            }
+            Err(err) => pool_log!(self, warn, "cannot add a worker to the pool: {}", err),
+        }
+    }
+
+    /// 执行一个任务并更新计数，任务 panic 时调用 panic 回调；CallerRuns 策略下在提交任务的线程上调用
+    fn run_job(&self, job: Job) {
+        match panic::catch_unwind(AssertUnwindSafe(job)) {
+            Ok(()) => {
+                self.counters.completed.fetch_add(1, Ordering::Relaxed);
+            }
+            Err(payload) => {
+                self.counters.panicked.fetch_add(1, Ordering::Relaxed);
+                let worker = thread::current();
+                let panic = JobPanic {
+                    worker: worker.name().unwrap_or_default(),
+                    payload: &*payload,
+                };
+                match &self.panic_handler {
+                    Some(handler) => handler(&panic),
+                    None => pool_log!(
+                        self,
+                        error,
+                        "job panicked on {}: {}",
+                        panic.worker,
+                        panic.message()
+                    ),
+                }
+            }
+        }
+    }
+}
+
+/// 工作线程退出时更新计数；如果是因为 panic 退出（比如 panic 回调本身 panic），启动一个新线程顶替它
+struct Sentinel<'a> {
+    workers: &'a Arc<Workers>,
     id: usize,
-    jh: Option<JoinHandle<()>>,
+    /// 空闲退出的线程在 retire 中已经减过计数
+    retired: bool,
 }
 
-impl Worker {
-    fn new(id: usize, receiver: Arc<Mutex<mpsc::Receiver<Message>>>) -> Worker {
-        let jh = thread::spawn(move || {
-            loop {
-                let msg = receiver.lock().unwrap().recv().unwrap();
-                println!("Worker {} got a msg.", id);
-                match msg {
-                    Message::NewJob(job) => job(),
-                    Message::Terminate => break,
+impl Drop for Sentinel<'_> {
+    fn drop(&mut self) {
+        // 替代的线程直接接过这个线程的计数，存活线程数不会短暂变化，execute 也不会看到 0
+        if thread::panicking() {
+            let mut handles = self
+                .workers
+                .handles
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner);
+            match self.workers.spawn(self.id) {
+                Ok(jh) => {
+                    pool_log!(
+                        self.workers,
+                        warn,
+                        "worker {} died, started a replacement",
+                        self.id
+                    );
+                    // 旧线程的 JoinHandle 被丢弃，线程在这个函数返回后就结束了
+                    handles[self.id] = Some(jh);
+                    return;
                 }
+                Err(err) => pool_log!(
+                    self.workers,
+                    error,
+                    "worker {} died and cannot be replaced: {}",
+                    self.id,
+                    err
+                ),
             }
-        });
-        Worker { id, jh: Some(jh) }
+        }
+        if !self.retired {
+            self.workers.counters.workers.fetch_sub(1, Ordering::SeqCst);
+            self.workers.exited();
+        }
+    }
+}
+
+/// 任务 panic 时交给 panic 回调的信息
+pub struct JobPanic<'a> {
+    /// 执行任务的工作线程的名字
+    pub worker: &'a str,
+    /// panic 的参数，通常是 &str 或者 String
+    pub payload: &'a (dyn Any + Send),
+}
+
+impl JobPanic<'_> {
+    /// panic 的消息，参数不是字符串时返回一个占位的描述
+    pub fn message(&self) -> &str {
+        if let Some(msg) = self.payload.downcast_ref::<&str>() {
+            msg
+        } else if let Some(msg) = self.payload.downcast_ref::<String>() {
+            msg
+        } else {
+            "Box<dyn Any>"
+        }
+    }
+}
+
+/// 线程池的计数器，execute 和工作线程共同维护
+#[derive(Default)]
+struct Counters {
+    workers: AtomicUsize,
+    peak: AtomicUsize,
+    queued: AtomicUsize,
+    busy: AtomicUsize,
+    completed: AtomicU64,
+    panicked: AtomicU64,
+    rejected: AtomicU64,
+}
+
+/// 工作线程正在执行任务，任务 panic 时也会在 drop 中归还
+struct Busy<'a>(&'a Counters);
+
+impl Busy<'_> {
+    fn start(counters: &Counters) -> Busy<'_> {
+        counters.busy.fetch_add(1, Ordering::Relaxed);
+        Busy(counters)
+    }
+}
+
+impl Drop for Busy<'_> {
+    fn drop(&mut self) {
+        self.0.busy.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// 线程池某一时刻的状态
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PoolStats {
+    /// 存活的工作线程数
+    pub workers: usize,
+    /// 同时存活的工作线程数的最大值
+    pub peak_workers: usize,
+    /// 已提交、还没有工作线程取走的任务数
+    pub queued: usize,
+    /// 正在执行任务的工作线程数
+    pub busy: usize,
+    /// 已经执行完的任务数，不包括 panic 的任务
+    pub completed: u64,
+    /// panic 的任务数
+    pub panicked: u64,
+    /// 因为队列已满被拒绝或者被挤掉的任务数
+    pub rejected: u64,
+}
+
+/// 读取线程池状态的句柄，可以交给其他线程，不会让线程池保持存活
+#[derive(Clone)]
+pub struct PoolMonitor {
+    counters: Arc<Counters>,
+}
+
+impl PoolMonitor {
+    pub fn stats(&self) -> PoolStats {
+        PoolStats {
+            workers: self.counters.workers.load(Ordering::Relaxed),
+            peak_workers: self.counters.peak.load(Ordering::Relaxed),
+            queued: self.counters.queued.load(Ordering::Relaxed),
+            busy: self.counters.busy.load(Ordering::Relaxed),
+            completed: self.counters.completed.load(Ordering::Relaxed),
+            panicked: self.counters.panicked.load(Ordering::Relaxed),
+            rejected: self.counters.rejected.load(Ordering::Relaxed),
+        }
     }
 }
 
-type Job = Box<dyn FnOnce() + Send + 'static>;
+/// 创建线程池失败的原因
+#[derive(Debug)]
+pub enum PoolCreationError {
+    /// 线程数为 0
+    ZeroSize,
+    /// 线程数的上限小于初始线程数
+    MaxBelowSize,
+    /// 操作系统无法创建工作线程，比如线程数或者内存达到了上限
+    Spawn(io::Error),
+}
 
-enum Message {
-    NewJob(Job),
-    Terminate,
+impl fmt::Display for PoolCreationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PoolCreationError::ZeroSize => write!(f, "thread pool size must be greater than zero"),
+            PoolCreationError::MaxBelowSize => {
+                write!(f, "max workers must not be less than the pool size")
+            }
+            PoolCreationError::Spawn(err) => write!(f, "failed to spawn worker thread: {}", err),
+        }
+    }
+}
+
+impl Error for PoolCreationError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            PoolCreationError::ZeroSize | PoolCreationError::MaxBelowSize => None,
+            PoolCreationError::Spawn(err) => Some(err),
+        }
+    }
+}
+
+/// 提交任务失败的原因，任务没有被执行
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExecuteError {
+    /// 线程池正在关闭，或者工作线程都已经退出
+    ShuttingDown,
+    /// 队列已满，只有 Reject 策略和 try_execute 会返回
+    Full,
+    /// execute_every 的间隔为 0
+    ZeroInterval,
+    /// execute_after 的延迟或者 execute_every 的间隔太长，到期时间超出了 Instant 的范围
+    DelayTooLong,
+}
+
+impl fmt::Display for ExecuteError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ExecuteError::ShuttingDown => write!(f, "thread pool is shutting down"),
+            ExecuteError::Full => write!(f, "thread pool queue is full"),
+            ExecuteError::ZeroInterval => write!(f, "periodic job interval must not be zero"),
+            ExecuteError::DelayTooLong => write!(f, "delay is too long to schedule"),
+        }
+    }
+}
+
+impl Error for ExecuteError {}
+
+/// 任务的优先级，默认是 Normal
+///
+/// 每个工作线程的队列里优先级高的任务先执行；不同工作线程之间不保证顺序，
+/// 空闲的线程从别的队列偷任务时同样先偷优先级高的
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub enum Priority {
+    Low,
+    #[default]
+    Normal,
+    High,
+}
+
+/// 队列已满时如何处理新提交的任务
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum QueuePolicy {
+    /// 提交任务的线程等待，直到队列有空位；在工作线程里提交时改为直接执行，避免工作线程互相等待
+    #[default]
+    Block,
+    /// 返回 [`ExecuteError::Full`]
+    Reject,
+    /// 丢弃队列里最早的任务，给新任务腾出位置；有不同优先级时先丢弃优先级最低的任务里最早提交的。
+    /// 被丢弃任务的 JobHandle 得到 Cancelled
+    DropOldest,
+    /// 在提交任务的线程上直接执行，提交得越快自己要做的事情越多，相当于自然的限流
+    CallerRuns,
+}
+
+impl FromStr for QueuePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<QueuePolicy, String> {
+        match s {
+            "block" => Ok(QueuePolicy::Block),
+            "reject" => Ok(QueuePolicy::Reject),
+            "drop_oldest" => Ok(QueuePolicy::DropOldest),
+            "caller_runs" => Ok(QueuePolicy::CallerRuns),
+            _ => Err(format!("unknown queue policy `{}`", s)),
+        }
+    }
 }
 
+/// 提交给线程池的任务，shutdown_now 把还没执行的任务以这个类型交还给调用方
+pub type Job = Box<dyn FnOnce() + Send + 'static>;
+
 pub struct ThreadPool {
-    workers: Vec<Worker>,
-    sender: mpsc::Sender<Message>,
+    workers: Arc<Workers>,
+    monitor: PoolMonitor,
+    timers: Arc<Timers>,
+    /// 定时线程，把到期的定时任务提交给线程池
+    timer: Option<JoinHandle<()>>,
+    /// 已经调用过 shutdown 系列方法，drop 时不再重复关闭和等待
+    closed: bool,
+}
+
+/// 配置并创建线程池
+///
+/// ```
+/// let pool = web_server::ThreadPool::builder(4)
+///     .name("worker")
+///     .stack_size(512 * 1024)
+///     .queue_capacity(1000)
+///     .queue_policy(web_server::QueuePolicy::Reject)
+///     .max_workers(16)
+///     .build()
+///     .unwrap();
+/// pool.execute(|| println!("hello")).unwrap();
+/// ```
+pub struct PoolBuilder {
+    size: u32,
+    name: String,
+    stack_size: Option<usize>,
+    panic_handler: Option<Box<PanicHandler>>,
+    quiet: bool,
+    queue_capacity: usize,
+    queue_policy: QueuePolicy,
+    max_workers: Option<u32>,
+    keep_alive: Duration,
+}
+
+impl PoolBuilder {
+    /// 工作线程名字的前缀，线程名是 `<name>-<id>`，默认是 worker
+    pub fn name(mut self, name: &str) -> PoolBuilder {
+        self.name = name.to_string();
+        self
+    }
+
+    /// 工作线程的栈大小，默认使用标准库的设置（可以通过 RUST_MIN_STACK 修改）
+    pub fn stack_size(mut self, bytes: usize) -> PoolBuilder {
+        self.stack_size = Some(bytes);
+        self
+    }
+
+    /// 任务 panic 时调用的回调，在执行任务的工作线程上运行；默认输出一条 error 日志
+    ///
+    /// 工作线程会继续执行后面的任务。回调本身 panic 时这个线程会退出，线程池启动一个新线程代替它
+    pub fn panic_handler<F>(mut self, handler: F) -> PoolBuilder
+    where
+        F: Fn(&JobPanic) + Send + Sync + 'static,
+    {
+        self.panic_handler = Some(Box::new(handler));
+        self
+    }
+
+    /// quiet 模式下线程池不输出任何日志，包括默认 panic 回调的 error 日志；
+    /// 线程的启动、退出和任务 panic 仍然会记在 [`PoolStats`] 里
+    pub fn quiet(mut self, quiet: bool) -> PoolBuilder {
+        self.quiet = quiet;
+        self
+    }
+
+    /// 排队等待执行的任务数上限，默认是 0，表示不限制
+    pub fn queue_capacity(mut self, capacity: usize) -> PoolBuilder {
+        self.queue_capacity = capacity;
+        self
+    }
+
+    /// 队列已满时的处理方式，默认是 [`QueuePolicy::Block`]
+    pub fn queue_policy(mut self, policy: QueuePolicy) -> PoolBuilder {
+        self.queue_policy = policy;
+        self
+    }
+
+    /// 工作线程数的上限，默认等于初始线程数，也就是不伸缩
+    ///
+    /// 排队的任务多于空闲线程时增加线程，直到这个上限；多出来的线程空闲超过 keep_alive 后退出，
+    /// 线程数最少回到初始线程数
+    pub fn max_workers(mut self, max: u32) -> PoolBuilder {
+        self.max_workers = Some(max);
+        self
+    }
+
+    /// 多出来的线程空闲多久后退出，默认 60 秒
+    pub fn keep_alive(mut self, idle: Duration) -> PoolBuilder {
+        self.keep_alive = idle;
+        self
+    }
+
+    pub fn build(self) -> Result<ThreadPool, PoolCreationError> {
+        if self.size == 0 {
+            return Err(PoolCreationError::ZeroSize);
+        }
+        let (min, max) = (
+            self.size as usize,
+            self.max_workers.unwrap_or(self.size) as usize,
+        );
+        if max < min {
+            return Err(PoolCreationError::MaxBelowSize);
+        }
+        let counters = Arc::new(Counters::default());
+        let workers = Arc::new(Workers {
+            scheduler: Scheduler::new(max, self.queue_capacity),
+            counters: Arc::clone(&counters),
+            handles: Mutex::new((0..max).map(|_| None).collect()),
+            name: self.name,
+            stack_size: self.stack_size,
+            panic_handler: self.panic_handler,
+            policy: self.queue_policy,
+            quiet: self.quiet,
+            exited: Mutex::new(()),
+            exited_cond: Condvar::new(),
+            min,
+            max,
+            keep_alive: (max > min).then_some(self.keep_alive),
+        });
+        let mut pool = ThreadPool {
+            workers: Arc::clone(&workers),
+            monitor: PoolMonitor { counters },
+            timers: Arc::default(),
+            timer: None,
+            closed: false,
+        };
+        {
+            let mut handles = workers.handles.lock().unwrap();
+            for id in 0..min {
+                // 创建失败时返回错误，pool 被丢弃，已经启动的工作线程会正常退出
+                let jh = workers.start(id).map_err(PoolCreationError::Spawn)?;
+                handles[id] = Some(jh);
+            }
+        }
+        let timers = Arc::clone(&pool.timers);
+        let timer = thread::Builder::new()
+            .name(format!("{}-timer", workers.name))
+            .spawn(move || {
+                timers.run(|job| {
+                    if let Err(err) = workers.submit(job, Priority::Normal, workers.policy) {
+                        pool_log!(workers, warn, "scheduled job dropped: {}", err);
+                    }
+                })
+            })
+            .map_err(PoolCreationError::Spawn)?;
+        pool.timer = Some(timer);
+        Ok(pool)
+    }
 }
 
 impl ThreadPool {
@@ -45,39 +676,743 @@ impl ThreadPool {
     ///
     /// #Panics
     ///
-    /// `new` 函数在 size 为 0 时会 panic
+    /// `new` 函数在 size 为 0 或者无法创建线程时会 panic，需要处理错误时使用 [`ThreadPool::build`]
     pub fn new(size: u32) -> ThreadPool {
-        assert!(size > 0);
-        let mut workers = Vec::new();
-        let (sender, receiver) = mpsc::channel();
-        let receiver = Arc::new(Mutex::new(receiver));
-        for i in 0..size {
-            workers.push(Worker::new(i.try_into().unwrap(), Arc::clone(&receiver)));
+        ThreadPool::build(size).unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    /// 创建线程池，size 为 0 或者无法创建工作线程时返回错误
+    pub fn build(size: u32) -> Result<ThreadPool, PoolCreationError> {
+        ThreadPool::builder(size).build()
+    }
+
+    /// 返回一个 PoolBuilder，可以设置线程名、栈大小和队列容量
+    pub fn builder(size: u32) -> PoolBuilder {
+        PoolBuilder {
+            size,
+            name: String::from("worker"),
+            stack_size: None,
+            panic_handler: None,
+            quiet: false,
+            queue_capacity: 0,
+            queue_policy: QueuePolicy::default(),
+            max_workers: None,
+            keep_alive: Duration::from_secs(60),
         }
+    }
 
-        ThreadPool { workers, sender }
+    /// 提交一个任务，线程池已经不能执行任务时返回错误；队列已满时按照 [`QueuePolicy`] 处理
+    pub fn execute<F>(&self, f: F) -> Result<(), ExecuteError>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.submit(Box::new(f))
+    }
+
+    /// 提交一个任务，队列已满时不管设置的是什么策略都立即返回 [`ExecuteError::Full`]
+    pub fn try_execute<F>(&self, f: F) -> Result<(), ExecuteError>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.workers
+            .submit(Box::new(f), Priority::Normal, QueuePolicy::Reject)
     }
 
-    pub fn execute<F>(&self, f: F)
+    /// 按照指定的优先级提交任务，同一个工作线程的队列里优先级高的任务先执行
+    pub fn execute_with_priority<F>(&self, priority: Priority, f: F) -> Result<(), ExecuteError>
     where
         F: FnOnce() + Send + 'static,
     {
-        let job = Message::NewJob(Box::new(f));
-        self.sender.send(job).unwrap();
+        self.workers
+            .submit(Box::new(f), priority, self.workers.policy)
+    }
+
+    fn submit(&self, job: Job) -> Result<(), ExecuteError> {
+        self.workers
+            .submit(job, Priority::Normal, self.workers.policy)
+    }
+
+    /// delay 之后提交任务，返回的 CancelToken 可以在到期之前取消它
+    ///
+    /// 到期时按普通任务提交，队列已满时同样按照 [`QueuePolicy`] 处理；线程池关闭时还没到期的任务被丢弃
+    /// delay 太长、到期时间超出 Instant 的范围时返回 [`ExecuteError::DelayTooLong`]
+    ///
+    /// ```
+    /// use std::time::Duration;
+    ///
+    /// let pool = web_server::ThreadPool::new(2);
+    /// let token = pool
+    ///     .execute_after(Duration::from_secs(60), || println!("never"))
+    ///     .unwrap();
+    /// token.cancel();
+    /// ```
+    pub fn execute_after<F>(&self, delay: Duration, f: F) -> Result<CancelToken, ExecuteError>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        if self.workers.scheduler.is_shutdown() {
+            return Err(ExecuteError::ShuttingDown);
+        }
+        self.timers
+            .after(delay, Box::new(f))
+            .ok_or(ExecuteError::DelayTooLong)
+    }
+
+    /// 每隔 interval 提交一次任务，第一次在 interval 之后，直到通过 CancelToken 取消或者线程池关闭
+    ///
+    /// 上一次提交的任务还没有执行完时跳过这一次，同一个周期任务不会同时执行；
+    /// interval 为 0 时返回 [`ExecuteError::ZeroInterval`]，太长时返回 [`ExecuteError::DelayTooLong`]
+    pub fn execute_every<F>(&self, interval: Duration, f: F) -> Result<CancelToken, ExecuteError>
+    where
+        F: Fn() + Send + Sync + 'static,
+    {
+        if interval.is_zero() {
+            return Err(ExecuteError::ZeroInterval);
+        }
+        if self.workers.scheduler.is_shutdown() {
+            return Err(ExecuteError::ShuttingDown);
+        }
+        self.timers
+            .every(interval, Arc::new(f))
+            .ok_or(ExecuteError::DelayTooLong)
+    }
+
+    /// 还没有到期的定时任务数，周期任务算一个
+    pub fn scheduled_len(&self) -> usize {
+        self.timers.len()
+    }
+
+    /// 正在排队、还没有被工作线程取走的任务数
+    pub fn queue_len(&self) -> usize {
+        self.workers.scheduler.len()
+    }
+
+    /// 队列容量，不限制时返回 None
+    pub fn queue_capacity(&self) -> Option<usize> {
+        self.workers.scheduler.capacity()
+    }
+
+    pub fn queue_policy(&self) -> QueuePolicy {
+        self.workers.policy
+    }
+
+    /// 工作线程数的下限和上限，两者相等时线程数固定
+    pub fn worker_range(&self) -> (usize, usize) {
+        (self.workers.min, self.workers.max)
+    }
+
+    pub fn stats(&self) -> PoolStats {
+        self.monitor.stats()
+    }
+
+    pub fn monitor(&self) -> PoolMonitor {
+        self.monitor.clone()
+    }
+}
+
+impl ThreadPool {
+    /// 关闭线程池：不再接受新任务，等待队列里的任务全部执行完、工作线程退出后返回
+    ///
+    /// 和直接丢弃线程池的效果相同；还没到期的定时任务被丢弃
+    pub fn shutdown(mut self) {
+        self.close();
+        self.join(None);
+    }
+
+    /// 和 shutdown 一样关闭线程池，但最多等待 timeout；返回 true 表示所有工作线程都已经退出
+    ///
+    /// 超时之后还在执行的线程不再等待，它们会执行完剩下的任务后自己退出；
+    /// timeout 太长、截止时间超出 Instant 的范围时和 shutdown 一样一直等下去
+    pub fn shutdown_timeout(mut self, timeout: Duration) -> bool {
+        self.close();
+        self.join(Instant::now().checked_add(timeout))
+    }
+
+    /// 立即关闭线程池：丢弃还在排队的任务并把它们返回，只等待正在执行的任务结束
+    ///
+    /// 返回的任务可以在别处执行，或者直接丢弃；通过 spawn 提交的任务被丢弃时 JobHandle 得到 Cancelled
+    pub fn shutdown_now(mut self) -> Vec<Job> {
+        self.close();
+        let jobs = self.workers.scheduler.drain();
+        self.monitor
+            .counters
+            .queued
+            .fetch_sub(jobs.len(), Ordering::Relaxed);
+        self.join(None);
+        jobs
+    }
+
+    /// 停止定时线程，然后让调度器不再接受新任务
+    fn close(&mut self) {
+        self.closed = true;
+        // 定时线程停下之后不会再往队列里放任务
+        self.timers.shutdown();
+        if let Some(timer) = self.timer.take()
+            && timer.join().is_err()
+        {
+            pool_log!(self.workers, warn, "timer thread panicked");
+        }
+        pool_log!(
+            self.workers,
+            debug,
+            "Telling all workers to finish queued jobs and exit."
+        );
+        self.workers.scheduler.shutdown();
+    }
+
+    /// 等待工作线程退出；有 deadline 时超时的线程不再等待，返回 false
+    fn join(&mut self, deadline: Option<Instant>) -> bool {
+        let exited = deadline.is_none_or(|deadline| self.workers.wait_exited(deadline));
+        let size = self.workers.handles.lock().unwrap().len();
+        pool_log!(self.workers, debug, "Shutting down all workers");
+        for id in 0..size {
+            // 线程因为 panic 退出之前已经放好了替代它的线程，继续等待新的线程
+            loop {
+                let jh = self.workers.handles.lock().unwrap()[id].take();
+                let Some(jh) = jh else {
+                    break;
+                };
+                // 超时的线程丢弃 JoinHandle，让它在后台结束
+                if !exited && !jh.is_finished() {
+                    break;
+                }
+                pool_log!(self.workers, debug, "Shutting down worker {}", id);
+                if jh.join().is_err() {
+                    pool_log!(self.workers, warn, "worker {} panicked", id);
+                }
+            }
+        }
+        exited
     }
 }
 
 impl Drop for ThreadPool {
+    /// 丢弃线程池时等待队列里的任务执行完；已经调用过 shutdown 系列方法时什么也不做
     fn drop(&mut self) {
-        println!("Sending terminate message to all workers.");
-        for _ in &mut self.workers {
-            self.sender.send(Message::Terminate).unwrap();
+        if self.closed {
+            return;
         }
+        self.close();
+        self.join(None);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        sync::{Condvar, mpsc},
+        time::{Duration, Instant},
+    };
+
+    #[test]
+    fn build_reports_errors_instead_of_panicking() {
+        assert!(matches!(
+            ThreadPool::build(0),
+            Err(PoolCreationError::ZeroSize)
+        ));
+        // 栈大小超出地址空间，线程一定创建失败
+        let err = ThreadPool::builder(2)
+            .stack_size(usize::MAX)
+            .build()
+            .err()
+            .unwrap();
+        assert!(matches!(err, PoolCreationError::Spawn(_)), "{:?}", err);
+    }
 
-        println!("Shutting down all workers");
-        for worker in &mut self.workers {
-            println!("Shutting down worker {}", worker.id);
-            worker.jh.take().unwrap().join().unwrap();
+    #[test]
+    fn workers_are_named_after_the_pool() {
+        let pool = ThreadPool::builder(2)
+            .name("test-pool")
+            .stack_size(256 * 1024)
+            .build()
+            .unwrap();
+        let (tx, rx) = mpsc::channel();
+        for _ in 0..4 {
+            let tx = tx.clone();
+            pool.execute(move || {
+                tx.send(thread::current().name().map(String::from)).unwrap();
+            })
+            .unwrap();
+        }
+        for _ in 0..4 {
+            let name = rx.recv().unwrap().unwrap();
+            assert!(name == "test-pool-0" || name == "test-pool-1", "{}", name);
         }
     }
+
+    /// 在线程池中同时运行 n 个互相等待的任务，只有 n 个工作线程都可用时才会全部成功
+    fn run_together(pool: &ThreadPool, n: usize) -> bool {
+        let arrived = Arc::new((Mutex::new(0), Condvar::new()));
+        let (tx, rx) = mpsc::channel();
+        for _ in 0..n {
+            let arrived = Arc::clone(&arrived);
+            let tx = tx.clone();
+            pool.execute(move || {
+                let (count, cond) = &*arrived;
+                let mut count = count.lock().unwrap();
+                *count += 1;
+                cond.notify_all();
+                let (count, _) = cond
+                    .wait_timeout_while(count, Duration::from_secs(5), |c| *c < n)
+                    .unwrap();
+                tx.send(*count >= n).unwrap();
+            })
+            .unwrap();
+        }
+        (0..n).all(|_| rx.recv().unwrap())
+    }
+
+    #[test]
+    fn pool_keeps_its_capacity_after_panicking_jobs() {
+        let messages = Arc::new(Mutex::new(Vec::new()));
+        let recorded = Arc::clone(&messages);
+        let pool = ThreadPool::builder(3)
+            .panic_handler(move |panic| {
+                recorded.lock().unwrap().push(panic.message().to_string());
+            })
+            .build()
+            .unwrap();
+        for i in 0..30 {
+            pool.execute(move || panic!("job {} failed", i)).unwrap();
+        }
+        pool.execute(|| std::panic::panic_any(42)).unwrap();
+        assert!(run_together(&pool, 3));
+
+        assert_eq!(pool.stats().workers, 3);
+        // 关闭线程池之后计数不再变化
+        let monitor = pool.monitor();
+        drop(pool);
+        let stats = monitor.stats();
+        assert_eq!((stats.workers, stats.panicked, stats.completed), (0, 31, 3));
+        let messages = messages.lock().unwrap();
+        assert_eq!(messages.len(), 31);
+        assert!(messages.contains(&String::from("job 7 failed")));
+        assert!(messages.contains(&String::from("Box<dyn Any>")));
+    }
+
+    #[test]
+    fn worker_is_replaced_when_the_panic_handler_panics() {
+        let pool = ThreadPool::builder(2)
+            .name("fragile")
+            .panic_handler(|panic| panic!("handler failed: {}", panic.message()))
+            .build()
+            .unwrap();
+        for _ in 0..5 {
+            pool.execute(|| panic!("boom")).unwrap();
+        }
+        assert!(run_together(&pool, 2));
+        let (tx, rx) = mpsc::channel();
+        pool.execute(move || tx.send(thread::current().name().map(String::from)).unwrap())
+            .unwrap();
+        let name = rx.recv().unwrap().unwrap();
+        assert!(name.starts_with("fragile-"), "{}", name);
+        assert_eq!(pool.stats().workers, 2);
+        assert_eq!(pool.stats().panicked, 5);
+        // drop 等待的是替换后的线程，不会 panic
+    }
+
+    /// 一个工作线程、容量为 2 的线程池，唯一的工作线程被占住，队列已经排满；
+    /// 向返回的 Sender 发送消息后工作线程开始执行，排队的任务把自己的编号发到 done
+    fn full_pool(policy: QueuePolicy) -> (ThreadPool, mpsc::Sender<()>, mpsc::Receiver<i32>) {
+        let pool = ThreadPool::builder(1)
+            .queue_capacity(2)
+            .queue_policy(policy)
+            .build()
+            .unwrap();
+        let (release, gate) = mpsc::channel::<()>();
+        pool.execute(move || gate.recv().unwrap()).unwrap();
+        while pool.stats().busy == 0 {
+            thread::sleep(Duration::from_millis(1));
+        }
+        let (done, finished) = mpsc::channel();
+        for i in 0..2 {
+            let done = done.clone();
+            pool.execute(move || done.send(i).unwrap()).unwrap();
+        }
+        assert_eq!(pool.queue_len(), 2);
+        (pool, release, finished)
+    }
+
+    #[test]
+    fn reject_policy_and_try_execute_fail_when_full() {
+        let (pool, release, finished) = full_pool(QueuePolicy::Reject);
+        assert_eq!(pool.queue_capacity(), Some(2));
+        assert_eq!(pool.execute(|| {}), Err(ExecuteError::Full));
+        assert_eq!(pool.try_execute(|| {}), Err(ExecuteError::Full));
+        assert_eq!(pool.stats().rejected, 2);
+        assert_eq!(pool.stats().queued, 2);
+        release.send(()).unwrap();
+        assert_eq!(finished.iter().take(2).collect::<Vec<_>>(), [0, 1]);
+        assert_eq!(pool.queue_len(), 0);
+        assert!(pool.try_execute(|| {}).is_ok());
+    }
+
+    #[test]
+    fn block_policy_waits_for_space() {
+        let (pool, release, finished) = full_pool(QueuePolicy::Block);
+        let pool = Arc::new(pool);
+        let submitted = Arc::new(AtomicUsize::new(0));
+        let submitter = {
+            let (pool, submitted) = (Arc::clone(&pool), Arc::clone(&submitted));
+            thread::spawn(move || {
+                pool.execute(|| {}).unwrap();
+                submitted.store(1, Ordering::SeqCst);
+            })
+        };
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(submitted.load(Ordering::SeqCst), 0);
+        // try_execute 不会等待
+        assert_eq!(pool.try_execute(|| {}), Err(ExecuteError::Full));
+        release.send(()).unwrap();
+        submitter.join().unwrap();
+        assert_eq!(finished.iter().take(2).collect::<Vec<_>>(), [0, 1]);
+        assert_eq!(pool.stats().rejected, 1);
+    }
+
+    #[test]
+    fn drop_oldest_policy_evicts_the_earliest_job() {
+        let (pool, release, finished) = full_pool(QueuePolicy::DropOldest);
+        // 依次挤掉编号 0、1 的任务，再挤掉 second
+        let second = pool.spawn(|| 2).unwrap();
+        let third = pool.spawn(|| 3).unwrap();
+        let fourth = pool.spawn(|| 4).unwrap();
+        assert_eq!(pool.queue_len(), 2);
+        assert_eq!(second.join(), Err(JobError::Cancelled));
+        release.send(()).unwrap();
+        assert_eq!(third.join(), Ok(3));
+        assert_eq!(fourth.join(), Ok(4));
+        // 被挤掉的任务没有执行，发送端随任务一起被丢弃
+        assert!(finished.recv().is_err());
+        assert_eq!(pool.stats().rejected, 3);
+        assert_eq!(pool.stats().queued, 0);
+    }
+
+    #[test]
+    fn caller_runs_policy_executes_on_the_submitting_thread() {
+        let (pool, release, finished) = full_pool(QueuePolicy::CallerRuns);
+        let (tx, rx) = mpsc::channel();
+        pool.execute(move || tx.send(thread::current().id()).unwrap())
+            .unwrap();
+        assert_eq!(rx.recv().unwrap(), thread::current().id());
+        // 调用方执行的任务 panic 时同样被捕获并计数，错误日志在调用方的线程上输出
+        let (result, logs) = log::capture(|| pool.execute(|| panic!("on the caller")));
+        result.unwrap();
+        assert_eq!(pool.stats().panicked, 1);
+        assert!(
+            logs.iter()
+                .any(|line| line.starts_with("ERROR") && line.contains("panicked")),
+            "{:?}",
+            logs
+        );
+        release.send(()).unwrap();
+        assert_eq!(finished.iter().take(2).collect::<Vec<_>>(), [0, 1]);
+        assert_eq!(pool.stats().rejected, 0);
+    }
+
+    #[test]
+    fn queue_policy_parses_from_config_names() {
+        assert_eq!("drop_oldest".parse(), Ok(QueuePolicy::DropOldest));
+        assert_eq!("caller_runs".parse(), Ok(QueuePolicy::CallerRuns));
+        assert!("lifo".parse::<QueuePolicy>().is_err());
+        assert_eq!(ThreadPool::new(1).queue_capacity(), None);
+    }
+
+    /// 提交 n 个任务，每个任务等到从返回的 Sender 收到一条消息（或者 Sender 被丢弃）才结束
+    fn block_workers(pool: &ThreadPool, n: usize) -> mpsc::Sender<()> {
+        let (release, gate) = mpsc::channel::<()>();
+        let gate = Arc::new(Mutex::new(gate));
+        for _ in 0..n {
+            let gate = Arc::clone(&gate);
+            pool.execute(move || {
+                let _ = gate.lock().unwrap().recv();
+            })
+            .unwrap();
+        }
+        release
+    }
+
+    /// 等待线程池的状态满足条件，超时则失败
+    fn eventually(pool: &ThreadPool, what: &str, check: impl Fn(&PoolStats) -> bool) {
+        for _ in 0..400 {
+            if check(&pool.stats()) {
+                return;
+            }
+            thread::sleep(Duration::from_millis(5));
+        }
+        panic!("{}: {:?}", what, pool.stats());
+    }
+
+    #[test]
+    fn pool_grows_under_bursts_and_shrinks_back_when_idle() {
+        let pool = ThreadPool::builder(1)
+            .max_workers(4)
+            .keep_alive(Duration::from_millis(50))
+            .build()
+            .unwrap();
+        assert_eq!(pool.worker_range(), (1, 4));
+        for _ in 0..2 {
+            // 4 个互相等待的任务只有线程数长到 4 才能同时完成
+            assert!(run_together(&pool, 4));
+            assert_eq!(pool.stats().peak_workers, 4);
+            eventually(&pool, "shrink back to the minimum", |s| s.workers == 1);
+        }
+        // 上限之外的任务排队等待，线程数不会超过上限
+        let release = block_workers(&pool, 6);
+        eventually(&pool, "grow to the maximum", |s| {
+            s.busy == 4 && s.queued == 2
+        });
+        assert_eq!(pool.stats().workers, 4);
+        for _ in 0..6 {
+            release.send(()).unwrap();
+        }
+        eventually(&pool, "shrink after the timeout", |s| {
+            s.workers == 1 && s.completed == 14
+        });
+
+        // 剩下的线程照常工作，空闲线程不会低于下限
+        let (tx, rx) = mpsc::channel();
+        pool.execute(move || tx.send(()).unwrap()).unwrap();
+        rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        thread::sleep(Duration::from_millis(150));
+        assert_eq!(pool.stats().workers, 1);
+    }
+
+    #[test]
+    fn fixed_size_pool_does_not_grow() {
+        let pool = ThreadPool::new(2);
+        assert_eq!(pool.worker_range(), (2, 2));
+        let release = block_workers(&pool, 3);
+        eventually(&pool, "fill the pool", |s| s.busy == 2 && s.queued == 1);
+        thread::sleep(Duration::from_millis(20));
+        assert_eq!(pool.stats().peak_workers, 2);
+        drop(release);
+        assert!(matches!(
+            ThreadPool::builder(4).max_workers(2).build(),
+            Err(PoolCreationError::MaxBelowSize)
+        ));
+    }
+
+    #[test]
+    fn higher_priority_jobs_run_first() {
+        let pool = ThreadPool::new(1);
+        let release = block_workers(&pool, 1);
+        eventually(&pool, "occupy the worker", |s| s.busy == 1);
+        let (tx, rx) = mpsc::channel();
+        for (i, priority) in [Priority::Low, Priority::Normal, Priority::High]
+            .into_iter()
+            .cycle()
+            .take(6)
+            .enumerate()
+        {
+            let tx = tx.clone();
+            pool.execute_with_priority(priority, move || tx.send((priority, i)).unwrap())
+                .unwrap();
+        }
+        drop(release);
+        let order: Vec<_> = rx.iter().take(6).collect();
+        // 优先级高的先执行，同一优先级内先进先出
+        assert_eq!(
+            order,
+            [
+                (Priority::High, 2),
+                (Priority::High, 5),
+                (Priority::Normal, 1),
+                (Priority::Normal, 4),
+                (Priority::Low, 0),
+                (Priority::Low, 3),
+            ]
+        );
+    }
+
+    #[test]
+    fn delayed_and_periodic_jobs_run_on_the_pool() {
+        let pool = ThreadPool::builder(2).name("timed").build().unwrap();
+        let (tx, rx) = mpsc::channel();
+        let started = Instant::now();
+        let once = tx.clone();
+        pool.execute_after(Duration::from_millis(30), move || {
+            once.send(thread::current().name().map(String::from))
+                .unwrap()
+        })
+        .unwrap();
+        let name = rx.recv_timeout(Duration::from_secs(5)).unwrap().unwrap();
+        assert!(started.elapsed() >= Duration::from_millis(30));
+        assert!(name.starts_with("timed-"), "{}", name);
+
+        assert_eq!(
+            pool.execute_every(Duration::ZERO, || {}).unwrap_err(),
+            ExecuteError::ZeroInterval
+        );
+        assert_eq!(
+            pool.execute_every(Duration::MAX, || {}).unwrap_err(),
+            ExecuteError::DelayTooLong
+        );
+        assert_eq!(
+            pool.execute_after(Duration::MAX, || {}).unwrap_err(),
+            ExecuteError::DelayTooLong
+        );
+        let ticks = Arc::new(AtomicUsize::new(0));
+        let counted = Arc::clone(&ticks);
+        let token = pool
+            .execute_every(Duration::from_millis(5), move || {
+                counted.fetch_add(1, Ordering::SeqCst);
+            })
+            .unwrap();
+        while ticks.load(Ordering::SeqCst) < 3 {
+            thread::sleep(Duration::from_millis(5));
+        }
+        token.cancel();
+        assert_eq!(pool.scheduled_len(), 0);
+        // 取消之前已经提交的那一次可能还没执行完
+        thread::sleep(Duration::from_millis(20));
+        let after_cancel = ticks.load(Ordering::SeqCst);
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(ticks.load(Ordering::SeqCst), after_cancel);
+
+        // 线程池关闭时没到期的任务被丢弃，不会执行
+        let pending = Arc::new(());
+        let held = Arc::clone(&pending);
+        pool.execute_after(Duration::from_secs(60), move || drop(held))
+            .unwrap();
+        assert_eq!(pool.scheduled_len(), 1);
+        drop(pool);
+        assert_eq!(Arc::strong_count(&pending), 1);
+    }
+
+    #[test]
+    fn shutdown_runs_queued_jobs_first() {
+        let pool = ThreadPool::builder(2).quiet(true).build().unwrap();
+        let done = Arc::new(AtomicUsize::new(0));
+        for i in 0..10 {
+            let done = Arc::clone(&done);
+            pool.execute(move || {
+                thread::sleep(Duration::from_millis(2));
+                done.fetch_add(1, Ordering::SeqCst);
+                if i == 0 {
+                    panic!("quiet");
+                }
+            })
+            .unwrap();
+        }
+        let monitor = pool.monitor();
+        pool.shutdown();
+        assert_eq!(done.load(Ordering::SeqCst), 10);
+        let stats = monitor.stats();
+        assert_eq!((stats.workers, stats.queued, stats.panicked), (0, 0, 1));
+    }
+
+    #[test]
+    fn shutdown_timeout_reports_workers_still_running() {
+        assert!(ThreadPool::new(2).shutdown_timeout(Duration::from_secs(5)));
+        assert!(ThreadPool::new(2).shutdown_timeout(Duration::MAX));
+
+        let pool = ThreadPool::new(1);
+        let monitor = pool.monitor();
+        let release = block_workers(&pool, 1);
+        let (tx, rx) = mpsc::channel();
+        pool.execute(move || tx.send(()).unwrap()).unwrap();
+        let started = Instant::now();
+        assert!(!pool.shutdown_timeout(Duration::from_millis(50)));
+        assert!(started.elapsed() >= Duration::from_millis(50));
+        // 没有等到的线程在后台执行完剩下的任务后退出
+        drop(release);
+        rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        while monitor.stats().workers > 0 {
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn drop_after_shutdown_does_nothing() {
+        let count = |logs: &[String], msg: &str| logs.iter().filter(|l| l.contains(msg)).count();
+        for shutdown in [
+            (|pool: ThreadPool| pool.shutdown()) as fn(ThreadPool),
+            |pool| assert!(pool.shutdown_timeout(Duration::from_secs(5))),
+            |pool| assert!(pool.shutdown_now().is_empty()),
+        ] {
+            // shutdown 系列方法消耗 self，返回前 drop 已经跑过
+            let ((), logs) = log::capture(|| shutdown(ThreadPool::new(2)));
+            assert_eq!(count(&logs, "Telling all workers"), 1, "{:?}", logs);
+            assert_eq!(count(&logs, "Shutting down all workers"), 1, "{:?}", logs);
+            assert_eq!(count(&logs, "Shutting down worker 0"), 1, "{:?}", logs);
+        }
+    }
+
+    #[test]
+    fn shutdown_now_returns_queued_jobs() {
+        let pool = ThreadPool::new(1);
+        let monitor = pool.monitor();
+        let release = block_workers(&pool, 1);
+        eventually(&pool, "occupy the worker", |s| s.busy == 1);
+        let (tx, rx) = mpsc::channel();
+        for priority in [Priority::Low, Priority::High] {
+            let tx = tx.clone();
+            pool.execute_with_priority(priority, move || tx.send(priority).unwrap())
+                .unwrap();
+        }
+        let handle = pool.spawn(|| 42).unwrap();
+        // 正在执行的任务结束之后 shutdown_now 才返回
+        let releaser = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            drop(release);
+        });
+        let jobs = pool.shutdown_now();
+        releaser.join().unwrap();
+        assert_eq!(jobs.len(), 3);
+        assert_eq!(monitor.stats().queued, 0);
+        assert_eq!(monitor.stats().completed, 1);
+        let mut jobs = jobs.into_iter();
+        jobs.next().unwrap()();
+        assert_eq!(rx.try_recv(), Ok(Priority::High));
+        drop(jobs);
+        assert!(rx.try_recv().is_err());
+        assert_eq!(handle.join(), Err(JobError::Cancelled));
+    }
+
+    #[test]
+    fn stats_track_queue_and_busy_workers() {
+        let pool = ThreadPool::new(2);
+        let (release, gate) = mpsc::channel::<()>();
+        let gate = Arc::new(Mutex::new(gate));
+        // 两个任务占住两个工作线程，第三个只能排队
+        for _ in 0..3 {
+            let gate = Arc::clone(&gate);
+            pool.execute(move || {
+                let _ = gate.lock().unwrap().recv();
+            })
+            .unwrap();
+        }
+        let wait_for = |expected: PoolStats| {
+            for _ in 0..200 {
+                if pool.stats() == expected {
+                    return;
+                }
+                thread::sleep(Duration::from_millis(5));
+            }
+            panic!("expected {:?}, got {:?}", expected, pool.stats());
+        };
+        // 第一个任务拿着锁等待，第二个任务在等锁，两个工作线程都算忙
+        wait_for(PoolStats {
+            workers: 2,
+            peak_workers: 2,
+            queued: 1,
+            busy: 2,
+            completed: 0,
+            panicked: 0,
+            rejected: 0,
+        });
+        for _ in 0..3 {
+            release.send(()).unwrap();
+        }
+        wait_for(PoolStats {
+            workers: 2,
+            peak_workers: 2,
+            queued: 0,
+            busy: 0,
+            completed: 3,
+            panicked: 0,
+            rejected: 0,
+        });
+    }
 }
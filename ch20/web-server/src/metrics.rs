@@ -0,0 +1,363 @@
+/*
+指标：
+    Metrics 是一个中间件，统计经过它的每个请求，并在 GET /metrics 上以 Prometheus 文本格式输出：
+
+        http_requests_total{method,route,status}      请求数
+        http_request_duration_seconds{route}          处理耗时的直方图
+        http_connections_active                       当前打开的连接数
+        thread_pool_workers                           工作线程数
+        thread_pool_peak_workers                      同时存活的工作线程数的最大值
+        thread_pool_busy_workers                      正在执行任务的工作线程数
+        thread_pool_queued_jobs                       排队等待的任务数
+        thread_pool_completed_jobs_total              执行完的任务数
+        thread_pool_panicked_jobs_total               panic 的任务数
+        thread_pool_rejected_jobs_total               队列已满时被拒绝或者挤掉的任务数
+
+    route 是路由器匹配到的模式（例如 /api/todos/:id），没有匹配任何路由时是 unmatched，
+    不使用实际路径，标签的取值个数就是有限的。方法同理，不认识的方法统一记为 OTHER。
+    连接数和线程池状态在抓取时通过 gauge / counter 注册的函数读取，也可以注册自定义的指标。
+*/
+
+use crate::{
+    PoolMonitor,
+    conn::ConnLimiter,
+    http::{Request, Response},
+    middleware::{Middleware, Next},
+};
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// 直方图的桶上界（秒），与 Prometheus 客户端库的默认值相同
+const BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+const METHODS: [&str; 9] = [
+    "GET", "HEAD", "POST", "PUT", "DELETE", "PATCH", "OPTIONS", "CONNECT", "TRACE",
+];
+
+#[derive(Default)]
+struct Histogram {
+    /// 每个桶各自的计数，输出时再累加；最后一个是超过所有上界的部分
+    counts: [u64; BUCKETS.len() + 1],
+    sum: f64,
+}
+
+impl Histogram {
+    fn observe(&mut self, secs: f64) {
+        let idx = BUCKETS
+            .iter()
+            .position(|le| secs <= *le)
+            .unwrap_or(BUCKETS.len());
+        self.counts[idx] += 1;
+        self.sum += secs;
+    }
+}
+
+/// 抓取时才读取的指标
+struct Sampled {
+    name: String,
+    help: String,
+    kind: &'static str,
+    read: Box<dyn Fn() -> f64 + Send + Sync>,
+}
+
+#[derive(Default)]
+struct Recorded {
+    /// (method, route, status) -> 请求数
+    requests: BTreeMap<(String, String, u16), u64>,
+    latency: BTreeMap<String, Histogram>,
+}
+
+pub struct Metrics {
+    path: String,
+    recorded: Mutex<Recorded>,
+    sampled: Vec<Sampled>,
+}
+
+impl Metrics {
+    /// path 是输出指标的路径，通常是 /metrics
+    pub fn new(path: &str) -> Metrics {
+        Metrics {
+            path: path.to_string(),
+            recorded: Mutex::default(),
+            sampled: Vec::new(),
+        }
+    }
+
+    /// 注册一个抓取时读取的 gauge
+    pub fn gauge<F>(self, name: &str, help: &str, read: F) -> Metrics
+    where
+        F: Fn() -> f64 + Send + Sync + 'static,
+    {
+        self.sampled("gauge", name, help, read)
+    }
+
+    /// 注册一个抓取时读取的 counter，read 返回的值应该只增不减
+    pub fn counter<F>(self, name: &str, help: &str, read: F) -> Metrics
+    where
+        F: Fn() -> f64 + Send + Sync + 'static,
+    {
+        self.sampled("counter", name, help, read)
+    }
+
+    fn sampled<F>(mut self, kind: &'static str, name: &str, help: &str, read: F) -> Metrics
+    where
+        F: Fn() -> f64 + Send + Sync + 'static,
+    {
+        self.sampled.push(Sampled {
+            name: name.to_string(),
+            help: help.to_string(),
+            kind,
+            read: Box::new(read),
+        });
+        self
+    }
+
+    /// 输出线程池的状态
+    pub fn with_pool(self, pool: PoolMonitor) -> Metrics {
+        let (peak, busy, queued) = (pool.clone(), pool.clone(), pool.clone());
+        let (completed, panicked, rejected) = (pool.clone(), pool.clone(), pool.clone());
+        self.gauge(
+            "thread_pool_workers",
+            "Worker threads in the pool.",
+            move || pool.stats().workers as f64,
+        )
+        .gauge(
+            "thread_pool_peak_workers",
+            "Most worker threads alive at the same time.",
+            move || peak.stats().peak_workers as f64,
+        )
+        .gauge(
+            "thread_pool_busy_workers",
+            "Worker threads currently running a job.",
+            move || busy.stats().busy as f64,
+        )
+        .gauge(
+            "thread_pool_queued_jobs",
+            "Jobs submitted but not yet picked up by a worker.",
+            move || queued.stats().queued as f64,
+        )
+        .counter(
+            "thread_pool_completed_jobs_total",
+            "Jobs the pool has finished running.",
+            move || completed.stats().completed as f64,
+        )
+        .counter(
+            "thread_pool_panicked_jobs_total",
+            "Jobs that panicked while running.",
+            move || panicked.stats().panicked as f64,
+        )
+        .counter(
+            "thread_pool_rejected_jobs_total",
+            "Jobs rejected or evicted because the queue was full.",
+            move || rejected.stats().rejected as f64,
+        )
+    }
+
+    /// 输出当前打开的连接数
+    pub fn with_connections(self, limiter: ConnLimiter) -> Metrics {
+        self.gauge(
+            "http_connections_active",
+            "Client connections currently open.",
+            move || limiter.total() as f64,
+        )
+    }
+
+    /// 记录一个请求
+    pub fn observe(&self, req: &Request, resp: &Response, elapsed: Duration) {
+        let method = if METHODS.contains(&req.method.as_str()) {
+            req.method.clone()
+        } else {
+            String::from("OTHER")
+        };
+        let route = req.route.as_deref().unwrap_or("unmatched").to_string();
+        let mut recorded = self.recorded.lock().unwrap();
+        *recorded
+            .requests
+            .entry((method, route.clone(), resp.status))
+            .or_insert(0) += 1;
+        recorded
+            .latency
+            .entry(route)
+            .or_default()
+            .observe(elapsed.as_secs_f64());
+    }
+
+    /// Prometheus 文本格式
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        {
+            let recorded = self.recorded.lock().unwrap();
+            header(
+                &mut out,
+                "http_requests_total",
+                "Requests handled, by method, matched route and status.",
+                "counter",
+            );
+            for ((method, route, status), count) in &recorded.requests {
+                let _ = writeln!(
+                    out,
+                    "http_requests_total{{method=\"{}\",route=\"{}\",status=\"{}\"}} {}",
+                    method,
+                    escape(route),
+                    status,
+                    count
+                );
+            }
+            header(
+                &mut out,
+                "http_request_duration_seconds",
+                "Time spent producing the response, by matched route.",
+                "histogram",
+            );
+            for (route, histogram) in &recorded.latency {
+                let route = escape(route);
+                let mut cumulative = 0;
+                for (i, count) in histogram.counts.iter().enumerate() {
+                    cumulative += count;
+                    let le = BUCKETS.get(i).map_or(String::from("+Inf"), f64::to_string);
+                    let _ = writeln!(
+                        out,
+                        "http_request_duration_seconds_bucket{{route=\"{}\",le=\"{}\"}} {}",
+                        route, le, cumulative
+                    );
+                }
+                let _ = writeln!(
+                    out,
+                    "http_request_duration_seconds_sum{{route=\"{}\"}} {}",
+                    route, histogram.sum
+                );
+                let _ = writeln!(
+                    out,
+                    "http_request_duration_seconds_count{{route=\"{}\"}} {}",
+                    route, cumulative
+                );
+            }
+        }
+        for sampled in &self.sampled {
+            header(&mut out, &sampled.name, &sampled.help, sampled.kind);
+            let _ = writeln!(out, "{} {}", sampled.name, (sampled.read)());
+        }
+        out
+    }
+}
+
+fn header(out: &mut String, name: &str, help: &str, kind: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+/// 标签值中的反斜杠、双引号和换行需要转义
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// 拦截 GET/HEAD 指标路径，其余请求交给后面的处理链，两者都会被统计
+impl Middleware for Metrics {
+    fn call(&self, req: &mut Request, next: Next<'_>) -> Response {
+        let timer = Instant::now();
+        let resp = if req.path == self.path && (req.method == "GET" || req.method == "HEAD") {
+            req.route = Some(self.path.clone());
+            Response::new(200)
+                .with_header("Content-Type", "text/plain; version=0.0.4; charset=utf-8")
+                .with_body(self.render())
+        } else {
+            next.run(req)
+        };
+        self.observe(req, &resp, timer.elapsed());
+        resp
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Handler, Pipeline, Router, ThreadPool};
+
+    fn lines(text: &str, prefix: &str) -> Vec<String> {
+        text.lines()
+            .filter(|line| line.starts_with(prefix))
+            .map(String::from)
+            .collect()
+    }
+
+    #[test]
+    fn counts_requests_by_route_and_status() {
+        let router = Router::new()
+            .get("/items/:id", |_req: &mut Request| Response::new(200))
+            .post("/items/:id", |_req: &mut Request| Response::new(201));
+        let pipeline = Pipeline::new(router).with(Metrics::new("/metrics"));
+        for target in ["/items/1", "/items/2", "/nope"] {
+            pipeline.handle(&mut Request::new("GET", target));
+        }
+        pipeline.handle(&mut Request::new("BREW", "/items/3"));
+        let body = pipeline.handle(&mut Request::new("GET", "/metrics")).body;
+        let text = String::from_utf8(body).unwrap();
+        assert_eq!(
+            lines(&text, "http_requests_total{"),
+            [
+                r#"http_requests_total{method="GET",route="/items/:id",status="200"} 2"#,
+                r#"http_requests_total{method="GET",route="unmatched",status="404"} 1"#,
+                r#"http_requests_total{method="OTHER",route="unmatched",status="405"} 1"#,
+            ]
+        );
+        assert!(text.contains("# TYPE http_request_duration_seconds histogram\n"));
+        assert!(text.contains(
+            "http_request_duration_seconds_bucket{route=\"/items/:id\",le=\"+Inf\"} 2\n"
+        ));
+        assert!(text.contains("http_request_duration_seconds_count{route=\"unmatched\"} 2\n"));
+    }
+
+    #[test]
+    fn histogram_buckets_are_cumulative() {
+        let metrics = Metrics::new("/metrics");
+        let req = Request::new("GET", "/");
+        let resp = Response::new(200);
+        for ms in [1, 20, 20, 20_000] {
+            metrics.observe(&req, &resp, Duration::from_millis(ms));
+        }
+        let text = metrics.render();
+        let bucket = |le: &str| {
+            format!(
+                "http_request_duration_seconds_bucket{{route=\"unmatched\",le=\"{}\"}}",
+                le
+            )
+        };
+        assert!(text.contains(&format!("{} 1\n", bucket("0.005"))));
+        assert!(text.contains(&format!("{} 3\n", bucket("0.025"))));
+        assert!(text.contains(&format!("{} 3\n", bucket("10"))));
+        assert!(text.contains(&format!("{} 4\n", bucket("+Inf"))));
+        assert!(text.contains("http_request_duration_seconds_count{route=\"unmatched\"} 4\n"));
+    }
+
+    #[test]
+    fn samples_pool_and_custom_gauges() {
+        let pool = ThreadPool::new(2);
+        let metrics = Metrics::new("/metrics").with_pool(pool.monitor()).gauge(
+            "answer",
+            "A constant.",
+            || 42.0,
+        );
+        let text = metrics.render();
+        assert!(text.contains("# TYPE thread_pool_workers gauge\nthread_pool_workers 2\n"));
+        assert!(text.contains("thread_pool_peak_workers 2\n"));
+        assert!(text.contains("thread_pool_queued_jobs 0\n"));
+        assert!(text.contains("# TYPE thread_pool_completed_jobs_total counter\n"));
+        assert!(text.contains("thread_pool_panicked_jobs_total 0\n"));
+        assert!(text.contains("thread_pool_rejected_jobs_total 0\n"));
+        assert!(text.contains("# HELP answer A constant.\n# TYPE answer gauge\nanswer 42\n"));
+    }
+
+    #[test]
+    fn escapes_label_values() {
+        assert_eq!(escape("a\"b\\c\nd"), "a\\\"b\\\\c\\nd");
+    }
+}
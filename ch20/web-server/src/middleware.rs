@@ -0,0 +1,391 @@
+/*
+中间件：
+    Handler 负责把请求变成响应；Middleware 包在 Handler 外面，可以在调用 next 之前修改请求、
+    在 next 返回之后修改响应，也可以不调用 next 直接返回（短路），例如鉴权失败、CORS 预检。
+
+    Pipeline::new(router).with(a).with(b) 的调用顺序是：
+        a 前半段 -> b 前半段 -> router -> b 后半段 -> a 后半段
+    也就是先添加的中间件在最外层。
+*/
+
+use crate::{
+    access_log::{AccessLog, Record},
+    http::{Request, Response},
+};
+use std::{
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+
+/// 处理请求并返回响应
+pub trait Handler: Send + Sync + 'static {
+    fn handle(&self, req: &mut Request) -> Response;
+}
+
+impl<F> Handler for F
+where
+    F: Fn(&mut Request) -> Response + Send + Sync + 'static,
+{
+    fn handle(&self, req: &mut Request) -> Response {
+        self(req)
+    }
+}
+
+impl<H: Handler + ?Sized> Handler for Arc<H> {
+    fn handle(&self, req: &mut Request) -> Response {
+        (**self).handle(req)
+    }
+}
+
+pub trait Middleware: Send + Sync + 'static {
+    fn call(&self, req: &mut Request, next: Next<'_>) -> Response;
+}
+
+impl<M: Middleware + ?Sized> Middleware for Arc<M> {
+    fn call(&self, req: &mut Request, next: Next<'_>) -> Response {
+        (**self).call(req, next)
+    }
+}
+
+/// 链条中剩余的部分，只能被调用一次
+pub struct Next<'a> {
+    middlewares: &'a [Arc<dyn Middleware>],
+    endpoint: &'a dyn Handler,
+}
+
+impl Next<'_> {
+    pub fn run(self, req: &mut Request) -> Response {
+        match self.middlewares.split_first() {
+            Some((first, rest)) => first.call(
+                req,
+                Next {
+                    middlewares: rest,
+                    endpoint: self.endpoint,
+                },
+            ),
+            None => self.endpoint.handle(req),
+        }
+    }
+}
+
+/// 由若干中间件和最终的 Handler 组成的处理链，本身也是一个 Handler
+#[derive(Clone)]
+pub struct Pipeline {
+    middlewares: Vec<Arc<dyn Middleware>>,
+    endpoint: Arc<dyn Handler>,
+}
+
+impl Pipeline {
+    pub fn new<H: Handler>(endpoint: H) -> Pipeline {
+        Pipeline {
+            middlewares: Vec::new(),
+            endpoint: Arc::new(endpoint),
+        }
+    }
+
+    /// 在现有中间件的内层追加一个中间件
+    pub fn with<M: Middleware>(mut self, middleware: M) -> Pipeline {
+        self.middlewares.push(Arc::new(middleware));
+        self
+    }
+
+    /// 替换最终的 Handler，已有的中间件保持不变
+    pub fn set_endpoint<H: Handler>(&mut self, endpoint: H) {
+        self.endpoint = Arc::new(endpoint);
+    }
+
+    pub fn push<M: Middleware>(&mut self, middleware: M) {
+        self.middlewares.push(Arc::new(middleware));
+    }
+}
+
+impl Handler for Pipeline {
+    fn handle(&self, req: &mut Request) -> Response {
+        Next {
+            middlewares: &self.middlewares,
+            endpoint: &*self.endpoint,
+        }
+        .run(req)
+    }
+}
+
+/// 请求处理完后写一条访问日志
+impl Middleware for AccessLog {
+    fn call(&self, req: &mut Request, next: Next<'_>) -> Response {
+        let started = SystemTime::now();
+        let timer = Instant::now();
+        let resp = next.run(req);
+        self.record(&Record::new(Some(req), &resp, started, timer.elapsed()));
+        resp
+    }
+}
+
+/// 为每个请求分配一个 `X-Request-Id`，客户端已经带了的话沿用客户端的值，并在响应中原样返回
+pub struct RequestId {
+    prefix: String,
+    next: AtomicU64,
+}
+
+impl RequestId {
+    pub const HEADER: &'static str = "X-Request-Id";
+
+    pub fn new() -> RequestId {
+        // 用启动时间做前缀，避免重启后生成重复的 id
+        let boot = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_millis() as u64);
+        RequestId {
+            prefix: format!("{:x}", boot),
+            next: AtomicU64::new(1),
+        }
+    }
+}
+
+impl Default for RequestId {
+    fn default() -> RequestId {
+        RequestId::new()
+    }
+}
+
+impl Middleware for RequestId {
+    fn call(&self, req: &mut Request, next: Next<'_>) -> Response {
+        let id = match req.header(RequestId::HEADER) {
+            Some(id) if !id.is_empty() && id.len() <= 128 => id.to_string(),
+            _ => {
+                let n = self.next.fetch_add(1, Ordering::Relaxed);
+                let id = format!("{}-{}", self.prefix, n);
+                req.headers.set(RequestId::HEADER, id.clone());
+                id
+            }
+        };
+        let mut resp = next.run(req);
+        resp.headers.set(RequestId::HEADER, id);
+        resp
+    }
+}
+
+/// 跨域资源共享，预检请求（OPTIONS + Access-Control-Request-Method）直接返回 204
+pub struct Cors {
+    allowed_origins: Vec<String>,
+    allowed_methods: String,
+    allowed_headers: String,
+    max_age: u32,
+}
+
+impl Cors {
+    /// 允许任意来源
+    pub fn any() -> Cors {
+        Cors::with_origins(&["*"])
+    }
+
+    pub fn with_origins(origins: &[&str]) -> Cors {
+        Cors {
+            allowed_origins: origins.iter().map(|o| o.to_string()).collect(),
+            allowed_methods: String::from("GET, POST, PUT, PATCH, DELETE, OPTIONS"),
+            allowed_headers: String::from("Content-Type, Authorization"),
+            max_age: 600,
+        }
+    }
+
+    /// 允许任意来源时所有响应都一样，否则 Access-Control-Allow-Origin 随 Origin 变化
+    fn any_origin(&self) -> bool {
+        self.allowed_origins.iter().any(|o| o == "*")
+    }
+
+    /// 按来源决定响应头时，不管这次是否允许都要加上 Vary: Origin，
+    /// 否则缓存可能把一个来源（或者没有 Origin）的响应交给另一个来源
+    fn vary(&self, mut resp: Response) -> Response {
+        if !self.any_origin() {
+            resp.headers.append("Vary", "Origin");
+        }
+        resp
+    }
+
+    fn allow_origin(&self, origin: &str) -> Option<String> {
+        if self.any_origin() {
+            Some(String::from("*"))
+        } else if self.allowed_origins.iter().any(|o| o == origin) {
+            Some(origin.to_string())
+        } else {
+            None
+        }
+    }
+}
+
+impl Middleware for Cors {
+    fn call(&self, req: &mut Request, next: Next<'_>) -> Response {
+        let origin = match req.header("Origin") {
+            Some(origin) => origin.to_string(),
+            None => return self.vary(next.run(req)),
+        };
+        let allowed = self.allow_origin(&origin);
+        let preflight =
+            req.method == "OPTIONS" && req.headers.contains("Access-Control-Request-Method");
+
+        let mut resp = if preflight {
+            let mut resp = Response::new(204);
+            if allowed.is_some() {
+                resp.headers.set(
+                    "Access-Control-Allow-Methods",
+                    self.allowed_methods.as_str(),
+                );
+                resp.headers.set(
+                    "Access-Control-Allow-Headers",
+                    self.allowed_headers.as_str(),
+                );
+                resp.headers
+                    .set("Access-Control-Max-Age", self.max_age.to_string());
+            }
+            resp
+        } else {
+            next.run(req)
+        };
+        if let Some(allowed) = allowed {
+            resp.headers.set("Access-Control-Allow-Origin", allowed);
+        }
+        self.vary(resp)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    /// 记录自己被调用的先后顺序
+    struct Trace {
+        name: &'static str,
+        log: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl Middleware for Trace {
+        fn call(&self, req: &mut Request, next: Next<'_>) -> Response {
+            self.log
+                .lock()
+                .unwrap()
+                .push(format!("{} before", self.name));
+            let resp = next.run(req);
+            self.log
+                .lock()
+                .unwrap()
+                .push(format!("{} after", self.name));
+            resp
+        }
+    }
+
+    /// 不调用 next，直接返回 401
+    struct Deny;
+
+    impl Middleware for Deny {
+        fn call(&self, _req: &mut Request, _next: Next<'_>) -> Response {
+            Response::new(401)
+        }
+    }
+
+    fn traced(log: &Arc<Mutex<Vec<String>>>) -> impl Handler {
+        let log = Arc::clone(log);
+        move |_req: &mut Request| {
+            log.lock().unwrap().push(String::from("handler"));
+            Response::text(200, "ok")
+        }
+    }
+
+    fn trace(name: &'static str, log: &Arc<Mutex<Vec<String>>>) -> Trace {
+        Trace {
+            name,
+            log: Arc::clone(log),
+        }
+    }
+
+    #[test]
+    fn first_added_is_outermost() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let pipeline = Pipeline::new(traced(&log))
+            .with(trace("a", &log))
+            .with(trace("b", &log));
+        let resp = pipeline.handle(&mut Request::new("GET", "/"));
+        assert_eq!(resp.status, 200);
+        assert_eq!(
+            *log.lock().unwrap(),
+            ["a before", "b before", "handler", "b after", "a after"]
+        );
+    }
+
+    #[test]
+    fn short_circuit_skips_inner_layers() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let pipeline = Pipeline::new(traced(&log))
+            .with(trace("outer", &log))
+            .with(Deny)
+            .with(trace("inner", &log));
+        let resp = pipeline.handle(&mut Request::new("GET", "/"));
+        assert_eq!(resp.status, 401);
+        assert_eq!(*log.lock().unwrap(), ["outer before", "outer after"]);
+    }
+
+    #[test]
+    fn request_id_is_generated_or_propagated() {
+        let pipeline = Pipeline::new(|req: &mut Request| {
+            // handler 能在请求中看到生成的 id
+            Response::text(200, req.header(RequestId::HEADER).unwrap_or("").to_string())
+        })
+        .with(RequestId::new());
+
+        let resp = pipeline.handle(&mut Request::new("GET", "/"));
+        let id = resp.header(RequestId::HEADER).unwrap().to_string();
+        assert_eq!(resp.body, id.as_bytes());
+        let resp2 = pipeline.handle(&mut Request::new("GET", "/"));
+        assert_ne!(resp2.header(RequestId::HEADER).unwrap(), id);
+
+        let mut req = Request::new("GET", "/");
+        req.headers.set("x-request-id", "abc");
+        assert_eq!(
+            pipeline.handle(&mut req).header(RequestId::HEADER),
+            Some("abc")
+        );
+    }
+
+    #[test]
+    fn cors_preflight_and_simple_requests() {
+        let pipeline = Pipeline::new(|_req: &mut Request| Response::text(200, "ok"))
+            .with(Cors::with_origins(&["https://a.example"]));
+
+        let mut preflight = Request::new("OPTIONS", "/");
+        preflight.headers.set("Origin", "https://a.example");
+        preflight
+            .headers
+            .set("Access-Control-Request-Method", "PUT");
+        let resp = pipeline.handle(&mut preflight);
+        assert_eq!(resp.status, 204);
+        assert_eq!(
+            resp.header("Access-Control-Allow-Origin"),
+            Some("https://a.example")
+        );
+        assert!(
+            resp.header("Access-Control-Allow-Methods")
+                .unwrap()
+                .contains("PUT")
+        );
+
+        let mut other = Request::new("GET", "/");
+        other.headers.set("Origin", "https://evil.example");
+        let resp = pipeline.handle(&mut other);
+        assert_eq!(resp.status, 200);
+        assert_eq!(resp.header("Access-Control-Allow-Origin"), None);
+        assert_eq!(resp.header("Vary"), Some("Origin"));
+        assert_eq!(
+            pipeline
+                .handle(&mut Request::new("GET", "/"))
+                .header("Vary"),
+            Some("Origin")
+        );
+
+        let any = Pipeline::new(|_req: &mut Request| Response::text(200, "ok")).with(Cors::any());
+        let resp = any.handle(&mut other);
+        assert_eq!(resp.header("Access-Control-Allow-Origin"), Some("*"));
+        assert_eq!(resp.header("Vary"), None);
+    }
+}
@@ -0,0 +1,451 @@
+/*
+反向代理：
+    按路径前缀把请求转发给一组上游服务器，没有匹配的请求仍然由原来的页面处理：
+
+        [proxy]
+        routes = ["/api=http://127.0.0.1:9001,http://127.0.0.1:9002", "/img=http://127.0.0.1:9003"]
+        balance = "round_robin"      # round_robin / least_connections
+        health_check = "/health"     # 主动健康检查的路径，不设置则只做被动检查
+        health_interval = "5s"
+        retries = 1                  # 转发失败时换一个上游重试的次数
+        timeout = "30s"              # 等待上游响应的总时长
+
+    转发发生在线程池的工作线程上，使用阻塞的 Client，和上游之间的连接由 Client 的连接池复用。
+    转发失败（连接不上、超时）的上游被标记为不健康，之后优先选择健康的上游；
+    请求成功或者健康检查通过后重新标记为健康。所有上游都不健康时仍然会尝试，总比直接返回 502 好。
+    只有幂等的请求会在失败后重试，非幂等的请求只有在连接被拒绝（请求肯定没有发出去）时才重试。
+    转发时去掉逐跳（hop-by-hop）头部，并追加 X-Forwarded-For / X-Forwarded-Host / X-Forwarded-Proto。
+*/
+
+use crate::{
+    client::{Client, ClientError, Url},
+    http::{Headers, Request, Response, is_idempotent},
+    info,
+    middleware::Handler,
+    router::Router,
+    warn,
+};
+use std::{
+    io,
+    str::FromStr,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+    },
+    thread,
+    time::Duration,
+};
+
+/// 只在一跳连接上有意义、不能转发的头部
+const HOP_BY_HOP: [&str; 9] = [
+    "Connection",
+    "Keep-Alive",
+    "Proxy-Connection",
+    "Proxy-Authenticate",
+    "Proxy-Authorization",
+    "TE",
+    "Trailer",
+    "Transfer-Encoding",
+    "Upgrade",
+];
+
+/// 负载均衡策略
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Balance {
+    RoundRobin,
+    /// 选择正在处理的请求最少的上游
+    LeastConnections,
+}
+
+impl FromStr for Balance {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Balance, String> {
+        match s {
+            "round_robin" => Ok(Balance::RoundRobin),
+            "least_connections" => Ok(Balance::LeastConnections),
+            _ => Err(format!("unknown balance strategy `{}`", s)),
+        }
+    }
+}
+
+/// 一条代理规则，格式为 `前缀=上游地址,上游地址`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProxyRoute {
+    pub prefix: String,
+    pub upstreams: Vec<String>,
+}
+
+impl FromStr for ProxyRoute {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<ProxyRoute, String> {
+        let (prefix, upstreams) = s
+            .split_once('=')
+            .ok_or_else(|| format!("expected `PREFIX=URL[,URL...]`, got `{}`", s))?;
+        let prefix = prefix.trim();
+        if !prefix.starts_with('/') {
+            return Err(format!("proxy prefix `{}` must start with `/`", prefix));
+        }
+        let upstreams = upstreams
+            .split(',')
+            .map(|url| {
+                let url = url.trim().trim_end_matches('/');
+                match Url::parse(url) {
+                    Ok(parsed) if parsed.target == "/" => Ok(url.to_string()),
+                    Ok(_) => Err(format!("upstream `{}` must not contain a path", url)),
+                    Err(err) => Err(err.to_string()),
+                }
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(ProxyRoute {
+            prefix: prefix.to_string(),
+            upstreams,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProxyConfig {
+    pub routes: Vec<ProxyRoute>,
+    pub balance: Balance,
+    /// 主动健康检查请求的路径
+    pub health_check: Option<String>,
+    pub health_interval: Duration,
+    pub retries: usize,
+    pub timeout: Option<Duration>,
+}
+
+impl Default for ProxyConfig {
+    fn default() -> ProxyConfig {
+        ProxyConfig {
+            routes: Vec::new(),
+            balance: Balance::RoundRobin,
+            health_check: None,
+            health_interval: Duration::from_secs(5),
+            retries: 1,
+            timeout: Some(Duration::from_secs(30)),
+        }
+    }
+}
+
+pub struct Upstream {
+    base: String,
+    healthy: AtomicBool,
+    /// 正在转发的请求数
+    active: AtomicUsize,
+}
+
+impl Upstream {
+    pub fn base(&self) -> &str {
+        &self.base
+    }
+
+    pub fn is_healthy(&self) -> bool {
+        self.healthy.load(Ordering::Relaxed)
+    }
+
+    pub fn active(&self) -> usize {
+        self.active.load(Ordering::Relaxed)
+    }
+
+    fn set_healthy(&self, healthy: bool) {
+        if self.healthy.swap(healthy, Ordering::Relaxed) != healthy {
+            let state = if healthy { "up" } else { "down" };
+            info!("upstream {} is {}", self.base, state);
+        }
+    }
+}
+
+/// 请求结束时把上游的活动计数减回去
+struct ActiveGuard<'a>(&'a AtomicUsize);
+
+impl Drop for ActiveGuard<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+pub struct UpstreamPool {
+    upstreams: Vec<Upstream>,
+    balance: Balance,
+    next: AtomicUsize,
+}
+
+impl UpstreamPool {
+    pub fn new(bases: &[String], balance: Balance) -> UpstreamPool {
+        UpstreamPool {
+            upstreams: bases
+                .iter()
+                .map(|base| Upstream {
+                    base: base.clone(),
+                    healthy: AtomicBool::new(true),
+                    active: AtomicUsize::new(0),
+                })
+                .collect(),
+            balance,
+            next: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn upstreams(&self) -> &[Upstream] {
+        &self.upstreams
+    }
+
+    /// 选出一个上游的下标，跳过 tried 中已经试过的；有健康的上游时只在健康的上游中选
+    pub fn pick(&self, tried: &[usize]) -> Option<usize> {
+        let n = self.upstreams.len();
+        let untried = |i: &usize| !tried.contains(i);
+        let any_healthy = (0..n)
+            .filter(untried)
+            .any(|i| self.upstreams[i].is_healthy());
+        // 每次从不同的位置开始找，轮询就是取第一个，最少连接在并列时也能轮流分配
+        let start = self.next.fetch_add(1, Ordering::Relaxed);
+        (0..n)
+            .map(|i| (start + i) % n)
+            .filter(untried)
+            .filter(|&i| !any_healthy || self.upstreams[i].is_healthy())
+            .min_by_key(|&i| match self.balance {
+                Balance::RoundRobin => 0,
+                Balance::LeastConnections => self.upstreams[i].active(),
+            })
+    }
+
+    /// 对每个上游发送一次健康检查请求，2xx 和 3xx 视为健康
+    pub fn check(&self, client: &Client, path: &str) {
+        for upstream in &self.upstreams {
+            let url = format!("{}{}", upstream.base, path);
+            let healthy = client.get(&url).send().is_ok_and(|resp| resp.status < 400);
+            upstream.set_healthy(healthy);
+        }
+    }
+}
+
+pub struct Proxy {
+    pool: Arc<UpstreamPool>,
+    client: Client,
+    retries: usize,
+}
+
+impl Proxy {
+    pub fn new(pool: Arc<UpstreamPool>, retries: usize, timeout: Option<Duration>) -> Proxy {
+        Proxy {
+            pool,
+            client: Client::new().timeout(timeout),
+            retries,
+        }
+    }
+
+    /// 按配置创建代理，配置了健康检查时启动一个后台线程定期检查
+    pub fn from_config(route: &ProxyRoute, config: &ProxyConfig) -> Proxy {
+        let pool = Arc::new(UpstreamPool::new(&route.upstreams, config.balance));
+        if let Some(path) = &config.health_check {
+            spawn_health_checks(&pool, path.clone(), config.health_interval);
+        }
+        Proxy::new(pool, config.retries, config.timeout)
+    }
+
+    pub fn pool(&self) -> &Arc<UpstreamPool> {
+        &self.pool
+    }
+}
+
+impl Handler for Proxy {
+    fn handle(&self, req: &mut Request) -> Response {
+        let headers = forward_headers(req);
+        let idempotent = is_idempotent(&req.method);
+        let mut tried = Vec::new();
+        let mut last_err = None;
+        while tried.len() <= self.retries {
+            let Some(idx) = self.pool.pick(&tried) else {
+                break;
+            };
+            tried.push(idx);
+            let upstream = &self.pool.upstreams[idx];
+            upstream.active.fetch_add(1, Ordering::Relaxed);
+            let _guard = ActiveGuard(&upstream.active);
+
+            let url = format!("{}{}", upstream.base, req.target);
+            let result = self
+                .client
+                .request(&req.method, &url)
+                .headers(headers.clone())
+                .body(req.body.clone())
+                .send();
+            match result {
+                Ok(mut resp) => {
+                    upstream.set_healthy(true);
+                    strip_hop_by_hop(&mut resp.headers);
+                    return resp;
+                }
+                Err(err) => {
+                    warn!("proxy {} {}: {}", req.method, url, err);
+                    upstream.set_healthy(false);
+                    let refused = matches!(
+                        &err,
+                        ClientError::Io(err) if err.kind() == io::ErrorKind::ConnectionRefused
+                    );
+                    last_err = Some(err);
+                    if !idempotent && !refused {
+                        break;
+                    }
+                }
+            }
+        }
+        match last_err {
+            Some(ClientError::Timeout) => Response::error(504),
+            _ => Response::error(502),
+        }
+    }
+}
+
+/// 把配置中的每个前缀挂载到对应的上游，其余请求交给 fallback
+pub fn router<H: Handler>(config: &ProxyConfig, fallback: H) -> Router {
+    config
+        .routes
+        .iter()
+        .fold(Router::new(), |router, route| {
+            router.mount(&route.prefix, Proxy::from_config(route, config))
+        })
+        .fallback(fallback)
+}
+
+/// 后台线程只持有 Weak，代理被丢弃之后线程自动退出
+fn spawn_health_checks(pool: &Arc<UpstreamPool>, path: String, interval: Duration) {
+    let pool = Arc::downgrade(pool);
+    let client = Client::new()
+        .timeout(Some(interval.min(Duration::from_secs(2))))
+        .max_idle_per_host(0);
+    thread::spawn(move || {
+        while let Some(pool) = pool.upgrade() {
+            pool.check(&client, &path);
+            drop(pool);
+            thread::sleep(interval);
+        }
+    });
+}
+
+/// Connection 头里列出的头部也是逐跳的
+fn hop_by_hop(headers: &Headers) -> Vec<String> {
+    let listed = headers
+        .get_all("Connection")
+        .flat_map(|v| v.split(','))
+        .map(|t| t.trim().to_string())
+        .filter(|t| !t.is_empty());
+    HOP_BY_HOP
+        .iter()
+        .map(|h| h.to_string())
+        .chain(listed)
+        .collect()
+}
+
+fn strip_hop_by_hop(headers: &mut Headers) {
+    for name in hop_by_hop(headers) {
+        headers.remove(&name);
+    }
+}
+
+/// 转发给上游的请求头：去掉逐跳头部和 Host（由 Client 按上游地址设置），追加 X-Forwarded-*
+fn forward_headers(req: &Request) -> Headers {
+    let mut headers = req.headers.clone();
+    strip_hop_by_hop(&mut headers);
+    headers.remove("Host");
+    // 前面的代理可能各自追加了一个 X-Forwarded-For，按顺序合并成一个，链路上的地址一个都不能丢
+    if let Some(addr) = req.remote_addr {
+        let mut chain: Vec<String> = req
+            .headers
+            .get_all("X-Forwarded-For")
+            .map(String::from)
+            .collect();
+        chain.push(addr.ip().to_string());
+        headers.set("X-Forwarded-For", chain.join(", "));
+    }
+    if let Some(host) = req.header("Host")
+        && !headers.contains("X-Forwarded-Host")
+    {
+        headers.set("X-Forwarded-Host", host);
+    }
+    if !headers.contains("X-Forwarded-Proto") {
+        headers.set("X-Forwarded-Proto", "http");
+    }
+    headers
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pool(balance: Balance) -> UpstreamPool {
+        let bases = ["http://a:1", "http://b:1", "http://c:1"].map(String::from);
+        UpstreamPool::new(&bases, balance)
+    }
+
+    #[test]
+    fn parses_routes() {
+        let route: ProxyRoute = "/api=http://127.0.0.1:9001/, http://[::1]:9002"
+            .parse()
+            .unwrap();
+        assert_eq!(route.prefix, "/api");
+        assert_eq!(
+            route.upstreams,
+            ["http://127.0.0.1:9001", "http://[::1]:9002"]
+        );
+        assert!("api=http://a:1".parse::<ProxyRoute>().is_err());
+        assert!("/api".parse::<ProxyRoute>().is_err());
+        assert!("/api=https://a:1".parse::<ProxyRoute>().is_err());
+        assert!("/api=http://a:1/v1".parse::<ProxyRoute>().is_err());
+    }
+
+    #[test]
+    fn round_robin_skips_unhealthy_and_tried() {
+        let pool = pool(Balance::RoundRobin);
+        let picks: Vec<_> = (0..4).map(|_| pool.pick(&[]).unwrap()).collect();
+        assert_eq!(picks, [0, 1, 2, 0]);
+
+        pool.upstreams[1].healthy.store(false, Ordering::Relaxed);
+        assert!((0..6).all(|_| pool.pick(&[]) != Some(1)));
+        assert_eq!(pool.pick(&[0, 2]), Some(1));
+        assert_eq!(pool.pick(&[0, 1, 2]), None);
+
+        // 全都不健康时仍然轮流尝试
+        for upstream in &pool.upstreams {
+            upstream.healthy.store(false, Ordering::Relaxed);
+        }
+        assert!(pool.pick(&[]).is_some());
+    }
+
+    #[test]
+    fn least_connections_prefers_idle_upstreams() {
+        let pool = pool(Balance::LeastConnections);
+        pool.upstreams[0].active.store(3, Ordering::Relaxed);
+        pool.upstreams[1].active.store(1, Ordering::Relaxed);
+        pool.upstreams[2].active.store(2, Ordering::Relaxed);
+        assert_eq!(pool.pick(&[]), Some(1));
+        assert_eq!(pool.pick(&[1]), Some(2));
+    }
+
+    #[test]
+    fn forwarded_headers() {
+        let mut req = Request::new("GET", "/api/items");
+        req.remote_addr = Some("10.0.0.7:5555".parse().unwrap());
+        req.headers.append("Host", "example.com");
+        req.headers.append("Connection", "keep-alive, X-Secret");
+        req.headers.append("X-Secret", "hop");
+        req.headers.append("X-Forwarded-For", "192.168.1.1");
+        req.headers.append("Accept", "*/*");
+        req.headers
+            .append("X-Forwarded-For", "172.16.0.1, 172.16.0.2");
+
+        let headers = forward_headers(&req);
+        assert_eq!(
+            headers.get_all("X-Forwarded-For").collect::<Vec<_>>(),
+            ["192.168.1.1, 172.16.0.1, 172.16.0.2, 10.0.0.7"]
+        );
+        assert_eq!(headers.get("X-Forwarded-Host"), Some("example.com"));
+        assert_eq!(headers.get("X-Forwarded-Proto"), Some("http"));
+        assert_eq!(headers.get("Accept"), Some("*/*"));
+        for name in ["Host", "Connection", "X-Secret"] {
+            assert!(!headers.contains(name), "{}", name);
+        }
+    }
+}
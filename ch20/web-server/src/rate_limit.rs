@@ -0,0 +1,404 @@
+/*
+限流：
+    RateLimit 是一个令牌桶中间件。每个客户端（按 IP，或者按某个请求头的值）对每条规则各有一个桶，
+    桶里最多有 burst 个令牌，按 requests / period 的速度补充，每个请求消耗一个令牌，
+    没有令牌时直接返回 429，Retry-After 告诉客户端多少秒之后会有新的令牌。
+
+        server.with(
+            RateLimit::new(Limit::per_second(10).burst(20))
+                .route("/api/login", Limit::per_minute(5))
+                .key_by(KeyBy::Header(String::from("X-Api-Key"))),
+        )
+
+    路由规则按路径前缀匹配，最长的前缀优先，没有匹配的请求使用默认规则。
+    桶的数量有上限，超过时淘汰最久没有访问的桶：空闲足够久的桶已经补满了令牌，淘汰它和保留它没有区别。
+    时间通过 Clock 获取，测试中可以换成手动拨动的 ManualClock。
+*/
+
+use crate::{
+    http::{Request, Response},
+    middleware::{Middleware, Next},
+};
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+pub trait Clock: Send + Sync + 'static {
+    fn now(&self) -> Instant;
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// 只有调用 advance 才会前进的时钟，克隆出来的时钟共享同一个时间
+#[derive(Clone)]
+pub struct ManualClock {
+    start: Instant,
+    offset: Arc<Mutex<Duration>>,
+}
+
+impl ManualClock {
+    pub fn new() -> ManualClock {
+        ManualClock {
+            start: Instant::now(),
+            offset: Arc::default(),
+        }
+    }
+
+    pub fn advance(&self, by: Duration) {
+        *self.offset.lock().unwrap() += by;
+    }
+}
+
+impl Default for ManualClock {
+    fn default() -> ManualClock {
+        ManualClock::new()
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Instant {
+        self.start + *self.offset.lock().unwrap()
+    }
+}
+
+/// 每 period 补充 requests 个令牌，桶里最多存 burst 个
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Limit {
+    requests: u32,
+    period: Duration,
+    burst: u32,
+}
+
+impl Limit {
+    /// # Panics
+    ///
+    /// requests 或 period 为 0 时会 panic
+    pub fn new(requests: u32, period: Duration) -> Limit {
+        assert!(requests > 0 && !period.is_zero());
+        Limit {
+            requests,
+            period,
+            burst: requests,
+        }
+    }
+
+    pub fn per_second(requests: u32) -> Limit {
+        Limit::new(requests, Duration::from_secs(1))
+    }
+
+    pub fn per_minute(requests: u32) -> Limit {
+        Limit::new(requests, Duration::from_secs(60))
+    }
+
+    /// 桶的容量，也就是短时间内最多允许的突发请求数，默认等于 requests
+    pub fn burst(mut self, burst: u32) -> Limit {
+        self.burst = burst.max(1);
+        self
+    }
+
+    /// 每秒补充的令牌数
+    fn rate(&self) -> f64 {
+        f64::from(self.requests) / self.period.as_secs_f64()
+    }
+}
+
+/// 用什么区分客户端
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KeyBy {
+    Ip,
+    /// 请求头的值，例如 API key；没有这个头的请求按 IP 区分
+    Header(String),
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+    /// 最近一次访问的序号，也是它在 State::lru 中的键
+    used: u64,
+}
+
+impl Bucket {
+    /// 先按经过的时间补充令牌，再尝试取一个；失败时返回需要等待的时间
+    fn take(&mut self, limit: &Limit, now: Instant) -> Result<u32, Duration> {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * limit.rate()).min(f64::from(limit.burst));
+        self.updated = now;
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            Ok(self.tokens as u32)
+        } else {
+            Err(Duration::from_secs_f64((1.0 - self.tokens) / limit.rate()))
+        }
+    }
+}
+
+/// (规则序号, 客户端)
+type BucketKey = (usize, String);
+
+#[derive(Default)]
+struct State {
+    buckets: HashMap<BucketKey, Bucket>,
+    /// 访问序号 -> 桶，第一个就是最久没有访问的桶
+    lru: BTreeMap<u64, BucketKey>,
+    next_use: u64,
+}
+
+pub struct RateLimit {
+    /// 第 0 条是默认规则，None 表示不匹配路由的请求不限流
+    default: Option<Limit>,
+    /// 路由规则，按前缀从长到短排列
+    routes: Vec<(String, Limit)>,
+    key_by: KeyBy,
+    max_keys: usize,
+    clock: Box<dyn Clock>,
+    state: Mutex<State>,
+}
+
+impl RateLimit {
+    /// 所有请求默认使用 limit
+    pub fn new(limit: Limit) -> RateLimit {
+        RateLimit {
+            default: Some(limit),
+            ..RateLimit::routes_only()
+        }
+    }
+
+    /// 只限制用 route 添加的路径，其余请求不限流
+    pub fn routes_only() -> RateLimit {
+        RateLimit {
+            default: None,
+            routes: Vec::new(),
+            key_by: KeyBy::Ip,
+            max_keys: 10_000,
+            clock: Box::new(SystemClock),
+            state: Mutex::default(),
+        }
+    }
+
+    /// prefix 下的请求使用单独的限制和单独的桶
+    pub fn route(mut self, prefix: &str, limit: Limit) -> RateLimit {
+        let prefix = prefix.trim_end_matches('/').to_string();
+        self.routes.push((prefix, limit));
+        self.routes
+            .sort_by_key(|(prefix, _)| std::cmp::Reverse(prefix.len()));
+        self
+    }
+
+    pub fn key_by(mut self, key_by: KeyBy) -> RateLimit {
+        self.key_by = key_by;
+        self
+    }
+
+    /// 最多保留多少个桶，默认 10000
+    pub fn max_keys(mut self, max_keys: usize) -> RateLimit {
+        self.max_keys = max_keys.max(1);
+        self
+    }
+
+    pub fn clock<C: Clock>(mut self, clock: C) -> RateLimit {
+        self.clock = Box::new(clock);
+        self
+    }
+
+    /// 当前保留的桶数
+    pub fn tracked_keys(&self) -> usize {
+        self.state.lock().unwrap().buckets.len()
+    }
+
+    /// 找到请求适用的规则，返回规则序号和限制
+    fn rule(&self, path: &str) -> Option<(usize, Limit)> {
+        let matched = self.routes.iter().position(|(prefix, _)| {
+            path.strip_prefix(prefix.as_str())
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+        });
+        match matched {
+            Some(idx) => Some((idx + 1, self.routes[idx].1)),
+            None => self.default.map(|limit| (0, limit)),
+        }
+    }
+
+    fn client(&self, req: &Request) -> String {
+        if let KeyBy::Header(name) = &self.key_by
+            && let Some(value) = req.header(name)
+        {
+            return format!("{}: {}", name, value);
+        }
+        req.remote_addr
+            .map_or(String::from("unknown"), |addr| addr.ip().to_string())
+    }
+
+    fn take(&self, key: BucketKey, limit: &Limit) -> Result<u32, Duration> {
+        let now = self.clock.now();
+        let mut state = self.state.lock().unwrap();
+        let state = &mut *state;
+        let used = state.next_use;
+        state.next_use += 1;
+        let bucket = state.buckets.entry(key.clone()).or_insert(Bucket {
+            tokens: f64::from(limit.burst),
+            updated: now,
+            used,
+        });
+        state.lru.remove(&bucket.used);
+        bucket.used = used;
+        state.lru.insert(used, key);
+        let taken = bucket.take(limit, now);
+        while state.buckets.len() > self.max_keys {
+            let (_, oldest) = state.lru.pop_first().unwrap();
+            state.buckets.remove(&oldest);
+        }
+        taken
+    }
+}
+
+impl Middleware for RateLimit {
+    fn call(&self, req: &mut Request, next: Next<'_>) -> Response {
+        let Some((rule, limit)) = self.rule(&req.path) else {
+            return next.run(req);
+        };
+        match self.take((rule, self.client(req)), &limit) {
+            Ok(remaining) => {
+                let mut resp = next.run(req);
+                resp.headers
+                    .set("X-RateLimit-Limit", limit.burst.to_string());
+                resp.headers
+                    .set("X-RateLimit-Remaining", remaining.to_string());
+                resp
+            }
+            Err(wait) => {
+                // Retry-After 只能是整秒，向上取整，保证客户端按它重试时一定有令牌
+                let secs = wait.as_secs() + u64::from(wait.subsec_nanos() > 0);
+                Response::error(429)
+                    .with_header("Retry-After", secs.max(1).to_string())
+                    .with_header("X-RateLimit-Limit", limit.burst.to_string())
+                    .with_header("X-RateLimit-Remaining", "0")
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Handler, Pipeline};
+
+    fn pipeline(limit: RateLimit) -> Pipeline {
+        Pipeline::new(|_req: &mut Request| Response::new(200)).with(limit)
+    }
+
+    fn request(path: &str, ip: &str) -> Request {
+        let mut req = Request::new("GET", path);
+        req.remote_addr = Some(format!("{}:5000", ip).parse().unwrap());
+        req
+    }
+
+    fn status(p: &Pipeline, path: &str, ip: &str) -> u16 {
+        p.handle(&mut request(path, ip)).status
+    }
+
+    #[test]
+    fn allows_bursts_then_refills() {
+        let clock = ManualClock::new();
+        let p = pipeline(RateLimit::new(Limit::per_second(2).burst(3)).clock(clock.clone()));
+        let resp = p.handle(&mut request("/", "10.0.0.1"));
+        assert_eq!(resp.header("X-RateLimit-Remaining"), Some("2"));
+        assert_eq!(status(&p, "/", "10.0.0.1"), 200);
+        assert_eq!(status(&p, "/", "10.0.0.1"), 200);
+
+        let resp = p.handle(&mut request("/", "10.0.0.1"));
+        assert_eq!(resp.status, 429);
+        assert_eq!(resp.header("Retry-After"), Some("1"));
+        // 其他客户端不受影响
+        assert_eq!(status(&p, "/", "10.0.0.2"), 200);
+
+        // 每秒两个令牌，半秒补充一个
+        clock.advance(Duration::from_millis(500));
+        assert_eq!(status(&p, "/", "10.0.0.1"), 200);
+        assert_eq!(status(&p, "/", "10.0.0.1"), 429);
+        // 空闲再久也只补满到 burst
+        clock.advance(Duration::from_secs(60));
+        for _ in 0..3 {
+            assert_eq!(status(&p, "/", "10.0.0.1"), 200);
+        }
+        assert_eq!(status(&p, "/", "10.0.0.1"), 429);
+    }
+
+    #[test]
+    fn retry_after_rounds_up() {
+        let clock = ManualClock::new();
+        let p = pipeline(RateLimit::new(Limit::per_minute(2)).clock(clock.clone()));
+        assert_eq!(status(&p, "/", "10.0.0.1"), 200);
+        assert_eq!(status(&p, "/", "10.0.0.1"), 200);
+        clock.advance(Duration::from_millis(100));
+        let resp = p.handle(&mut request("/", "10.0.0.1"));
+        assert_eq!(resp.header("Retry-After"), Some("30"));
+    }
+
+    #[test]
+    fn routes_have_their_own_limits_and_buckets() {
+        let clock = ManualClock::new();
+        let limit = RateLimit::routes_only()
+            .route("/api", Limit::per_minute(2))
+            .route("/api/login/", Limit::per_minute(1))
+            .clock(clock);
+        let p = pipeline(limit);
+        assert_eq!(status(&p, "/api/login", "10.0.0.1"), 200);
+        assert_eq!(status(&p, "/api/login", "10.0.0.1"), 429);
+        // /api 的桶是独立的
+        assert_eq!(status(&p, "/api/items", "10.0.0.1"), 200);
+        assert_eq!(status(&p, "/api", "10.0.0.1"), 200);
+        assert_eq!(status(&p, "/api/items", "10.0.0.1"), 429);
+        // 前缀按片段匹配，没有默认规则时不限流
+        for _ in 0..5 {
+            assert_eq!(status(&p, "/apix", "10.0.0.1"), 200);
+        }
+    }
+
+    #[test]
+    fn keys_by_header_with_ip_fallback() {
+        let clock = ManualClock::new();
+        let p = pipeline(
+            RateLimit::new(Limit::per_minute(1))
+                .key_by(KeyBy::Header(String::from("X-Api-Key")))
+                .clock(clock),
+        );
+        let with_key = |key: &str, ip: &str| {
+            let mut req = request("/", ip);
+            req.headers.set("X-Api-Key", key);
+            p.handle(&mut req).status
+        };
+        assert_eq!(with_key("alice", "10.0.0.1"), 200);
+        // 同一个 key 换了 IP 仍然是同一个桶
+        assert_eq!(with_key("alice", "10.0.0.2"), 429);
+        assert_eq!(with_key("bob", "10.0.0.1"), 200);
+        assert_eq!(status(&p, "/", "10.0.0.1"), 200);
+        assert_eq!(status(&p, "/", "10.0.0.1"), 429);
+    }
+
+    #[test]
+    fn evicts_least_recently_used_keys() {
+        let limit = Arc::new(
+            RateLimit::new(Limit::per_minute(1))
+                .max_keys(2)
+                .clock(ManualClock::new()),
+        );
+        let p = Pipeline::new(|_req: &mut Request| Response::new(200)).with(Arc::clone(&limit));
+        assert_eq!(status(&p, "/", "10.0.0.1"), 200);
+        assert_eq!(status(&p, "/", "10.0.0.2"), 200);
+        // 再次访问 .1，.2 变成最久没有访问的
+        assert_eq!(status(&p, "/", "10.0.0.1"), 429);
+        assert_eq!(status(&p, "/", "10.0.0.3"), 200);
+        assert_eq!(limit.tracked_keys(), 2);
+        // .2 已经被淘汰，重新得到一个满的桶
+        assert_eq!(status(&p, "/", "10.0.0.2"), 200);
+        assert_eq!(status(&p, "/", "10.0.0.3"), 429);
+        assert_eq!(limit.tracked_keys(), 2);
+    }
+}
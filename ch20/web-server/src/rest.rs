@@ -0,0 +1,274 @@
+/*
+REST 示例：
+    一个保存在内存里的 CRUD 资源，演示 Json 提取、查询参数和统一的错误格式。
+    resource 在 Router 上为 prefix 注册五个路由，任何实现了 FromJson + ToJson 的类型都可以用：
+
+        GET    /api/todos?offset=0&limit=20   列表：{"items":[...],"total":N}
+        POST   /api/todos                     创建，返回 201 和 Location
+        GET    /api/todos/:id
+        PUT    /api/todos/:id                 整体替换
+        DELETE /api/todos/:id                 返回 204
+
+    返回的每一项都是资源本身的 JSON 对象再加上 id 字段。
+    默认路由在 /api/todos 下挂了一个 Todo 资源。
+*/
+
+use crate::{
+    http::{Request, Response},
+    json::{self, FromJson, Json, JsonError, ToJson, Value},
+    router::Router,
+};
+use std::{
+    collections::BTreeMap,
+    sync::{
+        Arc, RwLock,
+        atomic::{AtomicU64, Ordering},
+    },
+};
+
+/// 列表默认和最多返回的条数
+const DEFAULT_LIMIT: usize = 100;
+const MAX_LIMIT: usize = 1000;
+
+/// 按 id 保存资源，id 从 1 开始递增，删除之后不会复用
+pub struct Store<T> {
+    items: RwLock<BTreeMap<u64, T>>,
+    next_id: AtomicU64,
+}
+
+impl<T: Clone> Store<T> {
+    pub fn new() -> Store<T> {
+        Store {
+            items: RwLock::new(BTreeMap::new()),
+            next_id: AtomicU64::new(1),
+        }
+    }
+
+    /// 按 id 顺序跳过 offset 条、最多取 limit 条，同时返回总数
+    pub fn list(&self, offset: usize, limit: usize) -> (Vec<(u64, T)>, usize) {
+        let items = self.items.read().unwrap();
+        let page = items
+            .iter()
+            .skip(offset)
+            .take(limit)
+            .map(|(id, item)| (*id, item.clone()))
+            .collect();
+        (page, items.len())
+    }
+
+    pub fn get(&self, id: u64) -> Option<T> {
+        self.items.read().unwrap().get(&id).cloned()
+    }
+
+    pub fn insert(&self, item: T) -> u64 {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        self.items.write().unwrap().insert(id, item);
+        id
+    }
+
+    /// 替换已有的资源，id 不存在时返回 false
+    pub fn replace(&self, id: u64, item: T) -> bool {
+        match self.items.write().unwrap().get_mut(&id) {
+            Some(slot) => {
+                *slot = item;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn remove(&self, id: u64) -> bool {
+        self.items.write().unwrap().remove(&id).is_some()
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.read().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<T: Clone> Default for Store<T> {
+    fn default() -> Store<T> {
+        Store::new()
+    }
+}
+
+/// 在 router 上注册 prefix 下的 CRUD 路由，数据保存在 store 中
+pub fn resource<T>(router: Router, prefix: &str, store: Arc<Store<T>>) -> Router
+where
+    T: FromJson + ToJson + Clone + Send + Sync + 'static,
+{
+    let prefix = prefix.trim_end_matches('/').to_string();
+    let item = format!("{}/:id", prefix);
+    let (list, create, read, update, delete) = (
+        Arc::clone(&store),
+        Arc::clone(&store),
+        Arc::clone(&store),
+        Arc::clone(&store),
+        store,
+    );
+    let location = prefix.clone();
+    router
+        .get(&prefix, move |req: &mut Request| list_items(&list, req))
+        .post(&prefix, move |req: &mut Request| {
+            let Json(value) = match Json::<T>::from_request(req) {
+                Ok(json) => json,
+                Err(resp) => return resp,
+            };
+            let id = create.insert(value.clone());
+            json::response(201, &with_id(id, &value))
+                .with_header("Location", format!("{}/{}", location, id))
+        })
+        .get(&item, move |req: &mut Request| {
+            match id_param(req).and_then(|id| read.get(id).map(|value| (id, value))) {
+                Some((id, value)) => json::response(200, &with_id(id, &value)),
+                None => not_found(),
+            }
+        })
+        .put(&item, move |req: &mut Request| {
+            let Some(id) = id_param(req) else {
+                return not_found();
+            };
+            let Json(value) = match Json::<T>::from_request(req) {
+                Ok(json) => json,
+                Err(resp) => return resp,
+            };
+            if !update.replace(id, value.clone()) {
+                return not_found();
+            }
+            json::response(200, &with_id(id, &value))
+        })
+        .delete(&item, move |req: &mut Request| {
+            if id_param(req).is_some_and(|id| delete.remove(id)) {
+                Response::new(204)
+            } else {
+                not_found()
+            }
+        })
+}
+
+fn list_items<T: ToJson + Clone>(store: &Store<T>, req: &Request) -> Response {
+    let offset = match query_number(req, "offset", 0) {
+        Ok(n) => n,
+        Err(resp) => return resp,
+    };
+    let limit = match query_number(req, "limit", DEFAULT_LIMIT) {
+        Ok(n) => n.min(MAX_LIMIT),
+        Err(resp) => return resp,
+    };
+    let (page, total) = store.list(offset, limit);
+    let items = page.iter().map(|(id, value)| with_id(*id, value)).collect();
+    json::response(
+        200,
+        &Value::from_iter([
+            ("items", Value::Array(items)),
+            ("total", Value::from(total)),
+        ]),
+    )
+}
+
+fn query_number(req: &Request, name: &str, default: usize) -> Result<usize, Response> {
+    match req.query_param(name) {
+        None => Ok(default),
+        Some(value) => value
+            .parse()
+            .map_err(|_| json::error(400, &format!("`{}` must be a non-negative integer", name))),
+    }
+}
+
+/// 无法解析的 id 和不存在的 id 一样按 404 处理
+fn id_param(req: &Request) -> Option<u64> {
+    req.param("id")?.parse().ok()
+}
+
+fn not_found() -> Response {
+    json::error(404, "no such item")
+}
+
+/// 资源的 JSON 对象加上 id 字段
+fn with_id<T: ToJson>(id: u64, value: &T) -> Value {
+    let mut json = value.to_json();
+    if let Value::Object(map) = &mut json {
+        map.insert(String::from("id"), Value::from(id));
+    }
+    json
+}
+
+/// 示例资源：待办事项
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Todo {
+    pub title: String,
+    pub done: bool,
+}
+
+impl FromJson for Todo {
+    fn from_json(value: &Value) -> Result<Todo, JsonError> {
+        let title: String = value.field("title")?;
+        if title.trim().is_empty() {
+            return Err(JsonError::new("must not be empty").at("title"));
+        }
+        Ok(Todo {
+            title,
+            done: value.field::<Option<bool>>("done")?.unwrap_or(false),
+        })
+    }
+}
+
+impl ToJson for Todo {
+    fn to_json(&self) -> Value {
+        Value::from_iter([
+            ("title", Value::from(self.title.as_str())),
+            ("done", Value::from(self.done)),
+        ])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn store_assigns_increasing_ids() {
+        let store = Store::new();
+        assert_eq!(store.insert("a"), 1);
+        assert_eq!(store.insert("b"), 2);
+        assert!(store.remove(1));
+        assert!(!store.remove(1));
+        // 删除的 id 不会复用
+        assert_eq!(store.insert("c"), 3);
+        assert!(store.replace(3, "d"));
+        assert!(!store.replace(9, "x"));
+        assert_eq!(store.get(3), Some("d"));
+        assert_eq!(store.list(1, 10), (vec![(3, "d")], 2));
+    }
+
+    #[test]
+    fn todo_validation() {
+        let parse = |src: &str| Todo::from_json(&Value::parse(src).unwrap());
+        assert_eq!(
+            parse(r#"{"title": "write docs"}"#),
+            Ok(Todo {
+                title: String::from("write docs"),
+                done: false
+            })
+        );
+        assert_eq!(
+            parse(r#"{"title": "  "}"#).unwrap_err().to_string(),
+            "`title`: must not be empty"
+        );
+        assert_eq!(
+            parse(r#"{"title": "x", "done": "yes"}"#)
+                .unwrap_err()
+                .to_string(),
+            "`done`: expected a boolean"
+        );
+        let todo = parse(r#"{"title": "x", "done": true}"#).unwrap();
+        assert_eq!(
+            with_id(7, &todo).to_string(),
+            r#"{"done":true,"id":7,"title":"x"}"#
+        );
+    }
+}
@@ -0,0 +1,233 @@
+/*
+路由：
+    按照注册顺序匹配请求的方法和路径，第一个匹配的路由负责处理请求。
+    路径模式支持三种片段：
+        /items          字面量
+        /items/:id      参数，匹配一个片段，可以通过 req.param("id") 取出
+        *               通配，只能作为最后一个片段，匹配剩余的所有片段（包括空）
+    路径匹配但方法不匹配时返回 405，什么都没匹配上时交给 fallback，默认返回 404。
+    匹配到的路由模式记录在 req.route 中，指标按它而不是实际路径分组。
+*/
+
+use crate::{
+    http::{Request, Response},
+    middleware::Handler,
+};
+
+enum Segment {
+    Literal(String),
+    Param(String),
+    Rest,
+}
+
+struct Route {
+    /// None 表示匹配任意方法
+    method: Option<String>,
+    /// 规范化之后的模式字符串，匹配时写入 req.route
+    path: String,
+    pattern: Vec<Segment>,
+    handler: Box<dyn Handler>,
+}
+
+#[derive(Default)]
+pub struct Router {
+    routes: Vec<Route>,
+    fallback: Option<Box<dyn Handler>>,
+}
+
+impl Router {
+    pub fn new() -> Router {
+        Router::default()
+    }
+
+    pub fn route<H: Handler>(mut self, method: &str, pattern: &str, handler: H) -> Router {
+        let pattern = parse_pattern(pattern);
+        self.routes.push(Route {
+            method: Some(method.to_ascii_uppercase()),
+            path: pattern_string(&pattern),
+            pattern,
+            handler: Box::new(handler),
+        });
+        self
+    }
+
+    pub fn get<H: Handler>(self, pattern: &str, handler: H) -> Router {
+        self.route("GET", pattern, handler)
+    }
+
+    pub fn post<H: Handler>(self, pattern: &str, handler: H) -> Router {
+        self.route("POST", pattern, handler)
+    }
+
+    pub fn put<H: Handler>(self, pattern: &str, handler: H) -> Router {
+        self.route("PUT", pattern, handler)
+    }
+
+    pub fn delete<H: Handler>(self, pattern: &str, handler: H) -> Router {
+        self.route("DELETE", pattern, handler)
+    }
+
+    /// 把 prefix 下的所有请求（任意方法）交给 handler
+    pub fn mount<H: Handler>(mut self, prefix: &str, handler: H) -> Router {
+        let mut pattern = parse_pattern(prefix);
+        pattern.push(Segment::Rest);
+        self.routes.push(Route {
+            method: None,
+            path: pattern_string(&pattern),
+            pattern,
+            handler: Box::new(handler),
+        });
+        self
+    }
+
+    /// 没有路由匹配时使用的 handler
+    pub fn fallback<H: Handler>(mut self, handler: H) -> Router {
+        self.fallback = Some(Box::new(handler));
+        self
+    }
+}
+
+impl Handler for Router {
+    fn handle(&self, req: &mut Request) -> Response {
+        let mut allowed: Vec<&str> = Vec::new();
+        for route in &self.routes {
+            let params = match match_path(&route.pattern, &req.path) {
+                Some(params) => params,
+                None => continue,
+            };
+            // HEAD 可以由 GET 路由处理
+            if let Some(method) = &route.method
+                && *method != req.method
+                && !(req.method == "HEAD" && method == "GET")
+            {
+                allowed.push(method);
+                continue;
+            }
+            req.params = params;
+            req.route = Some(route.path.clone());
+            return route.handler.handle(req);
+        }
+        if !allowed.is_empty() {
+            return Response::error(405).with_header("Allow", allowed.join(", "));
+        }
+        match &self.fallback {
+            Some(fallback) => fallback.handle(req),
+            None => Response::error(404),
+        }
+    }
+}
+
+fn parse_pattern(pattern: &str) -> Vec<Segment> {
+    pattern
+        .split('/')
+        .filter(|s| !s.is_empty())
+        .map(|s| {
+            if s == "*" {
+                Segment::Rest
+            } else if let Some(name) = s.strip_prefix(':') {
+                Segment::Param(name.to_string())
+            } else {
+                Segment::Literal(s.to_string())
+            }
+        })
+        .collect()
+}
+
+fn pattern_string(pattern: &[Segment]) -> String {
+    let parts: Vec<String> = pattern
+        .iter()
+        .map(|segment| match segment {
+            Segment::Literal(lit) => lit.clone(),
+            Segment::Param(name) => format!(":{}", name),
+            Segment::Rest => String::from("*"),
+        })
+        .collect();
+    format!("/{}", parts.join("/"))
+}
+
+fn match_path(pattern: &[Segment], path: &str) -> Option<Vec<(String, String)>> {
+    let mut params = Vec::new();
+    let mut parts = path.split('/').filter(|s| !s.is_empty());
+    for segment in pattern {
+        match segment {
+            Segment::Rest => return Some(params),
+            Segment::Literal(lit) => {
+                if parts.next()? != lit {
+                    return None;
+                }
+            }
+            Segment::Param(name) => params.push((name.clone(), parts.next()?.to_string())),
+        }
+    }
+    match parts.next() {
+        Some(_) => None,
+        None => Some(params),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn body(resp: Response) -> String {
+        String::from_utf8(resp.body).unwrap()
+    }
+
+    fn router() -> Router {
+        Router::new()
+            .get("/", |_req: &mut Request| Response::text(200, "index"))
+            .get("/items/:id", |req: &mut Request| {
+                Response::text(200, format!("item {}", req.param("id").unwrap()))
+            })
+            .delete("/items/:id", |_req: &mut Request| Response::new(204))
+            .mount("/static", |req: &mut Request| {
+                Response::text(200, req.path.clone())
+            })
+    }
+
+    #[test]
+    fn matches_literals_params_and_mounts() {
+        let r = router();
+        assert_eq!(body(r.handle(&mut Request::new("GET", "/"))), "index");
+        assert_eq!(
+            body(r.handle(&mut Request::new("GET", "/items/42"))),
+            "item 42"
+        );
+        assert_eq!(
+            body(r.handle(&mut Request::new("POST", "/static/css/a.css"))),
+            "/static/css/a.css"
+        );
+        assert_eq!(r.handle(&mut Request::new("GET", "/static")).status, 200);
+        assert_eq!(
+            r.handle(&mut Request::new("GET", "/items/42/extra")).status,
+            404
+        );
+    }
+
+    #[test]
+    fn records_the_matched_pattern() {
+        let r = router();
+        let mut req = Request::new("GET", "/items/42/");
+        r.handle(&mut req);
+        assert_eq!(req.route.as_deref(), Some("/items/:id"));
+        let mut req = Request::new("GET", "/static/a.css");
+        r.handle(&mut req);
+        assert_eq!(req.route.as_deref(), Some("/static/*"));
+        let mut req = Request::new("GET", "/nope");
+        r.handle(&mut req);
+        assert_eq!(req.route, None);
+    }
+
+    #[test]
+    fn wrong_method_is_405_with_allow() {
+        let resp = router().handle(&mut Request::new("PUT", "/items/1"));
+        assert_eq!(resp.status, 405);
+        assert_eq!(resp.header("Allow"), Some("GET, DELETE"));
+    }
+
+    #[test]
+    fn fallback_handles_unmatched() {
+        let r = router().fallback(|_req: &mut Request| Response::text(404, "custom"));
+        assert_eq!(body(r.handle(&mut Request::new("GET", "/nope"))), "custom");
+    }
+}
@@ -0,0 +1,439 @@
+/*
+异步 handler 和一个最小的执行器（仅 Linux，依赖 epoll 事件循环）：
+    同步的 Handler 在等待 I/O 时会占住一个工作线程。AsyncHandler 返回一个 Future，
+    由事件循环线程上的 Executor 轮询：Future 返回 Pending 时把 Waker 登记到反应器（Reactor），
+    反应器和连接共用同一个 epoll，socket 就绪或者定时器到期时调用 Waker，任务重新进入就绪队列。
+
+    提供给 handler 使用的几个 Future：
+        sleep(d)              不占线程的等待
+        spawn_blocking(f)     把阻塞的代码放到线程池中执行，完成后唤醒任务
+        AsyncTcpStream        非阻塞的 TCP 读写（连接本身通过 spawn_blocking 建立）
+
+    这些 Future 必须在事件循环线程上轮询，也就是在 AsyncHandler 返回的 Future 里使用。
+    异步 handler 不经过同步的中间件链，访问日志由服务器直接记录。
+*/
+
+use crate::{
+    event_loop::{READ, WRITE, with_reactor},
+    http::{Request, Response},
+};
+use std::{
+    collections::VecDeque,
+    future::Future,
+    io::{self, Read, Write},
+    net::{SocketAddr, TcpStream},
+    os::fd::{AsRawFd, RawFd},
+    panic::{self, AssertUnwindSafe},
+    pin::Pin,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+    task::{Context, Poll, Wake, Waker},
+    thread,
+    time::{Duration, Instant},
+};
+
+pub type BoxFuture<T> = Pin<Box<dyn Future<Output = T> + Send + 'static>>;
+
+/// 异步版本的 Handler，请求按值传入，这样返回的 Future 不需要借用任何东西
+pub trait AsyncHandler: Send + Sync + 'static {
+    fn call(&self, req: Request) -> BoxFuture<Response>;
+}
+
+impl<F, Fut> AsyncHandler for F
+where
+    F: Fn(Request) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Response> + Send + 'static,
+{
+    fn call(&self, req: Request) -> BoxFuture<Response> {
+        Box::pin(self(req))
+    }
+}
+
+impl<H: AsyncHandler + ?Sized> AsyncHandler for Arc<H> {
+    fn call(&self, req: Request) -> BoxFuture<Response> {
+        (**self).call(req)
+    }
+}
+
+/// 就绪队列，notify 用来通知事件循环有任务需要轮询
+struct Queue {
+    ready: Mutex<VecDeque<Arc<Task>>>,
+    notify: Box<dyn Fn() + Send + Sync>,
+}
+
+struct Task {
+    future: Mutex<Option<BoxFuture<()>>>,
+    /// 已经在就绪队列中时为 true，避免重复入队
+    queued: AtomicBool,
+    queue: Arc<Queue>,
+}
+
+impl Wake for Task {
+    fn wake(self: Arc<Self>) {
+        if !self.queued.swap(true, Ordering::AcqRel) {
+            let queue = Arc::clone(&self.queue);
+            queue.ready.lock().unwrap().push_back(self);
+            (queue.notify)();
+        }
+    }
+}
+
+pub(crate) struct Executor {
+    queue: Arc<Queue>,
+}
+
+impl Executor {
+    pub(crate) fn new<N: Fn() + Send + Sync + 'static>(notify: N) -> Executor {
+        Executor {
+            queue: Arc::new(Queue {
+                ready: Mutex::new(VecDeque::new()),
+                notify: Box::new(notify),
+            }),
+        }
+    }
+
+    pub(crate) fn spawn<F: Future<Output = ()> + Send + 'static>(&self, future: F) {
+        let task = Arc::new(Task {
+            future: Mutex::new(Some(Box::pin(future))),
+            queued: AtomicBool::new(false),
+            queue: Arc::clone(&self.queue),
+        });
+        task.wake();
+    }
+
+    /// 把当前就绪的任务各轮询一次，返回轮询的任务数
+    ///
+    /// 轮询过程中被再次唤醒的任务留到下一轮，避免一个总是立即唤醒自己的任务卡住事件循环
+    pub(crate) fn run_ready(&self) -> usize {
+        let batch: Vec<Arc<Task>> = self.queue.ready.lock().unwrap().drain(..).collect();
+        for task in &batch {
+            task.queued.store(false, Ordering::Release);
+            let waker = Waker::from(Arc::clone(task));
+            let mut cx = Context::from_waker(&waker);
+            let mut slot = task.future.lock().unwrap();
+            if let Some(mut future) = slot.take()
+                && future.as_mut().poll(&mut cx).is_pending()
+            {
+                *slot = Some(future);
+            }
+        }
+        batch.len()
+    }
+}
+
+/// 捕获 Future 轮询时的 panic，异步 handler 在事件循环线程上运行，不能让它把整个服务器带走
+pub(crate) struct CatchUnwind<T>(pub(crate) BoxFuture<T>);
+
+impl<T> Future for CatchUnwind<T> {
+    type Output = thread::Result<T>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let future = &mut self.0;
+        match panic::catch_unwind(AssertUnwindSafe(|| future.as_mut().poll(cx))) {
+            Ok(Poll::Ready(value)) => Poll::Ready(Ok(value)),
+            Ok(Poll::Pending) => Poll::Pending,
+            Err(payload) => Poll::Ready(Err(payload)),
+        }
+    }
+}
+
+/// 等待一段时间，不占用任何线程
+pub fn sleep(duration: Duration) -> Sleep {
+    Sleep {
+        at: Instant::now() + duration,
+        timer: None,
+    }
+}
+
+pub struct Sleep {
+    at: Instant,
+    timer: Option<u64>,
+}
+
+impl Future for Sleep {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if Instant::now() >= self.at {
+            return Poll::Ready(());
+        }
+        let at = self.at;
+        let timer = self.timer;
+        self.timer = Some(with_reactor(|r| match timer {
+            Some(id) => {
+                r.update_timer(id, cx.waker());
+                id
+            }
+            None => r.add_timer(at, cx.waker().clone()),
+        }));
+        Poll::Pending
+    }
+}
+
+impl Drop for Sleep {
+    fn drop(&mut self) {
+        if let Some(id) = self.timer {
+            crate::event_loop::try_with_reactor(|r| r.cancel_timer(id));
+        }
+    }
+}
+
+/// 在线程池中执行阻塞的代码，返回的 Future 在执行完成后就绪；f 中的 panic 会在 await 处重新抛出
+///
+/// 线程池队列已满或者正在关闭时任务不会执行，Future 以错误结束，调用方可以回复 503
+pub fn spawn_blocking<F, T>(f: F) -> SpawnBlocking<F, T>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    SpawnBlocking {
+        job: Some(f),
+        slot: Arc::new(Mutex::new(Slot {
+            result: None,
+            waker: None,
+        })),
+    }
+}
+
+struct Slot<T> {
+    result: Option<thread::Result<T>>,
+    waker: Option<Waker>,
+}
+
+pub struct SpawnBlocking<F, T> {
+    job: Option<F>,
+    slot: Arc<Mutex<Slot<T>>>,
+}
+
+// 只通过 Option::take 移出 job，从不对它做 pin 投影
+impl<F, T> Unpin for SpawnBlocking<F, T> {}
+
+impl<F, T> Future for SpawnBlocking<F, T>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    type Output = io::Result<T>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<T>> {
+        // 第一次轮询时才提交任务，这样 spawn_blocking 可以在事件循环之外创建
+        if let Some(job) = self.job.take() {
+            let slot = Arc::clone(&self.slot);
+            slot.lock().unwrap().waker = Some(cx.waker().clone());
+            let pool = with_reactor(|r| Arc::clone(r.pool()));
+            // 和同步 handler 一样用 try_execute，Block 和 CallerRuns 都会卡住事件循环
+            let submitted = pool.try_execute(move || {
+                let result = panic::catch_unwind(AssertUnwindSafe(job));
+                let mut slot = slot.lock().unwrap();
+                slot.result = Some(result);
+                if let Some(waker) = slot.waker.take() {
+                    waker.wake();
+                }
+            });
+            // 任务没有机会执行，直接返回错误，否则这个 future 永远不会完成
+            if let Err(err) = submitted {
+                return Poll::Ready(Err(io::Error::other(err)));
+            }
+            return Poll::Pending;
+        }
+        let mut slot = self.slot.lock().unwrap();
+        match slot.result.take() {
+            Some(Ok(value)) => Poll::Ready(Ok(value)),
+            Some(Err(payload)) => panic::resume_unwind(payload),
+            None => {
+                slot.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+/// 等待文件描述符可读或可写，就绪一次之后自动从 epoll 中移除
+struct Readiness {
+    fd: RawFd,
+    interest: u32,
+    token: Option<u64>,
+}
+
+impl Future for Readiness {
+    type Output = io::Result<()>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let (fd, interest) = (self.fd, self.interest);
+        match self.token {
+            None => match with_reactor(|r| r.register_io(fd, interest, cx.waker().clone())) {
+                Ok(token) => {
+                    self.token = Some(token);
+                    Poll::Pending
+                }
+                Err(err) => Poll::Ready(Err(err)),
+            },
+            Some(token) => {
+                if with_reactor(|r| r.poll_io(token, cx.waker())) {
+                    self.token = None;
+                    Poll::Ready(Ok(()))
+                } else {
+                    Poll::Pending
+                }
+            }
+        }
+    }
+}
+
+impl Drop for Readiness {
+    fn drop(&mut self) {
+        if let Some(token) = self.token {
+            crate::event_loop::try_with_reactor(|r| r.cancel_io(token));
+        }
+    }
+}
+
+fn readiness(fd: RawFd, interest: u32) -> Readiness {
+    Readiness {
+        fd,
+        interest,
+        token: None,
+    }
+}
+
+/// 非阻塞的 TCP 连接，同一时间只能有一个读或写操作在等待
+pub struct AsyncTcpStream {
+    inner: TcpStream,
+}
+
+impl AsyncTcpStream {
+    /// std 没有提供非阻塞的 connect，这里在线程池中建立连接，之后的读写都不占线程
+    pub async fn connect(addr: SocketAddr) -> io::Result<AsyncTcpStream> {
+        let stream = spawn_blocking(move || TcpStream::connect(addr)).await??;
+        AsyncTcpStream::from_std(stream)
+    }
+
+    pub fn from_std(stream: TcpStream) -> io::Result<AsyncTcpStream> {
+        stream.set_nonblocking(true)?;
+        Ok(AsyncTcpStream { inner: stream })
+    }
+
+    pub fn get_ref(&self) -> &TcpStream {
+        &self.inner
+    }
+
+    pub async fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            match self.inner.read(buf) {
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => {
+                    readiness(self.inner.as_raw_fd(), READ).await?
+                }
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                result => return result,
+            }
+        }
+    }
+
+    /// 一直读到对端关闭连接，返回读取的字节数
+    pub async fn read_to_end(&mut self, buf: &mut Vec<u8>) -> io::Result<usize> {
+        let mut chunk = [0; 4096];
+        let mut total = 0;
+        loop {
+            match self.read(&mut chunk).await? {
+                0 => return Ok(total),
+                n => {
+                    buf.extend_from_slice(&chunk[..n]);
+                    total += n;
+                }
+            }
+        }
+    }
+
+    pub async fn write_all(&mut self, mut buf: &[u8]) -> io::Result<()> {
+        while !buf.is_empty() {
+            match self.inner.write(buf) {
+                Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+                Ok(n) => buf = &buf[n..],
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => {
+                    readiness(self.inner.as_raw_fd(), WRITE).await?
+                }
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{
+        atomic::AtomicUsize,
+        mpsc::{self, Receiver},
+    };
+
+    /// 由其他线程填充结果的 Future，用来在没有反应器的情况下测试执行器
+    struct Remote(Arc<Mutex<Slot<u32>>>);
+
+    impl Future for Remote {
+        type Output = u32;
+
+        fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<u32> {
+            let mut slot = self.0.lock().unwrap();
+            match slot.result.take() {
+                Some(result) => Poll::Ready(result.unwrap()),
+                None => {
+                    slot.waker = Some(cx.waker().clone());
+                    Poll::Pending
+                }
+            }
+        }
+    }
+
+    fn executor() -> (Executor, Receiver<()>) {
+        let (tx, rx) = mpsc::channel();
+        let tx = Mutex::new(tx);
+        let executor = Executor::new(move || {
+            let _ = tx.lock().unwrap().send(());
+        });
+        (executor, rx)
+    }
+
+    #[test]
+    fn tasks_run_when_woken_from_another_thread() {
+        let (executor, notified) = executor();
+        let slot = Arc::new(Mutex::new(Slot {
+            result: None,
+            waker: None,
+        }));
+        let done = Arc::new(AtomicUsize::new(0));
+        let (remote, finished) = (Remote(Arc::clone(&slot)), Arc::clone(&done));
+        executor.spawn(async move {
+            let value = remote.await;
+            finished.store(value as usize, Ordering::SeqCst);
+        });
+        notified.recv().unwrap();
+        assert_eq!(executor.run_ready(), 1);
+        assert_eq!(executor.run_ready(), 0);
+
+        thread::spawn(move || {
+            let mut slot = slot.lock().unwrap();
+            slot.result = Some(Ok(42));
+            slot.waker.take().unwrap().wake();
+        });
+        notified.recv().unwrap();
+        assert_eq!(executor.run_ready(), 1);
+        assert_eq!(done.load(Ordering::SeqCst), 42);
+    }
+
+    #[test]
+    fn catch_unwind_turns_panics_into_errors() {
+        let (executor, _notified) = executor();
+        let result = Arc::new(Mutex::new(None));
+        let out = Arc::clone(&result);
+        executor.spawn(async move {
+            let future: BoxFuture<u32> = Box::pin(async { panic!("boom") });
+            *out.lock().unwrap() = Some(CatchUnwind(future).await.is_err());
+        });
+        executor.run_ready();
+        assert_eq!(*result.lock().unwrap(), Some(true));
+    }
+}
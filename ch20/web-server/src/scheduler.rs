@@ -0,0 +1,600 @@
+/*
+线程池的任务调度（工作窃取）：
+    每个工作线程有自己的队列，不再所有线程争抢同一个 Mutex<Receiver>。
+        提交任务    工作线程里提交的任务放进自己的队列；其他线程提交的任务轮流放进各个队列
+        取任务      先从自己队列的头部取（先进先出），自己的队列空了就依次查看其他线程的队列，
+                    从尾部偷走一半，一次偷多个可以减少之后再去偷的次数
+        休眠        所有队列都空时在 Condvar 上等待
+
+    每个队列仍然是 Mutex<VecDeque>，但平时只有队列的主人在用，锁基本没有竞争。
+
+    优先级：每个队列按优先级分成几条，取任务和偷任务都先看优先级高的那条。优先级只在一个队列之内
+    严格成立，不同工作线程之间不保证高优先级的任务一定先执行，这样不需要全局的锁。
+    len 统计所有队列里的任务数，提交时先加再放进队列，线程休眠前在锁内检查它，不会错过唤醒。
+
+    唤醒：每提交一个任务就唤醒一个线程会造成大量无用的上下文切换。searching 统计醒着、
+    正在找任务的线程数，只有没有线程在找任务时提交才唤醒一个；被唤醒的线程找到任务后，
+    如果它是最后一个在找的线程而且还有剩下的任务，再唤醒下一个，这样忙起来的线程数逐个增加。
+
+    伸缩：队列按工作线程数的上限创建，编号空着的队列不会收到外部提交的任务。休眠的线程可以带一个
+    超时，超时返回 None 由线程池决定它是否退出；退出前把自己队列里剩下的任务转给其他线程。
+
+    容量：len 同时用来限制队列里的任务总数，提交前先用 CAS 占一个名额，占不到说明队列满了，
+    由调用方决定是失败（try_push）、等待空位（push_blocking）还是挤掉最早的任务（push_evict）。
+    每个任务进入调度器时分到一个全局递增的序号，偷任务和转移任务时序号跟着任务走；
+    挤掉时先挑优先级最低的一条，再在所有队列的这一条里找序号最小的，也就是整个线程池里最早提交的任务。
+*/
+
+use crate::{Job, Priority};
+use std::{
+    cell::Cell,
+    cmp::Reverse,
+    collections::VecDeque,
+    sync::{
+        Condvar, Mutex, PoisonError,
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+    },
+    time::Duration,
+};
+
+thread_local! {
+    /// 当前线程所属的调度器（地址）和工作线程编号，不是工作线程时为 None
+    static CURRENT: Cell<Option<(usize, usize)>> = const { Cell::new(None) };
+}
+
+/// 任务和它提交时分到的序号
+type Queued = (u64, Job);
+
+/// 一个工作线程的队列，每个优先级一条，下标是 Priority 的值
+#[derive(Default)]
+struct Lanes([VecDeque<Queued>; 3]);
+
+impl Lanes {
+    fn push(&mut self, lane: usize, seq: u64, job: Job) {
+        self.0[lane].push_back((seq, job));
+    }
+
+    /// 优先级最高的一条的头部
+    fn pop(&mut self) -> Option<Job> {
+        self.0
+            .iter_mut()
+            .rev()
+            .find_map(VecDeque::pop_front)
+            .map(|(_, job)| job)
+    }
+
+    /// lane 这一条里最早提交的任务的序号；偷来和转来的任务排在后面，所以不一定在头部
+    fn oldest(&self, lane: usize) -> Option<u64> {
+        self.0[lane].iter().map(|&(seq, _)| seq).min()
+    }
+
+    fn remove(&mut self, lane: usize, seq: u64) -> Option<Job> {
+        let queue = &mut self.0[lane];
+        let at = queue.iter().position(|&(s, _)| s == seq)?;
+        queue.remove(at).map(|(_, job)| job)
+    }
+
+    /// 从优先级最高、不为空的一条的尾部取走一半
+    fn steal_half(&mut self) -> Option<(usize, VecDeque<Queued>)> {
+        let (lane, queue) = self
+            .0
+            .iter_mut()
+            .enumerate()
+            .rev()
+            .find(|(_, queue)| !queue.is_empty())?;
+        let len = queue.len();
+        Some((lane, queue.split_off(len - len.div_ceil(2))))
+    }
+
+    fn drain(&mut self) -> Vec<(usize, u64, Job)> {
+        let mut jobs = Vec::new();
+        for (lane, queue) in self.0.iter_mut().enumerate() {
+            jobs.extend(queue.drain(..).map(|(seq, job)| (lane, seq, job)));
+        }
+        jobs
+    }
+
+    #[cfg(test)]
+    fn len(&self) -> usize {
+        self.0.iter().map(VecDeque::len).sum()
+    }
+
+    #[cfg(test)]
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+pub(crate) struct Scheduler {
+    queues: Vec<Mutex<Lanes>>,
+    /// 对应编号的工作线程是否存活
+    live: Vec<AtomicBool>,
+    /// 外部提交的任务下一次放进哪个队列
+    next: AtomicUsize,
+    /// 下一个任务的序号
+    seq: AtomicU64,
+    len: AtomicUsize,
+    /// 所有队列里任务总数的上限，不限制时是 usize::MAX
+    capacity: usize,
+    searching: AtomicUsize,
+    sleepers: AtomicUsize,
+    /// 唤醒方已经发出、还没有被休眠线程领取的唤醒次数
+    sleep: Mutex<usize>,
+    wakeup: Condvar,
+    /// 在 push_blocking 中等待空位的线程数
+    blocked: AtomicUsize,
+    space: Mutex<()>,
+    space_freed: Condvar,
+    shutdown: AtomicBool,
+}
+
+impl Scheduler {
+    /// workers 是工作线程数的上限，capacity 为 0 表示不限制队列长度
+    pub(crate) fn new(workers: usize, capacity: usize) -> Scheduler {
+        Scheduler {
+            queues: (0..workers).map(|_| Mutex::default()).collect(),
+            live: (0..workers).map(|_| AtomicBool::new(false)).collect(),
+            next: AtomicUsize::new(0),
+            seq: AtomicU64::new(0),
+            len: AtomicUsize::new(0),
+            capacity: if capacity == 0 { usize::MAX } else { capacity },
+            searching: AtomicUsize::new(0),
+            sleepers: AtomicUsize::new(0),
+            sleep: Mutex::new(0),
+            wakeup: Condvar::new(),
+            blocked: AtomicUsize::new(0),
+            space: Mutex::new(()),
+            space_freed: Condvar::new(),
+            shutdown: AtomicBool::new(false),
+        }
+    }
+
+    /// 把当前线程登记为 id 号工作线程，之后在这个线程上提交的任务放进它自己的队列
+    pub(crate) fn enter(&self, id: usize) {
+        self.live[id].store(true, Ordering::SeqCst);
+        CURRENT.set(Some((self as *const Scheduler as usize, id)));
+    }
+
+    /// id 号工作线程退出，队列里剩下的任务（退出前刚好提交进来的）转给其他线程
+    pub(crate) fn leave(&self, id: usize) {
+        self.live[id].store(false, Ordering::SeqCst);
+        CURRENT.set(None);
+        let left = lock(&self.queues[id]).drain();
+        for (lane, seq, job) in left {
+            // 名额已经占好了，len 不变
+            self.place(lane, seq, job);
+        }
+    }
+
+    /// 当前线程是这个调度器的哪个工作线程
+    fn current(&self) -> Option<usize> {
+        match CURRENT.get() {
+            Some((owner, id)) if owner == self as *const Scheduler as usize => Some(id),
+            _ => None,
+        }
+    }
+
+    /// 当前线程是不是这个调度器的工作线程
+    pub(crate) fn is_worker(&self) -> bool {
+        self.current().is_some()
+    }
+
+    /// 队列里的任务数
+    pub(crate) fn len(&self) -> usize {
+        self.len.load(Ordering::SeqCst)
+    }
+
+    pub(crate) fn capacity(&self) -> Option<usize> {
+        (self.capacity != usize::MAX).then_some(self.capacity)
+    }
+
+    /// 占用一个名额，队列已满时返回 false
+    fn reserve(&self) -> bool {
+        self.len
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |len| {
+                (len < self.capacity).then_some(len + 1)
+            })
+            .is_ok()
+    }
+
+    /// 放进队列，调用方已经占好了名额
+    fn place(&self, lane: usize, seq: u64, job: Job) {
+        lock(&self.queues[self.target()]).push(lane, seq, job);
+        self.notify();
+    }
+
+    /// 新提交的任务分到下一个序号再放进队列
+    fn place_new(&self, lane: usize, job: Job) {
+        let seq = self.seq.fetch_add(1, Ordering::Relaxed);
+        self.place(lane, seq, job);
+    }
+
+    fn target(&self) -> usize {
+        self.current().unwrap_or_else(|| {
+            let n = self.queues.len();
+            let start = self.next.fetch_add(1, Ordering::Relaxed) % n;
+            (0..n)
+                .map(|k| (start + k) % n)
+                .find(|&i| self.live[i].load(Ordering::Relaxed))
+                .unwrap_or(start)
+        })
+    }
+
+    /// 队列满时把任务原样返回
+    pub(crate) fn try_push(&self, job: Job, priority: Priority) -> Result<(), Job> {
+        if !self.reserve() {
+            return Err(job);
+        }
+        self.place_new(priority as usize, job);
+        Ok(())
+    }
+
+    /// 队列满时等待空位；等待期间调度器关闭则把任务原样返回
+    pub(crate) fn push_blocking(&self, job: Job, priority: Priority) -> Result<(), Job> {
+        loop {
+            if self.reserve() {
+                self.place_new(priority as usize, job);
+                return Ok(());
+            }
+            let mut space = lock(&self.space);
+            self.blocked.fetch_add(1, Ordering::SeqCst);
+            while self.len.load(Ordering::SeqCst) >= self.capacity && !self.is_shutdown() {
+                space = self
+                    .space_freed
+                    .wait(space)
+                    .unwrap_or_else(PoisonError::into_inner);
+            }
+            self.blocked.fetch_sub(1, Ordering::SeqCst);
+            if self.is_shutdown() {
+                return Err(job);
+            }
+        }
+    }
+
+    /// 队列满时挤掉优先级最低的任务里最早提交的一个，返回被挤掉的任务
+    pub(crate) fn push_evict(&self, job: Job, priority: Priority) -> Option<Job> {
+        loop {
+            if self.reserve() {
+                self.place_new(priority as usize, job);
+                return None;
+            }
+            let oldest = (0..3).find_map(|lane| {
+                (0..self.queues.len())
+                    .filter_map(|i| Some((lock(&self.queues[i]).oldest(lane)?, i)))
+                    .min()
+                    .map(|(seq, i)| (lane, seq, i))
+            });
+            // 找的时候没有把所有队列同时锁住，任务可能刚好被取走或者偷走了，那就重新来一遍
+            let evicted = oldest.and_then(|(lane, seq, i)| lock(&self.queues[i]).remove(lane, seq));
+            if let Some(evicted) = evicted {
+                // 名额直接转给新任务，len 不变
+                self.place_new(priority as usize, job);
+                return Some(evicted);
+            }
+        }
+    }
+
+    /// 一个任务离开了队列
+    fn taken(&self) {
+        self.len.fetch_sub(1, Ordering::SeqCst);
+        if self.blocked.load(Ordering::SeqCst) > 0 {
+            let _space = lock(&self.space);
+            self.space_freed.notify_one();
+        }
+    }
+
+    /// 醒着没事做的线程数，包括正在找任务和已经被唤醒、还没开始找的线程
+    pub(crate) fn idle(&self) -> usize {
+        self.searching.load(Ordering::SeqCst) + self.sleepers.load(Ordering::SeqCst)
+    }
+
+    /// 没有线程在找任务时唤醒一个休眠的线程
+    fn notify(&self) {
+        if self.searching.load(Ordering::SeqCst) == 0 && self.sleepers.load(Ordering::SeqCst) > 0 {
+            let mut tokens = lock(&self.sleep);
+            // 被唤醒的线程在真正运行之前就算作在找任务，之后的提交不会再唤醒别的线程
+            if self.sleepers.load(Ordering::SeqCst) > 0 {
+                self.sleepers.fetch_sub(1, Ordering::SeqCst);
+                self.searching.fetch_add(1, Ordering::SeqCst);
+                *tokens += 1;
+                self.wakeup.notify_one();
+            }
+        }
+    }
+
+    /// 取下一个任务，没有任务时休眠；关闭之后队列也空了，或者休眠超过 keep_alive 返回 None
+    pub(crate) fn pop(&self, id: usize, keep_alive: Option<Duration>) -> Option<Job> {
+        // 自己的队列里有任务时不用进入 searching
+        let job = lock(&self.queues[id]).pop();
+        if let Some(job) = job {
+            self.taken();
+            return Some(job);
+        }
+        self.searching.fetch_add(1, Ordering::SeqCst);
+        loop {
+            if let Some(job) = self.find(id) {
+                self.taken();
+                if self.searching.fetch_sub(1, Ordering::SeqCst) == 1
+                    && self.len.load(Ordering::SeqCst) > 0
+                {
+                    self.notify();
+                }
+                return Some(job);
+            }
+            // 先退出 searching 再检查 len：提交方要么看到这个线程还在找，要么它的任务会被这里看到
+            self.searching.fetch_sub(1, Ordering::SeqCst);
+            let mut tokens = lock(&self.sleep);
+            self.sleepers.fetch_add(1, Ordering::SeqCst);
+            loop {
+                // 唤醒方已经替这个线程更新了计数
+                if *tokens > 0 {
+                    *tokens -= 1;
+                    break;
+                }
+                if self.len.load(Ordering::SeqCst) > 0 {
+                    self.sleepers.fetch_sub(1, Ordering::SeqCst);
+                    self.searching.fetch_add(1, Ordering::SeqCst);
+                    break;
+                }
+                if self.shutdown.load(Ordering::SeqCst) {
+                    self.sleepers.fetch_sub(1, Ordering::SeqCst);
+                    return None;
+                }
+                let Some(keep_alive) = keep_alive else {
+                    tokens = self
+                        .wakeup
+                        .wait(tokens)
+                        .unwrap_or_else(PoisonError::into_inner);
+                    continue;
+                };
+                let (guard, timeout) = self
+                    .wakeup
+                    .wait_timeout(tokens, keep_alive)
+                    .unwrap_or_else(PoisonError::into_inner);
+                tokens = guard;
+                // 超时的同时被唤醒时领取唤醒，继续找任务
+                if timeout.timed_out() && *tokens == 0 && self.len.load(Ordering::SeqCst) == 0 {
+                    self.sleepers.fetch_sub(1, Ordering::SeqCst);
+                    return None;
+                }
+            }
+        }
+    }
+
+    fn find(&self, id: usize) -> Option<Job> {
+        if let Some(job) = lock(&self.queues[id]).pop() {
+            return Some(job);
+        }
+        let n = self.queues.len();
+        for victim in (1..n).map(|k| (id + k) % n) {
+            let stolen = lock(&self.queues[victim]).steal_half();
+            let Some((lane, mut stolen)) = stolen else {
+                continue;
+            };
+            let job = stolen.pop_front().map(|(_, job)| job);
+            if !stolen.is_empty() {
+                lock(&self.queues[id]).0[lane].extend(stolen);
+            }
+            return job;
+        }
+        None
+    }
+
+    /// 取走所有队列里还没执行的任务，优先级高的排在前面，同一优先级按提交顺序
+    pub(crate) fn drain(&self) -> Vec<Job> {
+        let mut jobs = Vec::new();
+        for queue in &self.queues {
+            jobs.extend(lock(queue).drain());
+        }
+        for _ in &jobs {
+            self.taken();
+        }
+        jobs.sort_by_key(|&(lane, seq, _)| (Reverse(lane), seq));
+        jobs.into_iter().map(|(_, _, job)| job).collect()
+    }
+
+    /// 不再接受新任务，工作线程执行完剩下的任务后退出
+    pub(crate) fn shutdown(&self) {
+        self.shutdown.store(true, Ordering::SeqCst);
+        drop(lock(&self.sleep));
+        self.wakeup.notify_all();
+        drop(lock(&self.space));
+        self.space_freed.notify_all();
+    }
+
+    pub(crate) fn is_shutdown(&self) -> bool {
+        self.shutdown.load(Ordering::SeqCst)
+    }
+}
+
+/// 锁只在存取队列时持有，任务 panic 不会让它中毒，这里统一忽略中毒
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        sync::{Arc, mpsc},
+        thread,
+        time::{Duration, Instant},
+    };
+
+    fn job(f: impl FnOnce() + Send + 'static) -> Job {
+        Box::new(f)
+    }
+
+    fn push(scheduler: &Scheduler, f: impl FnOnce() + Send + 'static) {
+        assert!(scheduler.try_push(job(f), Priority::Normal).is_ok());
+    }
+
+    #[test]
+    fn jobs_from_a_worker_stay_local_until_stolen() {
+        let scheduler = Scheduler::new(3, 0);
+        scheduler.enter(1);
+        let (tx, rx) = mpsc::channel();
+        for i in 0..6 {
+            let tx = tx.clone();
+            push(&scheduler, move || tx.send(i).unwrap());
+        }
+        CURRENT.set(None);
+        assert_eq!(lock(&scheduler.queues[1]).len(), 6);
+
+        // 自己的队列先进先出
+        scheduler.pop(1, None).unwrap()();
+        assert_eq!(rx.recv().unwrap(), 0);
+        // 另一个线程偷走剩下 5 个中的 3 个（从尾部），执行第一个，其余放进自己的队列
+        scheduler.pop(2, None).unwrap()();
+        assert_eq!(rx.recv().unwrap(), 3);
+        assert_eq!(lock(&scheduler.queues[1]).len(), 2);
+        assert_eq!(lock(&scheduler.queues[2]).len(), 2);
+        assert_eq!(scheduler.len.load(Ordering::SeqCst), 4);
+    }
+
+    #[test]
+    fn external_jobs_are_spread_across_queues() {
+        let scheduler = Scheduler::new(4, 0);
+        for _ in 0..8 {
+            push(&scheduler, || {});
+        }
+        for queue in &scheduler.queues {
+            assert_eq!(lock(queue).len(), 2);
+        }
+    }
+
+    #[test]
+    fn sleeping_workers_wake_for_new_jobs_and_shutdown() {
+        let scheduler = Arc::new(Scheduler::new(2, 0));
+        let (tx, rx) = mpsc::channel();
+        let workers: Vec<_> = (0..2)
+            .map(|id| {
+                let scheduler = Arc::clone(&scheduler);
+                let tx = tx.clone();
+                thread::spawn(move || {
+                    scheduler.enter(id);
+                    let mut ran = 0;
+                    while let Some(job) = scheduler.pop(id, None) {
+                        job();
+                        ran += 1;
+                    }
+                    tx.send(ran).unwrap();
+                })
+            })
+            .collect();
+        // 等两个线程都进入休眠
+        while scheduler.sleepers.load(Ordering::SeqCst) < 2 {
+            thread::sleep(Duration::from_millis(1));
+        }
+        let (done_tx, done_rx) = mpsc::channel();
+        for _ in 0..100 {
+            let done_tx = done_tx.clone();
+            push(&scheduler, move || done_tx.send(()).unwrap());
+        }
+        for _ in 0..100 {
+            done_rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        }
+        scheduler.shutdown();
+        for worker in workers {
+            worker.join().unwrap();
+        }
+        assert_eq!(rx.iter().take(2).sum::<i32>(), 100);
+        assert!(scheduler.pop(0, None).is_none());
+    }
+
+    #[test]
+    fn idle_worker_times_out_and_hands_over_its_queue() {
+        let scheduler = Scheduler::new(2, 0);
+        let started = Instant::now();
+        assert!(scheduler.pop(0, Some(Duration::from_millis(20))).is_none());
+        assert!(started.elapsed() >= Duration::from_millis(20));
+        assert_eq!(scheduler.idle(), 0);
+
+        scheduler.enter(0);
+        scheduler.enter(1);
+        push(&scheduler, || {});
+        push(&scheduler, || {});
+        assert_eq!(lock(&scheduler.queues[1]).len(), 2);
+        // 1 号线程退出，它的任务和之后外部提交的任务都放进还在的 0 号队列
+        scheduler.leave(1);
+        push(&scheduler, || {});
+        assert_eq!(lock(&scheduler.queues[0]).len(), 3);
+        assert!(lock(&scheduler.queues[1]).is_empty());
+        assert_eq!(scheduler.len(), 3);
+    }
+
+    #[test]
+    fn full_queue_rejects_or_evicts() {
+        let scheduler = Scheduler::new(2, 3);
+        let (tx, rx) = mpsc::channel();
+        for i in 0..3 {
+            let tx = tx.clone();
+            push(&scheduler, move || tx.send(i).unwrap());
+        }
+        assert_eq!(scheduler.len(), 3);
+        assert!(scheduler.try_push(job(|| {}), Priority::Normal).is_err());
+
+        // 0、2 在 0 号队列，1 在 1 号队列；下一个要放入的是 1 号队列，但挤掉的是整体最早的 0
+        let tx3 = tx.clone();
+        let evicted = scheduler.push_evict(job(move || tx3.send(3).unwrap()), Priority::Normal);
+        evicted.unwrap()();
+        assert_eq!(rx.recv().unwrap(), 0);
+        assert_eq!(scheduler.len(), 3);
+        // 1 号线程退出，它的 1、3 转到 0 号队列排在 2 后面，被挤掉的仍然是最早的 1
+        scheduler.enter(0);
+        CURRENT.set(None);
+        scheduler.leave(1);
+        let evicted = scheduler.push_evict(job(|| {}), Priority::Normal);
+        evicted.unwrap()();
+        assert_eq!(rx.recv().unwrap(), 1);
+        // 优先级低的先被挤掉，即使它是最后提交的
+        let tx5 = tx.clone();
+        let evicted = scheduler.push_evict(job(move || tx5.send(5).unwrap()), Priority::Low);
+        evicted.unwrap()();
+        assert_eq!(rx.recv().unwrap(), 2);
+        let evicted = scheduler.push_evict(job(|| {}), Priority::High);
+        evicted.unwrap()();
+        assert_eq!(rx.recv().unwrap(), 5);
+        while let Some(job) = scheduler.find(0) {
+            scheduler.taken();
+            job();
+        }
+        assert_eq!(rx.try_iter().collect::<Vec<_>>(), [3]);
+        assert_eq!(scheduler.len(), 0);
+    }
+
+    #[test]
+    fn blocked_pushers_resume_when_space_frees_up() {
+        let scheduler = Arc::new(Scheduler::new(1, 1));
+        push(&scheduler, || {});
+        let pusher = {
+            let scheduler = Arc::clone(&scheduler);
+            thread::spawn(move || {
+                scheduler
+                    .push_blocking(job(|| {}), Priority::Normal)
+                    .is_ok()
+            })
+        };
+        while scheduler.blocked.load(Ordering::SeqCst) == 0 {
+            thread::sleep(Duration::from_millis(1));
+        }
+        scheduler.pop(0, None).unwrap()();
+        assert!(pusher.join().unwrap());
+        assert_eq!(scheduler.len(), 1);
+
+        // 关闭时还在等待的提交失败，任务还给调用方
+        let pusher = {
+            let scheduler = Arc::clone(&scheduler);
+            thread::spawn(move || {
+                scheduler
+                    .push_blocking(job(|| {}), Priority::Normal)
+                    .is_err()
+            })
+        };
+        while scheduler.blocked.load(Ordering::SeqCst) == 0 {
+            thread::sleep(Duration::from_millis(1));
+        }
+        scheduler.shutdown();
+        assert!(pusher.join().unwrap());
+    }
+}
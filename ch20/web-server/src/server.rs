@@ -0,0 +1,427 @@
+/*
+服务器：
+    按照配置监听一个或多个地址，每个监听地址一个 accept 线程，所有连接交给同一个线程池处理。
+    每个请求都经过 Pipeline：外层是访问日志等中间件，最内层是路由。
+    连接默认保持（keep-alive），但读取请求头有总的截止时间，连接本身也有最长存活时间，
+    慢速客户端会收到 408 并被断开，同一个 IP 的并发连接超过上限时直接返回 429。
+    连接的 I/O 有两种后端：
+        threads  每个连接在一个工作线程上阻塞读写，直到连接关闭
+        epoll    事件循环处理所有连接的 I/O，只有执行 handler 时才占用工作线程（仅 Linux）
+    epoll 后端还可以设置一个 AsyncHandler 代替同步的处理链，请求在事件循环线程上异步处理。
+    配置了代理规则时，匹配前缀的请求转发给上游（见 proxy 模块），其余请求仍由默认页面处理。
+    请求数、耗时、连接数和线程池状态以 Prometheus 格式输出在 /metrics（见 metrics 模块）。
+    以连接前言开头或者带 Upgrade: h2c 的连接交给 http2 模块，之后按 HTTP/2 处理。
+*/
+
+#[cfg(target_os = "linux")]
+use crate::runtime::AsyncHandler;
+use crate::{
+    ThreadPool,
+    access_log::{AccessLog, Record},
+    compress::Compression,
+    config::Config,
+    conn::{ConnGuard, ConnLimiter, DeadlineReader, earliest},
+    debug, error,
+    http::{HttpError, Request, Response},
+    http2, log,
+    metrics::Metrics,
+    middleware::{Handler, Middleware, Pipeline},
+    proxy,
+    rest::{self, Store, Todo},
+    router::Router,
+    warn,
+    websocket::{Endpoint, Message, WebSocket},
+};
+use std::{
+    fs,
+    io::{self, BufRead, BufReader},
+    net::{SocketAddr, TcpListener, TcpStream},
+    panic::{self, AssertUnwindSafe},
+    path::Path,
+    str::FromStr,
+    sync::Arc,
+    thread,
+    time::{Duration, Instant, SystemTime},
+};
+
+/// 连接 I/O 的处理方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    Threads,
+    Epoll,
+}
+
+impl FromStr for Backend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Backend, String> {
+        match s {
+            "threads" => Ok(Backend::Threads),
+            "epoll" if cfg!(target_os = "linux") => Ok(Backend::Epoll),
+            "epoll" => Err(String::from("the epoll backend is only available on linux")),
+            _ => Err(format!("unknown backend `{}`", s)),
+        }
+    }
+}
+
+/// 所有连接共享的只读状态
+pub(crate) struct Shared {
+    pub(crate) config: Config,
+    pub(crate) access_log: Arc<AccessLog>,
+    pub(crate) pipeline: Pipeline,
+    pub(crate) limiter: ConnLimiter,
+    /// HTTP/2 的流任务也交给这个线程池
+    pub(crate) pool: Arc<ThreadPool>,
+    #[cfg(target_os = "linux")]
+    pub(crate) async_handler: Option<Box<dyn AsyncHandler>>,
+}
+
+impl Shared {
+    /// 交给处理链处理请求；handler panic 时回复 500，连接照常继续，和异步 handler 的 CatchUnwind 一致
+    pub(crate) fn handle(&self, req: &mut Request) -> Response {
+        match panic::catch_unwind(AssertUnwindSafe(|| self.pipeline.handle(req))) {
+            Ok(resp) => resp,
+            Err(_) => {
+                error!("handler panicked: {} {}", req.method, req.target);
+                Response::error(500)
+            }
+        }
+    }
+}
+
+pub struct Server {
+    listeners: Vec<TcpListener>,
+    pool: Arc<ThreadPool>,
+    limiter: ConnLimiter,
+    config: Config,
+    access_log: Arc<AccessLog>,
+    pipeline: Pipeline,
+    #[cfg(target_os = "linux")]
+    async_handler: Option<Box<dyn AsyncHandler>>,
+}
+
+impl Server {
+    /// 绑定配置中的所有地址，任意一个地址绑定失败都会返回错误
+    ///
+    /// 默认的处理链是访问日志、指标、响应压缩和 `default_router` 提供的页面，配置了代理时先匹配代理规则
+    pub fn bind(config: Config) -> io::Result<Server> {
+        log::set_level(config.log_level);
+        let listeners = config
+            .binds
+            .iter()
+            .map(|addr| {
+                TcpListener::bind(addr)
+                    .map_err(|err| io::Error::new(err.kind(), format!("bind {}: {}", addr, err)))
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+        let access_log = Arc::new(AccessLog::new(
+            &config.access_log,
+            config.access_log_format,
+            config.access_log_rotation,
+        )?);
+        let router = if config.proxy.routes.is_empty() {
+            default_router(&config.doc_root)
+        } else {
+            proxy::router(&config.proxy, default_router(&config.doc_root))
+        };
+        let max_workers = match config.max_workers {
+            0 => config.workers,
+            max => max,
+        };
+        let pool = ThreadPool::builder(config.workers)
+            .max_workers(max_workers)
+            .keep_alive(config.worker_keep_alive)
+            .queue_capacity(config.queue_capacity)
+            .queue_policy(config.queue_policy)
+            .build()
+            .map_err(|err| io::Error::other(format!("thread pool: {}", err)))?;
+        let pool = Arc::new(pool);
+        let limiter = ConnLimiter::new(config.max_connections_per_ip);
+        let mut pipeline = Pipeline::new(router).with(Arc::clone(&access_log));
+        if config.metrics {
+            pipeline.push(
+                Metrics::new(&config.metrics_path)
+                    .with_pool(pool.monitor())
+                    .with_connections(limiter.clone()),
+            );
+        }
+        // 压缩放在访问日志内层，日志里记录的是实际发送的字节数
+        if config.compression {
+            pipeline.push(Compression::new(config.compression_min_size));
+        }
+        Ok(Server {
+            listeners,
+            pool,
+            limiter,
+            config,
+            access_log,
+            pipeline,
+            #[cfg(target_os = "linux")]
+            async_handler: None,
+        })
+    }
+
+    /// 在处理链的内层追加一个中间件
+    pub fn with<M: Middleware>(mut self, middleware: M) -> Server {
+        self.pipeline.push(middleware);
+        self
+    }
+
+    /// 替换最内层的 handler（通常是一个 Router），已添加的中间件保持不变
+    pub fn handler<H: Handler>(mut self, handler: H) -> Server {
+        self.pipeline.set_endpoint(handler);
+        self
+    }
+
+    /// 用异步 handler 处理所有请求，代替同步的处理链
+    ///
+    /// 异步 handler 不经过 `with` 添加的中间件，访问日志仍然会记录
+    ///
+    /// # Panics
+    ///
+    /// 配置的后端不是 epoll 时会 panic
+    #[cfg(target_os = "linux")]
+    pub fn async_handler<H: AsyncHandler>(mut self, handler: H) -> Server {
+        assert!(
+            self.config.backend == Backend::Epoll,
+            "async handlers require the epoll backend"
+        );
+        self.async_handler = Some(Box::new(handler));
+        self
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// 实际监听的地址，绑定端口 0 时可以通过它拿到系统分配的端口
+    pub fn local_addrs(&self) -> Vec<SocketAddr> {
+        self.listeners
+            .iter()
+            .filter_map(|ln| ln.local_addr().ok())
+            .collect()
+    }
+
+    /// 开始接收连接，该函数不会返回
+    pub fn run(self) {
+        let mut listeners = self.listeners;
+        let pool = self.pool;
+        let shared = Arc::new(Shared {
+            limiter: self.limiter,
+            pool: Arc::clone(&pool),
+            config: self.config,
+            access_log: self.access_log,
+            pipeline: self.pipeline,
+            #[cfg(target_os = "linux")]
+            async_handler: self.async_handler,
+        });
+        #[cfg(target_os = "linux")]
+        if shared.config.backend == Backend::Epoll {
+            if let Err(err) = crate::event_loop::run(listeners, pool, shared) {
+                error!("event loop failed: {}", err);
+            }
+            return;
+        }
+        let first = listeners.remove(0);
+        for ln in listeners {
+            let pool = Arc::clone(&pool);
+            let shared = Arc::clone(&shared);
+            thread::spawn(move || accept_loop(ln, &pool, &shared));
+        }
+        accept_loop(first, &pool, &shared);
+    }
+}
+
+fn accept_loop(ln: TcpListener, pool: &ThreadPool, shared: &Arc<Shared>) {
+    for stream in ln.incoming() {
+        let mut conn = match stream {
+            Ok(conn) => conn,
+            Err(err) => {
+                warn!("accept error: {}", err);
+                continue;
+            }
+        };
+        let peer = match conn.peer_addr() {
+            Ok(peer) => peer,
+            Err(_) => continue,
+        };
+        // 名额在 accept 线程上占用，这样超出限制的连接根本不会进入线程池排队
+        let guard = match shared.limiter.try_acquire(peer.ip()) {
+            Some(guard) => guard,
+            None => {
+                debug!("too many connections from {}", peer.ip());
+                let _ = conn.set_write_timeout(Some(Duration::from_secs(1)));
+                let resp = Response::error(429).with_header("Connection", "close");
+                let _ = resp.write_to(&mut conn);
+                continue;
+            }
+        };
+        let shared = Arc::clone(shared);
+        let rejected = conn.try_clone();
+        let submitted = pool.execute(move || {
+            if let Err(err) = handle_conn(conn, peer, &shared, guard) {
+                debug!("connection error: {}", err);
+            }
+        });
+        // 线程池队列已满或者不再接受任务时告诉客户端稍后重试，而不是直接断开
+        if let Err(err) = submitted {
+            warn!("dropping connection from {}: {}", peer, err);
+            if let Ok(mut conn) = rejected {
+                let _ = conn.set_write_timeout(Some(Duration::from_secs(1)));
+                let resp = Response::error(503).with_header("Connection", "close");
+                let _ = resp.write_to(&mut conn);
+            }
+        }
+    }
+}
+
+/// 在一个连接上循环处理请求，直到客户端要求关闭、出错或者超时
+///
+/// guard 随连接一起交给 Upgrade，其余情况在返回时归还名额
+fn handle_conn(
+    stream: TcpStream,
+    peer: SocketAddr,
+    shared: &Arc<Shared>,
+    guard: ConnGuard,
+) -> io::Result<()> {
+    let config = &shared.config;
+    debug!("Connection established!, remote addr: {}", peer);
+    let conn_deadline = config
+        .connection_timeout
+        .and_then(|t| Instant::now().checked_add(t));
+    stream.set_write_timeout(config.write_timeout)?;
+    let mut reader = BufReader::new(DeadlineReader::new(
+        stream.try_clone()?,
+        config.read_timeout,
+    ));
+    let mut writer = stream;
+
+    let mut first = true;
+    loop {
+        if !first {
+            // 等待下一个请求的第一个字节，空闲超时直接关闭连接，不需要响应
+            let idle = config
+                .keep_alive_timeout
+                .and_then(|t| Instant::now().checked_add(t));
+            reader.get_mut().set_deadline(earliest(idle, conn_deadline));
+            match reader.fill_buf() {
+                Ok(buf) if !buf.is_empty() => {}
+                _ => return Ok(()),
+            }
+        }
+
+        let header_deadline = config
+            .header_timeout
+            .and_then(|t| Instant::now().checked_add(t));
+        reader
+            .get_mut()
+            .set_deadline(earliest(header_deadline, conn_deadline));
+        let parsed = Request::read_head(&mut reader, &config.limits).and_then(|mut req| {
+            reader.get_mut().set_deadline(conn_deadline);
+            req.read_body(&mut reader, &config.limits)?;
+            Ok(req)
+        });
+
+        let mut req = match parsed {
+            Ok(req) => req,
+            Err(err) => {
+                if let Some(resp) = reject(shared, peer, &err) {
+                    resp.write_to(&mut writer)?;
+                }
+                return Ok(());
+            }
+        };
+        req.remote_addr = Some(peer);
+        debug!("Request: {} {} {}", req.method, req.target, req.version);
+        if http2::is_preface(&req) {
+            match http2::accept_preface(peer, shared) {
+                Some(upgrade) => upgrade.run(writer, reader.buffer().to_vec(), guard),
+                None => {
+                    let err = HttpError::BadRequest("unsupported http version");
+                    if let Some(resp) = reject(shared, peer, &err) {
+                        resp.write_to(&mut writer)?;
+                    }
+                }
+            }
+            return Ok(());
+        }
+        let mut resp = match http2::accept_upgrade(&req, peer, shared) {
+            Some(resp) => resp,
+            None => shared.handle(&mut req),
+        };
+        if let Some(upgrade) = resp.upgrade.clone() {
+            resp.write_to(&mut writer)?;
+            // 客户端可能紧跟着握手发送了数据，已经在缓冲区里的字节一起交出去
+            upgrade.run(writer, reader.buffer().to_vec(), guard);
+            return Ok(());
+        }
+        let keep_alive = finish(&req, &mut resp, conn_deadline);
+        resp.write_to(&mut writer)?;
+        if !keep_alive {
+            return Ok(());
+        }
+        first = false;
+    }
+}
+
+/// 请求无法解析时不会进入处理链，直接在这里记录访问日志并生成错误响应，之后连接会被关闭
+///
+/// 返回 None 表示不需要响应（例如连接已经断开）
+pub(crate) fn reject(shared: &Shared, peer: SocketAddr, err: &HttpError) -> Option<Response> {
+    let status = err.status()?;
+    debug!("rejecting request from {}: {}", peer, err);
+    let resp = Response::error(status).with_header("Connection", "close");
+    let mut record = Record::new(None, &resp, SystemTime::now(), Duration::ZERO);
+    record.remote_addr = Some(peer);
+    shared.access_log.record(&record);
+    Some(resp)
+}
+
+/// 决定响应之后是否保持连接，并设置相应的 Connection 头
+pub(crate) fn finish(req: &Request, resp: &mut Response, conn_deadline: Option<Instant>) -> bool {
+    let expired = conn_deadline.is_some_and(|d| Instant::now() >= d);
+    let keep_alive = req.keep_alive() && !expired && resp.header("Connection") != Some("close");
+    if keep_alive {
+        if req.version == "HTTP/1.0" {
+            resp.headers.set("Connection", "keep-alive");
+        }
+    } else {
+        resp.headers.set("Connection", "close");
+    }
+    if req.method == "HEAD" {
+        resp.strip_body();
+    }
+    keep_alive
+}
+
+/// 书中示例的几个页面：`/` 返回 hello.html，`/sleep` 模拟慢请求，`/ws/echo` 是回显的 WebSocket，
+/// `/api/todos` 是内存中的 REST 资源，其余返回 404.html
+pub fn default_router(doc_root: &Path) -> Router {
+    let hello = doc_root.join("hello.html");
+    let not_found = doc_root.join("404.html");
+    let sleep_page = hello.clone();
+    let todos = Arc::new(Store::<Todo>::new());
+    rest::resource(Router::new(), "/api/todos", todos)
+        .get("/", move |_req: &mut Request| page(200, &hello))
+        .get("/sleep", move |_req: &mut Request| {
+            thread::sleep(Duration::from_secs(20));
+            page(200, &sleep_page)
+        })
+        .get(
+            "/ws/echo",
+            Endpoint::new(|ws: &WebSocket, msg: Message| {
+                let _ = ws.send(msg);
+            }),
+        )
+        .fallback(move |_req: &mut Request| page(404, &not_found))
+}
+
+fn page(status: u16, path: &Path) -> Response {
+    match fs::read(path) {
+        Ok(content) => Response::html(status, content),
+        Err(err) => {
+            error!("read {}: {}", path.display(), err);
+            Response::error(500)
+        }
+    }
+}
@@ -0,0 +1,300 @@
+/*
+Server-Sent Events：
+    Sse 是一种流式响应：响应头写出之后连接交给一个单独的线程，
+    它从 mpsc::Receiver 中取出事件，编码成 `event:` / `id:` / `data:` 行写给客户端，
+    发送端全部丢弃之后响应结束、连接关闭。没有事件时定期写一行注释作为心跳，
+    既能让中间的代理不因为空闲断开连接，也能及时发现已经离开的客户端。
+
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || tx.send(Event::new("hello").with_event("greeting")));
+        Sse::new(rx).into_response()
+
+    Broadcast 把同一组事件发给所有订阅者，并保留最近的若干条事件。
+    浏览器重连时会带上 Last-Event-ID，subscribe 先补发这个 id 之后的事件，再接上新的事件：
+
+        let hub = Arc::new(Broadcast::new(100));
+        router.get("/events", move |req: &mut Request| {
+            hub.subscribe(req.header("Last-Event-ID")).into_response()
+        })
+*/
+
+use crate::{
+    debug,
+    http::{Response, Upgrade},
+};
+use std::{
+    collections::VecDeque,
+    io::{self, Write},
+    net::{Shutdown, TcpStream},
+    sync::{
+        Mutex,
+        mpsc::{self, Receiver, RecvTimeoutError, Sender},
+    },
+    thread,
+    time::Duration,
+};
+
+/// 写一个事件的最长时间，不读数据的客户端不会让流线程一直卡住
+const WRITE_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Event {
+    id: Option<String>,
+    event: Option<String>,
+    data: String,
+    retry: Option<Duration>,
+}
+
+impl Event {
+    pub fn new(data: impl Into<String>) -> Event {
+        Event {
+            id: None,
+            event: None,
+            data: data.into(),
+            retry: None,
+        }
+    }
+
+    /// 事件 id，客户端重连时通过 Last-Event-ID 带回来；换行会被去掉
+    pub fn with_id(mut self, id: impl Into<String>) -> Event {
+        self.id = Some(single_line(id.into()));
+        self
+    }
+
+    /// 事件类型，浏览器里对应 addEventListener 的名字；不设置时是 message
+    pub fn with_event(mut self, event: impl Into<String>) -> Event {
+        self.event = Some(single_line(event.into()));
+        self
+    }
+
+    /// 要求客户端断开之后等待多久再重连
+    pub fn with_retry(mut self, retry: Duration) -> Event {
+        self.retry = Some(retry);
+        self
+    }
+
+    pub fn id(&self) -> Option<&str> {
+        self.id.as_deref()
+    }
+
+    pub fn event(&self) -> Option<&str> {
+        self.event.as_deref()
+    }
+
+    pub fn data(&self) -> &str {
+        &self.data
+    }
+
+    /// 编码成事件流中的一段，多行数据拆成多个 data 行，最后以空行结束
+    pub fn encode(&self) -> String {
+        let mut out = String::new();
+        if let Some(event) = &self.event {
+            out.push_str(&format!("event: {}\n", event));
+        }
+        if let Some(id) = &self.id {
+            out.push_str(&format!("id: {}\n", id));
+        }
+        if let Some(retry) = self.retry {
+            out.push_str(&format!("retry: {}\n", retry.as_millis()));
+        }
+        let data = self.data.replace("\r\n", "\n").replace('\r', "\n");
+        for line in data.split('\n') {
+            out.push_str(&format!("data: {}\n", line));
+        }
+        out.push('\n');
+        out
+    }
+}
+
+fn single_line(s: String) -> String {
+    s.replace(['\r', '\n'], "")
+}
+
+/// 事件流响应
+pub struct Sse {
+    rx: Receiver<Event>,
+    /// 在新事件之前先发送的事件
+    replay: Vec<Event>,
+    heartbeat: Option<Duration>,
+}
+
+impl Sse {
+    pub fn new(rx: Receiver<Event>) -> Sse {
+        Sse {
+            rx,
+            replay: Vec::new(),
+            heartbeat: Some(Duration::from_secs(15)),
+        }
+    }
+
+    pub fn replay(mut self, events: Vec<Event>) -> Sse {
+        self.replay = events;
+        self
+    }
+
+    /// 没有事件时多久发送一次心跳注释，None 表示不发送
+    pub fn heartbeat(mut self, interval: Option<Duration>) -> Sse {
+        self.heartbeat = interval;
+        self
+    }
+
+    /// 生成 200 响应，写出响应头之后由一个单独的线程继续写事件，直到发送端全部丢弃或者客户端断开
+    pub fn into_response(self) -> Response {
+        Response::new(200)
+            .with_header("Content-Type", "text/event-stream")
+            .with_header("Cache-Control", "no-cache")
+            // 响应体以关闭连接结束，这个连接不能再用来处理下一个请求
+            .with_header("Connection", "close")
+            .with_upgrade(Upgrade::new(move |stream, _buffered, guard| {
+                // 订阅者占着一个线程，连接名额要一直保留到事件流结束
+                let spawned = thread::Builder::new()
+                    .name(String::from("sse"))
+                    .spawn(move || {
+                        if let Err(err) = self.stream(stream) {
+                            debug!("event stream closed: {}", err);
+                        }
+                        drop(guard);
+                    });
+                if let Err(err) = spawned {
+                    debug!("cannot start event stream: {}", err);
+                }
+            }))
+    }
+
+    fn stream(self, mut stream: TcpStream) -> io::Result<()> {
+        stream.set_write_timeout(Some(WRITE_TIMEOUT))?;
+        for event in &self.replay {
+            stream.write_all(event.encode().as_bytes())?;
+        }
+        stream.flush()?;
+        loop {
+            let next = match self.heartbeat {
+                Some(interval) => self.rx.recv_timeout(interval),
+                None => self.rx.recv().map_err(|_| RecvTimeoutError::Disconnected),
+            };
+            match next {
+                Ok(event) => stream.write_all(event.encode().as_bytes())?,
+                // 冒号开头的行是注释，客户端会忽略
+                Err(RecvTimeoutError::Timeout) => stream.write_all(b": ping\n\n")?,
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+            stream.flush()?;
+        }
+        stream.shutdown(Shutdown::Both)
+    }
+}
+
+struct Hub {
+    next_id: u64,
+    history: VecDeque<Event>,
+    subscribers: Vec<Sender<Event>>,
+}
+
+/// 把事件广播给所有订阅者，保留最近的 capacity 条事件用于断线重连
+pub struct Broadcast {
+    capacity: usize,
+    hub: Mutex<Hub>,
+}
+
+impl Broadcast {
+    pub fn new(capacity: usize) -> Broadcast {
+        Broadcast {
+            capacity,
+            hub: Mutex::new(Hub {
+                next_id: 1,
+                history: VecDeque::new(),
+                subscribers: Vec::new(),
+            }),
+        }
+    }
+
+    /// 发送一个事件，事件的 id 被替换成递增的序号，返回这个序号；已经断开的订阅者在这里被移除
+    pub fn send(&self, event: Event) -> u64 {
+        let mut hub = self.hub.lock().unwrap();
+        let id = hub.next_id;
+        hub.next_id += 1;
+        let event = event.with_id(id.to_string());
+        hub.subscribers.retain(|tx| tx.send(event.clone()).is_ok());
+        if self.capacity > 0 {
+            if hub.history.len() == self.capacity {
+                hub.history.pop_front();
+            }
+            hub.history.push_back(event);
+        }
+        id
+    }
+
+    /// 订阅之后的事件；带上 Last-Event-ID 时先补发保留的、在它之后的事件
+    ///
+    /// id 无法识别时补发全部保留的事件，宁可重复也不要漏掉
+    pub fn subscribe(&self, last_event_id: Option<&str>) -> Sse {
+        let mut hub = self.hub.lock().unwrap();
+        let replay = match last_event_id.map(|id| id.trim().parse::<u64>()) {
+            None => Vec::new(),
+            Some(Ok(last)) => hub
+                .history
+                .iter()
+                .filter(|ev| ev.id().and_then(|id| id.parse().ok()) > Some(last))
+                .cloned()
+                .collect(),
+            Some(Err(_)) => hub.history.iter().cloned().collect(),
+        };
+        // 在同一把锁里补发和订阅，两者之间不会漏掉事件
+        let (tx, rx) = mpsc::channel();
+        hub.subscribers.push(tx);
+        Sse::new(rx).replay(replay)
+    }
+
+    /// 当前的订阅者数量，断开的订阅者要到下一次 send 才会被移除
+    pub fn subscribers(&self) -> usize {
+        self.hub.lock().unwrap().subscribers.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodes_fields_and_multiline_data() {
+        let event = Event::new("line 1\r\nline 2\nline 3")
+            .with_event("update\n")
+            .with_id("7")
+            .with_retry(Duration::from_secs(3));
+        assert_eq!(
+            event.encode(),
+            "event: update\nid: 7\nretry: 3000\ndata: line 1\ndata: line 2\ndata: line 3\n\n"
+        );
+        assert_eq!(Event::new("").encode(), "data: \n\n");
+    }
+
+    #[test]
+    fn broadcast_replays_after_last_event_id() {
+        let hub = Broadcast::new(3);
+        for i in 1..=5 {
+            assert_eq!(hub.send(Event::new(format!("n{}", i))), i);
+        }
+        let data = |sse: &Sse| -> Vec<String> {
+            sse.replay.iter().map(|ev| ev.data().to_string()).collect()
+        };
+        // 只保留了 3、4、5
+        assert_eq!(data(&hub.subscribe(Some("3"))), ["n4", "n5"]);
+        assert_eq!(data(&hub.subscribe(Some("1"))), ["n3", "n4", "n5"]);
+        assert_eq!(data(&hub.subscribe(Some("bogus"))), ["n3", "n4", "n5"]);
+        assert!(data(&hub.subscribe(None)).is_empty());
+        assert!(data(&hub.subscribe(Some("5"))).is_empty());
+    }
+
+    #[test]
+    fn broadcast_drops_disconnected_subscribers() {
+        let hub = Broadcast::new(0);
+        let kept = hub.subscribe(None);
+        drop(hub.subscribe(None));
+        assert_eq!(hub.subscribers(), 2);
+        hub.send(Event::new("hi"));
+        assert_eq!(hub.subscribers(), 1);
+        let event = kept.rx.try_recv().unwrap();
+        assert_eq!(event.id(), Some("1"));
+        assert_eq!(event.data(), "hi");
+    }
+}
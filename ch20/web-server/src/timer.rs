@@ -0,0 +1,355 @@
+/*
+定时任务：
+    execute_after 和 execute_every 提交的任务先放进按到期时间排序的最小堆，线程池的定时线程
+    （<name>-timer）等到最早的到期时间，再把到期的任务当作普通任务提交给线程池。
+        一次性任务    到期后提交一次
+        周期任务      每隔 interval 提交一次；上一次还在排队或者还没执行完时跳过这一次，
+                      慢任务不会在队列里越积越多；定时线程被耽搁而错过的周期也直接跳过，不补执行
+
+    每个定时任务有一个 CancelToken，取消之后不会再提交，已经提交的那一次不受影响。
+    线程池关闭时还没到期的定时任务直接丢弃。
+
+    最初的线程池通过 Message::NewJob / Terminate 和工作线程通信，改成工作窃取之后已经没有这个
+    通道了，所以定时任务不经过消息，而是由定时线程在到期时调用和 execute 相同的提交接口。
+*/
+
+use crate::Job;
+use std::{
+    cmp::Ordering as CmpOrdering,
+    collections::BinaryHeap,
+    fmt,
+    sync::{
+        Arc, Condvar, Mutex, PoisonError, Weak,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+/// 取消定时任务的句柄，可以克隆后交给其他线程；丢弃它不会取消任务
+#[derive(Clone)]
+pub struct CancelToken {
+    cancelled: Arc<AtomicBool>,
+    timers: Weak<Timers>,
+}
+
+impl CancelToken {
+    /// 取消任务，之后不会再提交；已经提交的那一次照常执行
+    pub fn cancel(&self) {
+        if !self.cancelled.swap(true, Ordering::SeqCst)
+            && let Some(timers) = self.timers.upgrade()
+        {
+            // 及时释放任务持有的资源，不用等到它原本的到期时间
+            timers.purge();
+        }
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+}
+
+impl fmt::Debug for CancelToken {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("CancelToken")
+            .field("cancelled", &self.is_cancelled())
+            .finish()
+    }
+}
+
+type Periodic = Arc<dyn Fn() + Send + Sync + 'static>;
+
+enum Task {
+    Once(Job),
+    Every {
+        interval: Duration,
+        job: Periodic,
+        /// 上一次提交的任务还没有结束
+        running: Arc<AtomicBool>,
+    },
+}
+
+struct Entry {
+    at: Instant,
+    /// 到期时间相同时先加入的先提交
+    seq: u64,
+    task: Task,
+    cancelled: Arc<AtomicBool>,
+}
+
+impl PartialEq for Entry {
+    fn eq(&self, other: &Entry) -> bool {
+        (self.at, self.seq) == (other.at, other.seq)
+    }
+}
+
+impl Eq for Entry {}
+
+impl PartialOrd for Entry {
+    fn partial_cmp(&self, other: &Entry) -> Option<CmpOrdering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Entry {
+    /// BinaryHeap 是最大堆，反过来比较，最早到期的排在堆顶
+    fn cmp(&self, other: &Entry) -> CmpOrdering {
+        (other.at, other.seq).cmp(&(self.at, self.seq))
+    }
+}
+
+#[derive(Default)]
+struct State {
+    heap: BinaryHeap<Entry>,
+    seq: u64,
+    shutdown: bool,
+}
+
+#[derive(Default)]
+pub(crate) struct Timers {
+    state: Mutex<State>,
+    changed: Condvar,
+}
+
+impl Timers {
+    /// 到期时间超出 Instant 的范围时返回 None
+    pub(crate) fn after(self: &Arc<Timers>, delay: Duration, job: Job) -> Option<CancelToken> {
+        let at = Instant::now().checked_add(delay)?;
+        Some(self.add(at, Task::Once(job)))
+    }
+
+    /// 第一次在 interval 之后提交，到期时间超出 Instant 的范围时返回 None
+    pub(crate) fn every(
+        self: &Arc<Timers>,
+        interval: Duration,
+        job: Periodic,
+    ) -> Option<CancelToken> {
+        let at = Instant::now().checked_add(interval)?;
+        let task = Task::Every {
+            interval,
+            job,
+            running: Arc::new(AtomicBool::new(false)),
+        };
+        Some(self.add(at, task))
+    }
+
+    fn add(self: &Arc<Timers>, at: Instant, task: Task) -> CancelToken {
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let mut state = lock(&self.state);
+        state.seq += 1;
+        // 比原来最早的任务还早时叫醒定时线程，重新计算等待时间
+        let earliest = state.heap.peek().is_none_or(|first| at < first.at);
+        let seq = state.seq;
+        state.heap.push(Entry {
+            at,
+            seq,
+            task,
+            cancelled: Arc::clone(&cancelled),
+        });
+        if earliest {
+            self.changed.notify_one();
+        }
+        CancelToken {
+            cancelled,
+            timers: Arc::downgrade(self),
+        }
+    }
+
+    /// 等待到期的定时任务数
+    pub(crate) fn len(&self) -> usize {
+        lock(&self.state).heap.len()
+    }
+
+    fn purge(&self) {
+        let removed = {
+            let mut state = lock(&self.state);
+            let (kept, removed) = state
+                .heap
+                .drain()
+                .partition(|entry| !entry.cancelled.load(Ordering::SeqCst));
+            state.heap = kept;
+            removed
+        };
+        // 任务在锁外释放，它的 drop 可能做任何事情
+        drop(removed);
+    }
+
+    /// 让定时线程退出，还没到期的任务被丢弃
+    pub(crate) fn shutdown(&self) {
+        let pending = {
+            let mut state = lock(&self.state);
+            state.shutdown = true;
+            std::mem::take(&mut state.heap)
+        };
+        self.changed.notify_all();
+        drop(pending);
+    }
+
+    /// 定时线程的主循环，到期的任务交给 submit，shutdown 之后返回
+    pub(crate) fn run(&self, submit: impl Fn(Job)) {
+        let mut state = lock(&self.state);
+        while !state.shutdown {
+            let now = Instant::now();
+            let mut due = Vec::new();
+            while state.heap.peek().is_some_and(|first| first.at <= now) {
+                let Some(entry) = state.heap.pop() else {
+                    break;
+                };
+                if entry.cancelled.load(Ordering::SeqCst) {
+                    continue;
+                }
+                match entry.task {
+                    Task::Once(job) => due.push(job),
+                    Task::Every {
+                        interval,
+                        job,
+                        running,
+                    } => {
+                        if !running.swap(true, Ordering::SeqCst) {
+                            due.push(periodic_run(Arc::clone(&job), Arc::clone(&running)));
+                        }
+                        let next = match entry.at.checked_add(interval) {
+                            Some(at) if at > now => Some(at),
+                            _ => now.checked_add(interval),
+                        };
+                        // 下一次的时间超出了 Instant 的范围，永远不会到期，不再放回堆里
+                        let Some(at) = next else {
+                            continue;
+                        };
+                        state.heap.push(Entry {
+                            at,
+                            seq: entry.seq,
+                            task: Task::Every {
+                                interval,
+                                job,
+                                running,
+                            },
+                            cancelled: entry.cancelled,
+                        });
+                    }
+                }
+            }
+            if !due.is_empty() {
+                // 提交可能因为队列已满而等待，不能拿着锁
+                drop(state);
+                for job in due {
+                    submit(job);
+                }
+                state = lock(&self.state);
+                continue;
+            }
+            state = match state.heap.peek() {
+                Some(first) => {
+                    let timeout = first.at.saturating_duration_since(now);
+                    self.changed
+                        .wait_timeout(state, timeout)
+                        .unwrap_or_else(PoisonError::into_inner)
+                        .0
+                }
+                None => self
+                    .changed
+                    .wait(state)
+                    .unwrap_or_else(PoisonError::into_inner),
+            };
+        }
+    }
+}
+
+/// 周期任务的一次执行；任务结束、panic 或者没能提交被丢弃时都会清掉 running
+fn periodic_run(job: Periodic, running: Arc<AtomicBool>) -> Job {
+    let guard = Running(running);
+    Box::new(move || {
+        let _guard = guard;
+        job();
+    })
+}
+
+struct Running(Arc<AtomicBool>);
+
+impl Drop for Running {
+    fn drop(&mut self) {
+        self.0.store(false, Ordering::SeqCst);
+    }
+}
+
+/// 堆里的任务 panic 不会发生在锁内，这里统一忽略中毒
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{sync::mpsc, thread};
+
+    fn start() -> (Arc<Timers>, mpsc::Receiver<Job>, thread::JoinHandle<()>) {
+        let timers = Arc::new(Timers::default());
+        let (tx, rx) = mpsc::channel();
+        let runner = Arc::clone(&timers);
+        let jh = thread::spawn(move || runner.run(|job| tx.send(job).unwrap()));
+        (timers, rx, jh)
+    }
+
+    #[test]
+    fn jobs_are_submitted_in_deadline_order() {
+        let (timers, rx, jh) = start();
+        let (tx, order) = mpsc::channel();
+        for (i, ms) in [(0, 60), (1, 20), (2, 40)] {
+            let tx = tx.clone();
+            timers.after(
+                Duration::from_millis(ms),
+                Box::new(move || tx.send(i).unwrap()),
+            );
+        }
+        let started = Instant::now();
+        for _ in 0..3 {
+            rx.recv_timeout(Duration::from_secs(5)).unwrap()();
+        }
+        assert!(started.elapsed() >= Duration::from_millis(55));
+        assert_eq!(order.try_iter().collect::<Vec<_>>(), [1, 2, 0]);
+        assert_eq!(timers.len(), 0);
+        timers.shutdown();
+        jh.join().unwrap();
+    }
+
+    #[test]
+    fn cancelled_jobs_are_released_and_never_submitted() {
+        let (timers, rx, jh) = start();
+        let resource = Arc::new(());
+        let held = Arc::clone(&resource);
+        let token = timers
+            .after(Duration::from_millis(30), Box::new(move || drop(held)))
+            .unwrap();
+        assert_eq!(Arc::strong_count(&resource), 2);
+        token.clone().cancel();
+        assert!(token.is_cancelled());
+        assert_eq!(Arc::strong_count(&resource), 1);
+        assert!(rx.recv_timeout(Duration::from_millis(80)).is_err());
+        timers.shutdown();
+        jh.join().unwrap();
+    }
+
+    #[test]
+    fn periodic_job_skips_ticks_while_still_running() {
+        let (timers, rx, jh) = start();
+        let (tx, ran) = mpsc::channel();
+        let token = timers
+            .every(
+                Duration::from_millis(10),
+                Arc::new(move || tx.send(()).unwrap()),
+            )
+            .unwrap();
+        // 第一次提交的任务还没有执行，后面的周期都被跳过
+        let first = rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(rx.recv_timeout(Duration::from_millis(50)).is_err());
+        first();
+        ran.recv().unwrap();
+        // 执行完之后下一个周期照常提交；没能执行就被丢弃的任务同样不会挡住后面的周期
+        drop(rx.recv_timeout(Duration::from_secs(5)).unwrap());
+        rx.recv_timeout(Duration::from_secs(5)).unwrap()();
+        ran.recv().unwrap();
+        token.cancel();
+        assert_eq!(timers.len(), 0);
+        timers.shutdown();
+        jh.join().unwrap();
+    }
+}
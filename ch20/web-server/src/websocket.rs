@@ -0,0 +1,916 @@
+/*
+WebSocket（RFC 6455）：
+    Endpoint 是一个普通的 Handler，收到升级请求时校验握手并返回 101，
+    服务器写出响应之后把连接交给它，由一个单独的会话线程负责，线程池的工作线程立刻被释放：
+
+        let echo = Endpoint::new(|ws: &WebSocket, msg: Message| {
+            let _ = ws.send(msg);
+        });
+        Router::new().get("/ws/echo", echo)
+
+    会话线程读取帧、拼接分片、回复 ping，把完整的文本或二进制消息交给 WebSocketHandler。
+    会话期间连接仍然占用所属 IP 的名额；同一个 Endpoint 同时存在的会话数有上限，超过时握手回复 503。
+    WebSocket 句柄可以克隆到其他线程上主动推送消息，写入由一把锁串行化。
+    连接空闲超过 ping_interval 时发送一个 ping，再过一个间隔仍然没有收到任何数据就认为对端已经消失。
+    违反协议的帧会让服务器带着相应的关闭码（1002 / 1007 / 1009）关闭连接。
+
+    connect 是一个阻塞的客户端，用于测试和示例。
+*/
+
+use crate::{
+    client::{Client, ClientError, Url, read_response},
+    conn::is_timeout,
+    debug,
+    http::{Limits, Request, Response, Upgrade},
+    middleware::Handler,
+    warn,
+};
+use std::{
+    collections::hash_map::RandomState,
+    fmt,
+    hash::BuildHasher,
+    io::{self, BufRead, BufReader, Cursor, Read, Write},
+    net::{Shutdown, TcpStream},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+    },
+    thread,
+    time::Duration,
+};
+
+/// 握手时拼接在 Sec-WebSocket-Key 之后的固定 GUID
+const GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+const CONTINUATION: u8 = 0x0;
+const TEXT: u8 = 0x1;
+const BINARY: u8 = 0x2;
+const CLOSE: u8 = 0x8;
+const PING: u8 = 0x9;
+const PONG: u8 = 0xA;
+
+/// 关闭码
+pub const NORMAL_CLOSURE: u16 = 1000;
+pub const GOING_AWAY: u16 = 1001;
+pub const PROTOCOL_ERROR: u16 = 1002;
+pub const INVALID_DATA: u16 = 1007;
+pub const MESSAGE_TOO_BIG: u16 = 1009;
+
+/// 会话线程写一帧数据的最长时间，防止不读数据的客户端让发送方一直卡在锁里
+const WRITE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// 关闭之后最多等待对端多久
+const LINGER: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Message {
+    Text(String),
+    Binary(Vec<u8>),
+}
+
+impl Message {
+    fn opcode(&self) -> u8 {
+        match self {
+            Message::Text(_) => TEXT,
+            Message::Binary(_) => BINARY,
+        }
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        match self {
+            Message::Text(text) => text.as_bytes(),
+            Message::Binary(data) => data,
+        }
+    }
+}
+
+impl From<String> for Message {
+    fn from(text: String) -> Message {
+        Message::Text(text)
+    }
+}
+
+impl From<&str> for Message {
+    fn from(text: &str) -> Message {
+        Message::Text(text.to_string())
+    }
+}
+
+impl From<Vec<u8>> for Message {
+    fn from(data: Vec<u8>) -> Message {
+        Message::Binary(data)
+    }
+}
+
+/// 会话中的事件回调，都在该会话的线程上执行
+pub trait WebSocketHandler: Send + Sync + 'static {
+    fn on_open(&self, _ws: &WebSocket) {}
+
+    fn on_message(&self, ws: &WebSocket, msg: Message);
+
+    /// code 是对端发来的关闭码，或者因为协议错误由这一端关闭时使用的关闭码；连接直接断开时为 None
+    fn on_close(&self, _ws: &WebSocket, _code: Option<u16>) {}
+}
+
+impl<F> WebSocketHandler for F
+where
+    F: Fn(&WebSocket, Message) + Send + Sync + 'static,
+{
+    fn on_message(&self, ws: &WebSocket, msg: Message) {
+        self(ws, msg)
+    }
+}
+
+struct Session {
+    writer: Mutex<TcpStream>,
+    /// 已经发出关闭帧，之后不再发送数据
+    closing: AtomicBool,
+    /// 握手请求，不包含请求体
+    request: Request,
+}
+
+/// 服务端会话的句柄，克隆之后可以在其他线程上发送消息
+#[derive(Clone)]
+pub struct WebSocket {
+    session: Arc<Session>,
+}
+
+impl WebSocket {
+    pub fn request(&self) -> &Request {
+        &self.session.request
+    }
+
+    pub fn send(&self, msg: impl Into<Message>) -> io::Result<()> {
+        let msg = msg.into();
+        self.send_frame(msg.opcode(), msg.as_bytes())
+    }
+
+    /// 发送关闭帧，会话在对端回复关闭帧之后结束，重复调用没有效果
+    pub fn close(&self, code: u16, reason: &str) -> io::Result<()> {
+        let mut writer = self.session.writer.lock().unwrap();
+        if self.session.closing.swap(true, Ordering::SeqCst) {
+            return Ok(());
+        }
+        write_frame(&mut *writer, CLOSE, &close_payload(code, reason), None)
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.session.closing.load(Ordering::SeqCst)
+    }
+
+    fn send_frame(&self, opcode: u8, payload: &[u8]) -> io::Result<()> {
+        let mut writer = self.session.writer.lock().unwrap();
+        if self.is_closed() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotConnected,
+                "websocket is closed",
+            ));
+        }
+        write_frame(&mut *writer, opcode, payload, None)
+    }
+}
+
+impl fmt::Debug for WebSocket {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("WebSocket")
+            .field("path", &self.session.request.path)
+            .field("closed", &self.is_closed())
+            .finish()
+    }
+}
+
+/// 接受 WebSocket 升级请求的 Handler
+#[derive(Clone)]
+pub struct Endpoint {
+    handler: Arc<dyn WebSocketHandler>,
+    max_message_bytes: usize,
+    ping_interval: Option<Duration>,
+    max_sessions: usize,
+    /// 当前的会话数，所有克隆共享
+    sessions: Arc<AtomicUsize>,
+}
+
+/// 占用一个会话名额，丢弃时归还
+struct SessionSlot(Arc<AtomicUsize>);
+
+impl Drop for SessionSlot {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl Endpoint {
+    pub fn new<H: WebSocketHandler>(handler: H) -> Endpoint {
+        Endpoint {
+            handler: Arc::new(handler),
+            max_message_bytes: 1024 * 1024,
+            ping_interval: Some(Duration::from_secs(30)),
+            max_sessions: 1024,
+            sessions: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    /// 一条消息（所有分片加起来）的最大字节数，超过时以 1009 关闭连接
+    pub fn max_message_bytes(mut self, max: usize) -> Endpoint {
+        self.max_message_bytes = max;
+        self
+    }
+
+    /// 空闲多久发送一次 ping，None 表示不检测空闲连接
+    pub fn ping_interval(mut self, interval: Option<Duration>) -> Endpoint {
+        self.ping_interval = interval;
+        self
+    }
+
+    /// 同时存在的会话数上限（默认 1024），每个会话占用一个线程，超过时握手回复 503
+    pub fn max_sessions(mut self, max: usize) -> Endpoint {
+        self.max_sessions = max;
+        self
+    }
+
+    /// 当前的会话数
+    pub fn sessions(&self) -> usize {
+        self.sessions.load(Ordering::SeqCst)
+    }
+
+    fn try_open(&self) -> Option<SessionSlot> {
+        self.sessions
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| {
+                (n < self.max_sessions).then_some(n + 1)
+            })
+            .ok()?;
+        Some(SessionSlot(Arc::clone(&self.sessions)))
+    }
+
+    fn serve(&self, stream: TcpStream, buffered: Vec<u8>, request: Request) -> io::Result<()> {
+        stream.set_read_timeout(self.ping_interval)?;
+        stream.set_write_timeout(Some(WRITE_TIMEOUT))?;
+        let ws = WebSocket {
+            session: Arc::new(Session {
+                writer: Mutex::new(stream.try_clone()?),
+                closing: AtomicBool::new(false),
+                request,
+            }),
+        };
+        let reader = BufReader::new(Cursor::new(buffered).chain(stream));
+        let mut frames = Frames::new(reader, true, self.max_message_bytes);
+
+        self.handler.on_open(&ws);
+        let mut awaiting_pong = false;
+        let code = loop {
+            // 在帧与帧之间等待数据，读超时说明连接空闲
+            match frames.reader.fill_buf() {
+                Ok([]) => break None,
+                Ok(_) => awaiting_pong = false,
+                Err(err) if is_timeout(&err) && !awaiting_pong => {
+                    awaiting_pong = true;
+                    if ws.send_frame(PING, b"").is_err() {
+                        break None;
+                    }
+                    continue;
+                }
+                Err(_) => break None,
+            }
+            match frames.next() {
+                Ok(Some(Event::Message(msg))) => self.handler.on_message(&ws, msg),
+                Ok(Some(Event::Ping(data))) => {
+                    let _ = ws.send_frame(PONG, &data);
+                }
+                Ok(Some(Event::Close(code))) => {
+                    let _ = ws.close(code.unwrap_or(NORMAL_CLOSURE), "");
+                    break code;
+                }
+                Ok(Some(Event::Pong) | None) => {}
+                Err(err) => {
+                    debug!("websocket {}: {}", ws.request().path, err);
+                    let Some(code) = err.close_code() else {
+                        break None;
+                    };
+                    let _ = ws.close(code, &err.to_string());
+                    break Some(code);
+                }
+            }
+        };
+        self.handler.on_close(&ws, code);
+        ws.session.closing.store(true, Ordering::SeqCst);
+        // 直接关闭还有未读数据的 socket 会发出 RST，对端可能来不及读到关闭帧，
+        // 所以先关闭写方向，再把对端剩下的数据读完
+        let _ = ws.session.writer.lock().unwrap().shutdown(Shutdown::Write);
+        frames
+            .reader
+            .get_ref()
+            .get_ref()
+            .1
+            .set_read_timeout(Some(LINGER))?;
+        let _ = io::copy(&mut frames.reader, &mut io::sink());
+        Ok(())
+    }
+}
+
+impl Handler for Endpoint {
+    fn handle(&self, req: &mut Request) -> Response {
+        let upgrade = req.headers.has_token("Connection", "upgrade")
+            && req.headers.has_token("Upgrade", "websocket");
+        if !upgrade {
+            return Response::error(426)
+                .with_header("Upgrade", "websocket")
+                .with_header("Connection", "Upgrade");
+        }
+        if req.header("Sec-WebSocket-Version") != Some("13") {
+            return Response::error(426).with_header("Sec-WebSocket-Version", "13");
+        }
+        let key = match req.header("Sec-WebSocket-Key") {
+            Some(key) if valid_key(key) => key,
+            _ => return Response::error(400),
+        };
+
+        // 名额在握手时占用，连接没有交出去时随 Upgrade 一起丢弃
+        let Some(slot) = self.try_open() else {
+            warn!("too many websocket sessions on {}", req.path);
+            return Response::error(503);
+        };
+        let accept = accept_key(key);
+        let endpoint = self.clone();
+        let mut request = req.clone();
+        request.body = Vec::new();
+        Response::new(101)
+            .with_header("Upgrade", "websocket")
+            .with_header("Connection", "Upgrade")
+            .with_header("Sec-WebSocket-Accept", accept)
+            .with_upgrade(Upgrade::new(move |stream, buffered, guard| {
+                let spawned = thread::Builder::new()
+                    .name(String::from("websocket"))
+                    .spawn(move || {
+                        if let Err(err) = endpoint.serve(stream, buffered, request) {
+                            debug!("websocket session error: {}", err);
+                        }
+                        drop((guard, slot));
+                    });
+                if let Err(err) = spawned {
+                    warn!("cannot start websocket session: {}", err);
+                }
+            }))
+    }
+}
+
+/// 阻塞的客户端连接，发送的帧都带掩码
+pub struct ClientSocket {
+    writer: TcpStream,
+    frames: Frames<BufReader<TcpStream>>,
+    closing: bool,
+    close_code: Option<u16>,
+}
+
+/// 连接 ws:// 地址并完成握手
+pub fn connect(url: &str) -> Result<ClientSocket, ClientError> {
+    let http = match url.strip_prefix("ws://") {
+        Some(rest) => format!("http://{}", rest),
+        None => return Err(ClientError::InvalidUrl(format!("expected ws://: {}", url))),
+    };
+    let url = Url::parse(&http)?;
+    let mut stream = Client::new().connect(&url)?;
+
+    let mut nonce = [0; 16];
+    nonce[..8].copy_from_slice(&random().to_be_bytes());
+    nonce[8..].copy_from_slice(&random().to_be_bytes());
+    let key = base64_encode(&nonce);
+    write!(
+        stream,
+        "GET {} HTTP/1.1\r\nHost: {}\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+         Sec-WebSocket-Key: {}\r\nSec-WebSocket-Version: 13\r\n\r\n",
+        url.target, url.authority, key
+    )?;
+
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let (resp, _) = read_response(&mut reader, "GET", &Limits::default())?;
+    if resp.status != 101 {
+        return Err(ClientError::Protocol(
+            "server refused the websocket upgrade",
+        ));
+    }
+    if resp.header("Sec-WebSocket-Accept") != Some(accept_key(&key).as_str()) {
+        return Err(ClientError::Protocol("invalid Sec-WebSocket-Accept"));
+    }
+    Ok(ClientSocket {
+        writer: stream,
+        frames: Frames::new(reader, false, 64 * 1024 * 1024),
+        closing: false,
+        close_code: None,
+    })
+}
+
+impl ClientSocket {
+    pub fn send(&mut self, msg: impl Into<Message>) -> io::Result<()> {
+        let msg = msg.into();
+        write_frame(&mut self.writer, msg.opcode(), msg.as_bytes(), Some(mask()))
+    }
+
+    /// 读取下一条消息，期间自动回复 ping；收到关闭帧时返回 None
+    pub fn recv(&mut self) -> io::Result<Option<Message>> {
+        loop {
+            match self.frames.next().map_err(io::Error::from)? {
+                Some(Event::Message(msg)) => return Ok(Some(msg)),
+                Some(Event::Ping(data)) => {
+                    write_frame(&mut self.writer, PONG, &data, Some(mask()))?;
+                }
+                Some(Event::Close(code)) => {
+                    self.close_code = code;
+                    if !self.closing {
+                        self.closing = true;
+                        let payload = code.map_or(Vec::new(), |code| close_payload(code, ""));
+                        let _ = write_frame(&mut self.writer, CLOSE, &payload, Some(mask()));
+                    }
+                    return Ok(None);
+                }
+                Some(Event::Pong) | None => {}
+            }
+        }
+    }
+
+    /// 发送关闭帧并等待对端的关闭帧，期间收到的消息被丢弃
+    pub fn close(&mut self, code: u16, reason: &str) -> io::Result<()> {
+        if !self.closing {
+            self.closing = true;
+            let payload = close_payload(code, reason);
+            write_frame(&mut self.writer, CLOSE, &payload, Some(mask()))?;
+        }
+        while self.recv()?.is_some() {}
+        Ok(())
+    }
+
+    /// 对端关闭帧中的关闭码
+    pub fn close_code(&self) -> Option<u16> {
+        self.close_code
+    }
+
+    pub fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.writer.set_read_timeout(timeout)
+    }
+
+    /// 底层的连接，测试里可以用它发送不合规的帧
+    pub fn get_ref(&self) -> &TcpStream {
+        &self.writer
+    }
+}
+
+#[derive(Debug)]
+enum FrameError {
+    Io(io::Error),
+    Protocol(&'static str),
+    TooBig,
+    InvalidUtf8,
+}
+
+impl FrameError {
+    /// 关闭连接时使用的关闭码，I/O 错误时连接已经不可用，不再发送关闭帧
+    fn close_code(&self) -> Option<u16> {
+        match self {
+            FrameError::Io(_) => None,
+            FrameError::Protocol(_) => Some(PROTOCOL_ERROR),
+            FrameError::TooBig => Some(MESSAGE_TOO_BIG),
+            FrameError::InvalidUtf8 => Some(INVALID_DATA),
+        }
+    }
+}
+
+impl fmt::Display for FrameError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FrameError::Io(err) => write!(f, "{}", err),
+            FrameError::Protocol(msg) => write!(f, "{}", msg),
+            FrameError::TooBig => write!(f, "message too big"),
+            FrameError::InvalidUtf8 => write!(f, "text is not utf-8"),
+        }
+    }
+}
+
+impl From<io::Error> for FrameError {
+    fn from(err: io::Error) -> FrameError {
+        FrameError::Io(err)
+    }
+}
+
+impl From<FrameError> for io::Error {
+    fn from(err: FrameError) -> io::Error {
+        match err {
+            FrameError::Io(err) => err,
+            other => io::Error::new(io::ErrorKind::InvalidData, other.to_string()),
+        }
+    }
+}
+
+struct Frame {
+    fin: bool,
+    opcode: u8,
+    payload: Vec<u8>,
+}
+
+enum Event {
+    Message(Message),
+    Ping(Vec<u8>),
+    Pong,
+    Close(Option<u16>),
+}
+
+/// 一次读一帧，把分片拼成完整的消息，分片之间可以穿插控制帧
+struct Frames<R> {
+    reader: R,
+    /// 期望对端的帧带掩码（服务端读取客户端的帧）
+    masked: bool,
+    max_message: usize,
+    /// 还没有收齐的分片消息
+    partial: Option<(u8, Vec<u8>)>,
+}
+
+impl<R: Read> Frames<R> {
+    fn new(reader: R, masked: bool, max_message: usize) -> Frames<R> {
+        Frames {
+            reader,
+            masked,
+            max_message,
+            partial: None,
+        }
+    }
+
+    /// 读取一帧，分片消息还没有结束时返回 None
+    fn next(&mut self) -> Result<Option<Event>, FrameError> {
+        let received = self.partial.as_ref().map_or(0, |(_, buf)| buf.len());
+        let frame = read_frame(&mut self.reader, self.max_message - received, self.masked)?;
+        match frame.opcode {
+            PING => Ok(Some(Event::Ping(frame.payload))),
+            PONG => Ok(Some(Event::Pong)),
+            CLOSE => parse_close(&frame.payload).map(|code| Some(Event::Close(code))),
+            TEXT | BINARY if self.partial.is_some() => {
+                Err(FrameError::Protocol("expected a continuation frame"))
+            }
+            TEXT | BINARY if frame.fin => message(frame.opcode, frame.payload).map(Some),
+            TEXT | BINARY => {
+                self.partial = Some((frame.opcode, frame.payload));
+                Ok(None)
+            }
+            CONTINUATION => {
+                let Some((_, buf)) = &mut self.partial else {
+                    return Err(FrameError::Protocol("unexpected continuation frame"));
+                };
+                buf.extend_from_slice(&frame.payload);
+                if !frame.fin {
+                    return Ok(None);
+                }
+                let (opcode, buf) = self.partial.take().unwrap();
+                message(opcode, buf).map(Some)
+            }
+            _ => Err(FrameError::Protocol("unknown opcode")),
+        }
+    }
+}
+
+fn message(opcode: u8, payload: Vec<u8>) -> Result<Event, FrameError> {
+    let msg = if opcode == TEXT {
+        Message::Text(String::from_utf8(payload).map_err(|_| FrameError::InvalidUtf8)?)
+    } else {
+        Message::Binary(payload)
+    };
+    Ok(Event::Message(msg))
+}
+
+/// 关闭帧的内容是可选的两字节关闭码加 UTF-8 的原因
+///
+/// 关闭码只能是 RFC 6455 §7.4 定义的可以出现在帧里的值（1000-1003、1007-1014），
+/// 或者留给库和应用使用的 3000-4999；1004-1006、1015 等保留值和未分配的值都算违反协议
+fn parse_close(payload: &[u8]) -> Result<Option<u16>, FrameError> {
+    match payload {
+        [] => Ok(None),
+        [_] => Err(FrameError::Protocol("truncated close code")),
+        [hi, lo, reason @ ..] => {
+            let code = u16::from_be_bytes([*hi, *lo]);
+            if !matches!(code, 1000..=1003 | 1007..=1014 | 3000..=4999) {
+                return Err(FrameError::Protocol("invalid close code"));
+            }
+            std::str::from_utf8(reason).map_err(|_| FrameError::InvalidUtf8)?;
+            Ok(Some(code))
+        }
+    }
+}
+
+fn close_payload(code: u16, reason: &str) -> Vec<u8> {
+    // 控制帧的内容最多 125 字节
+    let mut end = reason.len().min(123);
+    while !reason.is_char_boundary(end) {
+        end -= 1;
+    }
+    let mut payload = code.to_be_bytes().to_vec();
+    payload.extend_from_slice(&reason.as_bytes()[..end]);
+    payload
+}
+
+/// 读取一帧，max_payload 只限制数据帧，控制帧本身不超过 125 字节
+fn read_frame<R: Read>(r: &mut R, max_payload: usize, masked: bool) -> Result<Frame, FrameError> {
+    let mut head = [0; 2];
+    r.read_exact(&mut head)?;
+    if head[0] & 0x70 != 0 {
+        return Err(FrameError::Protocol("reserved bits are set"));
+    }
+    let fin = head[0] & 0x80 != 0;
+    let opcode = head[0] & 0x0F;
+    if (head[1] & 0x80 != 0) != masked {
+        return Err(FrameError::Protocol(if masked {
+            "client frames must be masked"
+        } else {
+            "server frames must not be masked"
+        }));
+    }
+    let len = match head[1] & 0x7F {
+        126 => {
+            let mut buf = [0; 2];
+            r.read_exact(&mut buf)?;
+            u64::from(u16::from_be_bytes(buf))
+        }
+        127 => {
+            let mut buf = [0; 8];
+            r.read_exact(&mut buf)?;
+            u64::from_be_bytes(buf)
+        }
+        n => u64::from(n),
+    };
+    if opcode >= CLOSE {
+        if !fin || len > 125 {
+            return Err(FrameError::Protocol("invalid control frame"));
+        }
+    } else if len > max_payload as u64 {
+        return Err(FrameError::TooBig);
+    }
+    let mut key = [0; 4];
+    if masked {
+        r.read_exact(&mut key)?;
+    }
+    let mut payload = vec![0; len as usize];
+    r.read_exact(&mut payload)?;
+    apply_mask(&mut payload, key);
+    Ok(Frame {
+        fin,
+        opcode,
+        payload,
+    })
+}
+
+/// 写一个完整（不分片）的帧，客户端发送的帧必须带掩码
+fn write_frame<W: Write>(
+    w: &mut W,
+    opcode: u8,
+    payload: &[u8],
+    mask: Option<[u8; 4]>,
+) -> io::Result<()> {
+    let mut buf = Vec::with_capacity(payload.len() + 14);
+    buf.push(0x80 | opcode);
+    let mask_bit = if mask.is_some() { 0x80 } else { 0 };
+    match payload.len() {
+        n if n < 126 => buf.push(mask_bit | n as u8),
+        n if n <= usize::from(u16::MAX) => {
+            buf.push(mask_bit | 126);
+            buf.extend_from_slice(&(n as u16).to_be_bytes());
+        }
+        n => {
+            buf.push(mask_bit | 127);
+            buf.extend_from_slice(&(n as u64).to_be_bytes());
+        }
+    }
+    let start = buf.len();
+    match mask {
+        Some(key) => {
+            buf.extend_from_slice(&key);
+            buf.extend_from_slice(payload);
+            apply_mask(&mut buf[start + 4..], key);
+        }
+        None => buf.extend_from_slice(payload),
+    }
+    w.write_all(&buf)?;
+    w.flush()
+}
+
+fn apply_mask(data: &mut [u8], key: [u8; 4]) {
+    for (i, b) in data.iter_mut().enumerate() {
+        *b ^= key[i % 4];
+    }
+}
+
+/// 每次新建的 RandomState 种子都不同，用来生成掩码和握手的随机数已经足够
+fn random() -> u64 {
+    RandomState::new().hash_one(())
+}
+
+fn mask() -> [u8; 4] {
+    (random() as u32).to_be_bytes()
+}
+
+/// Sec-WebSocket-Accept 的值：base64(sha1(key + GUID))
+pub fn accept_key(key: &str) -> String {
+    base64_encode(&sha1(format!("{}{}", key, GUID).as_bytes()))
+}
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+fn base64_encode(data: &[u8]) -> String {
+    let mut out = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let mut bytes = [0; 3];
+        bytes[..chunk.len()].copy_from_slice(chunk);
+        let n = u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]);
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(BASE64[(n >> (18 - 6 * i)) as usize & 63] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+/// Sec-WebSocket-Key 必须是 16 个字节的 base64 编码
+fn valid_key(key: &str) -> bool {
+    key.len() == 24 && key.ends_with("==") && key[..22].bytes().all(|b| BASE64.contains(&b))
+}
+
+fn sha1(data: &[u8]) -> [u8; 20] {
+    let mut h: [u32; 5] = [0x67452301, 0xEFCDAB89, 0x98BADCFE, 0x10325476, 0xC3D2E1F0];
+    let mut msg = data.to_vec();
+    msg.push(0x80);
+    while msg.len() % 64 != 56 {
+        msg.push(0);
+    }
+    msg.extend_from_slice(&(data.len() as u64 * 8).to_be_bytes());
+
+    for block in msg.chunks(64) {
+        let mut w = [0u32; 80];
+        for (word, bytes) in w.iter_mut().zip(block.chunks(4)) {
+            *word = u32::from_be_bytes(bytes.try_into().unwrap());
+        }
+        for i in 16..80 {
+            w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+        }
+        let [mut a, mut b, mut c, mut d, mut e] = h;
+        for (i, &word) in w.iter().enumerate() {
+            let (f, k) = match i {
+                0..20 => ((b & c) | (!b & d), 0x5A827999),
+                20..40 => (b ^ c ^ d, 0x6ED9EBA1),
+                40..60 => ((b & c) | (b & d) | (c & d), 0x8F1BBCDC),
+                _ => (b ^ c ^ d, 0xCA62C1D6),
+            };
+            let t = a
+                .rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(word);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = t;
+        }
+        for (h, v) in h.iter_mut().zip([a, b, c, d, e]) {
+            *h = h.wrapping_add(v);
+        }
+    }
+
+    let mut out = [0; 20];
+    for (bytes, v) in out.chunks_mut(4).zip(h) {
+        bytes.copy_from_slice(&v.to_be_bytes());
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    /// 客户端发给服务器的帧：带掩码，可以指定是否为最后一个分片
+    fn client_frame(fin: bool, opcode: u8, payload: &[u8]) -> Vec<u8> {
+        let mut buf = Vec::new();
+        write_frame(&mut buf, opcode, payload, Some([1, 2, 3, 4])).unwrap();
+        if !fin {
+            buf[0] &= 0x7F;
+        }
+        buf
+    }
+
+    fn server_frames(raw: Vec<u8>, max: usize) -> Frames<Cursor<Vec<u8>>> {
+        Frames::new(Cursor::new(raw), true, max)
+    }
+
+    #[test]
+    fn handshake_hashes() {
+        assert_eq!(
+            hex(&sha1(b"abc")),
+            "a9993e364706816aba3e25717850c26c9cd0d89d"
+        );
+        assert_eq!(hex(&sha1(&[b'a'; 1000])).len(), 40);
+        assert_eq!(base64_encode(b"fo"), "Zm8=");
+        assert_eq!(base64_encode(b"foobar"), "Zm9vYmFy");
+        // RFC 6455 第 1.3 节的例子
+        assert_eq!(
+            accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+        assert!(valid_key("dGhlIHNhbXBsZSBub25jZQ=="));
+        assert!(!valid_key("short=="));
+    }
+
+    #[test]
+    fn frames_round_trip_at_every_length_encoding() {
+        for len in [0, 125, 126, 65535, 65536] {
+            let payload = vec![b'x'; len];
+            let mut buf = Vec::new();
+            write_frame(&mut buf, BINARY, &payload, Some(mask())).unwrap();
+            let frame = read_frame(&mut buf.as_slice(), usize::MAX, true).unwrap();
+            assert!(frame.fin);
+            assert_eq!(frame.opcode, BINARY);
+            assert_eq!(frame.payload, payload);
+        }
+    }
+
+    #[test]
+    fn fragments_are_joined_around_control_frames() {
+        let mut raw = client_frame(false, TEXT, b"hel");
+        raw.extend(client_frame(true, PING, b"p"));
+        raw.extend(client_frame(false, CONTINUATION, b"lo "));
+        raw.extend(client_frame(true, CONTINUATION, "世界".as_bytes()));
+        raw.extend(client_frame(
+            true,
+            CLOSE,
+            &close_payload(NORMAL_CLOSURE, "bye"),
+        ));
+        let mut frames = server_frames(raw, 1024);
+        assert!(frames.next().unwrap().is_none());
+        assert!(matches!(frames.next().unwrap(), Some(Event::Ping(p)) if p == b"p"));
+        assert!(frames.next().unwrap().is_none());
+        assert!(matches!(
+            frames.next().unwrap(),
+            Some(Event::Message(Message::Text(text))) if text == "hello 世界"
+        ));
+        assert!(matches!(
+            frames.next().unwrap(),
+            Some(Event::Close(Some(NORMAL_CLOSURE)))
+        ));
+    }
+
+    #[test]
+    fn protocol_violations_map_to_close_codes() {
+        let code = |raw: Vec<u8>, max: usize| {
+            let mut frames = server_frames(raw, max);
+            loop {
+                match frames.next() {
+                    Ok(_) => continue,
+                    Err(err) => return err.close_code(),
+                }
+            }
+        };
+        // 没有掩码的客户端帧
+        let mut unmasked = Vec::new();
+        write_frame(&mut unmasked, TEXT, b"hi", None).unwrap();
+        assert_eq!(code(unmasked, 1024), Some(PROTOCOL_ERROR));
+        assert_eq!(
+            code(client_frame(true, CONTINUATION, b"x"), 1024),
+            Some(PROTOCOL_ERROR)
+        );
+        assert_eq!(
+            code(client_frame(false, PING, b""), 1024),
+            Some(PROTOCOL_ERROR)
+        );
+        let mut interleaved = client_frame(false, TEXT, b"a");
+        interleaved.extend(client_frame(true, BINARY, b"b"));
+        assert_eq!(code(interleaved, 1024), Some(PROTOCOL_ERROR));
+        assert_eq!(
+            code(client_frame(true, TEXT, &[0xff, 0xfe]), 1024),
+            Some(INVALID_DATA)
+        );
+        // 单个分片没有超过限制，拼起来超过了
+        let mut big = client_frame(false, BINARY, &[0; 6]);
+        big.extend(client_frame(true, CONTINUATION, &[0; 6]));
+        assert_eq!(code(big, 10), Some(MESSAGE_TOO_BIG));
+        // 数据读完是 I/O 错误，不需要关闭码
+        assert_eq!(code(Vec::new(), 1024), None);
+    }
+
+    #[test]
+    fn close_reason_is_truncated_on_a_char_boundary() {
+        let payload = close_payload(GOING_AWAY, &"断".repeat(100));
+        assert!(payload.len() <= 125);
+        assert_eq!(parse_close(&payload).unwrap(), Some(GOING_AWAY));
+    }
+
+    #[test]
+    fn reserved_close_codes_are_rejected() {
+        for code in [1000, 1003, 1007, 1014, 3000, 4999] {
+            assert_eq!(parse_close(&close_payload(code, "")).unwrap(), Some(code));
+        }
+        for code in [0, 999, 1004, 1005, 1006, 1015, 1016, 2999, 5000, u16::MAX] {
+            let err = parse_close(&close_payload(code, "")).unwrap_err();
+            assert_eq!(err.close_code(), Some(PROTOCOL_ERROR), "{}", code);
+        }
+    }
+}
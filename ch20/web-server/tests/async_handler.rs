@@ -0,0 +1,150 @@
+#![cfg(target_os = "linux")]
+
+use std::{
+    io::{Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    thread,
+    time::{Duration, Instant},
+};
+use web_server::{
+    Config, Request, Response, Server,
+    access_log::LogTarget,
+    log::Level,
+    runtime::{self, AsyncTcpStream},
+    server::Backend,
+};
+mod common;
+
+use common::read_response;
+
+fn status_line(resp: &[u8]) -> String {
+    let resp = String::from_utf8_lossy(resp);
+    resp.lines().next().unwrap_or("").to_string()
+}
+
+fn body(resp: &[u8]) -> String {
+    let resp = String::from_utf8_lossy(resp);
+    resp.split_once("\r\n\r\n").unwrap().1.to_string()
+}
+
+/// 一个只会回复固定内容的上游服务，用来测试异步的 TCP 读写
+fn upstream() -> SocketAddr {
+    let ln = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = ln.local_addr().unwrap();
+    thread::spawn(move || {
+        for mut stream in ln.incoming().flatten() {
+            let mut buf = [0; 64];
+            let n = stream.read(&mut buf).unwrap_or(0);
+            let _ = stream.write_all(b"echo: ");
+            let _ = stream.write_all(&buf[..n]);
+        }
+    });
+    addr
+}
+
+/// 在当前进程中启动一个只有一个工作线程的 epoll 服务器
+fn start() -> SocketAddr {
+    let upstream = upstream();
+    let config = Config {
+        binds: vec![SocketAddr::from(([127, 0, 0, 1], 0))],
+        workers: 1,
+        queue_capacity: 1,
+        backend: Backend::Epoll,
+        log_level: Level::Off,
+        access_log: LogTarget::Off,
+        ..Config::default()
+    };
+    let server = Server::bind(config)
+        .unwrap()
+        .async_handler(move |req: Request| async move {
+            match req.path.as_str() {
+                "/sleep" => {
+                    runtime::sleep(Duration::from_millis(300)).await;
+                    Response::text(200, "slept")
+                }
+                "/blocking" => {
+                    let n = runtime::spawn_blocking(|| 6 * 7).await.unwrap();
+                    Response::text(200, n.to_string())
+                }
+                "/hold" => {
+                    let held =
+                        runtime::spawn_blocking(|| thread::sleep(Duration::from_millis(300)));
+                    match held.await {
+                        Ok(()) => Response::text(200, "held"),
+                        Err(_) => Response::error(503),
+                    }
+                }
+                "/upstream" => {
+                    let mut stream = AsyncTcpStream::connect(upstream).await.unwrap();
+                    stream.write_all(&req.body).await.unwrap();
+                    let mut reply = Vec::new();
+                    stream.read_to_end(&mut reply).await.unwrap();
+                    Response::text(200, reply)
+                }
+                "/panic" => panic!("handler bug"),
+                _ => Response::error(404),
+            }
+        });
+    let addr = server.local_addrs()[0];
+    thread::spawn(move || server.run());
+    addr
+}
+
+fn get(addr: SocketAddr, raw: &[u8]) -> Vec<u8> {
+    let mut stream = TcpStream::connect(addr).unwrap();
+    stream.write_all(raw).unwrap();
+    read_response(&mut stream)
+}
+
+#[test]
+fn sleeping_handlers_do_not_hold_workers() {
+    let addr = start();
+    let started = Instant::now();
+    // 只有一个工作线程，同步地睡 300ms 的话 10 个请求至少需要 3 秒
+    let clients: Vec<_> = (0..10)
+        .map(|_| thread::spawn(move || get(addr, b"GET /sleep HTTP/1.1\r\n\r\n")))
+        .collect();
+    for client in clients {
+        assert_eq!(body(&client.join().unwrap()), "slept");
+    }
+    assert!(started.elapsed() < Duration::from_millis(1500));
+}
+
+#[test]
+fn blocking_work_and_async_io() {
+    let addr = start();
+    assert_eq!(body(&get(addr, b"GET /blocking HTTP/1.1\r\n\r\n")), "42");
+    let resp = get(
+        addr,
+        b"POST /upstream HTTP/1.1\r\nContent-Length: 5\r\n\r\nhello",
+    );
+    assert_eq!(body(&resp), "echo: hello");
+}
+
+#[test]
+fn full_pool_fails_spawn_blocking_instead_of_panicking() {
+    let addr = start();
+    // 一个工作线程加一个排队的位置，同时发出的第三个请求一定放不进线程池
+    let clients: Vec<_> = (0..3)
+        .map(|_| thread::spawn(move || get(addr, b"GET /hold HTTP/1.1\r\n\r\n")))
+        .collect();
+    let mut statuses: Vec<_> = clients
+        .into_iter()
+        .map(|client| status_line(&client.join().unwrap()))
+        .collect();
+    statuses.sort();
+    assert_eq!(statuses[0], "HTTP/1.1 200 OK");
+    assert_eq!(statuses[2], "HTTP/1.1 503 Service Unavailable");
+}
+
+#[test]
+fn panicking_handler_returns_500() {
+    let addr = start();
+    let resp = get(addr, b"GET /panic HTTP/1.1\r\n\r\n");
+    assert_eq!(status_line(&resp), "HTTP/1.1 500 Internal Server Error");
+    // 事件循环还活着
+    assert_eq!(
+        status_line(&get(addr, b"GET /nope HTTP/1.1\r\n\r\n")),
+        "HTTP/1.1 404 Not Found"
+    );
+}
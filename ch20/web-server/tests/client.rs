@@ -0,0 +1,134 @@
+use std::{
+    io::{BufReader, Write},
+    net::TcpStream,
+    sync::atomic::Ordering,
+    thread,
+    time::{Duration, Instant},
+};
+use web_server::{
+    Request, Response,
+    client::{Client, ClientError},
+    http::Limits,
+};
+mod common;
+
+use common::{ServerProcess, stand_in};
+
+/// 在一个连接上持续回显请求体，close 为 true 时每个响应之后直接断开（但不声明 Connection: close）
+fn echo(stream: TcpStream, close: bool) {
+    let mut writer = stream.try_clone().unwrap();
+    let mut reader = BufReader::new(stream);
+    while let Ok(req) = Request::read_from(&mut reader, &Limits::default()) {
+        let body = format!(
+            "{} {} {}",
+            req.method,
+            req.target,
+            String::from_utf8_lossy(&req.body)
+        );
+        if Response::text(200, body).write_to(&mut writer).is_err() || close {
+            return;
+        }
+    }
+}
+
+#[test]
+fn drives_the_real_server_over_one_connection() {
+    let server = ServerProcess::start(&[]);
+    let client = Client::new();
+    for _ in 0..3 {
+        let resp = client.get(&server.url("/")).send().unwrap();
+        assert_eq!(resp.status, 200);
+        assert_eq!(client.idle_connections(), 1);
+    }
+    let resp = client.request("DELETE", &server.url("/")).send().unwrap();
+    assert_eq!(resp.status, 405);
+    assert_eq!(resp.header("Allow"), Some("GET"));
+}
+
+#[test]
+fn keep_alive_connections_are_reused() {
+    let (addr, accepted) = stand_in(|stream| echo(stream, false));
+    let client = Client::new();
+    let url = format!("http://{}/items?page=2", addr);
+    for i in 0..5 {
+        let resp = client.post(&url, format!("body {}", i)).send().unwrap();
+        assert_eq!(
+            String::from_utf8(resp.body).unwrap(),
+            format!("POST /items?page=2 body {}", i)
+        );
+    }
+    assert_eq!(accepted.load(Ordering::SeqCst), 1);
+
+    let fresh = Client::new().max_idle_per_host(0);
+    fresh.get(&url).send().unwrap();
+    fresh.get(&url).send().unwrap();
+    assert_eq!(fresh.idle_connections(), 0);
+    assert_eq!(accepted.load(Ordering::SeqCst), 3);
+}
+
+#[test]
+fn stale_pooled_connection_is_retried() {
+    let (addr, accepted) = stand_in(|stream| echo(stream, true));
+    let client = Client::new();
+    let url = format!("http://{}/", addr);
+    for _ in 0..3 {
+        let resp = client.get(&url).send().unwrap();
+        assert_eq!(resp.body, b"GET / ");
+        // 给服务器一点时间关闭连接，下一个请求会先在已经关闭的连接上失败
+        thread::sleep(Duration::from_millis(50));
+    }
+    assert_eq!(accepted.load(Ordering::SeqCst), 3);
+    // POST 不是幂等的，服务器可能已经处理过它，错误直接交给调用方
+    assert!(client.post(&url, "once").send().is_err());
+    assert_eq!(accepted.load(Ordering::SeqCst), 3);
+    assert_eq!(
+        client.post(&url, "once").send().unwrap().body,
+        b"POST / once"
+    );
+}
+
+#[test]
+fn decodes_chunked_responses() {
+    let (addr, _) = stand_in(|mut stream| {
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        Request::read_from(&mut reader, &Limits::default()).unwrap();
+        stream
+            .write_all(
+                b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n\
+                  6\r\nchunky\r\n1\r\n!\r\n0\r\n\r\n",
+            )
+            .unwrap();
+    });
+    let resp = Client::new()
+        .get(&format!("http://{}/", addr))
+        .send()
+        .unwrap();
+    assert_eq!(resp.body, b"chunky!");
+}
+
+#[test]
+fn slow_server_times_out() {
+    let (addr, _) = stand_in(|stream| {
+        thread::sleep(Duration::from_secs(3));
+        drop(stream);
+    });
+    let client = Client::new().timeout(Some(Duration::from_millis(300)));
+    let started = Instant::now();
+    let err = client.get(&format!("http://{}/", addr)).send().unwrap_err();
+    assert!(matches!(err, ClientError::Timeout), "{}", err);
+    assert!(started.elapsed() < Duration::from_secs(2));
+}
+
+#[test]
+fn oversized_response_is_rejected() {
+    let (addr, _) = stand_in(|stream| echo(stream, false));
+    let client = Client::new().limits(Limits {
+        max_header_bytes: 1024,
+        max_body_bytes: 8,
+    });
+    let err = client
+        .post(&format!("http://{}/", addr), "a long request body")
+        .send()
+        .unwrap_err();
+    assert!(matches!(err, ClientError::TooLarge), "{}", err);
+}
@@ -0,0 +1,109 @@
+// 每个集成测试文件只用到其中一部分辅助函数
+#![allow(dead_code)]
+
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    process::{Child, Command, Stdio},
+    sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    },
+    thread,
+};
+
+/// 以子进程方式启动服务器，退出作用域时自动结束进程
+pub struct ServerProcess {
+    child: Child,
+    pub addr: SocketAddr,
+}
+
+impl ServerProcess {
+    /// 启动 main 二进制，绑定 127.0.0.1:0，并从标准输出中读取实际监听的端口
+    pub fn start(extra_args: &[&str]) -> ServerProcess {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_main"))
+            .args(["--bind", "127.0.0.1:0"])
+            .args(extra_args)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .expect("failed to start server");
+        let mut stdout = BufReader::new(child.stdout.take().unwrap());
+        let mut line = String::new();
+        stdout.read_line(&mut line).unwrap();
+        let addr = line
+            .trim()
+            .strip_prefix("Listening on http://")
+            .unwrap_or_else(|| panic!("unexpected first line: {:?}", line))
+            .parse()
+            .unwrap();
+        // 继续消费标准输出，避免管道写满后阻塞服务器
+        std::thread::spawn(move || {
+            let _ = std::io::copy(&mut stdout, &mut std::io::sink());
+        });
+        ServerProcess { child, addr }
+    }
+
+    pub fn url(&self, path: &str) -> String {
+        format!("http://{}{}", self.addr, path)
+    }
+}
+
+impl Drop for ServerProcess {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// 发送一段原始请求并读取一个完整的响应
+pub fn raw_request(addr: SocketAddr, raw: &str) -> String {
+    String::from_utf8_lossy(&raw_request_bytes(addr, raw)).into_owned()
+}
+
+pub fn raw_request_bytes(addr: SocketAddr, raw: &str) -> Vec<u8> {
+    let mut stream = TcpStream::connect(addr).unwrap();
+    stream.write_all(raw.as_bytes()).unwrap();
+    read_response(&mut stream)
+}
+
+/// 从连接上读取一个响应：先读到空行，再按 Content-Length 读取响应体，连接可以继续复用
+pub fn read_response<R: Read>(stream: &mut R) -> Vec<u8> {
+    let mut resp = Vec::new();
+    let mut byte = [0; 1];
+    while !resp.ends_with(b"\r\n\r\n") {
+        if stream.read(&mut byte).unwrap() == 0 {
+            return resp;
+        }
+        resp.push(byte[0]);
+    }
+    let head = String::from_utf8_lossy(&resp).to_ascii_lowercase();
+    let len = head
+        .lines()
+        .find_map(|line| line.strip_prefix("content-length:"))
+        .map_or(0, |len| len.trim().parse().unwrap());
+    let mut body = vec![0; len];
+    stream.read_exact(&mut body).unwrap();
+    resp.extend(body);
+    resp
+}
+
+/// 本地的替身服务器：每个连接交给 serve 处理，返回监听地址和已经接受的连接数
+pub fn stand_in<F>(serve: F) -> (SocketAddr, Arc<AtomicUsize>)
+where
+    F: Fn(TcpStream) + Send + Sync + 'static,
+{
+    let ln = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = ln.local_addr().unwrap();
+    let accepted = Arc::new(AtomicUsize::new(0));
+    let counter = Arc::clone(&accepted);
+    let serve = Arc::new(serve);
+    thread::spawn(move || {
+        for stream in ln.incoming().flatten() {
+            counter.fetch_add(1, Ordering::SeqCst);
+            let serve = Arc::clone(&serve);
+            thread::spawn(move || serve(stream));
+        }
+    });
+    (addr, accepted)
+}
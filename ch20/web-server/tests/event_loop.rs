@@ -0,0 +1,121 @@
+#![cfg(target_os = "linux")]
+
+use std::{
+    io::{Read, Write},
+    net::TcpStream,
+    thread,
+    time::{Duration, Instant},
+};
+mod common;
+
+use common::{ServerProcess, read_response};
+
+fn status_line(resp: &[u8]) -> String {
+    let resp = String::from_utf8_lossy(resp);
+    resp.lines().next().unwrap_or("").to_string()
+}
+
+fn start(extra_args: &[&str]) -> ServerProcess {
+    let mut args = vec!["--backend", "epoll"];
+    args.extend_from_slice(extra_args);
+    ServerProcess::start(&args)
+}
+
+#[test]
+fn serves_pages_and_pipelined_requests() {
+    let server = start(&[]);
+    let mut stream = TcpStream::connect(server.addr).unwrap();
+    // 三个请求一次性发出，其中 POST 的请求体和下一个请求挨在一起
+    stream
+        .write_all(
+            b"GET / HTTP/1.1\r\n\r\n\
+              POST / HTTP/1.1\r\nContent-Length: 5\r\n\r\nhello\
+              GET /missing HTTP/1.1\r\n\r\n",
+        )
+        .unwrap();
+    assert_eq!(status_line(&read_response(&mut stream)), "HTTP/1.1 200 OK");
+    let resp = String::from_utf8(read_response(&mut stream)).unwrap();
+    assert!(resp.starts_with("HTTP/1.1 405"), "{}", resp);
+    assert!(resp.contains("Allow: GET\r\n"));
+    assert_eq!(
+        status_line(&read_response(&mut stream)),
+        "HTTP/1.1 404 Not Found"
+    );
+}
+
+#[test]
+fn body_may_arrive_in_pieces() {
+    let server = start(&[]);
+    let mut stream = TcpStream::connect(server.addr).unwrap();
+    stream.write_all(b"POST / HTTP/1.1\r\nContent-Le").unwrap();
+    thread::sleep(Duration::from_millis(50));
+    stream.write_all(b"ngth: 4\r\n\r\nab").unwrap();
+    thread::sleep(Duration::from_millis(50));
+    stream.write_all(b"cd").unwrap();
+    assert!(status_line(&read_response(&mut stream)).starts_with("HTTP/1.1 405"));
+}
+
+#[test]
+fn incomplete_header_gets_408_and_idle_is_closed() {
+    let server = start(&["--header-timeout", "300ms", "--keep-alive-timeout", "300ms"]);
+    let mut slow = TcpStream::connect(server.addr).unwrap();
+    slow.write_all(b"GET / HTTP/1.1\r\nHost: x\r\n").unwrap();
+    let started = Instant::now();
+    assert_eq!(
+        status_line(&read_response(&mut slow)),
+        "HTTP/1.1 408 Request Timeout"
+    );
+    assert!(started.elapsed() < Duration::from_secs(5));
+    assert_eq!(slow.read(&mut [0; 1]).unwrap(), 0);
+
+    let mut idle = TcpStream::connect(server.addr).unwrap();
+    idle.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+    assert_eq!(status_line(&read_response(&mut idle)), "HTTP/1.1 200 OK");
+    // keep-alive 空闲超时后不发送任何内容直接关闭
+    assert_eq!(idle.read(&mut [0; 1]).unwrap(), 0);
+}
+
+#[test]
+fn oversized_header_is_rejected() {
+    let server = start(&["--max-header-bytes", "128"]);
+    let mut stream = TcpStream::connect(server.addr).unwrap();
+    let raw = format!("GET / HTTP/1.1\r\nX-Big: {}\r\n\r\n", "a".repeat(512));
+    stream.write_all(raw.as_bytes()).unwrap();
+    assert_eq!(
+        status_line(&read_response(&mut stream)),
+        "HTTP/1.1 431 Request Header Fields Too Large"
+    );
+}
+
+#[test]
+fn idle_connections_do_not_hold_workers() {
+    let server = start(&["--workers", "1", "--keep-alive-timeout", "30s"]);
+    // 线程池后端下每一个空闲连接都会占住唯一的工作线程
+    let mut idle = Vec::new();
+    for _ in 0..50 {
+        let mut stream = TcpStream::connect(server.addr).unwrap();
+        stream.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+        assert_eq!(status_line(&read_response(&mut stream)), "HTTP/1.1 200 OK");
+        idle.push(stream);
+    }
+
+    let started = Instant::now();
+    let mut fresh = TcpStream::connect(server.addr).unwrap();
+    fresh.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+    assert_eq!(status_line(&read_response(&mut fresh)), "HTTP/1.1 200 OK");
+    assert!(started.elapsed() < Duration::from_secs(2));
+}
+
+#[test]
+fn slow_handler_does_not_block_the_loop() {
+    let server = start(&["--workers", "2"]);
+    let mut sleeper = TcpStream::connect(server.addr).unwrap();
+    sleeper.write_all(b"GET /sleep HTTP/1.1\r\n\r\n").unwrap();
+    thread::sleep(Duration::from_millis(100));
+
+    let started = Instant::now();
+    let mut stream = TcpStream::connect(server.addr).unwrap();
+    stream.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+    assert_eq!(status_line(&read_response(&mut stream)), "HTTP/1.1 200 OK");
+    assert!(started.elapsed() < Duration::from_secs(2));
+}
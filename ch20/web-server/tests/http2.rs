@@ -0,0 +1,377 @@
+use std::{
+    io::Write,
+    net::{SocketAddr, TcpStream},
+    sync::{Arc, Condvar, Mutex, mpsc},
+    thread,
+    time::{Duration, Instant},
+};
+use web_server::{
+    Config, Request, Response, Router, Server,
+    access_log::LogTarget,
+    client::Client,
+    hpack::Encoder,
+    http2::{self, DEFAULT_MAX_FRAME_SIZE, Frame, H2Error, PREFACE, SETTINGS_INITIAL_WINDOW_SIZE},
+    json::Value,
+    log::Level,
+    server::Backend,
+};
+mod common;
+
+use common::ServerProcess;
+
+fn config() -> Config {
+    Config {
+        binds: vec![SocketAddr::from(([127, 0, 0, 1], 0))],
+        log_level: Level::Off,
+        access_log: LogTarget::Off,
+        ..Config::default()
+    }
+}
+
+fn start(router: Router) -> SocketAddr {
+    start_with(router, config())
+}
+
+fn start_with(router: Router, config: Config) -> SocketAddr {
+    let server = Server::bind(config).unwrap().handler(router);
+    let addr = server.local_addrs()[0];
+    thread::spawn(move || server.run());
+    addr
+}
+
+fn post(target: &str, content_type: &str, body: impl Into<Vec<u8>>) -> Request {
+    let mut req = Request::new("POST", target);
+    req.headers.set("Content-Type", content_type);
+    req.body = body.into();
+    req
+}
+
+/// 不经过 Connection，直接收发帧，用来观察服务器的行为
+struct RawConn {
+    stream: TcpStream,
+    encoder: Encoder,
+}
+
+impl RawConn {
+    fn open(addr: SocketAddr, settings: Vec<(u16, u32)>) -> RawConn {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        stream.write_all(PREFACE).unwrap();
+        let mut conn = RawConn {
+            stream,
+            encoder: Encoder::new(),
+        };
+        conn.send(Frame::Settings {
+            ack: false,
+            params: settings,
+        });
+        conn
+    }
+
+    fn send(&mut self, frame: Frame) {
+        frame.write_to(&mut self.stream).unwrap();
+    }
+
+    fn get(&mut self, stream: u32, path: &str) {
+        let block = self.encoder.encode(&[
+            (":method", "GET"),
+            (":scheme", "http"),
+            (":path", path),
+            (":authority", "localhost"),
+        ]);
+        self.send(Frame::Headers {
+            stream,
+            block,
+            end_stream: true,
+            end_headers: true,
+        });
+    }
+
+    fn recv(&mut self) -> Result<Frame, H2Error> {
+        Frame::read_from(&mut self.stream, DEFAULT_MAX_FRAME_SIZE)
+    }
+
+    /// 读取帧直到超时，返回期间收到的 DATA 的总长度
+    fn drain_data(&mut self, timeout: Duration) -> usize {
+        self.stream.set_read_timeout(Some(timeout)).unwrap();
+        let mut total = 0;
+        while let Ok(frame) = self.recv() {
+            if let Frame::Data { data, .. } = frame {
+                total += data.len();
+            }
+        }
+        self.stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        total
+    }
+
+    /// 跳过其他帧，返回 GOAWAY 的错误码
+    fn goaway_code(&mut self) -> u32 {
+        loop {
+            match self.recv().unwrap() {
+                Frame::GoAway { code, .. } => return code,
+                _ => continue,
+            }
+        }
+    }
+}
+
+#[test]
+fn prior_knowledge_serves_the_default_routes() {
+    let server = ServerProcess::start(&[]);
+    let mut conn = http2::connect(&server.url("/")).unwrap();
+
+    let resp = conn.send(&Request::new("GET", "/")).unwrap();
+    assert_eq!(resp.status, 200);
+    assert_eq!(
+        resp.header("content-type"),
+        Some("text/html; charset=utf-8")
+    );
+    assert!(String::from_utf8_lossy(&resp.body).contains("Hello!"));
+
+    // 同一个连接上的后续请求
+    let resp = conn
+        .send(&post(
+            "/api/todos",
+            "application/json",
+            r#"{"title": "speak h2"}"#,
+        ))
+        .unwrap();
+    assert_eq!(resp.status, 201);
+    assert_eq!(resp.header("location"), Some("/api/todos/1"));
+    let resp = conn.send(&Request::new("GET", "/api/todos/1")).unwrap();
+    let todo = Value::parse(std::str::from_utf8(&resp.body).unwrap()).unwrap();
+    assert_eq!(todo["title"].as_str(), Some("speak h2"));
+
+    let resp = conn.send(&Request::new("HEAD", "/")).unwrap();
+    assert_eq!(resp.status, 200);
+    assert!(resp.body.is_empty());
+    assert_ne!(resp.header("content-length"), Some("0"));
+}
+
+#[test]
+fn h2c_upgrade_continues_on_the_same_connection() {
+    for backend in ["threads", "epoll"] {
+        let server = ServerProcess::start(&["--backend", backend]);
+        let (mut conn, resp) = http2::connect_upgrade(&server.url("/api/todos")).unwrap();
+        assert_eq!(resp.status, 200, "{}", backend);
+        assert_eq!(
+            String::from_utf8(resp.body).unwrap(),
+            r#"{"items":[],"total":0}"#
+        );
+        let resp = conn.send(&Request::new("GET", "/missing")).unwrap();
+        assert_eq!(resp.status, 404);
+
+        let mut conn = http2::connect(&server.url("/")).unwrap();
+        assert_eq!(conn.send(&Request::new("GET", "/")).unwrap().status, 200);
+    }
+}
+
+#[test]
+fn streams_on_one_connection_are_handled_concurrently() {
+    // 两个请求互相等待：只有同时处理时才能都在超时之前返回
+    let arrived = Arc::new((Mutex::new(0), Condvar::new()));
+    let addr = start(Router::new().get("/meet", move |_req: &mut Request| {
+        let (count, cond) = &*arrived;
+        let mut count = count.lock().unwrap();
+        *count += 1;
+        cond.notify_all();
+        let (count, _) = cond
+            .wait_timeout_while(count, Duration::from_secs(5), |n| *n < 2)
+            .unwrap();
+        Response::new(if *count >= 2 { 200 } else { 504 })
+    }));
+    let mut conn = http2::connect(&format!("http://{}/", addr)).unwrap();
+    let first = conn.request(&Request::new("GET", "/meet")).unwrap();
+    let second = conn.request(&Request::new("GET", "/meet")).unwrap();
+    assert_eq!((first, second), (1, 3));
+    // 后发出的请求也可以先读取
+    assert_eq!(conn.response(second).unwrap().status, 200);
+    assert_eq!(conn.response(first).unwrap().status, 200);
+}
+
+#[test]
+fn large_bodies_flow_in_both_directions() {
+    let addr = start(Router::new().post("/echo", |req: &mut Request| {
+        Response::new(200).with_body(req.body.clone())
+    }));
+    let mut conn = http2::connect(&format!("http://{}/", addr)).unwrap();
+    // 比默认的 64KB 窗口大得多，两个方向都需要 WINDOW_UPDATE 才能完成
+    let body: Vec<u8> = (0..300_000u32).map(|i| (i % 251) as u8).collect();
+    let resp = conn
+        .send(&post("/echo", "application/octet-stream", body.clone()))
+        .unwrap();
+    assert_eq!(resp.status, 200);
+    assert_eq!(resp.header("content-length"), Some("300000"));
+    assert!(resp.body == body);
+
+    // 超过 max_body_bytes 的请求体得到 413
+    let resp = conn
+        .send(&post(
+            "/echo",
+            "application/octet-stream",
+            vec![0; 2 * 1024 * 1024],
+        ))
+        .unwrap();
+    assert_eq!(resp.status, 413);
+}
+
+#[test]
+fn server_waits_for_the_client_window() {
+    let addr = start(Router::new().get("/big", |_req: &mut Request| {
+        Response::new(200).with_body(vec![b'x'; 1000])
+    }));
+    let mut conn = RawConn::open(addr, vec![(SETTINGS_INITIAL_WINDOW_SIZE, 100)]);
+    conn.get(1, "/big");
+    assert_eq!(conn.drain_data(Duration::from_millis(300)), 100);
+
+    conn.send(Frame::WindowUpdate {
+        stream: 1,
+        increment: 500,
+    });
+    assert_eq!(conn.drain_data(Duration::from_millis(300)), 500);
+
+    // 调大初始窗口会同时作用到已经打开的流上
+    conn.send(Frame::Settings {
+        ack: false,
+        params: vec![(SETTINGS_INITIAL_WINDOW_SIZE, 500)],
+    });
+    assert_eq!(conn.drain_data(Duration::from_millis(300)), 400);
+}
+
+#[test]
+fn protocol_violations_close_the_connection() {
+    let addr = start(Router::new().get("/", |_req: &mut Request| Response::new(200)));
+
+    // 格式错误的请求只重置这个流
+    let mut conn = RawConn::open(addr, Vec::new());
+    let block = conn.encoder.encode(&[
+        (":method", "GET"),
+        (":scheme", "http"),
+        (":path", "/"),
+        ("Bad-Name", "x"),
+    ]);
+    conn.send(Frame::Headers {
+        stream: 1,
+        block,
+        end_stream: true,
+        end_headers: true,
+    });
+    conn.get(3, "/");
+    let mut reset = None;
+    let mut answered = None;
+    while reset.is_none() || answered.is_none() {
+        match conn.recv().unwrap() {
+            Frame::RstStream { stream, code } => reset = Some((stream, code)),
+            Frame::Headers { stream, .. } => answered = Some(stream),
+            _ => {}
+        }
+    }
+    assert_eq!(reset, Some((1, http2::PROTOCOL_ERROR)));
+    assert_eq!(answered, Some(3));
+
+    // 0 号流上的 DATA
+    let mut conn = RawConn::open(addr, Vec::new());
+    conn.stream
+        .write_all(&[0, 0, 1, 0, 0, 0, 0, 0, 0, b'x'])
+        .unwrap();
+    assert_eq!(conn.goaway_code(), http2::PROTOCOL_ERROR);
+
+    // 引用不存在的动态表条目
+    let mut conn = RawConn::open(addr, Vec::new());
+    conn.send(Frame::Headers {
+        stream: 1,
+        block: vec![0xbe],
+        end_stream: true,
+        end_headers: true,
+    });
+    assert_eq!(conn.goaway_code(), http2::COMPRESSION_ERROR);
+
+    // 前言之后的第一帧必须是 SETTINGS
+    let mut stream = TcpStream::connect(addr).unwrap();
+    stream.write_all(PREFACE).unwrap();
+    let mut conn = RawConn {
+        stream,
+        encoder: Encoder::new(),
+    };
+    conn.send(Frame::Ping {
+        ack: false,
+        data: [0; 8],
+    });
+    assert_eq!(conn.goaway_code(), http2::PROTOCOL_ERROR);
+}
+
+#[test]
+fn streams_beyond_the_pool_queue_are_refused() {
+    // 唯一的工作线程被第一个流占住，第二个流占满队列，第三个流被拒绝
+    let (entered_tx, entered) = mpsc::channel();
+    let (release, gate) = mpsc::channel::<()>();
+    let entered_tx = Mutex::new(entered_tx);
+    let gate = Mutex::new(gate);
+    let router = Router::new().get("/wait", move |_req: &mut Request| {
+        entered_tx.lock().unwrap().send(()).unwrap();
+        let _ = gate.lock().unwrap().recv();
+        Response::new(200)
+    });
+    let addr = start_with(
+        router,
+        Config {
+            workers: 1,
+            queue_capacity: 1,
+            ..config()
+        },
+    );
+    let mut conn = http2::connect(&format!("http://{}/", addr)).unwrap();
+    let busy = conn.request(&Request::new("GET", "/wait")).unwrap();
+    entered.recv_timeout(Duration::from_secs(5)).unwrap();
+    let queued = conn.request(&Request::new("GET", "/wait")).unwrap();
+    let refused = conn.request(&Request::new("GET", "/wait")).unwrap();
+    assert!(conn.response(refused).is_err());
+
+    drop(release);
+    assert_eq!(conn.response(busy).unwrap().status, 200);
+    assert_eq!(conn.response(queued).unwrap().status, 200);
+    // 队列空出来之后新的流照常处理
+    assert_eq!(
+        conn.send(&Request::new("GET", "/wait")).unwrap().status,
+        200
+    );
+}
+
+#[test]
+fn connections_count_against_the_per_ip_limit() {
+    let mut backends = vec![Backend::Threads];
+    if cfg!(target_os = "linux") {
+        backends.push(Backend::Epoll);
+    }
+    for backend in backends {
+        let addr = start_with(
+            Router::new().get("/", |_req: &mut Request| Response::new(200)),
+            Config {
+                backend,
+                max_connections_per_ip: 1,
+                ..config()
+            },
+        );
+        let url = format!("http://{}/", addr);
+        let mut conn = http2::connect(&url).unwrap();
+        assert_eq!(conn.send(&Request::new("GET", "/")).unwrap().status, 200);
+        // 连接线程占着唯一的名额
+        let resp = Client::new().get(&url).send().unwrap();
+        assert_eq!(resp.status, 429, "{:?}", backend);
+
+        drop(conn);
+        let deadline = Instant::now() + Duration::from_secs(2);
+        while Client::new().get(&url).send().unwrap().status == 429 {
+            assert!(
+                Instant::now() < deadline,
+                "{:?}: slot not released",
+                backend
+            );
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+}
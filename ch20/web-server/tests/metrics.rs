@@ -0,0 +1,88 @@
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
+use web_server::client::Client;
+mod common;
+
+use common::ServerProcess;
+
+fn scrape(server: &ServerProcess) -> String {
+    let resp = Client::new().get(&server.url("/metrics")).send().unwrap();
+    assert_eq!(resp.status, 200);
+    assert_eq!(
+        resp.header("Content-Type"),
+        Some("text/plain; version=0.0.4; charset=utf-8")
+    );
+    String::from_utf8(resp.body).unwrap()
+}
+
+/// 取出某个没有标签的指标的值
+fn sample(text: &str, name: &str) -> f64 {
+    text.lines()
+        .find_map(|line| line.strip_prefix(name)?.strip_prefix(' '))
+        .unwrap_or_else(|| panic!("{} not found in\n{}", name, text))
+        .parse()
+        .unwrap()
+}
+
+#[test]
+fn metrics_endpoint_reports_requests_and_pool_stats() {
+    let server = ServerProcess::start(&["--workers", "3"]);
+    let client = Client::new();
+    for path in ["/", "/", "/api/todos/7", "/missing"] {
+        client.get(&server.url(path)).send().unwrap();
+    }
+
+    let text = scrape(&server);
+    for line in [
+        r#"http_requests_total{method="GET",route="/",status="200"} 2"#,
+        r#"http_requests_total{method="GET",route="/api/todos/:id",status="404"} 1"#,
+        r#"http_requests_total{method="GET",route="unmatched",status="404"} 1"#,
+        r#"http_request_duration_seconds_count{route="/"} 2"#,
+        "thread_pool_workers 3",
+    ] {
+        assert!(
+            text.lines().any(|l| l == line),
+            "{} not found in\n{}",
+            line,
+            text
+        );
+    }
+    // 正在抓取的这个连接本身也占着一个工作线程
+    assert!(sample(&text, "http_connections_active") >= 1.0);
+    assert!(sample(&text, "thread_pool_busy_workers") >= 1.0);
+    assert_eq!(sample(&text, "thread_pool_queued_jobs"), 0.0);
+
+    // 一个连接对应线程池里的一个任务，连接关闭之后任务才算完成
+    drop(client);
+    let started = Instant::now();
+    let later = loop {
+        let text = scrape(&server);
+        if sample(&text, "thread_pool_completed_jobs_total") >= 1.0 {
+            break text;
+        }
+        assert!(started.elapsed() < Duration::from_secs(2), "{}", text);
+        thread::sleep(Duration::from_millis(20));
+    };
+    assert!(later.contains(r#"http_requests_total{method="GET",route="/metrics",status="200"}"#));
+}
+
+#[test]
+fn metrics_can_be_disabled_or_moved() {
+    let server = ServerProcess::start(&["--metrics", "off"]);
+    let resp = Client::new().get(&server.url("/metrics")).send().unwrap();
+    assert_eq!(resp.status, 404);
+
+    let server = ServerProcess::start(&["--metrics-path", "/internal/stats"]);
+    let resp = Client::new()
+        .get(&server.url("/internal/stats"))
+        .send()
+        .unwrap();
+    assert_eq!(resp.status, 200);
+    assert!(
+        String::from_utf8(resp.body)
+            .unwrap()
+            .contains("thread_pool_workers 4\n")
+    );
+}
@@ -0,0 +1,194 @@
+use std::{
+    io::BufReader,
+    net::{SocketAddr, TcpListener, TcpStream},
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    thread,
+    time::{Duration, Instant},
+};
+use web_server::{
+    Config, Request, Response, Server,
+    access_log::LogTarget,
+    client::Client,
+    http::Limits,
+    log::Level,
+    proxy::{ProxyConfig, ProxyRoute},
+};
+mod common;
+
+use common::{ServerProcess, stand_in};
+
+/// 替身上游：回复自己的名字和收到的转发头部，healthy 为 false 时 /health 返回 503
+fn upstream(name: &'static str, healthy: Arc<AtomicBool>) -> SocketAddr {
+    let (addr, _) = stand_in(move |stream| {
+        let mut writer = stream.try_clone().unwrap();
+        let mut reader = BufReader::new(stream);
+        while let Ok(req) = Request::read_from(&mut reader, &Limits::default()) {
+            let resp = if req.path == "/health" {
+                let status = if healthy.load(Ordering::SeqCst) {
+                    200
+                } else {
+                    503
+                };
+                Response::text(status, name)
+            } else {
+                let body = format!(
+                    "{} {} {} for={} host={} connection={}",
+                    name,
+                    req.method,
+                    req.target,
+                    req.header("X-Forwarded-For").unwrap_or("-"),
+                    req.header("X-Forwarded-Host").unwrap_or("-"),
+                    req.header("X-Hop").unwrap_or("-"),
+                );
+                Response::text(200, body).with_header("Keep-Alive", "timeout=5")
+            };
+            if resp.write_to(&mut writer).is_err() {
+                return;
+            }
+        }
+    });
+    addr
+}
+
+/// 一个拒绝连接的地址：先绑定端口再释放
+fn dead_upstream() -> SocketAddr {
+    TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+}
+
+fn body(client: &Client, url: &str) -> String {
+    let resp = client.get(url).send().unwrap();
+    assert_eq!(resp.status, 200, "{}", String::from_utf8_lossy(&resp.body));
+    String::from_utf8(resp.body).unwrap()
+}
+
+#[test]
+fn balances_requests_across_upstreams() {
+    let a = upstream("a", Arc::new(AtomicBool::new(true)));
+    let b = upstream("b", Arc::new(AtomicBool::new(true)));
+    let route = format!("/api=http://{},http://{}", a, b);
+    let server = ServerProcess::start(&["--proxy", &route]);
+    let client = Client::new();
+
+    let names: Vec<_> = (0..4)
+        .map(|_| body(&client, &server.url("/api/items?page=1"))[..1].to_string())
+        .collect();
+    assert_eq!(names, ["a", "b", "a", "b"]);
+    assert!(body(&client, &server.url("/api/items?page=1")).contains("GET /api/items?page=1"));
+
+    // 没有匹配的路径仍然由默认页面处理
+    let resp = client.get(&server.url("/")).send().unwrap();
+    assert_eq!(resp.status, 200);
+    assert!(String::from_utf8_lossy(&resp.body).contains("Hello"));
+}
+
+#[test]
+fn adds_forwarded_headers_and_drops_hop_by_hop() {
+    let a = upstream("a", Arc::new(AtomicBool::new(true)));
+    let server = ServerProcess::start(&["--proxy", &format!("/=http://{}", a)]);
+    let resp = Client::new()
+        .get(&server.url("/anything"))
+        .header("X-Forwarded-For", "203.0.113.9")
+        .header("Connection", "X-Hop")
+        .header("X-Hop", "secret")
+        .send()
+        .unwrap();
+    let body = String::from_utf8(resp.body).unwrap();
+    assert!(body.contains("for=203.0.113.9, 127.0.0.1"), "{}", body);
+    assert!(body.contains(&format!("host={}", server.addr)), "{}", body);
+    assert!(body.contains("connection=-"), "{}", body);
+    assert_eq!(resp.headers.get("Keep-Alive"), None);
+}
+
+#[test]
+fn retries_on_the_next_upstream() {
+    let live = upstream("live", Arc::new(AtomicBool::new(true)));
+    let route = format!("/api=http://{},http://{}", dead_upstream(), live);
+    let server = ServerProcess::start(&["--proxy", &route]);
+    let client = Client::new();
+    for _ in 0..4 {
+        assert!(body(&client, &server.url("/api")).starts_with("live GET"));
+    }
+    // 连接被拒绝时请求没有发出去，非幂等的请求也可以重试
+    let resp = client.post(&server.url("/api"), "data").send().unwrap();
+    assert_eq!(resp.status, 200);
+}
+
+#[test]
+fn all_upstreams_down_is_bad_gateway() {
+    let route = format!("/api=http://{}", dead_upstream());
+    let server = ServerProcess::start(&["--proxy", &route]);
+    let resp = Client::new().get(&server.url("/api/x")).send().unwrap();
+    assert_eq!(resp.status, 502);
+}
+
+#[test]
+fn health_checks_take_upstreams_out_of_rotation() {
+    let a_healthy = Arc::new(AtomicBool::new(false));
+    let a = upstream("a", Arc::clone(&a_healthy));
+    let b = upstream("b", Arc::new(AtomicBool::new(true)));
+    let config = Config {
+        binds: vec![SocketAddr::from(([127, 0, 0, 1], 0))],
+        log_level: Level::Off,
+        access_log: LogTarget::Off,
+        proxy: ProxyConfig {
+            routes: vec![ProxyRoute {
+                prefix: String::from("/api"),
+                upstreams: vec![format!("http://{}", a), format!("http://{}", b)],
+            }],
+            health_check: Some(String::from("/health")),
+            health_interval: Duration::from_millis(50),
+            ..ProxyConfig::default()
+        },
+        ..Config::default()
+    };
+    let server = Server::bind(config).unwrap();
+    let url = format!("http://{}/api", server.local_addrs()[0]);
+    thread::spawn(move || server.run());
+    let client = Client::new();
+
+    thread::sleep(Duration::from_millis(200));
+    for _ in 0..4 {
+        assert!(body(&client, &url).starts_with('b'));
+    }
+
+    a_healthy.store(true, Ordering::SeqCst);
+    let started = Instant::now();
+    while !body(&client, &url).starts_with('a') {
+        assert!(
+            started.elapsed() < Duration::from_secs(2),
+            "a never came back"
+        );
+        thread::sleep(Duration::from_millis(20));
+    }
+}
+
+#[test]
+fn slow_upstream_is_gateway_timeout() {
+    let (slow, _) = stand_in(|stream: TcpStream| {
+        thread::sleep(Duration::from_secs(3));
+        drop(stream);
+    });
+    let config = Config {
+        binds: vec![SocketAddr::from(([127, 0, 0, 1], 0))],
+        log_level: Level::Off,
+        access_log: LogTarget::Off,
+        proxy: ProxyConfig {
+            routes: vec![format!("/=http://{}", slow).parse().unwrap()],
+            retries: 0,
+            timeout: Some(Duration::from_millis(200)),
+            ..ProxyConfig::default()
+        },
+        ..Config::default()
+    };
+    let server = Server::bind(config).unwrap();
+    let url = format!("http://{}/", server.local_addrs()[0]);
+    thread::spawn(move || server.run());
+    let resp = Client::new().get(&url).send().unwrap();
+    assert_eq!(resp.status, 504);
+}
@@ -0,0 +1,73 @@
+use std::{net::SocketAddr, thread, time::Duration};
+use web_server::{
+    Config, Server,
+    access_log::LogTarget,
+    client::Client,
+    log::Level,
+    rate_limit::{KeyBy, Limit, ManualClock, RateLimit},
+};
+
+fn start(limit: RateLimit) -> SocketAddr {
+    let config = Config {
+        binds: vec![SocketAddr::from(([127, 0, 0, 1], 0))],
+        log_level: Level::Off,
+        access_log: LogTarget::Off,
+        ..Config::default()
+    };
+    let server = Server::bind(config).unwrap().with(limit);
+    let addr = server.local_addrs()[0];
+    thread::spawn(move || server.run());
+    addr
+}
+
+#[test]
+fn over_limit_requests_get_429_with_retry_after() {
+    let clock = ManualClock::new();
+    let addr = start(
+        RateLimit::new(Limit::per_minute(60).burst(2))
+            .route("/api/todos", Limit::per_minute(1))
+            .clock(clock.clone()),
+    );
+    let client = Client::new();
+    let url = |path: &str| format!("http://{}{}", addr, path);
+
+    for remaining in ["1", "0"] {
+        let resp = client.get(&url("/")).send().unwrap();
+        assert_eq!(resp.status, 200);
+        assert_eq!(resp.header("X-RateLimit-Remaining"), Some(remaining));
+    }
+    let resp = client.get(&url("/")).send().unwrap();
+    assert_eq!(resp.status, 429);
+    assert_eq!(resp.header("Retry-After"), Some("1"));
+
+    // 路由规则有自己的桶，不受默认规则的影响
+    assert_eq!(client.get(&url("/api/todos")).send().unwrap().status, 200);
+    let resp = client.get(&url("/api/todos")).send().unwrap();
+    assert_eq!(resp.status, 429);
+    assert_eq!(resp.header("Retry-After"), Some("60"));
+
+    clock.advance(Duration::from_secs(1));
+    assert_eq!(client.get(&url("/")).send().unwrap().status, 200);
+    assert_eq!(client.get(&url("/api/todos")).send().unwrap().status, 429);
+}
+
+#[test]
+fn api_keys_are_limited_separately() {
+    let addr = start(
+        RateLimit::new(Limit::per_minute(1))
+            .key_by(KeyBy::Header(String::from("X-Api-Key")))
+            .clock(ManualClock::new()),
+    );
+    let client = Client::new();
+    let send = |key: &str| {
+        client
+            .get(&format!("http://{}/", addr))
+            .header("X-Api-Key", key)
+            .send()
+            .unwrap()
+            .status
+    };
+    assert_eq!(send("alice"), 200);
+    assert_eq!(send("alice"), 429);
+    assert_eq!(send("bob"), 200);
+}
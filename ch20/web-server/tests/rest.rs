@@ -0,0 +1,124 @@
+use web_server::{Response, client::Client, form, json::Value};
+mod common;
+
+use common::ServerProcess;
+
+fn body(resp: &Response) -> Value {
+    assert_eq!(resp.header("Content-Type"), Some("application/json"));
+    Value::parse(std::str::from_utf8(&resp.body).unwrap()).unwrap()
+}
+
+fn send_json(client: &Client, method: &str, url: &str, json: &str) -> Response {
+    client
+        .request(method, url)
+        .header("Content-Type", "application/json")
+        .body(json)
+        .send()
+        .unwrap()
+}
+
+#[test]
+fn todos_crud_round_trip() {
+    let server = ServerProcess::start(&[]);
+    let client = Client::new();
+    let todos = server.url("/api/todos");
+
+    let resp = send_json(&client, "POST", &todos, r#"{"title": "read chapter 20"}"#);
+    assert_eq!(resp.status, 201);
+    assert_eq!(resp.header("Location"), Some("/api/todos/1"));
+    let created = body(&resp);
+    assert_eq!(created["id"].as_u64(), Some(1));
+    assert_eq!(created["done"], Value::Bool(false));
+
+    let item = server.url("/api/todos/1");
+    let resp = send_json(
+        &client,
+        "PUT",
+        &item,
+        r#"{"title": "read chapter 20", "done": true}"#,
+    );
+    assert_eq!(resp.status, 200);
+    let resp = client.get(&item).send().unwrap();
+    assert_eq!(
+        String::from_utf8(resp.body).unwrap(),
+        r#"{"done":true,"id":1,"title":"read chapter 20"}"#
+    );
+
+    let resp = client.request("DELETE", &item).send().unwrap();
+    assert_eq!(resp.status, 204);
+    for resp in [
+        client.get(&item).send().unwrap(),
+        client.request("DELETE", &item).send().unwrap(),
+        send_json(&client, "PUT", &item, r#"{"title": "gone"}"#),
+    ] {
+        assert_eq!(resp.status, 404);
+        assert_eq!(
+            body(&resp)["error"]["message"].as_str(),
+            Some("no such item")
+        );
+    }
+}
+
+#[test]
+fn lists_with_offset_and_limit() {
+    let server = ServerProcess::start(&[]);
+    let client = Client::new();
+    let todos = server.url("/api/todos");
+    for n in 1..=5 {
+        let title = format!(r#"{{"title": "task {}"}}"#, n);
+        assert_eq!(send_json(&client, "POST", &todos, &title).status, 201);
+    }
+
+    let query = form::serialize(&[("offset", "1"), ("limit", "2")]);
+    let resp = client.get(&format!("{}?{}", todos, query)).send().unwrap();
+    assert_eq!(resp.status, 200);
+    let page = body(&resp);
+    assert_eq!(page["total"].as_u64(), Some(5));
+    let titles: Vec<_> = page["items"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|item| item["title"].as_str().unwrap().to_string())
+        .collect();
+    assert_eq!(titles, ["task 2", "task 3"]);
+
+    let resp = client.get(&format!("{}?limit=-1", todos)).send().unwrap();
+    assert_eq!(resp.status, 400);
+    assert_eq!(
+        body(&resp)["error"]["message"].as_str(),
+        Some("`limit` must be a non-negative integer")
+    );
+}
+
+#[test]
+fn invalid_bodies_get_consistent_errors() {
+    let server = ServerProcess::start(&[]);
+    let client = Client::new();
+    let todos = server.url("/api/todos");
+
+    let resp = client
+        .post(&todos, r#"{"title": "x"}"#)
+        .header("Content-Type", "text/plain")
+        .send()
+        .unwrap();
+    assert_eq!(resp.status, 415);
+    assert_eq!(body(&resp)["error"]["status"].as_u64(), Some(415));
+
+    let resp = send_json(&client, "POST", &todos, r#"{"title": "x",}"#);
+    assert_eq!(resp.status, 400);
+    assert_eq!(
+        body(&resp)["error"]["message"].as_str(),
+        Some("invalid json: expected a string key at byte 14")
+    );
+
+    let resp = send_json(&client, "POST", &todos, r#"{"title": ""}"#);
+    assert_eq!(resp.status, 422);
+    assert_eq!(
+        body(&resp)["error"]["message"].as_str(),
+        Some("`title`: must not be empty")
+    );
+
+    // 失败的请求没有留下任何数据
+    let resp = client.get(&todos).send().unwrap();
+    assert_eq!(body(&resp)["total"].as_u64(), Some(0));
+}
@@ -0,0 +1,262 @@
+use std::{
+    io::{Read, Write},
+    net::{SocketAddr, TcpStream},
+    process::Command,
+    thread,
+    time::Duration,
+};
+use web_server::{
+    Config, Request, Response, Server, access_log::LogTarget, client::Client, log::Level,
+    server::Backend,
+};
+mod common;
+
+use common::{ServerProcess, raw_request, read_response};
+
+#[test]
+fn serves_index_on_ephemeral_port() {
+    let server = ServerProcess::start(&["--workers", "2"]);
+    assert_ne!(server.addr.port(), 0);
+
+    let resp = Client::new().get(&server.url("/")).send().unwrap();
+    assert_eq!(resp.status, 200);
+    assert!(String::from_utf8_lossy(&resp.body).contains("<h1>Hello!</h1>"));
+}
+
+#[test]
+fn unknown_path_is_404() {
+    let server = ServerProcess::start(&[]);
+    let resp = Client::new().get(&server.url("/missing")).send().unwrap();
+    assert_eq!(resp.status, 404);
+    assert!(String::from_utf8_lossy(&resp.body).contains("Oops!"));
+}
+
+#[test]
+fn head_then_get_on_one_connection() {
+    for backend in ["threads", "epoll"] {
+        let server = ServerProcess::start(&["--backend", backend]);
+        let mut stream = TcpStream::connect(server.addr).unwrap();
+        // 两个请求一起发出，HEAD 的响应如果带了响应体，GET 的响应就会错位
+        stream
+            .write_all(b"HEAD / HTTP/1.1\r\nHost: x\r\n\r\nGET / HTTP/1.1\r\nHost: x\r\n\r\n")
+            .unwrap();
+
+        // HEAD 的响应只有头部，不能按 Content-Length 读取
+        let mut head = Vec::new();
+        let mut byte = [0; 1];
+        while !head.ends_with(b"\r\n\r\n") {
+            stream.read_exact(&mut byte).unwrap();
+            head.push(byte[0]);
+        }
+        let head = String::from_utf8(head).unwrap();
+        assert!(head.starts_with("HTTP/1.1 200 "), "{}", head);
+        let len = head
+            .lines()
+            .find_map(|line| line.strip_prefix("Content-Length: "))
+            .unwrap();
+
+        let get = read_response(&mut stream);
+        let get = String::from_utf8_lossy(&get);
+        assert!(get.starts_with("HTTP/1.1 200 "), "{}", get);
+        assert!(
+            get.contains(&format!("Content-Length: {}\r\n", len)),
+            "{}",
+            get
+        );
+        assert!(get.contains("<h1>Hello!</h1>"), "{}", get);
+    }
+}
+
+#[test]
+fn oversized_header_is_rejected() {
+    let server = ServerProcess::start(&["--max-header-bytes", "128"]);
+    let raw = format!("GET / HTTP/1.1\r\nX-Pad: {}\r\n\r\n", "a".repeat(256));
+    let resp = raw_request(server.addr, &raw);
+    assert!(resp.starts_with("HTTP/1.1 431 "), "{}", resp);
+}
+
+#[test]
+fn invalid_config_fails_at_startup() {
+    let out = Command::new(env!("CARGO_BIN_EXE_main"))
+        .args(["--workers", "0"])
+        .output()
+        .unwrap();
+    assert_eq!(out.status.code(), Some(2));
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(
+        stderr.contains("workers must be between 1 and 1024"),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn access_log_is_written_as_json() {
+    let dir = std::env::temp_dir().join(format!("web-server-access-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("access.log");
+    let _ = std::fs::remove_file(&path);
+    let server = ServerProcess::start(&[
+        "--access-log",
+        path.to_str().unwrap(),
+        "--access-log-format",
+        "json",
+    ]);
+    Client::new()
+        .get(&server.url("/missing"))
+        .header("User-Agent", "test-agent")
+        .send()
+        .unwrap();
+    drop(server);
+
+    let log = std::fs::read_to_string(&path).unwrap();
+    assert!(
+        log.contains("\"method\":\"GET\",\"path\":\"/missing\""),
+        "{}",
+        log
+    );
+    assert!(
+        log.contains("\"user_agent\":\"test-agent\",\"status\":404"),
+        "{}",
+        log
+    );
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn responses_carry_request_id() {
+    let server = ServerProcess::start(&[]);
+    let client = Client::new();
+    let resp = client
+        .get(&server.url("/"))
+        .header("X-Request-Id", "from-client")
+        .send()
+        .unwrap();
+    assert_eq!(resp.header("X-Request-Id"), Some("from-client"));
+    let resp = client.get(&server.url("/")).send().unwrap();
+    assert!(resp.header("X-Request-Id").is_some_and(|id| !id.is_empty()));
+}
+
+#[test]
+fn compresses_when_client_accepts_gzip() {
+    let dir = std::env::temp_dir().join(format!("web-server-gzip-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let config = dir.join("server.toml");
+    std::fs::write(&config, "[compression]\nmin_size = 16\n").unwrap();
+    let server = ServerProcess::start(&["--config", config.to_str().unwrap()]);
+
+    let client = Client::new();
+    let resp = client
+        .get(&server.url("/"))
+        .header("Accept-Encoding", "gzip, deflate")
+        .send()
+        .unwrap();
+    assert_eq!(resp.header("Content-Encoding"), Some("gzip"));
+    assert_eq!(resp.header("Vary"), Some("Accept-Encoding"));
+    assert_eq!(&resp.body[..2], &[0x1f, 0x8b]);
+
+    let resp = client.get(&server.url("/")).send().unwrap();
+    assert!(resp.header("Content-Encoding").is_none());
+    assert!(String::from_utf8_lossy(&resp.body).contains("<h1>Hello!</h1>"));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+/// 在当前进程中启动服务器，/panic 的 handler 会 panic
+fn start_panicking(backend: Backend) -> SocketAddr {
+    let config = Config {
+        binds: vec![SocketAddr::from(([127, 0, 0, 1], 0))],
+        workers: 1,
+        backend,
+        log_level: Level::Off,
+        access_log: LogTarget::Off,
+        ..Config::default()
+    };
+    let server =
+        Server::bind(config)
+            .unwrap()
+            .handler(|req: &mut Request| match req.path.as_str() {
+                "/panic" => panic!("handler bug"),
+                _ => Response::text(200, "ok"),
+            });
+    let addr = server.local_addrs()[0];
+    thread::spawn(move || server.run());
+    addr
+}
+
+#[test]
+fn panicking_handler_returns_500_and_keeps_the_connection() {
+    let mut backends = vec![Backend::Threads];
+    if cfg!(target_os = "linux") {
+        backends.push(Backend::Epoll);
+    }
+    for backend in backends {
+        let addr = start_panicking(backend);
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        for _ in 0..2 {
+            stream.write_all(b"GET /panic HTTP/1.1\r\n\r\n").unwrap();
+            let resp = String::from_utf8(read_response(&mut stream)).unwrap();
+            assert!(resp.starts_with("HTTP/1.1 500 "), "{:?}: {}", backend, resp);
+        }
+        stream.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+        let resp = String::from_utf8(read_response(&mut stream)).unwrap();
+        assert!(resp.ends_with("\r\n\r\nok"), "{:?}: {}", backend, resp);
+    }
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn epoll_answers_503_instead_of_running_handlers_on_the_event_loop() {
+    use std::sync::{Arc, Mutex, mpsc};
+    use web_server::QueuePolicy;
+
+    let config = Config {
+        binds: vec![SocketAddr::from(([127, 0, 0, 1], 0))],
+        workers: 1,
+        queue_capacity: 1,
+        queue_policy: QueuePolicy::CallerRuns,
+        backend: Backend::Epoll,
+        log_level: Level::Off,
+        access_log: LogTarget::Off,
+        ..Config::default()
+    };
+    let (entered_tx, entered) = mpsc::channel();
+    let (release, gate) = mpsc::channel::<()>();
+    let entered_tx = Mutex::new(entered_tx);
+    let gate = Arc::new(Mutex::new(gate));
+    let server = Server::bind(config)
+        .unwrap()
+        .handler(move |_: &mut Request| {
+            entered_tx.lock().unwrap().send(()).unwrap();
+            let _ = gate.lock().unwrap().recv();
+            Response::text(200, "ok")
+        });
+    let addr = server.local_addrs()[0];
+    thread::spawn(move || server.run());
+
+    let send = || {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        stream.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+        stream
+    };
+    // 第一个请求占住唯一的工作线程，第二个请求占满队列
+    let mut busy = send();
+    entered.recv_timeout(Duration::from_secs(5)).unwrap();
+    let mut queued = send();
+    thread::sleep(Duration::from_millis(50));
+    // 队列满了，事件循环不能自己去跑 handler，否则会卡在 gate 上
+    let mut rejected = send();
+    let resp = String::from_utf8(read_response(&mut rejected)).unwrap();
+    assert!(resp.starts_with("HTTP/1.1 503 "), "{}", resp);
+
+    drop(release);
+    for stream in [&mut busy, &mut queued] {
+        let resp = String::from_utf8(read_response(stream)).unwrap();
+        assert!(resp.starts_with("HTTP/1.1 200 "), "{}", resp);
+    }
+}
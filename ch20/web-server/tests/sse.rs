@@ -0,0 +1,203 @@
+use std::{
+    io::{BufRead, BufReader, Write},
+    net::{SocketAddr, TcpStream},
+    sync::{Arc, mpsc},
+    thread,
+    time::{Duration, Instant},
+};
+use web_server::{
+    Config, Request, Router, Server,
+    access_log::LogTarget,
+    client::Client,
+    log::Level,
+    server::Backend,
+    sse::{Broadcast, Event, Sse},
+};
+
+fn config(backend: Backend) -> Config {
+    Config {
+        binds: vec![SocketAddr::from(([127, 0, 0, 1], 0))],
+        workers: 1,
+        backend,
+        log_level: Level::Off,
+        access_log: LogTarget::Off,
+        ..Config::default()
+    }
+}
+
+fn start(backend: Backend, hub: Arc<Broadcast>) -> SocketAddr {
+    start_with(config(backend), hub)
+}
+
+fn start_with(config: Config, hub: Arc<Broadcast>) -> SocketAddr {
+    let router = Router::new()
+        .get("/countdown", |_req: &mut Request| {
+            let (tx, rx) = mpsc::channel();
+            thread::spawn(move || {
+                for n in (1..=3).rev() {
+                    tx.send(Event::new(n.to_string()).with_event("tick"))
+                        .unwrap();
+                }
+            });
+            Sse::new(rx).into_response()
+        })
+        .get("/slow", |_req: &mut Request| {
+            let (tx, rx) = mpsc::channel();
+            thread::spawn(move || {
+                thread::sleep(Duration::from_millis(250));
+                tx.send(Event::new("finally")).unwrap();
+            });
+            Sse::new(rx)
+                .heartbeat(Some(Duration::from_millis(50)))
+                .into_response()
+        })
+        .get("/events", move |req: &mut Request| {
+            hub.subscribe(req.header("Last-Event-ID")).into_response()
+        });
+    let server = Server::bind(config).unwrap().handler(router);
+    let addr = server.local_addrs()[0];
+    thread::spawn(move || server.run());
+    addr
+}
+
+/// 打开一个事件流，跳过响应头
+fn open(addr: SocketAddr, last_event_id: Option<&str>) -> BufReader<TcpStream> {
+    let mut stream = TcpStream::connect(addr).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    let resume = last_event_id.map_or(String::new(), |id| format!("Last-Event-ID: {}\r\n", id));
+    write!(stream, "GET /events HTTP/1.1\r\n{}\r\n", resume).unwrap();
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    reader.read_line(&mut line).unwrap();
+    assert_eq!(line, "HTTP/1.1 200 OK\r\n");
+    while line != "\r\n" {
+        line.clear();
+        reader.read_line(&mut line).unwrap();
+    }
+    reader
+}
+
+/// 读取下一个事件的 id 和 data，跳过心跳注释
+fn next_event(reader: &mut BufReader<TcpStream>) -> (String, String) {
+    let (mut id, mut data) = (String::new(), String::new());
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        match line.trim_end_matches('\n').split_once(": ") {
+            Some(("id", value)) => id = value.to_string(),
+            Some(("data", value)) => data = value.to_string(),
+            _ if line == "\n" && !data.is_empty() => return (id, data),
+            _ => {}
+        }
+    }
+}
+
+fn wait_for_subscribers(hub: &Broadcast, n: usize) {
+    let started = Instant::now();
+    while hub.subscribers() != n {
+        assert!(started.elapsed() < Duration::from_secs(2));
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+#[test]
+fn streams_events_until_the_sender_is_dropped() {
+    let addr = start(Backend::Threads, Arc::new(Broadcast::new(0)));
+    let resp = Client::new()
+        .get(&format!("http://{}/countdown", addr))
+        .send()
+        .unwrap();
+    assert_eq!(resp.header("Content-Type"), Some("text/event-stream"));
+    assert_eq!(resp.header("Content-Length"), None);
+    assert_eq!(
+        String::from_utf8(resp.body).unwrap(),
+        "event: tick\ndata: 3\n\nevent: tick\ndata: 2\n\nevent: tick\ndata: 1\n\n"
+    );
+}
+
+#[test]
+fn idle_streams_send_heartbeats() {
+    let addr = start(Backend::Threads, Arc::new(Broadcast::new(0)));
+    let resp = Client::new()
+        .get(&format!("http://{}/slow", addr))
+        .send()
+        .unwrap();
+    let body = String::from_utf8(resp.body).unwrap();
+    assert!(body.starts_with(": ping\n\n"), "{}", body);
+    assert!(body.ends_with("data: finally\n\n"), "{}", body);
+}
+
+fn resumes_from_last_event_id(backend: Backend) {
+    let hub = Arc::new(Broadcast::new(10));
+    let addr = start(backend, Arc::clone(&hub));
+
+    let mut first = open(addr, None);
+    // 只有一个工作线程，事件流没有占住它
+    let mut second = open(addr, None);
+    wait_for_subscribers(&hub, 2);
+    for n in 1..=3 {
+        hub.send(Event::new(format!("n{}", n)));
+    }
+    for n in 1..=3 {
+        let expected = (n.to_string(), format!("n{}", n));
+        assert_eq!(next_event(&mut first), expected);
+        assert_eq!(next_event(&mut second), expected);
+    }
+
+    // 断线期间错过了 4 和 5，重连之后先补发，再接上新的事件
+    drop(first);
+    hub.send(Event::new("n4"));
+    hub.send(Event::new("n5"));
+    // 断开的订阅者要到下一次 send 才移除，这里只等新的订阅者出现
+    let before = hub.subscribers();
+    let mut resumed = open(addr, Some("3"));
+    wait_for_subscribers(&hub, before + 1);
+    hub.send(Event::new("n6"));
+    for n in 4..=6 {
+        assert_eq!(next_event(&mut resumed), (n.to_string(), format!("n{}", n)));
+    }
+}
+
+#[test]
+fn resumes_from_last_event_id_on_threads() {
+    resumes_from_last_event_id(Backend::Threads);
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn resumes_from_last_event_id_on_epoll() {
+    resumes_from_last_event_id(Backend::Epoll);
+}
+
+#[test]
+fn subscribers_count_against_the_per_ip_limit() {
+    let mut backends = vec![Backend::Threads];
+    if cfg!(target_os = "linux") {
+        backends.push(Backend::Epoll);
+    }
+    for backend in backends {
+        let hub = Arc::new(Broadcast::new(0));
+        let config = Config {
+            max_connections_per_ip: 1,
+            ..config(backend)
+        };
+        let addr = start_with(config, Arc::clone(&hub));
+        let url = format!("http://{}/countdown", addr);
+        let subscriber = open(addr, None);
+        wait_for_subscribers(&hub, 1);
+        // 事件流占着唯一的名额
+        let resp = Client::new().get(&url).send().unwrap();
+        assert_eq!(resp.status, 429, "{:?}", backend);
+
+        // 客户端断开之后，下一次写入失败，事件流结束并归还名额
+        drop(subscriber);
+        let started = Instant::now();
+        while Client::new().get(&url).send().unwrap().status == 429 {
+            assert!(started.elapsed() < Duration::from_secs(2), "{:?}", backend);
+            hub.send(Event::new("ping"));
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+}
@@ -0,0 +1,100 @@
+use std::{
+    io::{Read, Write},
+    net::TcpStream,
+    thread,
+    time::{Duration, Instant},
+};
+mod common;
+
+use common::{ServerProcess, read_response};
+
+fn status_line(resp: &[u8]) -> String {
+    let resp = String::from_utf8_lossy(resp);
+    resp.lines().next().unwrap_or("").to_string()
+}
+
+#[test]
+fn incomplete_header_gets_408() {
+    let server = ServerProcess::start(&["--header-timeout", "300ms"]);
+    let mut stream = TcpStream::connect(server.addr).unwrap();
+    stream.write_all(b"GET / HTTP/1.1\r\nHost: x\r\n").unwrap();
+
+    let started = Instant::now();
+    let resp = read_response(&mut stream);
+    assert_eq!(status_line(&resp), "HTTP/1.1 408 Request Timeout");
+    assert!(started.elapsed() < Duration::from_secs(5));
+    // 服务器在 408 之后关闭连接
+    assert_eq!(stream.read(&mut [0; 1]).unwrap(), 0);
+}
+
+#[test]
+fn trickling_client_is_cut_off() {
+    let server = ServerProcess::start(&["--header-timeout", "500ms", "--read-timeout", "10s"]);
+    let mut stream = TcpStream::connect(server.addr).unwrap();
+    let mut writer = stream.try_clone().unwrap();
+    // 每 50ms 发送一个字节，永远不会触发单次读超时
+    let slowloris = thread::spawn(move || {
+        for b in b"GET / HTTP/1.1\r\nX-Slow: "
+            .iter()
+            .chain([b'a'; 200].iter())
+        {
+            if writer.write_all(&[*b]).is_err() {
+                return;
+            }
+            thread::sleep(Duration::from_millis(50));
+        }
+    });
+
+    let started = Instant::now();
+    let resp = read_response(&mut stream);
+    assert_eq!(status_line(&resp), "HTTP/1.1 408 Request Timeout");
+    assert!(started.elapsed() < Duration::from_secs(5));
+    drop(stream);
+    slowloris.join().unwrap();
+}
+
+#[test]
+fn keep_alive_reuses_connection_until_idle_timeout() {
+    let server = ServerProcess::start(&["--keep-alive-timeout", "300ms"]);
+    let mut stream = TcpStream::connect(server.addr).unwrap();
+    for _ in 0..3 {
+        stream
+            .write_all(b"GET / HTTP/1.1\r\nHost: x\r\n\r\n")
+            .unwrap();
+        let resp = read_response(&mut stream);
+        assert_eq!(status_line(&resp), "HTTP/1.1 200 OK");
+    }
+    // 空闲超过 keep-alive 时间后，服务器不发送任何内容直接关闭连接
+    let started = Instant::now();
+    assert_eq!(stream.read(&mut [0; 1]).unwrap(), 0);
+    assert!(started.elapsed() < Duration::from_secs(5));
+}
+
+#[test]
+fn connection_close_is_honoured() {
+    let server = ServerProcess::start(&[]);
+    let mut stream = TcpStream::connect(server.addr).unwrap();
+    stream
+        .write_all(b"GET / HTTP/1.1\r\nConnection: close\r\n\r\n")
+        .unwrap();
+    let resp = read_response(&mut stream);
+    assert!(String::from_utf8_lossy(&resp).contains("Connection: close\r\n"));
+    assert_eq!(stream.read(&mut [0; 1]).unwrap(), 0);
+}
+
+#[test]
+fn per_ip_connection_cap_returns_429() {
+    let server = ServerProcess::start(&["--max-conns-per-ip", "2", "--header-timeout", "5s"]);
+    let _idle1 = TcpStream::connect(server.addr).unwrap();
+    let _idle2 = TcpStream::connect(server.addr).unwrap();
+    let mut third = TcpStream::connect(server.addr).unwrap();
+    let resp = read_response(&mut third);
+    assert_eq!(status_line(&resp), "HTTP/1.1 429 Too Many Requests");
+
+    // 释放一个名额之后可以重新连接
+    drop(_idle1);
+    thread::sleep(Duration::from_millis(200));
+    let mut again = TcpStream::connect(server.addr).unwrap();
+    again.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+    assert_eq!(status_line(&read_response(&mut again)), "HTTP/1.1 200 OK");
+}
@@ -0,0 +1,247 @@
+use std::{
+    io::Write,
+    net::SocketAddr,
+    sync::{Mutex, mpsc},
+    thread,
+    time::{Duration, Instant},
+};
+use web_server::{
+    Config, Server,
+    access_log::LogTarget,
+    client::Client,
+    log::Level,
+    server::Backend,
+    websocket::{
+        self, Endpoint, MESSAGE_TOO_BIG, Message, NORMAL_CLOSURE, PROTOCOL_ERROR, WebSocket,
+        WebSocketHandler,
+    },
+};
+mod common;
+
+use common::ServerProcess;
+
+fn ws_url(addr: SocketAddr, path: &str) -> String {
+    format!("ws://{}{}", addr, path)
+}
+
+fn echo_round_trip(addr: SocketAddr) {
+    let mut ws = websocket::connect(&ws_url(addr, "/ws/echo")).unwrap();
+    ws.send("hello").unwrap();
+    assert_eq!(ws.recv().unwrap(), Some(Message::Text("hello".into())));
+    ws.send(vec![0, 1, 2, 255]).unwrap();
+    assert_eq!(
+        ws.recv().unwrap(),
+        Some(Message::Binary(vec![0, 1, 2, 255]))
+    );
+    let big = "x".repeat(70_000);
+    ws.send(big.as_str()).unwrap();
+    assert_eq!(ws.recv().unwrap(), Some(Message::Text(big)));
+    ws.close(NORMAL_CLOSURE, "done").unwrap();
+    assert_eq!(ws.close_code(), Some(NORMAL_CLOSURE));
+}
+
+#[test]
+fn echo_endpoint_round_trips_messages() {
+    let server = ServerProcess::start(&[]);
+    echo_round_trip(server.addr);
+}
+
+#[test]
+fn plain_requests_are_rejected() {
+    let server = ServerProcess::start(&[]);
+    let client = Client::new();
+    let resp = client.get(&server.url("/ws/echo")).send().unwrap();
+    assert_eq!(resp.status, 426);
+    assert_eq!(resp.header("Upgrade"), Some("websocket"));
+
+    let upgrade = |version: &str, key: &str| {
+        client
+            .get(&server.url("/ws/echo"))
+            .header("Connection", "keep-alive, Upgrade")
+            .header("Upgrade", "websocket")
+            .header("Sec-WebSocket-Version", version)
+            .header("Sec-WebSocket-Key", key)
+            .send()
+            .unwrap()
+    };
+    let resp = upgrade("8", "dGhlIHNhbXBsZSBub25jZQ==");
+    assert_eq!(resp.status, 426);
+    assert_eq!(resp.header("Sec-WebSocket-Version"), Some("13"));
+    assert_eq!(upgrade("13", "not a key").status, 400);
+}
+
+#[test]
+fn sessions_do_not_hold_workers() {
+    let server = ServerProcess::start(&["--workers", "1"]);
+    let mut sessions: Vec<_> = (0..4)
+        .map(|_| websocket::connect(&ws_url(server.addr, "/ws/echo")).unwrap())
+        .collect();
+    // 唯一的工作线程没有被会话占住
+    let resp = Client::new().get(&server.url("/")).send().unwrap();
+    assert_eq!(resp.status, 200);
+    for (i, ws) in sessions.iter_mut().enumerate() {
+        ws.send(format!("session {}", i)).unwrap();
+        assert_eq!(
+            ws.recv().unwrap(),
+            Some(Message::from(format!("session {}", i)))
+        );
+    }
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn epoll_backend_hands_off_upgraded_connections() {
+    let server = ServerProcess::start(&["--backend", "epoll", "--workers", "1"]);
+    let _idle = websocket::connect(&ws_url(server.addr, "/ws/echo")).unwrap();
+    echo_round_trip(server.addr);
+    let resp = Client::new().get(&server.url("/")).send().unwrap();
+    assert_eq!(resp.status, 200);
+}
+
+#[test]
+fn protocol_errors_close_the_session() {
+    let server = ServerProcess::start(&[]);
+    let mut ws = websocket::connect(&ws_url(server.addr, "/ws/echo")).unwrap();
+    // 客户端的帧必须带掩码
+    ws.get_ref().write_all(&[0x81, 0x02, b'h', b'i']).unwrap();
+    assert_eq!(ws.recv().unwrap(), None);
+    assert_eq!(ws.close_code(), Some(PROTOCOL_ERROR));
+}
+
+/// 记录会话事件的 handler：连接时推送欢迎消息，关闭时报告关闭码
+struct Recorder {
+    closed: Mutex<mpsc::Sender<Option<u16>>>,
+}
+
+impl WebSocketHandler for Recorder {
+    fn on_open(&self, ws: &WebSocket) {
+        // 句柄可以交给其他线程主动推送
+        let ws = ws.clone();
+        thread::spawn(move || ws.send(format!("welcome to {}", ws.request().path)));
+    }
+
+    fn on_message(&self, ws: &WebSocket, msg: Message) {
+        let _ = ws.send(msg);
+    }
+
+    fn on_close(&self, _ws: &WebSocket, code: Option<u16>) {
+        let _ = self.closed.lock().unwrap().send(code);
+    }
+}
+
+fn start(endpoint: Endpoint) -> SocketAddr {
+    start_with(
+        endpoint,
+        Config {
+            binds: vec![SocketAddr::from(([127, 0, 0, 1], 0))],
+            log_level: Level::Off,
+            access_log: LogTarget::Off,
+            ..Config::default()
+        },
+    )
+}
+
+fn start_with(endpoint: Endpoint, config: Config) -> SocketAddr {
+    let server = Server::bind(config).unwrap().handler(endpoint);
+    let addr = server.local_addrs()[0];
+    thread::spawn(move || server.run());
+    addr
+}
+
+#[test]
+fn server_pushes_messages_and_drops_silent_clients() {
+    let (tx, rx) = mpsc::channel();
+    let endpoint = Endpoint::new(Recorder {
+        closed: Mutex::new(tx),
+    })
+    .ping_interval(Some(Duration::from_millis(100)));
+    let addr = start(endpoint);
+
+    let mut ws = websocket::connect(&ws_url(addr, "/chat")).unwrap();
+    assert_eq!(ws.recv().unwrap(), Some(Message::from("welcome to /chat")));
+    // 不再读取，ping 得不到回应，两个间隔之后服务器断开连接
+    let code = rx.recv_timeout(Duration::from_secs(2)).unwrap();
+    assert_eq!(code, None);
+}
+
+#[test]
+fn oversized_messages_are_refused() {
+    let (tx, rx) = mpsc::channel();
+    let endpoint = Endpoint::new(Recorder {
+        closed: Mutex::new(tx),
+    })
+    .max_message_bytes(16);
+    let addr = start(endpoint);
+
+    let mut ws = websocket::connect(&ws_url(addr, "/")).unwrap();
+    ws.recv().unwrap();
+    ws.send("this message is longer than sixteen bytes")
+        .unwrap();
+    assert_eq!(ws.recv().unwrap(), None);
+    assert_eq!(ws.close_code(), Some(MESSAGE_TOO_BIG));
+    let code = rx.recv_timeout(Duration::from_secs(2)).unwrap();
+    assert_eq!(code, Some(MESSAGE_TOO_BIG));
+}
+
+/// 反复执行 f，直到返回 true 或者超过两秒
+fn eventually(what: &str, mut f: impl FnMut() -> bool) {
+    let deadline = Instant::now() + Duration::from_secs(2);
+    while !f() {
+        assert!(Instant::now() < deadline, "timed out waiting to {}", what);
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+#[test]
+fn sessions_count_against_the_per_ip_limit() {
+    let mut backends = vec![Backend::Threads];
+    if cfg!(target_os = "linux") {
+        backends.push(Backend::Epoll);
+    }
+    for backend in backends {
+        let (tx, _rx) = mpsc::channel();
+        let endpoint = Endpoint::new(Recorder {
+            closed: Mutex::new(tx),
+        });
+        let addr = start_with(
+            endpoint,
+            Config {
+                binds: vec![SocketAddr::from(([127, 0, 0, 1], 0))],
+                backend,
+                max_connections_per_ip: 2,
+                log_level: Level::Off,
+                access_log: LogTarget::Off,
+                ..Config::default()
+            },
+        );
+        let url = format!("http://{}/", addr);
+        let mut first = websocket::connect(&ws_url(addr, "/")).unwrap();
+        let _second = websocket::connect(&ws_url(addr, "/")).unwrap();
+        // 两个会话占满了名额，新连接被拒绝
+        let resp = Client::new().get(&url).send().unwrap();
+        assert_eq!(resp.status, 429, "{:?}", backend);
+        // 会话结束之后名额归还
+        first.close(NORMAL_CLOSURE, "").unwrap();
+        eventually("release the slot", || {
+            Client::new().get(&url).send().unwrap().status != 429
+        });
+    }
+}
+
+#[test]
+fn sessions_beyond_the_cap_are_refused() {
+    let (tx, _rx) = mpsc::channel();
+    let endpoint = Endpoint::new(Recorder {
+        closed: Mutex::new(tx),
+    })
+    .max_sessions(1);
+    let sessions = endpoint.clone();
+    let addr = start(endpoint);
+
+    let mut first = websocket::connect(&ws_url(addr, "/")).unwrap();
+    assert_eq!(sessions.sessions(), 1);
+    assert!(websocket::connect(&ws_url(addr, "/")).is_err());
+    first.close(NORMAL_CLOSURE, "").unwrap();
+    eventually("end the session", || sessions.sessions() == 0);
+    websocket::connect(&ws_url(addr, "/")).unwrap();
+}